@@ -2,7 +2,8 @@
 extern crate criterion;
 
 use bumblebee::prelude::*;
-use criterion::{Benchmark, Criterion, Throughput};
+use criterion::{Benchmark, Criterion, ParameterizedBenchmark, Throughput};
+use serde_json::{Map, Value};
 
 fn criterion_benchmark(c: &mut Criterion) {
     let trans = TransformerBuilder::default()
@@ -20,7 +21,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         Benchmark::new("1_top_level", move |b| {
             b.iter(|| trans.apply_from_str(input))
         })
-        .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+        .throughput(Throughput::Bytes(input.len() as u32)),
     );
 
     let trans = TransformerBuilder::default()
@@ -65,7 +66,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         Benchmark::new("10_top_level", move |b| {
             b.iter(|| trans.apply_from_str(input))
         })
-        .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+        .throughput(Throughput::Bytes(input.len() as u32)),
     );
 
     let trans = TransformerBuilder::default()
@@ -81,7 +82,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench(
         "constant",
         Benchmark::new("contant", move |b| b.iter(|| trans.apply_from_str(input)))
-            .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+            .throughput(Throughput::Bytes(input.len() as u32)),
     );
 
     let trans = TransformerBuilder::default()
@@ -125,7 +126,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         Benchmark::new("10_top_level_many_2_many", move |b| {
             b.iter(|| trans.apply_from_str(input))
         })
-        .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+        .throughput(Throughput::Bytes(input.len() as u32)),
     );
 
     let trans = TransformerBuilder::default()
@@ -136,7 +137,11 @@ fn criterion_benchmark(c: &mut Criterion) {
                 recursive: false,
                 prefix: Some("new"),
                 separator: Some("_"),
-                manipulation: None,
+                array_separator: None,
+                index_base: None,
+                index_format: None,
+                max_depth: None,
+                manipulations: Vec::new(),
             },
         )
         .unwrap()
@@ -160,7 +165,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         Benchmark::new("10_flatten_direct", move |b| {
             b.iter(|| trans.apply_from_str(input))
         })
-        .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+        .throughput(Throughput::Bytes(input.len() as u32)),
     );
 
     let trans = TransformerBuilder::default()
@@ -171,7 +176,11 @@ fn criterion_benchmark(c: &mut Criterion) {
                 recursive: false,
                 prefix: None,
                 separator: Some("_"),
-                manipulation: None,
+                array_separator: None,
+                index_base: None,
+                index_format: None,
+                max_depth: None,
+                manipulations: Vec::new(),
             },
         )
         .unwrap()
@@ -195,9 +204,53 @@ fn criterion_benchmark(c: &mut Criterion) {
         Benchmark::new("10_flatten_array", move |b| {
             b.iter(|| trans.apply_from_str(input))
         })
-        .throughput(Throughput::Bytes(input.as_bytes().len() as u32)),
+        .throughput(Throughput::Bytes(input.len() as u32)),
     );
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// measures how applying scales with the number of rules, each reading a nested source and
+/// writing a nested destination, so the cost per rule can be compared.
+fn rule_count_benchmark(c: &mut Criterion) {
+    c.bench(
+        "rule_count",
+        ParameterizedBenchmark::new(
+            "nested",
+            |b, &rules| {
+                let mut builder = TransformerBuilder::default();
+                let mut customer = Map::new();
+                let mut line = Map::new();
+                for i in 0..rules {
+                    builder = builder
+                        .add_direct(
+                            &format!("order.customer.field{}", i),
+                            &format!("customer.details.field{}", i),
+                        )
+                        .unwrap()
+                        .add_direct(
+                            &format!("order.lines[*].field{}", i),
+                            &format!("lines[*].field{}", i),
+                        )
+                        .unwrap();
+                    customer.insert(format!("field{}", i), Value::from(i));
+                    line.insert(format!("field{}", i), Value::from(i));
+                }
+                let trans = builder.build().unwrap();
+                let mut order = Map::new();
+                order.insert(String::from("customer"), Value::Object(customer));
+                order.insert(
+                    String::from("lines"),
+                    Value::Array(vec![Value::Object(line); 4]),
+                );
+                let mut input = Map::new();
+                input.insert(String::from("order"), Value::Object(order));
+                let input = Value::Object(input);
+                b.iter(|| trans.apply(&input))
+            },
+            vec![1, 10, 100],
+        )
+        .throughput(|&rules| Throughput::Elements(rules as u32 * 2)),
+    );
+}
+
+criterion_group!(benches, criterion_benchmark, rule_count_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,343 @@
+//! Derive macro for `bumblebee::transformer::Transformable`, generating the Transformer which
+//! builds a struct from the fields of another document eg.
+//!
+//! ```ignore
+//! #[derive(Deserialize, Bumblebee)]
+//! struct User {
+//!     #[bee(from = "user_id")]
+//!     id: String,
+//!     #[bee(from = "nested.inner.key")]
+//!     key: String,
+//!     // fields without a `from` are read from the field of the same name
+//!     name: String,
+//!     #[bee(skip)]
+//!     cache: Option<String>,
+//! }
+//! ```
+//!
+//! Fields are written under the name serde deserializes them from, their name, their
+//! `#[serde(rename = "...")]` or the struct's `#[serde(rename_all = "...")]`, so the output can be
+//! deserialized into the struct.
+
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Lit, LitStr, Meta, NestedMeta};
+
+#[proc_macro_derive(Bumblebee, attributes(bee))]
+pub fn derive_bumblebee(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// BeeField is a field of the derived struct and where its value is read from.
+struct BeeField {
+    from: String,
+    to: String,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.ident.span(),
+                    "Bumblebee can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "Bumblebee can only be derived for structs",
+            ))
+        }
+    };
+
+    let rename_all = parse_rename_all(&input.attrs)?;
+    let mut mappings = Vec::with_capacity(fields.len());
+    for field in fields {
+        if let Some(field) = parse_field(field, rename_all)? {
+            mappings.push(field);
+        }
+    }
+    let adds = mappings.iter().map(|BeeField { from, to }| {
+        quote! {
+            let builder = builder.add_direct(#from, #to)?;
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bumblebee::transformer::Transformable for #ident #ty_generics #where_clause {
+            fn transformer_builder() -> ::bumblebee::errors::Result<::bumblebee::transformer::TransformerBuilder> {
+                let builder = ::bumblebee::transformer::TransformerBuilder::default();
+                #(#adds)*
+                Ok(builder)
+            }
+        }
+    })
+}
+
+/// RenameRule is a serde `rename_all` rule, converting the snake_case name of a field to the name
+/// it's deserialized from.
+#[derive(Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(rule: &LitStr) -> syn::Result<Self> {
+        Ok(match rule.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            _ => {
+                return Err(syn::Error::new(
+                    rule.span(),
+                    "unknown rename_all rule, expected one of serde's eg. \"camelCase\"",
+                ))
+            }
+        })
+    }
+
+    fn apply(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_owned(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::with_capacity(field.len());
+                let mut capitalize = true;
+                for c in field.chars() {
+                    if c == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(c.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(c);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => pascal,
+                }
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// returns the name serde deserializes from of a `rename` or `rename_all` attribute, either
+/// `rename = "..."` or `rename(deserialize = "...")`. None when only the serialized name is set.
+fn deserialize_name(meta: &Meta) -> syn::Result<Option<LitStr>> {
+    match meta {
+        Meta::NameValue(nv) => match &nv.lit {
+            Lit::Str(s) => Ok(Some(s.clone())),
+            lit => Err(syn::Error::new(lit.span(), "expected a string")),
+        },
+        Meta::List(list) => {
+            for nested in &list.nested {
+                if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+                    if nv.path.is_ident("deserialize") {
+                        return deserialize_name(&Meta::NameValue(nv.clone()));
+                    }
+                }
+            }
+            Ok(None)
+        }
+        Meta::Path(path) => Err(syn::Error::new(path.span(), "expected a name")),
+    }
+}
+
+/// returns the struct's `#[serde(rename_all = "...")]` rule, applied to fields not renamed.
+fn parse_rename_all(attrs: &[Attribute]) -> syn::Result<Option<RenameRule>> {
+    let mut rule = None;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("serde")) {
+        // other forms of serde attributes are left to serde to report
+        if let Ok(Meta::List(list)) = attr.parse_meta() {
+            for nested in &list.nested {
+                if let NestedMeta::Meta(meta) = nested {
+                    if meta.path().is_ident("rename_all") {
+                        if let Some(name) = deserialize_name(meta)? {
+                            rule = Some(RenameRule::parse(&name)?);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(rule)
+}
+
+/// returns where the field is read from and written to, None when the field is skipped.
+fn parse_field(
+    field: &syn::Field,
+    rename_all: Option<RenameRule>,
+) -> syn::Result<Option<BeeField>> {
+    let ident = field.ident.as_ref().expect("named field");
+    let name = ident.to_string();
+    let name = name.trim_start_matches("r#");
+    let name = match rename_all {
+        Some(rule) => rule.apply(name),
+        None => name.to_owned(),
+    };
+    let mut from = None;
+    let mut to = None;
+    let mut skip = false;
+
+    for attr in &field.attrs {
+        let is_bee = attr.path.is_ident("bee");
+        if !is_bee && !attr.path.is_ident("serde") {
+            continue;
+        }
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            // other forms of serde attributes are left to serde to report
+            _ if !is_bee => continue,
+            meta => return Err(syn::Error::new(meta.span(), "expected #[bee(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(meta) if meta.path().is_ident("rename") => {
+                    if is_bee {
+                        return Err(syn::Error::new(
+                            meta.span(),
+                            "use #[serde(rename = \"...\")] to rename the field",
+                        ));
+                    }
+                    if let Some(name) = deserialize_name(&meta)? {
+                        to = Some(name.value());
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if is_bee && nv.path.is_ident("from") => {
+                    match &nv.lit {
+                        Lit::Str(s) if !s.value().is_empty() => from = Some(s.value()),
+                        lit => {
+                            return Err(syn::Error::new(
+                                lit.span(),
+                                "expected a source namespace eg. from = \"nested.inner.key\"",
+                            ))
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(path)) if is_bee && path.is_ident("skip") => {
+                    skip = true;
+                }
+                nested if is_bee => {
+                    return Err(syn::Error::new(
+                        nested.span(),
+                        "unknown bee attribute, expected `from = \"...\"` or `skip`",
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+    if skip {
+        return Ok(None);
+    }
+    let to = to.unwrap_or(name);
+    Ok(Some(BeeField {
+        from: from.unwrap_or_else(|| to.clone()),
+        to,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let input: DeriveInput = syn::parse_str(
+            r#"
+            struct User {
+                #[bee(from = "nested.inner.key")]
+                key: String,
+                #[serde(rename = "full-name")]
+                name: String,
+                #[bee(skip)]
+                cache: Option<String>,
+            }"#,
+        )
+        .unwrap();
+        let expanded = expand(&input).unwrap().to_string();
+        assert!(expanded.contains(r#"add_direct ("nested.inner.key" , "key")"#));
+        assert!(expanded.contains(r#"add_direct ("full-name" , "full-name")"#));
+        assert!(!expanded.contains("cache"));
+
+        let input: DeriveInput = syn::parse_str(
+            r#"
+            #[serde(rename_all = "camelCase")]
+            struct User {
+                user_id: u64,
+                #[bee(from = "profile.name")]
+                display_name: String,
+                #[serde(rename = "Email")]
+                email_address: String,
+                #[serde(rename(serialize = "ignored", deserialize = "Phone"))]
+                phone_number: String,
+            }"#,
+        )
+        .unwrap();
+        let expanded = expand(&input).unwrap().to_string();
+        assert!(expanded.contains(r#"add_direct ("userId" , "userId")"#));
+        assert!(expanded.contains(r#"add_direct ("profile.name" , "displayName")"#));
+        assert!(expanded.contains(r#"add_direct ("Email" , "Email")"#));
+        assert!(expanded.contains(r#"add_direct ("Phone" , "Phone")"#));
+
+        for (rule, name) in &[
+            ("lowercase", "user_id"),
+            ("UPPERCASE", "USER_ID"),
+            ("PascalCase", "UserId"),
+            ("snake_case", "user_id"),
+            ("SCREAMING_SNAKE_CASE", "USER_ID"),
+            ("kebab-case", "user-id"),
+            ("SCREAMING-KEBAB-CASE", "USER-ID"),
+        ] {
+            let input: DeriveInput = syn::parse_str(&format!(
+                "#[serde(rename_all(deserialize = \"{}\"))] struct User {{ user_id: u64 }}",
+                rule
+            ))
+            .unwrap();
+            let expanded = expand(&input).unwrap().to_string();
+            assert!(expanded.contains(&format!(r#"add_direct ("{0}" , "{0}")"#, name)));
+        }
+
+        for input in &[
+            "struct User(String);",
+            "enum User { A }",
+            "struct User { #[bee(from = 1)] id: String }",
+            "struct User { #[bee(to = \"id\")] id: String }",
+            "#[serde(rename_all = \"Title Case\")] struct User { id: String }",
+        ] {
+            let input: DeriveInput = syn::parse_str(input).unwrap();
+            assert!(expand(&input).is_err());
+        }
+    }
+}
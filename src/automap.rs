@@ -0,0 +1,167 @@
+use crate::namespace::Namespace;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// NameMatch is how auto mapping compares the field names of the source and target samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NameMatch {
+    /// names must be identical.
+    #[default]
+    Exact,
+    /// names are compared ignoring case and `_`, `-` and space separators, so `userId`,
+    /// `user_id` and `User-ID` all match.
+    Fuzzy,
+}
+
+impl NameMatch {
+    #[inline]
+    fn normalize(self, name: &str) -> String {
+        match self {
+            NameMatch::Exact => name.to_owned(),
+            NameMatch::Fuzzy => name
+                .chars()
+                .filter(|c| !matches!(c, '_' | '-' | ' '))
+                .flat_map(char::to_lowercase)
+                .collect(),
+        }
+    }
+}
+
+/// returns the `(from, to)` namespaces of the target sample's fields found in the source sample,
+/// along with the namespaces of the target fields which were not. Fields are first matched by
+/// their whole namespace and otherwise by their name alone, when a single source field has it.
+/// Arrays of Objects are matched element wise using wildcards eg. `items[*].sku`.
+pub(crate) fn auto_map(
+    source: &Value,
+    target: &Value,
+    name_match: NameMatch,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut sources = BTreeMap::new();
+    leaves(&mut Vec::new(), source, &mut sources);
+    let mut targets = BTreeMap::new();
+    leaves(&mut Vec::new(), target, &mut targets);
+
+    let key = |namespace: &[Namespace]| {
+        namespace
+            .iter()
+            .map(|ns| (name_match.normalize(ns.id()), ns.is_object()))
+            .collect::<Vec<_>>()
+    };
+    let wildcards = |namespace: &[Namespace]| namespace.iter().filter(|ns| !ns.is_object()).count();
+    let mut by_key = BTreeMap::new();
+    let mut by_name: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (path, namespace) in &sources {
+        by_key.entry(key(namespace)).or_insert(path);
+        if let Some(last) = namespace.last() {
+            by_name
+                .entry((name_match.normalize(last.id()), wildcards(namespace)))
+                .or_default()
+                .push(path);
+        }
+    }
+
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for (path, namespace) in &targets {
+        let from = by_key.get(&key(namespace)).copied().or_else(|| {
+            let name = (
+                name_match.normalize(namespace.last()?.id()),
+                wildcards(namespace),
+            );
+            match by_name.get(&name).map(Vec::as_slice) {
+                Some([from]) => Some(*from),
+                _ => None,
+            }
+        });
+        match from {
+            Some(from) => matched.push((from.clone(), path.clone())),
+            None => unmatched.push(path.clone()),
+        }
+    }
+    (matched, unmatched)
+}
+
+/// collects the namespaces of the leaf values of the sample, keyed by their formatted namespace.
+/// Scalars, empty Objects and Arrays not containing Objects are leaves.
+fn leaves(
+    namespace: &mut Vec<Namespace>,
+    value: &Value,
+    found: &mut BTreeMap<String, Vec<Namespace>>,
+) {
+    match value {
+        Value::Object(m) if !m.is_empty() => {
+            for (k, v) in m {
+                namespace.push(Namespace::Object { id: k.clone() });
+                leaves(namespace, v, found);
+                namespace.pop();
+            }
+        }
+        Value::Array(arr) if arr.iter().any(Value::is_object) => {
+            // the Array's own namespace is replaced by a wildcard while collecting the elements
+            let id = match namespace.pop() {
+                Some(Namespace::Object { id }) => id,
+                Some(ns) => {
+                    namespace.push(ns);
+                    String::new()
+                }
+                None => String::new(),
+            };
+            namespace.push(Namespace::Wildcard { id: id.clone() });
+            for v in arr.iter().filter(|v| v.is_object()) {
+                leaves(namespace, v, found);
+            }
+            namespace.pop();
+            if !id.is_empty() {
+                namespace.push(Namespace::Object { id });
+            }
+        }
+        _ if !namespace.is_empty() => {
+            found.insert(Namespace::format(namespace), namespace.clone());
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_auto_map() {
+        let source = json!({
+            "id": 1,
+            "user": {"first_name": "Joey", "email": "joey@example.com"},
+            "items": [{"sku": "a", "qty": 1}],
+            "tags": ["a"]
+        });
+        let target = json!({
+            "id": 0,
+            "user": {"firstName": "", "phone": ""},
+            "email": "",
+            "lines": [{"sku": "", "qty": 0}],
+            "tags": []
+        });
+
+        let (matched, unmatched) = auto_map(&source, &target, NameMatch::Exact);
+        assert_eq!(
+            vec![
+                ("user.email", "email"),
+                ("id", "id"),
+                ("items[*].qty", "lines[*].qty"),
+                ("items[*].sku", "lines[*].sku"),
+                ("tags", "tags"),
+            ],
+            matched
+                .iter()
+                .map(|(a, b)| (a.as_str(), b.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["user.firstName", "user.phone"], unmatched);
+
+        let (matched, unmatched) = auto_map(&source, &target, NameMatch::Fuzzy);
+        assert!(matched.contains(&("user.first_name".to_owned(), "user.firstName".to_owned())));
+        assert_eq!(vec!["user.phone"], unmatched);
+    }
+}
@@ -0,0 +1,106 @@
+//! applies a mapping definition to JSON read from a file or stdin, writing the result to stdout.
+use bumblebee::errors::{Error, Result};
+use bumblebee::output::OutputOptions;
+use bumblebee::transformer::{Mode, Transformer};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &str = "usage: bumblebee [OPTIONS] <DEFINITION> [INPUT]
+
+Transforms the JSON read from INPUT, or stdin when not provided, using the mapping definition
+file and writes the result to stdout. Definitions may be .json or .bb files.
+
+options:
+    --mode <MODE>   one2one, many2many or many2one, overriding the mode of the definition
+    --pretty        pretty prints the output, unless streaming NDJSON
+    --ndjson        reads and writes newline delimited JSON, transforming a line at a time
+    -h, --help      prints this message";
+
+#[derive(Default)]
+struct Args {
+    definition: String,
+    input: Option<String>,
+    mode: Option<Mode>,
+    pretty: bool,
+    ndjson: bool,
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// returns the parsed arguments or None when help was requested.
+fn parse_args<I>(mut args: I) -> std::result::Result<Option<Args>, String>
+where
+    I: Iterator<Item = String>,
+{
+    let mut parsed = Args::default();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--pretty" => parsed.pretty = true,
+            "--ndjson" => parsed.ndjson = true,
+            "--mode" => {
+                parsed.mode = Some(match args.next().as_deref() {
+                    Some("one2one") => Mode::One2One,
+                    Some("many2many") => Mode::Many2Many,
+                    Some("many2one") => Mode::Many2One,
+                    Some(mode) => return Err(format!("unknown mode `{}`", mode)),
+                    None => return Err(String::from("--mode requires a value")),
+                })
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option `{}`", flag)),
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    parsed.definition = positional
+        .next()
+        .ok_or_else(|| String::from("missing the definition file"))?;
+    parsed.input = positional.next();
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument `{}`", extra));
+    }
+    Ok(Some(parsed))
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut trans = Transformer::from_path(&args.definition)?;
+    if let Some(mode) = args.mode {
+        trans = trans.into_builder().mode(mode).build()?;
+    }
+    let mut input: Box<dyn Read> = match &args.input {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new(stdout.lock());
+    if args.ndjson {
+        trans.apply_ndjson(input, &mut stdout)?;
+    } else {
+        let mut source = String::new();
+        input.read_to_string(&mut source)?;
+        let options = OutputOptions {
+            pretty: args.pretty,
+            ..OutputOptions::default()
+        };
+        writeln!(stdout, "{}", trans.apply_from_str_with(source, options)?)?;
+    }
+    stdout.flush().map_err(Error::from)
+}
@@ -0,0 +1,119 @@
+use crate::errors::{Error, Result};
+use crate::rules::Rule;
+use crate::tree::{Arena, Node};
+use serde::{Deserialize, Serialize};
+
+/// the number of elements each wildcard is assumed to expand to when estimating fan-out.
+pub const ESTIMATED_ARRAY_LEN: usize = 10;
+
+/// ComplexityScore is a static estimate of how expensive a Transformer is to apply, without
+/// applying it to any documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ComplexityScore {
+    rules: usize,
+    max_depth: usize,
+    recursive_flattens: usize,
+    fan_out: usize,
+}
+
+impl ComplexityScore {
+    /// returns the number of rules, including deferred rules.
+    #[inline]
+    pub fn rules(&self) -> usize {
+        self.rules
+    }
+
+    /// returns the deepest source namespace any rule is applied at.
+    #[inline]
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// returns the number of rules recursively flattening their source value.
+    #[inline]
+    pub fn recursive_flattens(&self) -> usize {
+        self.recursive_flattens
+    }
+
+    /// returns the estimated number of rule applications per document, assuming every wildcard
+    /// expands to ESTIMATED_ARRAY_LEN elements.
+    #[inline]
+    pub fn fan_out(&self) -> usize {
+        self.fan_out
+    }
+
+    pub(crate) fn new(arena: &Arena, deferred: &[Box<dyn Rule>]) -> Self {
+        let mut score = ComplexityScore::default();
+        score.visit(arena, 0, 0, 0);
+        for rule in deferred {
+            score.add(rule.as_ref(), 0, 0);
+        }
+        score
+    }
+
+    fn visit(&mut self, arena: &Arena, idx: usize, depth: usize, wildcards: u32) {
+        let node = &arena.tree[idx];
+        let wildcards = match node {
+            Node::Wildcard { .. } => wildcards + 1,
+            _ => wildcards,
+        };
+        if let Some(rules) = node.rules() {
+            for rule in rules {
+                self.add(rule.as_ref(), depth, wildcards);
+            }
+        }
+        for &child in node.children() {
+            self.visit(arena, child, depth + 1, wildcards);
+        }
+    }
+
+    #[inline]
+    fn add(&mut self, rule: &dyn Rule, depth: usize, wildcards: u32) {
+        self.rules += 1;
+        self.max_depth = self.max_depth.max(depth);
+        if rule.is_recursive() {
+            self.recursive_flattens += 1;
+        }
+        self.fan_out = self
+            .fan_out
+            .saturating_add(ESTIMATED_ARRAY_LEN.saturating_pow(wildcards));
+    }
+}
+
+/// ComplexityLimits are the maximum ComplexityScore a TransformerBuilder will build, eg. to reject
+/// overly expensive user authored mappings. Limits which are not set are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComplexityLimits {
+    pub rules: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub recursive_flattens: Option<usize>,
+    pub fan_out: Option<usize>,
+}
+
+impl ComplexityLimits {
+    /// returns an error naming the first limit the score exceeds.
+    pub fn check(&self, score: &ComplexityScore) -> Result<()> {
+        let measures = [
+            ("rules", self.rules, score.rules),
+            ("max_depth", self.max_depth, score.max_depth),
+            (
+                "recursive_flattens",
+                self.recursive_flattens,
+                score.recursive_flattens,
+            ),
+            ("fan_out", self.fan_out, score.fan_out),
+        ];
+        for (name, limit, value) in measures.iter() {
+            match limit {
+                Some(limit) if value > limit => {
+                    return Err(Error::ComplexityExceeded(format!(
+                        "{} of {} exceeds the limit of {}",
+                        name, value, limit
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
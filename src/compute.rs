@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// ComputeOp is a single arithmetic step of a Compute mapping, applied in order to the numeric
+/// source value eg. `[Scale(0.01), Round(2)]` converts cents to dollars. Integers remain integers
+/// for as long as every step can be computed exactly, otherwise the value becomes a float.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ComputeOp {
+    /// multiplies the value by the factor.
+    Scale(Number),
+    /// adds the offset, which may be negative, to the value.
+    Offset(Number),
+    /// rounds the value to the number of decimal places, half away from zero. Rounding to `0`
+    /// places writes an integer.
+    Round(u32),
+    /// the absolute value.
+    Abs,
+}
+
+/// Num is a number being computed, kept as an integer while exact.
+#[derive(Debug, Clone, Copy)]
+enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Num {
+    #[inline]
+    fn new(n: &Number) -> Option<Num> {
+        match n.as_i64() {
+            Some(i) => Some(Num::Int(i)),
+            None => n.as_f64().map(Num::Float),
+        }
+    }
+
+    #[inline]
+    fn float(self) -> f64 {
+        match self {
+            Num::Int(i) => i as f64,
+            Num::Float(f) => f,
+        }
+    }
+
+    #[inline]
+    fn number(self) -> Option<Number> {
+        match self {
+            Num::Int(i) => Some(Number::from(i)),
+            Num::Float(f) => Number::from_f64(f),
+        }
+    }
+}
+
+impl ComputeOp {
+    #[inline]
+    fn apply(&self, n: Num) -> Option<Num> {
+        Some(match (self, n) {
+            (ComputeOp::Scale(factor), n) => match (n, Num::new(factor)?) {
+                (Num::Int(a), Num::Int(b)) => a
+                    .checked_mul(b)
+                    .map_or_else(|| Num::Float(a as f64 * b as f64), Num::Int),
+                (a, b) => Num::Float(a.float() * b.float()),
+            },
+            (ComputeOp::Offset(offset), n) => match (n, Num::new(offset)?) {
+                (Num::Int(a), Num::Int(b)) => a
+                    .checked_add(b)
+                    .map_or_else(|| Num::Float(a as f64 + b as f64), Num::Int),
+                (a, b) => Num::Float(a.float() + b.float()),
+            },
+            (ComputeOp::Round(_), Num::Int(i)) => Num::Int(i),
+            (ComputeOp::Round(0), Num::Float(f)) => {
+                let rounded = f.round();
+                // i64::MAX as f64 rounds up to 2^63, which is out of range
+                if rounded >= i64::MIN as f64 && rounded < i64::MAX as f64 {
+                    Num::Int(rounded as i64)
+                } else {
+                    Num::Float(rounded)
+                }
+            }
+            (ComputeOp::Round(places), Num::Float(f)) => {
+                let scale = 10f64.powi((*places).min(308) as i32);
+                let rounded = (f * scale).round() / scale;
+                // values too large to scale have no fractional part to round
+                Num::Float(if rounded.is_finite() { rounded } else { f })
+            }
+            (ComputeOp::Abs, Num::Int(i)) => i
+                .checked_abs()
+                .map_or_else(|| Num::Float((i as f64).abs()), Num::Int),
+            (ComputeOp::Abs, Num::Float(f)) => Num::Float(f.abs()),
+        })
+    }
+}
+
+/// returns the result of applying the ops in order to the number, or None if it's not finite eg.
+/// after scaling by a huge factor.
+pub fn compute(ops: &[ComputeOp], n: &Number) -> Option<Value> {
+    let mut n = Num::new(n)?;
+    for op in ops {
+        n = op.apply(n)?;
+    }
+    n.number().map(Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_compute() {
+        let n = |v: Value| match v {
+            Value::Number(n) => n,
+            _ => unreachable!(),
+        };
+        let dollars = [ComputeOp::Scale(n(json!(0.01))), ComputeOp::Round(2)];
+        assert_eq!(Some(json!(19.99)), compute(&dollars, &n(json!(1999))));
+        assert_eq!(Some(json!(0.1)), compute(&dollars, &n(json!(10))));
+
+        let ints = [
+            ComputeOp::Offset(n(json!(-10))),
+            ComputeOp::Scale(n(json!(3))),
+            ComputeOp::Abs,
+            ComputeOp::Round(2),
+        ];
+        assert_eq!(Some(json!(21)), compute(&ints, &n(json!(3))));
+        assert_eq!(
+            Some(json!(2.0 * i64::MAX as f64)),
+            compute(&[ComputeOp::Scale(n(json!(2)))], &n(json!(i64::MAX)))
+        );
+        assert_eq!(
+            Some(json!(-3)),
+            compute(&[ComputeOp::Round(0)], &n(json!(-2.5)))
+        );
+        assert_eq!(
+            None,
+            compute(&[ComputeOp::Scale(n(json!(1e308)))], &n(json!(1e308)))
+        );
+
+        assert_eq!(
+            r#"[{"Scale":0.01},{"Round":2}]"#,
+            serde_json::to_string(&dollars).unwrap()
+        );
+        let ops: Vec<ComputeOp> = serde_json::from_str(r#"[{"Offset":1},"Abs"]"#).unwrap();
+        assert_eq!(vec![ComputeOp::Offset(n(json!(1))), ComputeOp::Abs], ops);
+    }
+}
@@ -0,0 +1,632 @@
+#[cfg(feature = "crypto")]
+use crate::crypto::{Cipher, Keys};
+use crate::errors::{Error, Result};
+use crate::explain::{diff, RuleId, Trace};
+use crate::functions::{Function, FunctionRegistry};
+use crate::namespace::Namespace;
+use crate::rules::{lookup, IndexKeys, Rule};
+use crate::transformer::{Missing, MissingPolicy, Navigation};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Entropy is the source of randomness and time for rules which generate values. It can be seeded,
+/// along with a fixed clock, so that transformations are reproducible for tests and replays.
+#[derive(Debug)]
+pub struct Entropy {
+    state: Cell<u64>,
+    now: SystemTime,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Entropy::new(
+            RandomState::new().build_hasher().finish(),
+            SystemTime::now(),
+        )
+    }
+}
+
+impl Entropy {
+    /// creates a new Entropy, the same seed and time will always generate the same values.
+    #[inline]
+    pub fn new(seed: u64, now: SystemTime) -> Self {
+        Entropy {
+            state: Cell::new(seed),
+            now,
+        }
+    }
+
+    /// returns the next pseudo random number using the splitmix64 algorithm.
+    #[inline]
+    pub fn next_u64(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// returns the time the transformation is considered to be running at.
+    #[inline]
+    pub fn now(&self) -> SystemTime {
+        self.now
+    }
+
+    /// seeds the Entropy, returning its previous state so it can be restored by seeding it again.
+    #[inline]
+    pub(crate) fn reseed(&self, seed: u64) -> u64 {
+        self.state.replace(seed)
+    }
+
+    /// returns a new Entropy, at the same time, seeded from this one so seeded transformations
+    /// remain reproducible.
+    #[inline]
+    pub(crate) fn split(&self) -> Entropy {
+        Entropy::new(self.next_u64(), self.now)
+    }
+}
+
+/// LocaleContext is the locale and timezone used by rules which are sensitive to them, the locale
+/// by casing and the timezone by generated timestamps. It can be set on the Transformer and
+/// overridden per mapping.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocaleContext {
+    /// BCP 47 language tag eg. `en-US`.
+    pub locale: String,
+    /// offset from UTC in minutes eg. `-420` for UTC-07:00.
+    pub utc_offset: i32,
+}
+
+impl Default for LocaleContext {
+    fn default() -> Self {
+        LocaleContext::new("en-US", 0)
+    }
+}
+
+impl LocaleContext {
+    #[inline]
+    pub fn new<S>(locale: S, utc_offset: i32) -> Self
+    where
+        S: Into<String>,
+    {
+        LocaleContext {
+            locale: locale.into(),
+            utc_offset,
+        }
+    }
+
+    /// returns the lowercase primary language subtag eg. `en` for `en-US`.
+    #[inline]
+    pub fn language(&self) -> String {
+        self.locale
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    }
+}
+
+/// Context contains the apply time state available to rules while transforming a single document.
+#[derive(Debug, Default)]
+pub struct Context {
+    navigation: Navigation,
+    missing: Missing,
+    missing_policy: MissingPolicy,
+    locale: LocaleContext,
+    index_width: usize,
+    // apply time values referenced by `$ctx.` sources eg. `$ctx.flags.new_pricing`
+    values: Value,
+    // named functions called by Function mappings
+    functions: FunctionRegistry,
+    // environment variables ConstantEnv mappings are allowed to read
+    env: Arc<BTreeSet<String>>,
+    // apply time Ciphers used by Encrypt and Decrypt mappings
+    #[cfg(feature = "crypto")]
+    keys: Keys,
+    entropy: Entropy,
+    indices: RefCell<Vec<usize>>,
+    // index of the document within the batch being transformed
+    record: Cell<usize>,
+    timings: Option<RefCell<BTreeMap<RuleId, (Duration, usize)>>>,
+    traces: Option<RefCell<Vec<Trace>>>,
+    // source reported by the rule currently being applied, when tracing
+    source: RefCell<Option<(String, bool)>>,
+    // errors collected rather than returned, when collecting
+    errors: Option<RefCell<Vec<Error>>>,
+}
+
+impl Context {
+    #[inline]
+    pub(crate) fn new(navigation: Navigation, entropy: Entropy) -> Self {
+        Context {
+            navigation,
+            missing: Missing::default(),
+            missing_policy: MissingPolicy::default(),
+            locale: LocaleContext::default(),
+            index_width: 0,
+            values: Value::Null,
+            functions: FunctionRegistry::default(),
+            env: Arc::default(),
+            #[cfg(feature = "crypto")]
+            keys: Keys::default(),
+            entropy,
+            indices: RefCell::new(Vec::new()),
+            record: Cell::new(0),
+            timings: None,
+            traces: None,
+            source: RefCell::new(None),
+            errors: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn with_missing(mut self, missing: Missing) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_missing_policy(mut self, missing_policy: MissingPolicy) -> Self {
+        self.missing_policy = missing_policy;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_locale(mut self, locale: LocaleContext) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_index_width(mut self, index_width: usize) -> Self {
+        self.index_width = index_width;
+        self
+    }
+
+    /// sets the apply time values, available to rules as `$ctx.`.
+    #[inline]
+    pub(crate) fn with_values(mut self, values: &Map<String, Value>) -> Self {
+        self.values = Value::Object(values.clone());
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// returns the named function called by Function mappings.
+    #[inline]
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    #[inline]
+    pub(crate) fn with_env(mut self, env: Arc<BTreeSet<String>>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// returns the value of an environment variable read by ConstantEnv mappings, or an
+    /// `Error::EnvNotAllowed` when the Transformer wasn't allowed to read it.
+    #[inline]
+    pub(crate) fn env_var(&self, var: &str) -> Result<Option<String>> {
+        if !self.env.contains(var) {
+            return Err(Error::EnvNotAllowed(var.to_owned()));
+        }
+        Ok(env::var(var).ok())
+    }
+
+    #[cfg(feature = "crypto")]
+    #[inline]
+    pub(crate) fn with_keys(mut self, keys: &Keys) -> Self {
+        self.keys = keys.clone();
+        self
+    }
+
+    /// returns the named Cipher used by Encrypt and Decrypt mappings.
+    #[cfg(feature = "crypto")]
+    #[inline]
+    pub fn key(&self, name: &str) -> Option<&dyn Cipher> {
+        self.keys.get(name).map(|cipher| &**cipher)
+    }
+
+    /// sets the apply time flags, available to rules as `$ctx.flags`.
+    #[inline]
+    pub(crate) fn with_flags(mut self, flags: Map<String, Value>) -> Self {
+        if !self.values.is_object() {
+            self.values = Value::Object(Map::new());
+        }
+        self.values["flags"] = Value::Object(flags);
+        self
+    }
+
+    /// makes this the Context of a nested Transformer, see add_map_array, applied within the
+    /// parent. It shares the apply time values, registered functions, allowed environment
+    /// variables and record of the parent, so functions only need registering, and variables
+    /// allowing, with the outermost Transformer, and collects errors when
+    /// the parent does, which are added to the parent by join. Its Entropy should be split from
+    /// the parent's. Rules of the nested Transformer aren't timed or traced individually, the
+    /// rule applying it is.
+    #[inline]
+    pub(crate) fn nested_in(mut self, parent: &Context) -> Self {
+        self.values = parent.values.clone();
+        #[cfg(feature = "crypto")]
+        {
+            self.keys = parent.keys.clone();
+        }
+        // the functions registered with the nested Transformer take precedence
+        let mut functions = parent.functions.clone();
+        functions.extend(&self.functions);
+        self.functions = functions;
+        if !parent.env.is_empty() {
+            let mut env = (*parent.env).clone();
+            env.extend(self.env.iter().cloned());
+            self.env = Arc::new(env);
+        }
+        self.record.set(parent.record.get());
+        self.errors = parent.errors.as_ref().map(|_| RefCell::new(Vec::new()));
+        self
+    }
+
+    /// creates a Context for transforming an element of a batch on another thread, sharing the
+    /// settings and apply time values of the parent. Its Entropy is seeded from the parent's so
+    /// seeded transformations remain reproducible.
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub(crate) fn fork(&self) -> Self {
+        Context {
+            navigation: self.navigation,
+            missing: self.missing,
+            missing_policy: self.missing_policy,
+            locale: self.locale.clone(),
+            index_width: self.index_width,
+            values: self.values.clone(),
+            functions: self.functions.clone(),
+            env: self.env.clone(),
+            #[cfg(feature = "crypto")]
+            keys: self.keys.clone(),
+            entropy: self.entropy.split(),
+            indices: RefCell::new(Vec::new()),
+            record: Cell::new(self.record.get()),
+            timings: self.timings.as_ref().map(|_| RefCell::new(BTreeMap::new())),
+            traces: self.traces.as_ref().map(|_| RefCell::new(Vec::new())),
+            source: RefCell::new(None),
+            errors: self.errors.as_ref().map(|_| RefCell::new(Vec::new())),
+        }
+    }
+
+    /// adds the timings, traces and errors recorded by a forked, or nested, Context to this one.
+    #[inline]
+    pub(crate) fn join(&self, fork: Context) {
+        if let Some(errors) = &self.errors {
+            errors.borrow_mut().append(&mut fork.take_errors());
+        }
+        if let Some(traces) = &self.traces {
+            traces.borrow_mut().append(&mut fork.take_traces());
+        }
+        if let Some(timings) = &self.timings {
+            let mut timings = timings.borrow_mut();
+            for (id, (elapsed, count)) in fork.take_timings() {
+                let timing = timings.entry(id).or_insert((Duration::default(), 0));
+                timing.0 += elapsed;
+                timing.1 += count;
+            }
+        }
+    }
+
+    /// returns the apply time value at the provided namespace, relative to `$ctx.`, if present.
+    #[inline]
+    pub fn value(&self, namespace: &[Namespace]) -> Option<&Value> {
+        lookup(namespace, &self.values)
+    }
+
+    /// enables measuring the time spent applying each rule.
+    #[inline]
+    pub(crate) fn timed(mut self) -> Self {
+        self.timings = Some(RefCell::new(BTreeMap::new()));
+        self
+    }
+
+    /// enables recording what each rule reads and writes, to explain a transformation.
+    #[inline]
+    pub(crate) fn traced(mut self) -> Self {
+        self.traces = Some(RefCell::new(Vec::new()));
+        self
+    }
+
+    /// enables collecting the errors of rules, missing sources and type mismatches rather than
+    /// stopping at the first.
+    #[inline]
+    pub(crate) fn collecting(mut self) -> Self {
+        self.errors = Some(RefCell::new(Vec::new()));
+        self
+    }
+
+    #[inline]
+    pub(crate) fn take_errors(&self) -> Vec<Error> {
+        match &self.errors {
+            Some(errors) => errors.replace(Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    /// collects an error which doesn't fail the transformation, when collecting errors.
+    #[cfg(feature = "schema")]
+    #[inline]
+    pub(crate) fn warn(&self, e: Error) {
+        if let Some(errors) = &self.errors {
+            errors.borrow_mut().push(e);
+        }
+    }
+
+    /// returns the error, or collects it when collecting errors.
+    #[inline]
+    fn fail(&self, e: Error) -> Result<()> {
+        match &self.errors {
+            Some(errors) => {
+                errors.borrow_mut().push(e);
+                Ok(())
+            }
+            None => Err(e),
+        }
+    }
+
+    /// transforms a child of the source tree, prefixing the path of any error returned or
+    /// collected with the child's segment.
+    #[inline]
+    pub(crate) fn within<S, F>(&self, segment: S, f: F) -> Result<()>
+    where
+        S: FnOnce() -> String,
+        F: FnOnce() -> Result<()>,
+    {
+        let collected = self
+            .errors
+            .as_ref()
+            .map_or(0, |errors| errors.borrow().len());
+        let res = f();
+        match &self.errors {
+            Some(errors) if res.is_ok() && errors.borrow().len() > collected => {
+                let segment = segment();
+                let mut errors = errors.borrow_mut();
+                let tail = errors.split_off(collected);
+                errors.extend(tail.into_iter().map(|e| e.within(&segment)));
+                Ok(())
+            }
+            _ => res.map_err(|e| e.within(&segment())),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn take_traces(&self) -> Vec<Trace> {
+        match &self.traces {
+            Some(traces) => traces.replace(Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    /// returns if what each rule reads and writes is being recorded.
+    #[inline]
+    pub(crate) fn is_traced(&self) -> bool {
+        self.traces.is_some()
+    }
+
+    /// records the rule, which isn't applied as a parent of its source is missing, as not having
+    /// resolved its source, when tracing.
+    #[inline]
+    pub(crate) fn trace_skipped(&self, id: RuleId, rule: &dyn Rule) {
+        if let Some(traces) = &self.traces {
+            let source = rule
+                .source_paths(&[])
+                .first()
+                .map(|path| (Namespace::format(path), false));
+            traces.borrow_mut().push(Trace {
+                id,
+                indices: self.indices.borrow().clone(),
+                source,
+                written: Vec::new(),
+                failed: false,
+            });
+        }
+    }
+
+    /// reports the source path, relative to the namespace the rule is applied at, read by the
+    /// rule being applied and if it resolved to a value. Only recorded when explaining a
+    /// transformation so the path is only built then.
+    #[inline]
+    pub fn record_source<F>(&self, path: F, resolved: bool)
+    where
+        F: FnOnce() -> String,
+    {
+        if self.traces.is_some() {
+            self.source.replace(Some((path(), resolved)));
+        }
+    }
+
+    #[inline]
+    pub(crate) fn take_timings(&self) -> BTreeMap<RuleId, (Duration, usize)> {
+        match &self.timings {
+            Some(timings) => timings.replace(BTreeMap::new()),
+            None => BTreeMap::new(),
+        }
+    }
+
+    /// applies the identified rule, recording the time taken when timing is enabled.
+    #[inline]
+    pub(crate) fn apply(
+        &self,
+        id: RuleId,
+        rule: &dyn Rule,
+        from: &Value,
+        to: &mut Map<String, Value>,
+    ) -> Result<()> {
+        if let Some(traces) = &self.traces {
+            let before = to.clone();
+            let res = rule
+                .apply_with_context(self, from, to)
+                .or_else(|e| self.fail(e.in_rule(rule, id)));
+            let mut written = Vec::new();
+            diff("", &before, to, &mut written);
+            traces.borrow_mut().push(Trace {
+                id,
+                indices: self.indices.borrow().clone(),
+                source: self.source.take(),
+                written,
+                failed: res.is_err(),
+            });
+            return res;
+        }
+        match &self.timings {
+            None => rule
+                .apply_with_context(self, from, to)
+                .or_else(|e| self.fail(e.in_rule(rule, id))),
+            Some(timings) => {
+                let start = Instant::now();
+                let res = rule
+                    .apply_with_context(self, from, to)
+                    .or_else(|e| self.fail(e.in_rule(rule, id)));
+                let elapsed = start.elapsed();
+                let mut timings = timings.borrow_mut();
+                let timing = timings.entry(id).or_insert((Duration::default(), 0));
+                timing.0 += elapsed;
+                timing.1 += 1;
+                res
+            }
+        }
+    }
+
+    /// returns the index of the element currently being transformed for the wildcard at the
+    /// provided level, where level 0 is the outermost wildcard.
+    #[inline]
+    pub fn index(&self, level: usize) -> Option<usize> {
+        self.indices.borrow().get(level).copied()
+    }
+
+    /// returns the number of wildcard elements being transformed.
+    #[inline]
+    pub(crate) fn depth(&self) -> usize {
+        self.indices.borrow().len()
+    }
+
+    #[inline]
+    pub(crate) fn push_index(&self, index: usize) {
+        self.indices.borrow_mut().push(index);
+    }
+
+    #[inline]
+    pub(crate) fn pop_index(&self) {
+        self.indices.borrow_mut().pop();
+    }
+
+    /// returns the index of the document being transformed within a Many2Many batch, 0 for single
+    /// documents.
+    #[inline]
+    pub fn record(&self) -> usize {
+        self.record.get()
+    }
+
+    #[inline]
+    pub(crate) fn set_record(&self, record: usize) {
+        self.record.set(record);
+    }
+
+    /// returns the Entropy to be used by rules generating values.
+    #[inline]
+    pub fn entropy(&self) -> &Entropy {
+        &self.entropy
+    }
+
+    /// returns the Navigation the Transformer was built with.
+    #[inline]
+    pub fn navigation(&self) -> Navigation {
+        self.navigation
+    }
+
+    /// returns the Missing policy the Transformer was built with.
+    #[inline]
+    pub fn missing(&self) -> Missing {
+        self.missing
+    }
+
+    /// returns the MissingPolicy the Transformer was built with.
+    #[inline]
+    pub fn missing_policy(&self) -> MissingPolicy {
+        self.missing_policy
+    }
+
+    /// reports a source value, or parent of one, that was not found. When the MissingPolicy is
+    /// `Error` an `Error::MissingSource` is returned.
+    #[inline]
+    pub fn missing_source(&self, path: &str) -> Result<()> {
+        match self.missing_policy {
+            MissingPolicy::Error => self.fail(Error::MissingSource(path.to_owned())),
+            _ => Ok(()),
+        }
+    }
+
+    /// like missing_source but only formats the path when an error is returned, avoiding an
+    /// allocation per apply for paths which are built at apply time.
+    #[inline]
+    pub(crate) fn missing_source_with<F>(&self, path: F) -> Result<()>
+    where
+        F: FnOnce() -> String,
+    {
+        match self.missing_policy {
+            MissingPolicy::Error => self.fail(Error::MissingSource(path())),
+            _ => Ok(()),
+        }
+    }
+
+    /// returns the LocaleContext the Transformer was built with.
+    #[inline]
+    pub fn locale(&self) -> &LocaleContext {
+        &self.locale
+    }
+
+    /// returns the formatting of keys generated for Array elements when flattening.
+    #[inline]
+    pub(crate) fn index_keys<'a>(&self, separator: &'a str) -> IndexKeys<'a> {
+        IndexKeys {
+            separator,
+            width: self.index_width,
+            base: 1,
+            format: None,
+        }
+    }
+
+    /// reports a source value that was present but not of the expected type. When navigating
+    /// `Strict` an `Error::TypeMismatch` is returned, otherwise the value is treated as missing.
+    #[inline]
+    pub fn type_mismatch(&self, path: &str, expected: &'static str, found: &Value) -> Result<()> {
+        match self.navigation {
+            Navigation::NullSafe => Ok(()),
+            Navigation::Strict => self.fail(Error::TypeMismatch {
+                path: path.to_owned(),
+                expected,
+                found: value_type(found),
+            }),
+        }
+    }
+}
+
+/// returns the JSON type name of the provided value.
+#[inline]
+pub(crate) fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
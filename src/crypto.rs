@@ -0,0 +1,206 @@
+//! Field level encryption, protecting sensitive values while the rest of the document is
+//! transformed normally. Encrypt and Decrypt mappings only store the name of the key they use,
+//! the Ciphers themselves are supplied when applying the Transformer, see
+//! Transformer::apply_with_keys, so keys never end up in serialized definitions.
+//!
+//! The encryption itself is provided by the application implementing Cipher eg. with AES-GCM from
+//! its cryptography library of choice. Values are encrypted as their JSON serialization, so their
+//! type survives the round trip, and written as base64 Strings.
+//!
+//! ```
+//! use bumblebee::crypto::{Cipher, Keys};
+//! use bumblebee::errors::Result;
+//! use bumblebee::prelude::*;
+//!
+//! // a real Cipher would use an authenticated cipher such as AES-GCM
+//! struct Reverse;
+//!
+//! impl Cipher for Reverse {
+//!     fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+//!         Ok(plaintext.iter().rev().copied().collect())
+//!     }
+//!
+//!     fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+//!         self.encrypt(ciphertext)
+//!     }
+//! }
+//!
+//! let keys = Keys::new().insert("pii", Reverse);
+//! let trans = TransformerBuilder::default()
+//!     .add_encrypt("ssn", "ssn", "pii")?
+//!     .build()?;
+//! let output = trans.apply_with_keys(r#"{"ssn":"123"}"#, &keys)?;
+//! assert_eq!(r#"{"ssn":"IjMyMSI="}"#, output.to_string());
+//! # Ok::<(), bumblebee::errors::Error>(())
+//! ```
+
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// Cipher encrypts and decrypts the values of Encrypt and Decrypt mappings. Implementations
+/// should use an authenticated cipher, such as AES-GCM, and include the nonce in the ciphertext.
+pub trait Cipher: Send + Sync {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>>;
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Keys is the set of Ciphers available to Encrypt and Decrypt mappings, by the name mappings
+/// refer to them with. It's cheap to clone, sharing the Ciphers.
+#[derive(Clone, Default)]
+pub struct Keys {
+    keys: Arc<HashMap<String, Arc<dyn Cipher>>>,
+}
+
+impl Keys {
+    #[inline]
+    pub fn new() -> Self {
+        Keys::default()
+    }
+
+    /// adds the Cipher under the name, replacing any Cipher already added with it.
+    #[inline]
+    pub fn insert<S, C>(mut self, name: S, cipher: C) -> Self
+    where
+        S: Into<String>,
+        C: Cipher + 'static,
+    {
+        Arc::make_mut(&mut self.keys).insert(name.into(), Arc::new(cipher));
+        self
+    }
+
+    /// returns the Cipher added under the name.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Cipher>> {
+        self.keys.get(name)
+    }
+}
+
+impl Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.keys.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("Keys").field("keys", &names).finish()
+    }
+}
+
+/// Crypt is the direction of an Encrypt or Decrypt mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Crypt {
+    Encrypt,
+    Decrypt,
+}
+
+impl Crypt {
+    /// returns the value encrypted, or decrypted, with the Cipher. `null` is returned as is.
+    pub(crate) fn apply(self, cipher: &dyn Cipher, path: &str, value: &Value) -> Result<Value> {
+        let invalid = |reason: &str| {
+            Error::Crypto(format!(
+                "can not decrypt the value at `{}`: {}",
+                path, reason
+            ))
+        };
+        Ok(match (self, value) {
+            (_, Value::Null) => Value::Null,
+            (Crypt::Encrypt, value) => {
+                Value::String(encode(&cipher.encrypt(&serde_json::to_vec(value)?)?))
+            }
+            (Crypt::Decrypt, Value::String(s)) => {
+                let ciphertext = decode(s).ok_or_else(|| invalid("invalid base64"))?;
+                serde_json::from_slice(&cipher.decrypt(&ciphertext)?)
+                    .map_err(|_| invalid("the plaintext is not JSON"))?
+            }
+            (Crypt::Decrypt, _) => return Err(invalid("expected a base64 string")),
+        })
+    }
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// returns the standard, padded, base64 encoding of the bytes.
+fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// decodes standard base64, with or without padding.
+fn decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let v = ALPHABET.iter().position(|a| *a == c)? as u32;
+        n = n << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    struct Xor(u8);
+
+    impl Cipher for Xor {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+            Ok(plaintext.iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+            self.encrypt(ciphertext)
+        }
+    }
+
+    #[test]
+    fn test_crypt() -> Result<()> {
+        for (bytes, encoded) in &[
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(*encoded, encode(bytes));
+            assert_eq!(Some(bytes.to_vec()), decode(encoded));
+        }
+        assert_eq!(None, decode("Zm9v!"));
+
+        let cipher = Xor(42);
+        for value in &[json!("secret"), json!(42), json!({"a": [1, true]})] {
+            let encrypted = Crypt::Encrypt.apply(&cipher, "a", value)?;
+            assert!(encrypted.is_string());
+            assert_eq!(*value, Crypt::Decrypt.apply(&cipher, "a", &encrypted)?);
+        }
+        assert_eq!(
+            Value::Null,
+            Crypt::Encrypt.apply(&cipher, "a", &Value::Null)?
+        );
+        assert!(Crypt::Decrypt.apply(&cipher, "a", &json!(1)).is_err());
+        assert!(Crypt::Decrypt.apply(&cipher, "a", &json!("Zm9v")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+//! A small, line based, language for writing mappings by hand eg.
+//!
+//! ```text
+//! # comments start with a `#`
+//! user_id -> id
+//! nicknames => flatten(prefix="nickname", sep="_")
+//! tags => split(",") -> tag_list
+//! age => coerce(integer) -> age
+//! ```
+//!
+//! Each line maps a source namespace either directly, with `->`, to a destination namespace or,
+//! with `=>`, through a function whose result is written to an optional `-> destination`. The
+//! supported functions are:
+//!
+//! - `flatten(prefix, sep, array_sep, recursive)` flattens the source, by default into the root.
+//! - `list(recursive)` flattens the source into a list.
+//! - `coerce(type)` converts the source to a `string`, `number`, `integer` or `boolean`.
+//! - `split(sep, part)` splits a string source by the separator.
+//! - `join(sep)` joins an array source using the separator.
+//! - `canonical()` writes the source with sorted Object keys.
+//!
+//! Arguments are `key=value` pairs or, for the first argument, just the value eg. `split(",")`.
+//! Values containing whitespace, `,`, `)` or `=` must be double quoted, with `\"` and `\\`
+//! escapes. Boolean arguments such as `recursive` may be written alone to mean true.
+use crate::errors::{Error, Result};
+use crate::namespace::parse_path;
+use crate::rules::{Coerce, CoerceFailure, Mapping, Stringify};
+use std::borrow::Cow;
+
+/// parses the mappings of the DSL, returning an `Error::Dsl` with the line and column of the
+/// first invalid mapping.
+pub fn parse(dsl: &str) -> Result<Vec<Mapping<'_>>> {
+    let mut mappings = Vec::new();
+    for (i, text) in dsl.lines().enumerate() {
+        let trimmed = text.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut line = Line {
+            number: i + 1,
+            text,
+            pos: 0,
+        };
+        mappings.push(line.mapping()?);
+    }
+    Ok(mappings)
+}
+
+/// an argument of a function, with the column it starts at.
+struct Arg<'a> {
+    key: Option<&'a str>,
+    value: Cow<'a, str>,
+    column: usize,
+}
+
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Line<'a> {
+    fn mapping(&mut self) -> Result<Mapping<'a>> {
+        let from = self.path("source")?;
+        self.skip_whitespace();
+        let column = self.column();
+        let mapping = if self.eat("->") {
+            Mapping::Direct {
+                from,
+                to: self.path("destination")?,
+                kind: None,
+                only_if_absent: false,
+                default: None,
+            }
+        } else if self.eat("=>") {
+            self.function(from)?
+        } else {
+            return Err(self.error(column, "expected `->` or `=>`"));
+        };
+        self.skip_whitespace();
+        if self.pos < self.text.len() {
+            return Err(self.error(self.column(), "unexpected trailing input"));
+        }
+        Ok(mapping)
+    }
+
+    fn function(&mut self, from: Cow<'a, str>) -> Result<Mapping<'a>> {
+        self.skip_whitespace();
+        let column = self.column();
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(self.error(column, "expected a function name"));
+        }
+        self.skip_whitespace();
+        if !self.eat("(") {
+            return Err(self.error(self.column(), "expected `(`"));
+        }
+        let args = self.args()?;
+        self.skip_whitespace();
+        let to = if self.eat("->") {
+            Some(self.path("destination")?)
+        } else {
+            None
+        };
+        let required = |to: Option<Cow<'a, str>>| {
+            to.ok_or_else(|| self.error(column, &format!("`{}` requires a destination", name)))
+        };
+
+        let mapping = match name {
+            "flatten" => {
+                let [prefix, sep, array_sep, recursive] =
+                    self.named(args, ["prefix", "sep", "array_sep", "recursive"], false)?;
+                Mapping::Flatten {
+                    from,
+                    to: to.unwrap_or(Cow::Borrowed("")),
+                    prefix: prefix.map(|arg| arg.value),
+                    separator: sep.map(|arg| arg.value),
+                    array_separator: array_sep.map(|arg| arg.value),
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                    recursive: self.flag(recursive)?,
+                    only_if_absent: false,
+                    locale: None,
+                }
+            }
+            "list" => {
+                let [recursive] = self.named(args, ["recursive"], false)?;
+                Mapping::FlattenList {
+                    from,
+                    to: required(to)?,
+                    manipulations: Vec::new(),
+                    recursive: self.flag(recursive)?,
+                    only_if_absent: false,
+                    locale: None,
+                }
+            }
+            "coerce" => {
+                let [kind] = self.named(args, ["type"], true)?;
+                let coerce = match kind {
+                    Some(kind) => match kind.value.as_ref() {
+                        "string" => Coerce::String,
+                        "number" => Coerce::Number,
+                        "integer" => Coerce::Integer,
+                        "boolean" | "bool" => Coerce::Bool,
+                        v => return Err(self.error(kind.column, &format!("unknown type `{}`", v))),
+                    },
+                    None => return Err(self.error(column, "`coerce` requires a type")),
+                };
+                Mapping::Coerce {
+                    from,
+                    to: required(to)?,
+                    coerce,
+                    on_failure: CoerceFailure::default(),
+                    only_if_absent: false,
+                }
+            }
+            "split" => {
+                let [sep, part] = self.named(args, ["sep", "part"], true)?;
+                let part = match part {
+                    Some(part) => Some(part.value.parse().map_err(|_| {
+                        self.error(part.column, &format!("`{}` is not an index", part.value))
+                    })?),
+                    None => None,
+                };
+                Mapping::Split {
+                    from,
+                    to: required(to)?,
+                    separator: sep
+                        .map(|arg| arg.value)
+                        .ok_or_else(|| self.error(column, "`split` requires a separator"))?,
+                    part,
+                    only_if_absent: false,
+                }
+            }
+            "join" => {
+                let [sep] = self.named(args, ["sep"], true)?;
+                Mapping::Join {
+                    from,
+                    to: required(to)?,
+                    separator: sep.map_or(Cow::Borrowed(""), |arg| arg.value),
+                    stringify: Stringify::default(),
+                    only_if_absent: false,
+                }
+            }
+            "canonical" => {
+                let [] = self.named(args, [], false)?;
+                Mapping::Canonical {
+                    from,
+                    to: required(to)?,
+                    only_if_absent: false,
+                }
+            }
+            _ => return Err(self.error(column, &format!("unknown function `{}`", name))),
+        };
+        Ok(mapping)
+    }
+
+    /// returns the argument for each key, the last winning when repeated. When positional the
+    /// first argument may be given without it's key, the first key. Any `recursive` argument
+    /// may also be written without a value.
+    fn named<const N: usize>(
+        &self,
+        args: Vec<Arg<'a>>,
+        keys: [&'a str; N],
+        positional: bool,
+    ) -> Result<[Option<Arg<'a>>; N]> {
+        let mut named = [(); N].map(|_| None);
+        for (i, mut arg) in args.into_iter().enumerate() {
+            if arg.key.is_none() {
+                arg.key = match keys.first() {
+                    _ if arg.value == "recursive" => {
+                        arg.value = Cow::Borrowed("true");
+                        Some("recursive")
+                    }
+                    Some(key) if i == 0 && positional => Some(*key),
+                    _ => return Err(self.error(arg.column, "expected `key=value`")),
+                };
+            }
+            match keys.iter().position(|key| arg.key == Some(key)) {
+                Some(i) => named[i] = Some(arg),
+                None => {
+                    return Err(self.error(
+                        arg.column,
+                        &format!("unknown argument `{}`", arg.key.unwrap_or_default()),
+                    ))
+                }
+            }
+        }
+        Ok(named)
+    }
+
+    fn flag(&self, arg: Option<Arg>) -> Result<bool> {
+        match arg {
+            None => Ok(false),
+            Some(arg) => match arg.value.as_ref() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                v => Err(self.error(
+                    arg.column,
+                    &format!("expected `true` or `false`, found `{}`", v),
+                )),
+            },
+        }
+    }
+
+    /// parses the arguments of a function, up to and including the closing `)`.
+    fn args(&mut self) -> Result<Vec<Arg<'a>>> {
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            self.skip_whitespace();
+            let column = self.column();
+            let mut arg = Arg {
+                key: None,
+                value: self.value()?,
+                column,
+            };
+            self.skip_whitespace();
+            if self.eat("=") {
+                match arg.value {
+                    Cow::Borrowed(key) if !key.is_empty() => arg.key = Some(key),
+                    _ => return Err(self.error(column, "expected an argument name")),
+                }
+                self.skip_whitespace();
+                arg.value = self.value()?;
+                self.skip_whitespace();
+            }
+            args.push(arg);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            if !self.eat(",") {
+                return Err(self.error(self.column(), "expected `,` or `)`"));
+            }
+        }
+    }
+
+    /// parses a double quoted or bare value.
+    fn value(&mut self) -> Result<Cow<'a, str>> {
+        if !self.eat("\"") {
+            return Ok(Cow::Borrowed(self.take_while(|c| {
+                !c.is_whitespace() && c != ',' && c != ')' && c != '='
+            })));
+        }
+        let column = self.column() - 1;
+        let mut value = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(Cow::Owned(value));
+                }
+                '\\' => match chars.next() {
+                    Some((_, c @ '"')) | Some((_, c @ '\\')) => value.push(c),
+                    _ => return Err(self.error(column, "invalid escape in quoted value")),
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error(column, "unclosed quoted value"))
+    }
+
+    /// parses a namespace, validating it so errors report where it is.
+    fn path(&mut self, what: &str) -> Result<Cow<'a, str>> {
+        self.skip_whitespace();
+        let column = self.column();
+        let path = self.take_while(|c| !c.is_whitespace());
+        if path.is_empty() {
+            return Err(self.error(column, &format!("expected a {} namespace", what)));
+        }
+        parse_path(Cow::Borrowed(path)).map_err(|e| self.error(column, &e.to_string()))?;
+        Ok(Cow::Borrowed(path))
+    }
+
+    #[inline]
+    fn take_while<F>(&mut self, f: F) -> &'a str
+    where
+        F: Fn(char) -> bool,
+    {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    #[inline]
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    #[inline]
+    fn eat(&mut self, s: &str) -> bool {
+        if self.text[self.pos..].starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// returns the 1-based column of the current position.
+    #[inline]
+    fn column(&self) -> usize {
+        self.text[..self.pos].chars().count() + 1
+    }
+
+    #[inline]
+    fn error(&self, column: usize, message: &str) -> Error {
+        Error::Dsl {
+            line: self.number,
+            column,
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformer::TransformerBuilder;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let dsl = r#"
+            # the user
+            user_id -> id
+            full-name -> name
+            nicknames => flatten(prefix="nickname", sep="_")
+            tags => split(",", part=1) -> tag
+            age => coerce(integer) -> age
+            roles => join(sep=" | ") -> roles
+        "#;
+        let trans = TransformerBuilder::from_dsl(dsl)?.build()?;
+        let input = r#"{"user_id":"111","full-name":"Dean Karn","nicknames":["Deano","Joey Bloggs"],"tags":"a,b","age":"42","roles":["admin","dev"]}"#;
+        let expected = r#"{"age":42,"id":"111","name":"Dean Karn","nickname_1":"Deano","nickname_2":"Joey Bloggs","roles":"admin | dev","tag":"b"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (dsl, (line, column, expected)) in [
+            ("a -> b\nc d", (2, 3, "expected `->` or `=>`")),
+            ("a ->", (1, 5, "expected a destination namespace")),
+            ("a => upper() -> b", (1, 6, "unknown function `upper`")),
+            ("a => coerce(date) -> b", (1, 13, "unknown type `date`")),
+            (
+                "a => split(\",\")",
+                (1, 6, "`split` requires a destination"),
+            ),
+            (
+                "a => flatten(prefix=\"p) -> b",
+                (1, 21, "unclosed quoted value"),
+            ),
+            ("a => flatten(depth=1)", (1, 14, "unknown argument `depth`")),
+            ("a -> b c", (1, 8, "unexpected trailing input")),
+        ] {
+            match parse(dsl) {
+                Err(Error::Dsl {
+                    line: l,
+                    column: c,
+                    message,
+                }) => assert_eq!((line, column, expected), (l, c, message.as_str())),
+                res => panic!("unexpected result for {}: {:?}", dsl, res),
+            }
+        }
+    }
+}
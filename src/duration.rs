@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const SECOND: u64 = 1_000;
+const MINUTE: u64 = 60 * SECOND;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// DurationFormat is the representation of a duration value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DurationFormat {
+    /// a number of seconds, with a fractional part when not a whole number of seconds.
+    #[default]
+    Seconds,
+    /// a whole number of milliseconds.
+    Milliseconds,
+    /// an ISO-8601 duration eg. `PT1H30M`, years and months are not supported.
+    Iso8601,
+    /// a sequence of numbers and units eg. `1h30m` using the units `w`, `d`, `h`, `m`, `s` and
+    /// `ms`.
+    Human,
+}
+
+impl DurationFormat {
+    /// returns the name of the format, used when reporting values which can not be converted.
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            DurationFormat::Seconds => "seconds",
+            DurationFormat::Milliseconds => "milliseconds",
+            DurationFormat::Iso8601 => "ISO-8601 duration",
+            DurationFormat::Human => "duration",
+        }
+    }
+
+    /// parses the value as a number of milliseconds. Numbers, and numeric strings, are in this
+    /// format's unit, milliseconds for Milliseconds otherwise seconds. Other strings may be either
+    /// ISO-8601 or human durations regardless of the format, as sources commonly mix them.
+    pub fn parse(self, value: &Value) -> Option<u64> {
+        let unit = match self {
+            DurationFormat::Milliseconds => 1.0,
+            _ => SECOND as f64,
+        };
+        let number = match value {
+            Value::Number(n) => n.as_f64()?,
+            Value::String(s) => {
+                let s = s.trim();
+                match s.parse::<f64>() {
+                    Ok(n) => n,
+                    Err(_) if s.starts_with('P') || s.starts_with('p') => return parse_iso(s),
+                    Err(_) => return parse_human(s),
+                }
+            }
+            _ => return None,
+        };
+        millis(number * unit)
+    }
+
+    /// formats the number of milliseconds in this format.
+    pub fn format(self, ms: u64) -> Value {
+        match self {
+            DurationFormat::Seconds if ms.is_multiple_of(SECOND) => Value::from(ms / SECOND),
+            DurationFormat::Seconds => Value::from(ms as f64 / SECOND as f64),
+            DurationFormat::Milliseconds => Value::from(ms),
+            DurationFormat::Iso8601 => {
+                let (days, ms) = (ms / DAY, ms % DAY);
+                let mut s = String::from("P");
+                if days > 0 {
+                    s.push_str(&format!("{}D", days));
+                }
+                if ms > 0 || days == 0 {
+                    s.push('T');
+                    let (hours, minutes, seconds) = (ms / HOUR, ms % HOUR / MINUTE, ms % MINUTE);
+                    if hours > 0 {
+                        s.push_str(&format!("{}H", hours));
+                    }
+                    if minutes > 0 {
+                        s.push_str(&format!("{}M", minutes));
+                    }
+                    if seconds > 0 || ms == 0 {
+                        s.push_str(&format!("{}S", DurationFormat::Seconds.format(seconds)));
+                    }
+                }
+                Value::String(s)
+            }
+            DurationFormat::Human if ms == 0 => Value::from("0s"),
+            DurationFormat::Human => {
+                let mut s = String::new();
+                let mut rest = ms;
+                for (unit, suffix) in &[(DAY, "d"), (HOUR, "h"), (MINUTE, "m"), (SECOND, "s")] {
+                    if rest >= *unit {
+                        s.push_str(&format!("{}{}", rest / unit, suffix));
+                        rest %= unit;
+                    }
+                }
+                if rest > 0 {
+                    s.push_str(&format!("{}ms", rest));
+                }
+                Value::String(s)
+            }
+        }
+    }
+
+    /// converts a duration value from this format to the output format, returning None when the
+    /// value is not a valid duration.
+    #[inline]
+    pub fn convert(self, output: DurationFormat, value: &Value) -> Option<Value> {
+        self.parse(value).map(|ms| output.format(ms))
+    }
+}
+
+/// returns the number of milliseconds, rounded, if non negative and finite.
+#[inline]
+fn millis(ms: f64) -> Option<u64> {
+    if ms.is_finite() && ms >= 0.0 && ms <= u64::MAX as f64 {
+        Some(ms.round() as u64)
+    } else {
+        None
+    }
+}
+
+/// parses durations such as `1h30m`, `1.5h` or `250ms`.
+fn parse_human(s: &str) -> Option<u64> {
+    let mut total = 0f64;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number = rest[..end].parse::<f64>().ok()?;
+        rest = rest[end..].trim_start();
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit = match rest[..end].to_ascii_lowercase().as_str() {
+            "ms" => 1,
+            "s" => SECOND,
+            "m" => MINUTE,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            _ => return None,
+        };
+        total += number * unit as f64;
+        rest = rest[end..].trim_start();
+    }
+    millis(total)
+}
+
+/// parses ISO-8601 durations such as `PT20S` or `P1DT2H`.
+fn parse_iso(s: &str) -> Option<u64> {
+    let s = s.to_ascii_uppercase();
+    let rest = s.strip_prefix('P')?;
+    let (date, time) = match rest.find('T') {
+        Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+        None => (rest, None),
+    };
+    if date.is_empty() && time.is_none_or(str::is_empty) {
+        return None;
+    }
+    let mut total = 0f64;
+    for (part, units) in &[
+        (date, &[('W', WEEK), ('D', DAY)][..]),
+        (
+            time.unwrap_or(""),
+            &[('H', HOUR), ('M', MINUTE), ('S', SECOND)][..],
+        ),
+    ] {
+        let mut part = *part;
+        let mut units = units.iter();
+        while !part.is_empty() {
+            let idx = part.find(|c: char| c.is_ascii_alphabetic())?;
+            let number = part[..idx].replace(',', ".").parse::<f64>().ok()?;
+            let designator = part[idx..].chars().next()?;
+            // designators must be in order and appear at most once
+            let (_, unit) = units.find(|(d, _)| *d == designator)?;
+            total += number * *unit as f64;
+            part = &part[idx + 1..];
+        }
+    }
+    millis(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let f = DurationFormat::Seconds;
+        assert_eq!(Some(300_000), f.parse(&json!("5m")));
+        assert_eq!(Some(5_400_000), f.parse(&json!("1h30m")));
+        assert_eq!(Some(5_400_000), f.parse(&json!("1.5h")));
+        assert_eq!(Some(20_000), f.parse(&json!("PT20S")));
+        assert_eq!(Some(93_600_000), f.parse(&json!("P1DT2H")));
+        assert_eq!(Some(1_500), f.parse(&json!(1.5)));
+        assert_eq!(Some(1_500), f.parse(&json!("1.5")));
+        assert_eq!(
+            Some(1_500),
+            DurationFormat::Milliseconds.parse(&json!(1500))
+        );
+        for invalid in &[
+            json!("5x"),
+            json!("P"),
+            json!("PT"),
+            json!("P1M"),
+            json!(-1),
+            json!(true),
+        ] {
+            assert_eq!(None, f.parse(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(json!(5400), DurationFormat::Seconds.format(5_400_000));
+        assert_eq!(json!(1.5), DurationFormat::Seconds.format(1_500));
+        assert_eq!(json!(1500), DurationFormat::Milliseconds.format(1_500));
+        assert_eq!(json!("PT1H30M"), DurationFormat::Iso8601.format(5_400_000));
+        assert_eq!(
+            json!("P1DT2H0.5S"),
+            DurationFormat::Iso8601.format(93_600_500)
+        );
+        assert_eq!(json!("P1D"), DurationFormat::Iso8601.format(DAY));
+        assert_eq!(json!("PT0S"), DurationFormat::Iso8601.format(0));
+        assert_eq!(json!("1h30m"), DurationFormat::Human.format(5_400_000));
+        assert_eq!(json!("1d2h250ms"), DurationFormat::Human.format(93_600_250));
+        assert_eq!(json!("0s"), DurationFormat::Human.format(0));
+    }
+}
@@ -1,4 +1,11 @@
+use crate::explain::RuleId;
+use crate::namespace::{join_path, Namespace};
+use crate::rules::Rule;
+#[cfg(feature = "schema")]
+use crate::schema::Violation;
 use failure::Fail;
+use serde_json::Value;
+use std::fmt;
 use std::io;
 use std::num::ParseIntError;
 
@@ -18,6 +25,209 @@ pub enum Error {
     InvalidNamespaceArrayIndex(#[fail(cause)] ParseIntError),
     #[fail(display = "error: {}", _0)]
     Rule(String),
+    #[fail(display = "invalid JSONPath: {}", _0)]
+    InvalidJsonPath(String),
+    #[fail(display = "invalid template: {}", _0)]
+    InvalidTemplate(String),
+    #[fail(
+        display = "type mismatch at `{}`: expected {} but found {}",
+        path, expected, found
+    )]
+    TypeMismatch {
+        path: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[fail(display = "missing source value at `{}`", _0)]
+    MissingSource(String),
+    #[fail(display = "complexity limit exceeded: {}", _0)]
+    ComplexityExceeded(String),
+    #[fail(display = "conflicting destinations: {}", _0)]
+    DestinationConflict(String),
+    #[fail(display = "transformer is not invertible: {}", _0)]
+    NotInvertible(String),
+    #[fail(
+        display = "invalid mapping at line {}, column {}: {}",
+        line, column, message
+    )]
+    Dsl {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[fail(
+        display = "can not parse `{}` at line {}, column {}: {}",
+        path, line, column, message
+    )]
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[fail(display = "invalid transformer definition: {}", _0)]
+    Definition(String),
+    #[fail(display = "unknown function: {}", _0)]
+    UnknownFunction(String),
+    #[fail(display = "environment variable not allowed: {}", _0)]
+    EnvNotAllowed(String),
+    #[cfg(feature = "crypto")]
+    #[fail(display = "unknown key: {}", _0)]
+    UnknownKey(String),
+    #[cfg(feature = "crypto")]
+    #[fail(display = "encryption error: {}", _0)]
+    Crypto(String),
+    #[fail(display = "invalid schema: {}", _0)]
+    InvalidSchema(String),
+    /// a document does not match its schema, see Transformer::with_input_schema and
+    /// Transformer::with_output_schema.
+    #[cfg(feature = "schema")]
+    #[fail(display = "{} does not match its schema: {}", document, summary)]
+    SchemaViolation {
+        document: &'static str,
+        summary: String,
+        violations: Vec<Violation>,
+    },
+    /// a rule failed to apply, rule is the kind of rule eg. `Direct` and rule_id where it is
+    /// within the Transformer, source_path is the full namespace the rule reads from, when known,
+    /// and destination_path the namespace it writes to, when it has a single destination.
+    #[fail(
+        display = "`{}` rule {} reading `{}` {} failed: {}",
+        rule, rule_id, source_path, destination_path, cause
+    )]
+    RuleFailed {
+        rule: String,
+        rule_id: RuleId,
+        source_path: String,
+        destination_path: Destination,
+        cause: Box<Error>,
+    },
+    #[fail(display = "conflicting values at `{}`", _0)]
+    MergeConflict(String),
+    #[fail(display = "can not coerce {} at `{}` to {}", value, path, expected)]
+    Coercion {
+        path: String,
+        expected: &'static str,
+        value: String,
+    },
+}
+
+impl Error {
+    /// prefixes the path of path aware errors with the parent namespace segment as the error
+    /// travels back up the source tree.
+    #[inline]
+    pub(crate) fn within(self, segment: &str) -> Self {
+        match self {
+            Error::TypeMismatch {
+                path,
+                expected,
+                found,
+            } => Error::TypeMismatch {
+                path: join_path(segment, &path),
+                expected,
+                found,
+            },
+            Error::MissingSource(path) => Error::MissingSource(join_path(segment, &path)),
+            Error::RuleFailed {
+                rule,
+                rule_id,
+                source_path,
+                destination_path,
+                cause,
+            } => Error::RuleFailed {
+                rule,
+                rule_id,
+                source_path: join_path(segment, &source_path),
+                destination_path,
+                cause: Box::new(cause.within(segment)),
+            },
+            Error::Coercion {
+                path,
+                expected,
+                value,
+            } => Error::Coercion {
+                path: join_path(segment, &path),
+                expected,
+                value,
+            },
+            _ => self,
+        }
+    }
+
+    /// wraps the error of a rule with the rule type, and the namespaces it reads and writes,
+    /// relative to the namespace the rule is applied at until the error travels back up the
+    /// source tree.
+    #[inline]
+    pub(crate) fn in_rule(self, rule: &dyn Rule, rule_id: RuleId) -> Self {
+        Error::RuleFailed {
+            rule: rule_kind(rule),
+            rule_id,
+            source_path: rule
+                .source_paths(&[])
+                .iter()
+                .map(|namespace| Namespace::format(namespace))
+                .collect::<Vec<_>>()
+                .join(", "),
+            destination_path: Destination(
+                rule.destination()
+                    .map(|namespace| Namespace::format(&namespace)),
+            ),
+            cause: Box::new(self),
+        }
+    }
+
+    /// returns the error which caused a rule to fail, or the error itself when not a
+    /// RuleFailed error.
+    #[inline]
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::RuleFailed { cause, .. } => cause.root_cause(),
+            e => e,
+        }
+    }
+
+    /// returns the error which caused a rule to fail, see root_cause.
+    #[inline]
+    pub fn into_root_cause(self) -> Error {
+        match self {
+            Error::RuleFailed { cause, .. } => cause.into_root_cause(),
+            e => e,
+        }
+    }
+}
+
+/// the namespace a failed rule writes to, when it has a single destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination(pub Option<String>);
+
+impl Destination {
+    #[inline]
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some(path) => write!(f, "writing `{}`", path),
+            None => f.write_str("writing multiple destinations"),
+        }
+    }
+}
+
+/// returns the kind of a rule for reporting, the Mapping variant it was built from eg. `Direct`,
+/// or the type name of custom rules.
+fn rule_kind(rule: &dyn Rule) -> String {
+    let kind = match rule.mapping(&[]) {
+        Ok(Some(mapping)) => match serde_json::to_value(mapping) {
+            Ok(Value::Object(map)) => map.into_iter().next().map(|(kind, _)| kind),
+            Ok(Value::String(kind)) => Some(kind),
+            _ => None,
+        },
+        _ => None,
+    };
+    kind.unwrap_or_else(|| rule.typetag_name().to_owned())
 }
 
 impl From<ParseIntError> for Error {
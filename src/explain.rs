@@ -0,0 +1,373 @@
+use crate::errors::Result;
+use crate::namespace::join_path;
+use crate::rules::Rule;
+use crate::tree::Arena;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// identifies a rule within a Transformer by it's location in the source tree, or it's position
+/// amongst the deferred rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RuleId {
+    /// the rule'th rule applied at the node'th node of the source tree, with the root at 0 and
+    /// the other nodes numbered in the order their namespaces were first added.
+    Tree { node: usize, rule: usize },
+    /// the n'th rule applied to the output once the source tree has been transformed.
+    Deferred(usize),
+}
+
+impl fmt::Display for RuleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleId::Tree { node, rule } => write!(f, "{} of node {}", rule, node),
+            RuleId::Deferred(i) => write!(f, "{} of the deferred rules", i),
+        }
+    }
+}
+
+/// RuleTiming is the time spent applying a single rule across all documents.
+#[derive(Debug, Clone)]
+pub struct RuleTiming {
+    path: String,
+    rule: String,
+    calls: usize,
+    total: Duration,
+}
+
+impl RuleTiming {
+    /// returns the source namespace the rule is applied at, `$out` for rules applied to the output.
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// returns the debug representation of the rule.
+    #[inline]
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    /// returns the number of times the rule was applied.
+    #[inline]
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+
+    /// returns the total time spent applying the rule.
+    #[inline]
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+}
+
+/// Timings contains the time spent applying each rule that was applied during a transformation.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    rules: Vec<RuleTiming>,
+}
+
+impl Timings {
+    pub(crate) fn new(
+        arena: &Arena,
+        deferred: &[Box<dyn Rule>],
+        timings: BTreeMap<RuleId, (Duration, usize)>,
+    ) -> Self {
+        let paths = arena.paths();
+        let rules = timings
+            .into_iter()
+            .map(|(id, (total, calls))| {
+                let (path, rule) = match id {
+                    RuleId::Tree { node, rule } => (
+                        paths[node].clone(),
+                        arena.tree[node].rules().as_ref().map(|r| &r[rule]),
+                    ),
+                    RuleId::Deferred(i) => (String::from("$out"), deferred.get(i)),
+                };
+                RuleTiming {
+                    path,
+                    rule: rule.map(|r| format!("{:?}", r)).unwrap_or_default(),
+                    calls,
+                    total,
+                }
+            })
+            .collect();
+        Timings { rules }
+    }
+
+    /// returns the timings of every rule applied, in the order they are applied.
+    #[inline]
+    pub fn rules(&self) -> &[RuleTiming] {
+        &self.rules
+    }
+
+    /// returns the total time spent applying all rules.
+    #[inline]
+    pub fn total(&self) -> Duration {
+        self.rules.iter().map(|r| r.total).sum()
+    }
+
+    /// returns up to `n` of the slowest rules, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&RuleTiming> {
+        let mut rules: Vec<&RuleTiming> = self.rules.iter().collect();
+        rules.sort_by_key(|r| std::cmp::Reverse(r.total));
+        rules.truncate(n);
+        rules
+    }
+}
+
+/// the record of a single rule application, made while explaining a transformation.
+#[derive(Debug)]
+pub(crate) struct Trace {
+    pub(crate) id: RuleId,
+    // indexes of the wildcard elements being transformed, outermost first
+    pub(crate) indices: Vec<usize>,
+    // source path relative to the rule's node, and if it resolved
+    pub(crate) source: Option<(String, bool)>,
+    pub(crate) written: Vec<(String, Value)>,
+    pub(crate) failed: bool,
+}
+
+/// RuleTrace is what a single application of a rule read and wrote.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleTrace {
+    rule: String,
+    source: Option<String>,
+    resolved: Option<bool>,
+    written: Vec<(String, Value)>,
+    error: Option<String>,
+}
+
+impl RuleTrace {
+    /// returns the debug representation of the rule.
+    #[inline]
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    /// returns the source path the rule read, with the index of the element in place of any
+    /// wildcards, or None for rules which do not report their source eg. constants.
+    #[inline]
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// returns if the source path resolved to a value, None when the source is not reported.
+    #[inline]
+    pub fn resolved(&self) -> Option<bool> {
+        self.resolved
+    }
+
+    /// returns the destination paths written by the rule along with the values written.
+    #[inline]
+    pub fn written(&self) -> &[(String, Value)] {
+        &self.written
+    }
+
+    /// returns the error the rule failed with, which ends the transformation.
+    #[inline]
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// TransformReport explains a transformation, rule by rule, in the order the rules were applied.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransformReport {
+    rules: Vec<RuleTrace>,
+    output: Option<Value>,
+    error: Option<String>,
+}
+
+impl TransformReport {
+    pub(crate) fn new(
+        arena: &Arena,
+        deferred: &[Box<dyn Rule>],
+        traces: Vec<Trace>,
+        output: Result<Value>,
+    ) -> Self {
+        let error = output.as_ref().err().map(ToString::to_string);
+        let paths = arena.paths();
+        let rules = traces
+            .into_iter()
+            .map(|trace| {
+                let (path, rule) = match trace.id {
+                    RuleId::Tree { node, rule } => (
+                        Some(&paths[node]),
+                        arena.tree[node].rules().as_ref().map(|r| &r[rule]),
+                    ),
+                    RuleId::Deferred(i) => (None, deferred.get(i)),
+                };
+                let (source, resolved) = match trace.source {
+                    Some((source, resolved)) => {
+                        let source = match path {
+                            Some(path) => {
+                                let mut path = join_path(path, &source);
+                                for i in &trace.indices {
+                                    path = path.replacen("[*]", &format!("[{}]", i), 1);
+                                }
+                                path
+                            }
+                            None => source,
+                        };
+                        (Some(source), Some(resolved))
+                    }
+                    None => (None, None),
+                };
+                RuleTrace {
+                    rule: rule.map(|r| format!("{:?}", r)).unwrap_or_default(),
+                    source,
+                    resolved,
+                    written: trace.written,
+                    // errors end the transformation, and gain the full path on the way out
+                    error: if trace.failed { error.clone() } else { None },
+                }
+            })
+            .collect();
+        TransformReport {
+            rules,
+            output: output.ok(),
+            error,
+        }
+    }
+
+    /// returns what each rule read and wrote, in the order they were applied.
+    #[inline]
+    pub fn rules(&self) -> &[RuleTrace] {
+        &self.rules
+    }
+
+    /// returns the output of the transformation, None when it failed.
+    #[inline]
+    pub fn output(&self) -> Option<&Value> {
+        self.output.as_ref()
+    }
+
+    /// returns the error the transformation failed with, if any.
+    #[inline]
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// returns the rules whose source did not resolve.
+    pub fn unresolved(&self) -> Vec<&RuleTrace> {
+        self.rules
+            .iter()
+            .filter(|r| r.resolved == Some(false))
+            .collect()
+    }
+}
+
+/// Metrics accumulates statistics across every transformation applied with them, eg. to detect
+/// upstream schema drift by source paths which stop resolving.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    applies: usize,
+    written: usize,
+    nulls: usize,
+    // number of times each source path resolved and didn't
+    sources: BTreeMap<String, (usize, usize)>,
+}
+
+impl Metrics {
+    /// records the rules applied during a single transformation.
+    pub(crate) fn record(&mut self, arena: &Arena, traces: Vec<Trace>) {
+        let paths = arena.paths();
+        self.applies += 1;
+        for trace in traces {
+            for (_, value) in &trace.written {
+                self.written += 1;
+                if value.is_null() {
+                    self.nulls += 1;
+                }
+            }
+            if let Some((source, resolved)) = trace.source {
+                let source = match trace.id {
+                    RuleId::Tree { node, .. } => join_path(&paths[node], &source),
+                    RuleId::Deferred(_) => source,
+                };
+                let counts = self.sources.entry(source).or_insert((0, 0));
+                if resolved {
+                    counts.0 += 1;
+                } else {
+                    counts.1 += 1;
+                }
+            }
+        }
+    }
+
+    /// returns the number of transformations recorded.
+    #[inline]
+    pub fn applies(&self) -> usize {
+        self.applies
+    }
+
+    /// returns the number of destination fields written, including those set to null.
+    #[inline]
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// returns the number of destination fields set to null.
+    #[inline]
+    pub fn nulls(&self) -> usize {
+        self.nulls
+    }
+
+    /// returns the number of times the source path, with any wildcards eg. `items[*].sku`,
+    /// resolved and did not resolve.
+    #[inline]
+    pub fn source(&self, path: &str) -> Option<(usize, usize)> {
+        self.sources.get(path).copied()
+    }
+
+    /// returns the source paths which were read but never resolved.
+    pub fn never_resolved(&self) -> Vec<&str> {
+        self.sources
+            .iter()
+            .filter(|(_, (resolved, _))| *resolved == 0)
+            .map(|(path, _)| path.as_str())
+            .collect()
+    }
+}
+
+/// appends the path and value of every value in after which differs from before, treating
+/// nested Objects and Arrays, other than empty ones, element by element.
+pub(crate) fn diff(
+    path: &str,
+    before: &Map<String, Value>,
+    after: &Map<String, Value>,
+    written: &mut Vec<(String, Value)>,
+) {
+    for (key, value) in after {
+        diff_value(&join_path(path, key), before.get(key), value, written);
+    }
+}
+
+fn diff_value(
+    path: &str,
+    before: Option<&Value>,
+    after: &Value,
+    written: &mut Vec<(String, Value)>,
+) {
+    match (before, after) {
+        (Some(before), after) if before == after => {}
+        (Some(Value::Object(before)), Value::Object(after)) => diff(path, before, after, written),
+        (None, Value::Object(after)) if !after.is_empty() => {
+            diff(path, &Map::new(), after, written)
+        }
+        (before, Value::Array(after)) if !after.is_empty() => {
+            let before = match before {
+                Some(Value::Array(before)) => &before[..],
+                Some(_) => return written.push((path.to_owned(), Value::Array(after.clone()))),
+                None => &[],
+            };
+            for (i, value) in after.iter().enumerate() {
+                diff_value(&format!("{}[{}]", path, i), before.get(i), value, written);
+            }
+        }
+        _ => written.push((path.to_owned(), after.clone())),
+    }
+}
@@ -0,0 +1,125 @@
+//! Named functions which Function mappings call by name, so mappings using arbitrary logic remain
+//! serializable. Applications register the functions with the TransformerBuilder, which checks
+//! every function a mapping calls is registered when it's built.
+//!
+//! ```
+//! use bumblebee::functions::FunctionRegistry;
+//! use bumblebee::prelude::*;
+//! use serde_json::Value;
+//!
+//! let functions = FunctionRegistry::new().register("normalize_phone", |v| {
+//!     Ok(Value::String(v.as_str().unwrap_or_default().chars().filter(char::is_ascii_digit).collect()))
+//! });
+//! let trans = TransformerBuilder::default()
+//!     .functions(functions)
+//!     .add_function("phone", "phone", "normalize_phone")?
+//!     .build()?;
+//! let output = trans.apply_from_str(r#"{"phone":"(555) 010-9999"}"#)?;
+//! assert_eq!(r#"{"phone":"5550109999"}"#, output.to_string());
+//! # Ok::<(), bumblebee::errors::Error>(())
+//! ```
+
+use crate::errors::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// Function is a named function, called with the source value of a Function mapping and
+/// returning the value to write.
+pub type Function = Arc<dyn Fn(&Value) -> Result<Value> + Send + Sync>;
+
+/// FunctionRegistry is the set of named functions available to Function mappings. It's cheap to
+/// clone, sharing the registered functions.
+#[derive(Clone, Default)]
+pub struct FunctionRegistry {
+    functions: Arc<HashMap<String, Function>>,
+}
+
+impl FunctionRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        FunctionRegistry::default()
+    }
+
+    /// registers the function under the name, replacing any function already registered with it.
+    #[inline]
+    pub fn register<S, F>(mut self, name: S, f: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.functions).insert(name.into(), Arc::new(f));
+        self
+    }
+
+    /// adds the functions of another registry, replacing those registered with the same name.
+    #[inline]
+    pub fn extend(&mut self, other: &FunctionRegistry) {
+        if other.functions.is_empty() {
+            return;
+        }
+        let functions = Arc::make_mut(&mut self.functions);
+        for (name, f) in other.functions.iter() {
+            functions.insert(name.clone(), f.clone());
+        }
+    }
+
+    /// returns the function registered under the name.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// returns if no functions are registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// returns if a function is registered under the name.
+    #[inline]
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+}
+
+impl Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.functions.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("FunctionRegistry")
+            .field("functions", &names)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() -> Result<()> {
+        let registry = FunctionRegistry::new()
+            .register("double", |v| {
+                Ok(Value::from(v.as_i64().unwrap_or_default() * 2))
+            })
+            .register("upper", |v| {
+                Ok(Value::from(v.as_str().unwrap_or_default().to_uppercase()))
+            });
+        let mut copy = FunctionRegistry::new().register("double", |_| Ok(Value::Null));
+        copy.extend(&registry);
+
+        assert!(copy.contains("upper"));
+        assert!(!copy.contains("lower"));
+        assert_eq!(
+            Value::from(4),
+            (copy.get("double").unwrap())(&Value::from(2))?
+        );
+        assert_eq!(
+            r#"FunctionRegistry { functions: ["double", "upper"] }"#,
+            format!("{:?}", registry)
+        );
+        Ok(())
+    }
+}
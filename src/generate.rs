@@ -0,0 +1,120 @@
+use crate::context::{Context, LocaleContext};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::UNIX_EPOCH;
+
+/// Generator is how a Generate mapping creates a value which doesn't come from the source eg. ids
+/// and processing timestamps. Random values and times are taken from the Context's Entropy, so
+/// seeded transformations remain reproducible, see Transformer::apply_with_entropy.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    /// a random, version 4, UUID eg. `7c9e6679-7425-40de-944b-e07fc1f90ae7`.
+    UuidV4,
+    /// the time the transformation runs at as an RFC 3339 timestamp, at the UTC offset of the
+    /// LocaleContext with millisecond precision, eg. `2019-05-01T12:30:00.000Z` in UTC or
+    /// `2019-05-01T05:30:00.000-07:00`.
+    NowRfc3339,
+    /// the index of the document within a Many2Many batch, or of the value within a stream eg.
+    /// see Transformer::iter_array, added to start, so each record is numbered. Single documents
+    /// are numbered start.
+    Sequence { start: i64 },
+}
+
+impl Generator {
+    /// returns the generated value, timestamps being at the UTC offset of the locale.
+    pub fn generate(self, ctx: &Context, locale: &LocaleContext) -> Value {
+        match self {
+            Generator::UuidV4 => {
+                let entropy = ctx.entropy();
+                let (hi, lo) = (entropy.next_u64(), entropy.next_u64());
+                // the version and variant bits
+                let hi = hi & !0xf000 | 0x4000;
+                let lo = lo & !(0xc << 60) | 0x8 << 60;
+                Value::String(format!(
+                    "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                    hi >> 32,
+                    hi >> 16 & 0xffff,
+                    hi & 0xffff,
+                    lo >> 48,
+                    lo & 0xffff_ffff_ffff
+                ))
+            }
+            Generator::NowRfc3339 => {
+                let since = ctx
+                    .entropy()
+                    .now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                Value::String(rfc3339(
+                    since.as_secs() as i64,
+                    since.subsec_millis(),
+                    locale.utc_offset,
+                ))
+            }
+            Generator::Sequence { start } => Value::from(start.saturating_add(ctx.record() as i64)),
+        }
+    }
+}
+
+/// formats the seconds since the Unix epoch, and milliseconds, as an RFC 3339 timestamp at the
+/// offset from UTC in minutes.
+fn rfc3339(secs: i64, millis: u32, utc_offset: i32) -> String {
+    let secs = secs + i64::from(utc_offset) * 60;
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // converts days since the epoch to the civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let offset = match utc_offset {
+        0 => String::from("Z"),
+        offset => format!(
+            "{}{:02}:{:02}",
+            if offset < 0 { '-' } else { '+' },
+            offset.unsigned_abs() / 60,
+            offset.unsigned_abs() % 60
+        ),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis,
+        offset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!("1970-01-01T00:00:00.000Z", rfc3339(0, 0, 0));
+        assert_eq!("2000-02-29T23:59:59.999Z", rfc3339(951_868_799, 999, 0));
+        assert_eq!("2019-05-01T12:30:00.000Z", rfc3339(1_556_713_800, 0, 0));
+        assert_eq!("1969-12-31T23:59:59.000Z", rfc3339(-1, 0, 0));
+        assert_eq!(
+            "2019-05-01T05:30:00.000-07:00",
+            rfc3339(1_556_713_800, 0, -420)
+        );
+        assert_eq!(
+            "2019-05-01T18:15:00.000+05:45",
+            rfc3339(1_556_713_800, 0, 345)
+        );
+        assert_eq!(
+            "2000-03-01T00:59:59.999+01:00",
+            rfc3339(951_868_799, 999, 60)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::errors::{Error, Result};
+use serde_json::Value;
+use std::io::{self, BufReader, Bytes, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    First,
+    Elements,
+    Done,
+}
+
+/// ArrayReader incrementally parses the elements of a top-level JSON Array from a reader, holding
+/// only a single element in memory at a time.
+#[derive(Debug)]
+pub struct ArrayReader<R> {
+    bytes: Bytes<BufReader<R>>,
+    state: State,
+}
+
+impl<R: Read> ArrayReader<R> {
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        ArrayReader {
+            bytes: BufReader::new(reader).bytes(),
+            state: State::Start,
+        }
+    }
+
+    /// returns the next non whitespace byte, if any.
+    #[inline]
+    fn next_token(&mut self) -> Result<Option<u8>> {
+        for b in &mut self.bytes {
+            let b = b?;
+            if !b.is_ascii_whitespace() {
+                return Ok(Some(b));
+            }
+        }
+        Ok(None)
+    }
+
+    /// reads the raw bytes of the next element, None when the end of the Array is reached.
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.state == State::Start {
+            match self.next_token()? {
+                Some(b'[') => self.state = State::First,
+                _ => return Err(invalid("expected a JSON Array")),
+            }
+        }
+        let first = match self.next_token()? {
+            Some(b']') if self.state == State::First => return Ok(None),
+            Some(b) => b,
+            None => return Err(unexpected_eof()),
+        };
+        self.state = State::Elements;
+
+        let mut element = vec![first];
+        let (mut depth, mut in_string, mut escaped) = (0usize, first == b'"', false);
+        if first == b'[' || first == b'{' {
+            depth += 1;
+        }
+        for b in &mut self.bytes {
+            let b = b?;
+            if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match b {
+                    b',' | b']' if depth == 0 => {
+                        if b == b']' {
+                            self.state = State::Done;
+                        }
+                        return Ok(Some(element));
+                    }
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            element.push(b);
+        }
+        Err(unexpected_eof())
+    }
+}
+
+impl<R: Read> Iterator for ArrayReader<R> {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == State::Done {
+            return None;
+        }
+        let res = match self.next_element() {
+            Ok(Some(element)) => serde_json::from_slice(&element).map_err(Error::from),
+            Ok(None) => {
+                self.state = State::Done;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            self.state = State::Done;
+        }
+        Some(res)
+    }
+}
+
+#[inline]
+fn invalid(msg: &str) -> Error {
+    Error::InvalidSourceValue(msg.to_owned())
+}
+
+#[inline]
+fn unexpected_eof() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "unexpected end of JSON Array",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_array_reader() -> Result<()> {
+        let input = r#" [ {"id":1,"s":"a,]}\"["} , 2,"x" ,[3,[4]],null ] "#;
+        let elements = ArrayReader::new(input.as_bytes()).collect::<Result<Vec<_>>>()?;
+        let expected = vec![
+            json!({"id":1,"s":"a,]}\"["}),
+            json!(2),
+            json!("x"),
+            json!([3, [4]]),
+            Value::Null,
+        ];
+        assert_eq!(expected, elements);
+        assert_eq!(0, ArrayReader::new("[ ]".as_bytes()).count());
+
+        for input in &["{}", "[1,", "[1,,2]", "[{\"a\":1]"] {
+            let res = ArrayReader::new(input.as_bytes()).collect::<Result<Vec<_>>>();
+            assert!(res.is_err(), "{}", input);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// JsonPath is a compiled JSONPath expression eg. `$.items[?(@.active==true)].id` used to select
+/// source values the ordinary namespace syntax can not express.
+///
+/// Supported are child names `.name` or `['name']`, indexes `[0]` and `[-1]`, wildcards `.*` and
+/// `[*]`, slices `[1:3]`, recursive descent `..name` and filters `[?(@.key op literal)]` where op
+/// is one of `==`, `!=`, `<`, `<=`, `>`, `>=`. A filter without an op tests for the existence of
+/// the key and conditions can be combined using `&&` and `||`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonPath {
+    expr: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Segment {
+    Child(Selector),
+    Descendant(Selector),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Wildcard,
+    Slice {
+        start: Option<i64>,
+        end: Option<i64>,
+    },
+    Filter(Vec<Vec<Condition>>), // any of all
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Condition {
+    path: Vec<Selector>,
+    comparison: Option<(Op, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl JsonPath {
+    /// parses a JSONPath expression, which must start with the root `$`.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let mut parser = Parser { expr, pos: 0 };
+        let segments = parser.parse()?;
+        Ok(JsonPath {
+            expr: expr.to_owned(),
+            segments,
+        })
+    }
+
+    /// returns the expression the JsonPath was parsed from.
+    #[inline]
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    /// returns if the expression can select at most a single value, ie. it contains only names
+    /// and indexes.
+    pub fn is_definite(&self) -> bool {
+        self.segments.iter().all(|s| {
+            matches!(
+                s,
+                Segment::Child(Selector::Name(_)) | Segment::Child(Selector::Index(_))
+            )
+        })
+    }
+
+    /// returns every value matching the expression in document order.
+    pub fn select<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![root];
+        for segment in &self.segments {
+            let mut next = Vec::new();
+            for value in current {
+                match segment {
+                    Segment::Child(selector) => selector.select(value, &mut next),
+                    Segment::Descendant(selector) => {
+                        let mut all = Vec::new();
+                        descendants(value, &mut all);
+                        for v in all {
+                            selector.select(v, &mut next);
+                        }
+                    }
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// returns the selected value for definite expressions, or null when not found, otherwise
+    /// an Array of all selected values.
+    pub fn evaluate(&self, root: &Value) -> Value {
+        let selected = self.select(root);
+        if self.is_definite() {
+            selected.first().map_or(Value::Null, |v| (*v).clone())
+        } else {
+            Value::Array(selected.into_iter().cloned().collect())
+        }
+    }
+}
+
+fn descendants<'a>(value: &'a Value, to: &mut Vec<&'a Value>) {
+    to.push(value);
+    match value {
+        Value::Array(arr) => arr.iter().for_each(|v| descendants(v, to)),
+        Value::Object(m) => m.values().for_each(|v| descendants(v, to)),
+        _ => {}
+    }
+}
+
+#[inline]
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index >= 0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+impl Selector {
+    fn select<'a>(&self, value: &'a Value, to: &mut Vec<&'a Value>) {
+        match (self, value) {
+            (Selector::Name(name), Value::Object(m)) => to.extend(m.get(name)),
+            (Selector::Index(index), Value::Array(arr)) => {
+                to.extend(resolve_index(*index, arr.len()).map(|i| &arr[i]))
+            }
+            (Selector::Wildcard, Value::Array(arr)) => to.extend(arr),
+            (Selector::Wildcard, Value::Object(m)) => to.extend(m.values()),
+            (Selector::Slice { start, end }, Value::Array(arr)) => {
+                let len = arr.len() as i64;
+                let clamp = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) };
+                let start = start.map_or(0, clamp) as usize;
+                let end = end.map_or(len, clamp) as usize;
+                if start < end {
+                    to.extend(&arr[start..end]);
+                }
+            }
+            (Selector::Filter(any), Value::Array(arr)) => {
+                to.extend(arr.iter().filter(|v| matches_filter(any, v)))
+            }
+            (Selector::Filter(any), Value::Object(m)) => {
+                to.extend(m.values().filter(|v| matches_filter(any, v)))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn matches_filter(any: &[Vec<Condition>], value: &Value) -> bool {
+    any.iter()
+        .any(|all| all.iter().all(|condition| condition.matches(value)))
+}
+
+impl Condition {
+    fn matches(&self, value: &Value) -> bool {
+        let mut current = vec![value];
+        for selector in &self.path {
+            let mut next = Vec::new();
+            for v in current {
+                selector.select(v, &mut next);
+            }
+            current = next;
+        }
+        let found = match current.first() {
+            Some(v) => *v,
+            None => return false,
+        };
+        match &self.comparison {
+            None => true,
+            Some((op, expected)) => {
+                let ordering = compare(found, expected);
+                match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    Op::Ne => ordering != Some(Ordering::Equal),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)),
+                }
+            }
+        }
+    }
+}
+
+/// compares numbers by value and strings lexicographically, any other values are only equal
+/// when identical.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        _ if left == right => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    expr: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(&mut self) -> Result<Vec<Segment>> {
+        self.skip_whitespace();
+        if !self.eat("$") {
+            return Err(self.error("expected expression to start with $"));
+        }
+        let mut segments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.rest().is_empty() {
+                break;
+            }
+            if self.eat("..") {
+                segments.push(Segment::Descendant(self.dotted_or_bracket()?));
+            } else if self.eat(".") {
+                segments.push(Segment::Child(self.dotted()?));
+            } else if self.eat("[") {
+                segments.push(Segment::Child(self.bracket()?));
+            } else {
+                return Err(self.error("expected . or ["));
+            }
+        }
+        Ok(segments)
+    }
+
+    #[inline]
+    fn rest(&self) -> &'a str {
+        &self.expr[self.pos..]
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    #[inline]
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        self.skip_whitespace();
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {}", token)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidJsonPath(format!("{} at {} in {}", message, self.pos, self.expr))
+    }
+
+    fn dotted_or_bracket(&mut self) -> Result<Selector> {
+        if self.eat("[") {
+            self.bracket()
+        } else {
+            self.dotted()
+        }
+    }
+
+    fn dotted(&mut self) -> Result<Selector> {
+        if self.eat("*") {
+            return Ok(Selector::Wildcard);
+        }
+        let name = self.name();
+        if name.is_empty() {
+            return Err(self.error("expected a name"));
+        }
+        Ok(Selector::Name(name))
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_' || c == '-' || c == '$') {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        self.expr[start..self.pos].to_owned()
+    }
+
+    /// parses the contents of a bracket after the opening `[` up to and including the `]`.
+    fn bracket(&mut self) -> Result<Selector> {
+        self.skip_whitespace();
+        let selector = if self.eat("*") {
+            Selector::Wildcard
+        } else if self.eat("?") {
+            self.expect("(")?;
+            let filter = self.filter()?;
+            self.expect(")")?;
+            Selector::Filter(filter)
+        } else if let Some('\'') | Some('"') = self.peek() {
+            Selector::Name(self.string()?)
+        } else {
+            let start = self.integer()?;
+            self.skip_whitespace();
+            if self.eat(":") {
+                self.skip_whitespace();
+                let end = self.integer()?;
+                Selector::Slice { start, end }
+            } else {
+                Selector::Index(start.ok_or_else(|| self.error("expected an index"))?)
+            }
+        };
+        self.expect("]")?;
+        Ok(selector)
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>> {
+        let start = self.pos;
+        self.eat("-");
+        while let Some('0'..='9') = self.peek() {
+            self.pos += 1;
+        }
+        match &self.expr[start..self.pos] {
+            "" => Ok(None),
+            digits => digits
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error("invalid index")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let quote = self.peek().unwrap_or_default();
+        self.pos += 1;
+        let mut s = String::new();
+        let mut chars = self.rest().chars();
+        loop {
+            match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some(c) => s.push(c),
+                    None => break,
+                },
+                Some(c) if c == quote => {
+                    self.pos = self.expr.len() - chars.as_str().len();
+                    return Ok(s);
+                }
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn filter(&mut self) -> Result<Vec<Vec<Condition>>> {
+        let mut any = vec![vec![self.condition()?]];
+        loop {
+            self.skip_whitespace();
+            if self.eat("&&") {
+                let condition = self.condition()?;
+                any.last_mut().unwrap().push(condition);
+            } else if self.eat("||") {
+                any.push(vec![self.condition()?]);
+            } else {
+                return Ok(any);
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        self.expect("@")?;
+        let mut path = Vec::new();
+        loop {
+            if self.eat(".") {
+                path.push(self.dotted()?);
+            } else if self.eat("[") {
+                path.push(self.bracket()?);
+            } else {
+                break;
+            }
+        }
+        self.skip_whitespace();
+        let op = if self.eat("==") {
+            Op::Eq
+        } else if self.eat("!=") {
+            Op::Ne
+        } else if self.eat("<=") {
+            Op::Le
+        } else if self.eat(">=") {
+            Op::Ge
+        } else if self.eat("<") {
+            Op::Lt
+        } else if self.eat(">") {
+            Op::Gt
+        } else {
+            return Ok(Condition {
+                path,
+                comparison: None,
+            });
+        };
+        self.skip_whitespace();
+        let literal = self.literal()?;
+        Ok(Condition {
+            path,
+            comparison: Some((op, literal)),
+        })
+    }
+
+    fn literal(&mut self) -> Result<Value> {
+        if let Some('\'') | Some('"') = self.peek() {
+            return Ok(Value::String(self.string()?));
+        }
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '-' || c == '+' || c == '.') {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        serde_json::from_str(&self.expr[start..self.pos])
+            .ok()
+            .filter(|v: &Value| !v.is_object() && !v.is_array())
+            .ok_or_else(|| self.error("expected a literal"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_select() -> Result<()> {
+        let doc = json!({
+            "store": {
+                "items": [
+                    {"id": 1, "active": true, "price": 8.5, "tags": ["a"]},
+                    {"id": 2, "active": false, "price": 12},
+                    {"id": 3, "active": true, "price": 22.99, "name": "it's"}
+                ],
+                "owner": {"id": 4}
+            }
+        });
+        let cases = vec![
+            ("$.store.items[?(@.active==true)].id", json!([1, 3])),
+            (
+                "$.store.items[?(@.price > 10 && @.active == true)].id",
+                json!([3]),
+            ),
+            (
+                "$.store.items[?(@.id == 1 || @.name == 'it\\'s')].id",
+                json!([1, 3]),
+            ),
+            ("$.store.items[?(@.tags)].id", json!([1])),
+            ("$['store'].items[-1].id", json!(3)),
+            ("$.store.items[1:].id", json!([2, 3])),
+            ("$..id", json!([1, 2, 3, 4])),
+            ("$.store.*.id", json!([4])),
+            ("$.store.missing", Value::Null),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(expected, JsonPath::parse(expr)?.evaluate(&doc), "{}", expr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        for expr in &[
+            "store.items",
+            "$.items[?(@.id ==)]",
+            "$.items[0",
+            "$.items['id]",
+        ] {
+            assert!(JsonPath::parse(expr).is_err(), "{}", expr);
+        }
+    }
+}
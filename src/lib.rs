@@ -22,7 +22,11 @@
 //!                    recursive: true,
 //!                    prefix: Some("nickname"),
 //!                    separator: Some("_"),
-//!                    manipulation: None,
+//!                    array_separator: None,
+//!                    index_base: None,
+//!                    index_format: None,
+//!                    max_depth: None,
+//!                    manipulations: Vec::new(),
 //!                },
 //!            )?
 //!         .add_direct("nested.inner.key", "prev_nested")?
@@ -82,13 +86,57 @@
 //! }
 //! ```
 //!
+// typetag and failure derive impls inside anonymous consts which newer compilers lint against.
+#![allow(non_local_definitions)]
+
+// allows the derive macro's generated `::bumblebee` paths to be used within the crate's own tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as bumblebee;
+
+/// derives Transformable for a struct, see the `bumblebee_derive` crate for the attributes.
+#[cfg(feature = "derive")]
+pub use bumblebee_derive::Bumblebee;
+
+pub mod automap;
+pub mod complexity;
+pub mod compute;
+pub mod context;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod dsl;
+pub mod duration;
 pub mod errors;
+pub mod explain;
+pub mod functions;
+pub mod generate;
+pub mod input;
+pub mod jsonpath;
+pub mod mask;
 pub mod namespace;
+pub mod output;
+pub mod patch;
+pub mod predicate;
+pub mod profile;
 pub mod rules;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod template;
 pub mod transformer;
 mod tree;
 
 pub mod prelude {
-    pub use crate::rules::FlattenOps;
-    pub use crate::transformer::TransformerBuilder;
+    pub use crate::automap::NameMatch;
+    pub use crate::compute::ComputeOp;
+    pub use crate::duration::DurationFormat;
+    pub use crate::generate::Generator;
+    pub use crate::mask::Mask;
+    pub use crate::rules::{
+        Agg, ArrayMerge, Casing, Coerce, CoerceFailure, Fallback, FlattenOps, Stringify,
+        SubtreeOps, Type, ZipLength,
+    };
+    #[cfg(feature = "schema")]
+    pub use crate::schema::SchemaPolicy;
+    pub use crate::transformer::{Transformable, TransformerBuilder};
+    #[cfg(feature = "derive")]
+    pub use bumblebee_derive::Bumblebee;
 }
@@ -0,0 +1,140 @@
+use crate::output;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Mask is how a Mask mapping anonymizes a sensitive source value eg. an email, token or card
+/// number. Strings are masked as is, other values as their canonical serialization, see
+/// output::canonical, and `null` is written as is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Mask {
+    /// the lowercase hex SHA-256 digest of the value, so masked values can still be joined on.
+    Sha256,
+    /// replaces all but the last keep_last characters with `*` eg. `************4242`. Values no
+    /// longer than keep_last are replaced entirely.
+    Redact { keep_last: usize },
+    /// replaces the value with the String.
+    Fixed(String),
+}
+
+impl Mask {
+    /// returns the masked value.
+    pub fn apply(&self, value: &Value) -> Value {
+        let text = match value {
+            Value::Null => return Value::Null,
+            Value::String(s) => s.clone(),
+            value => output::canonical(value),
+        };
+        Value::String(match self {
+            Mask::Sha256 => sha256_hex(text.as_bytes()),
+            Mask::Redact { keep_last } => {
+                let len = text.chars().count();
+                let keep = if len > *keep_last { *keep_last } else { 0 };
+                text.chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < len - keep { '*' } else { c })
+                    .collect()
+            }
+            Mask::Fixed(s) => s.clone(),
+        })
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// returns the lowercase hex SHA-256 digest of the bytes, as specified by FIPS 180-4.
+fn sha256_hex(bytes: &[u8]) -> String {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip(&[a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+    h.iter().map(|word| format!("{:08x}", word)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            sha256_hex(b"")
+        );
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+        );
+        assert_eq!(
+            json!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            Mask::Sha256.apply(&json!("abc"))
+        );
+
+        let redact = Mask::Redact { keep_last: 4 };
+        assert_eq!(
+            json!("************4242"),
+            redact.apply(&json!("4242424242424242"))
+        );
+        assert_eq!(json!("**3456"), redact.apply(&json!(123_456)));
+        assert_eq!(json!("****"), redact.apply(&json!("ab\u{e9}d")));
+        assert_eq!(
+            json!("[redacted]"),
+            Mask::Fixed("[redacted]".to_owned()).apply(&json!({"token": "abc"}))
+        );
+        assert_eq!(Value::Null, redact.apply(&Value::Null));
+    }
+}
@@ -1,15 +1,30 @@
-use crate::errors::Result;
+use crate::errors::{Error, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::borrow::Cow;
 
 /// represents a single namespace level for traversion JSON structures.
 ///
 /// # Example
 /// `test.value` would be represented by two Namespace Object's `test` and `value`.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Namespace {
-    Object { id: String },
-    Array { id: String, index: usize }, // TODO: look into making Array id an Option
+    Object {
+        id: String,
+    },
+    Array {
+        id: String,
+        index: usize,
+    }, // TODO: look into making Array id an Option
+    Wildcard {
+        id: String,
+    },
+    /// the first element of the Array whose `key` field equals `value` eg. `phones[type=mobile]`.
+    Match {
+        id: String,
+        key: String,
+        value: String,
+    },
 }
 
 impl Namespace {
@@ -29,52 +44,304 @@ impl Namespace {
     }
 
     pub(crate) fn is_object(&self) -> bool {
-        match self {
-            Namespace::Object { .. } => true,
-            _ => false,
-        }
+        matches!(self, Namespace::Object { .. })
     }
 
     pub(crate) fn is_array(&self) -> bool {
-        match self {
-            Namespace::Array { .. } => true,
-            _ => false,
-        }
+        matches!(self, Namespace::Array { .. })
+    }
+
+    pub(crate) fn is_wildcard(&self) -> bool {
+        matches!(self, Namespace::Wildcard { .. })
     }
 
     pub(crate) fn id(&self) -> &String {
         match self {
-            Namespace::Object { id } => &id,
-            Namespace::Array { id, .. } => &id,
+            Namespace::Object { id } => id,
+            Namespace::Array { id, .. } => id,
+            Namespace::Wildcard { id } => id,
+            Namespace::Match { id, .. } => id,
         }
     }
 
     /// parse takes an ordinary namespaced string eg. `object.nested[0][1].nested.field` and
     /// turns it into a usable namespace object for use in transformations.
     ///
-    /// **NOTE:** This parser assumes `[` or `]` in the namespace denotes an array, if this is not true
-    ///       you will have to manually create your own namespace; the backend transformer handles
-    ///       the distinction, just the parser has no way of knowing the difference.
+    /// An array index of `*` eg. `items[*].name` is a wildcard matching every element of the array.
+    ///
+    /// Keys containing `.`, `[` or `]` can be addressed by quoting them eg. `user["first.name"]`
+    /// or by escaping the characters with a `\` eg. `user.first\.name`.
     ///
     pub fn parse<'a, S>(input: S) -> Result<Vec<Namespace>>
     where
         S: Into<Cow<'a, str>>,
     {
-        input
-            .into()
-            .split('.')
-            .flat_map(|s| s.split_terminator(']'))
-            .map(|v| {
-                if let Some(idx) = v.find('[') {
-                    Ok(Namespace::Array {
-                        id: v[..idx].to_string(),
-                        index: v[idx + 1..].parse()?,
-                    })
-                } else {
-                    Ok(Namespace::Object { id: v.to_string() })
+        let input = input.into();
+        let mut namespaces = Vec::new();
+        let mut chars = input.chars().peekable();
+        loop {
+            let mut id = String::new();
+            while let Some(c) = chars.next_if(|c| *c != '.' && *c != '[') {
+                match c {
+                    '\\' => id.push(chars.next().ok_or_else(|| {
+                        Error::InvalidNamespace(format!("Dangling escape in namespace {}", input))
+                    })?),
+                    ']' => {
+                        return Err(Error::InvalidNamespace(format!(
+                            "Unexpected ] in namespace {}",
+                            input
+                        )))
+                    }
+                    _ => id.push(c),
+                }
+            }
+
+            let mut id = Some(id);
+            while chars.next_if_eq(&'[').is_some() {
+                match chars.next_if(|c| *c == '"' || *c == '\'') {
+                    Some(quote) => {
+                        // quoted keys are always objects
+                        if let Some(id) = id.take().filter(|id| !id.is_empty()) {
+                            namespaces.push(Namespace::Object { id });
+                        }
+                        let mut key = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('\\') => key.push(chars.next().ok_or_else(|| {
+                                    Error::InvalidNamespace(format!(
+                                        "Dangling escape in namespace {}",
+                                        input
+                                    ))
+                                })?),
+                                Some(c) if c == quote => break,
+                                Some(c) => key.push(c),
+                                None => {
+                                    return Err(Error::InvalidNamespace(format!(
+                                        "Unterminated quote in namespace {}",
+                                        input
+                                    )))
+                                }
+                            }
+                        }
+                        if chars.next() != Some(']') {
+                            return Err(Error::InvalidNamespace(format!(
+                                "Expected ] after quoted key in namespace {}",
+                                input
+                            )));
+                        }
+                        namespaces.push(Namespace::Object { id: key });
+                    }
+                    None => {
+                        let mut index = String::new();
+                        // position of the first unescaped = when matching by field value
+                        let mut eq = None;
+                        loop {
+                            match chars.next() {
+                                Some(']') => break,
+                                Some('\\') => index.push(chars.next().ok_or_else(|| {
+                                    Error::InvalidNamespace(format!(
+                                        "Dangling escape in namespace {}",
+                                        input
+                                    ))
+                                })?),
+                                Some('=') if eq.is_none() => {
+                                    eq = Some(index.len());
+                                    index.push('=');
+                                }
+                                Some(c) => index.push(c),
+                                None => {
+                                    return Err(Error::InvalidNamespace(format!(
+                                        "Unterminated [ in namespace {}",
+                                        input
+                                    )))
+                                }
+                            }
+                        }
+                        let id = id.take().unwrap_or_default();
+                        namespaces.push(match (index.as_str(), eq) {
+                            (_, Some(eq)) => Namespace::Match {
+                                id,
+                                key: index[..eq].to_owned(),
+                                value: index[eq + 1..].to_owned(),
+                            },
+                            ("*", None) => Namespace::Wildcard { id },
+                            _ => Namespace::Array {
+                                id,
+                                index: index.parse()?,
+                            },
+                        });
+                    }
+                }
+            }
+            if let Some(id) = id {
+                namespaces.push(Namespace::Object { id });
+            }
+
+            match chars.next() {
+                Some('.') => continue,
+                None => break,
+                Some(c) => {
+                    return Err(Error::InvalidNamespace(format!(
+                        "Unexpected {} after ] in namespace {}",
+                        c, input
+                    )))
+                }
+            }
+        }
+        Ok(namespaces)
+    }
+
+    /// parse_pointer takes a JSON Pointer (RFC 6901) eg. `/nested/arr/0/key` and turns it into a
+    /// usable namespace object for use in transformations. Segments made up only of digits are
+    /// treated as array indexes, so the above is equivalent to `nested.arr[0].key`.
+    ///
+    pub fn parse_pointer<'a, S>(input: S) -> Result<Vec<Namespace>>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let input = input.into();
+        if input.is_empty() {
+            return Ok(vec![Namespace::Object { id: String::new() }]);
+        }
+        let pointer = input.strip_prefix('/').ok_or_else(|| {
+            Error::InvalidNamespace(format!("JSON Pointer {} must start with /", input))
+        })?;
+
+        let mut namespaces = Vec::new();
+        for token in pointer.split('/') {
+            let mut id = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => id.push('~'),
+                        Some('1') => id.push('/'),
+                        _ => {
+                            return Err(Error::InvalidNamespace(format!(
+                                "Invalid escape in JSON Pointer {}",
+                                input
+                            )))
+                        }
+                    },
+                    _ => id.push(c),
+                }
+            }
+
+            // leading zeros are not valid array indexes and so are treated as object keys
+            let is_index = !id.is_empty()
+                && id.bytes().all(|b| b.is_ascii_digit())
+                && (id == "0" || !id.starts_with('0'));
+            if !is_index {
+                namespaces.push(Namespace::Object { id });
+                continue;
+            }
+            let index = id.parse()?;
+            match namespaces.pop() {
+                Some(Namespace::Object { id }) => namespaces.push(Namespace::Array { id, index }),
+                Some(other) => {
+                    namespaces.push(other);
+                    namespaces.push(Namespace::Array {
+                        id: String::new(),
+                        index,
+                    });
+                }
+                None => namespaces.push(Namespace::Array {
+                    id: String::new(),
+                    index,
+                }),
+            }
+        }
+        Ok(namespaces)
+    }
+
+    /// format turns namespaces back into a namespaced string which parses to the same namespaces,
+    /// escaping any characters with special meaning.
+    pub fn format(namespaces: &[Namespace]) -> String {
+        let mut output = String::new();
+        for (i, ns) in namespaces.iter().enumerate() {
+            // chained array indexes without an id are only valid directly after another index
+            let chained =
+                i > 0 && ns.id().is_empty() && !ns.is_object() && !namespaces[i - 1].is_object();
+            if i > 0 && !chained {
+                output.push('.');
+            }
+            for c in ns.id().chars() {
+                if let '.' | '[' | ']' | '\\' = c {
+                    output.push('\\');
+                }
+                output.push(c);
+            }
+            match ns {
+                Namespace::Object { .. } => {}
+                Namespace::Array { index, .. } => {
+                    output.push('[');
+                    output.push_str(&index.to_string());
+                    output.push(']');
                 }
-            })
-            .collect()
+                Namespace::Wildcard { .. } => output.push_str("[*]"),
+                Namespace::Match { key, value, .. } => {
+                    output.push('[');
+                    for c in key.chars() {
+                        if let ']' | '=' | '\\' = c {
+                            output.push('\\');
+                        }
+                        output.push(c);
+                    }
+                    output.push('=');
+                    for c in value.chars() {
+                        if let ']' | '\\' = c {
+                            output.push('\\');
+                        }
+                        output.push(c);
+                    }
+                    output.push(']');
+                }
+            }
+        }
+        output
+    }
+}
+
+/// returns if the `key` field of an Array element equals the value of a `Namespace::Match`. Values
+/// which are not Strings are compared using their JSON representation eg. `[id=1]`.
+#[inline]
+pub(crate) fn element_matches(element: &Value, key: &str, value: &str) -> bool {
+    match element.get(key) {
+        Some(Value::String(s)) => s == value,
+        Some(v) => serde_json::to_string(v).is_ok_and(|s| s == value),
+        None => false,
+    }
+}
+
+/// returns the first element of an Array matching a `Namespace::Match`.
+#[inline]
+pub(crate) fn find_match<'a>(arr: &'a Value, key: &str, value: &str) -> Option<&'a Value> {
+    arr.as_array()?
+        .iter()
+        .find(|element| element_matches(element, key, value))
+}
+
+/// parses either a JSON Pointer, when starting with a `/`, or an ordinary namespaced string.
+#[inline]
+pub(crate) fn parse_path(input: Cow<str>) -> Result<Vec<Namespace>> {
+    if input.starts_with('/') {
+        Namespace::parse_pointer(input)
+    } else {
+        Namespace::parse(input)
+    }
+}
+
+/// joins a parent and child namespace string, array indexes eg. `[0]` are joined without a `.`.
+#[inline]
+pub(crate) fn join_path(parent: &str, child: &str) -> String {
+    if child.is_empty() {
+        parent.to_owned()
+    } else if parent.is_empty() {
+        child.to_owned()
+    } else if child.starts_with('[') {
+        parent.to_owned() + child
+    } else {
+        parent.to_owned() + "." + child
     }
 }
 
@@ -119,4 +386,125 @@ mod tests {
         }];
         assert_eq!(expected, results);
     }
+
+    #[test]
+    fn test_wildcard() {
+        let ns = "items[*].prices[*]";
+        let results = Namespace::parse(ns).unwrap();
+        let expected = vec![
+            Namespace::Wildcard {
+                id: String::from("items"),
+            },
+            Namespace::Wildcard {
+                id: String::from("prices"),
+            },
+        ];
+        assert_eq!(expected, results);
+    }
+
+    #[test]
+    fn test_quoted_and_escaped() {
+        let expected = vec![
+            Namespace::Object {
+                id: String::from("user"),
+            },
+            Namespace::Object {
+                id: String::from("first.name"),
+            },
+            Namespace::Array {
+                id: String::from("items[0]"),
+                index: 1,
+            },
+        ];
+        let results = Namespace::parse(r#"user["first.name"].items\[0\][1]"#).unwrap();
+        assert_eq!(expected, results);
+        let results = Namespace::parse(r#"user.first\.name.items\[0\][1]"#).unwrap();
+        assert_eq!(expected, results);
+
+        let results = Namespace::parse(r#"['user.name']"#).unwrap();
+        let expected = vec![Namespace::Object {
+            id: String::from("user.name"),
+        }];
+        assert_eq!(expected, results);
+
+        assert!(Namespace::parse(r#"user["first.name"#).is_err());
+        assert!(Namespace::parse("user[0").is_err());
+        assert!(Namespace::parse("user[0]name").is_err());
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        for ns in &[
+            "",
+            "field",
+            "embedded.array[0][1]",
+            "items[*].name",
+            "a.[0]",
+            r#"user.first\.name.items\[0\][1]"#,
+        ] {
+            let results = Namespace::parse(*ns).unwrap();
+            assert_eq!(*ns, Namespace::format(&results));
+            assert_eq!(
+                results,
+                Namespace::parse(Namespace::format(&results)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_pointer() {
+        let results = Namespace::parse_pointer("/nested/arr/0/key").unwrap();
+        assert_eq!(Namespace::parse("nested.arr[0].key").unwrap(), results);
+
+        let results = Namespace::parse_pointer("/arr/0/1/a~1b~0c/01").unwrap();
+        let expected = vec![
+            Namespace::Array {
+                id: String::from("arr"),
+                index: 0,
+            },
+            Namespace::Array {
+                id: String::from(""),
+                index: 1,
+            },
+            Namespace::Object {
+                id: String::from("a/b~c"),
+            },
+            Namespace::Object {
+                id: String::from("01"),
+            },
+        ];
+        assert_eq!(expected, results);
+
+        assert_eq!(
+            Namespace::parse("").unwrap(),
+            Namespace::parse_pointer("").unwrap()
+        );
+        assert!(Namespace::parse_pointer("nested/key").is_err());
+        assert!(Namespace::parse_pointer("/nested~2").is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let results = Namespace::parse("phones[type=mobile].number").unwrap();
+        let expected = vec![
+            Namespace::Match {
+                id: String::from("phones"),
+                key: String::from("type"),
+                value: String::from("mobile"),
+            },
+            Namespace::Object {
+                id: String::from("number"),
+            },
+        ];
+        assert_eq!(expected, results);
+
+        let results = Namespace::parse(r#"a[b\=c=d=e\]]"#).unwrap();
+        let expected = vec![Namespace::Match {
+            id: String::from("a"),
+            key: String::from("b=c"),
+            value: String::from("d=e]"),
+        }];
+        assert_eq!(expected, results);
+        assert_eq!(r#"a[b\=c=d=e\]]"#, Namespace::format(&results));
+    }
 }
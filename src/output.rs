@@ -0,0 +1,342 @@
+use crate::errors::Result;
+use serde::Serialize;
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+use serde_json::Value;
+use std::io;
+
+/// FloatFormat controls how floating point numbers are written to the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FloatFormat {
+    /// the shortest representation which round trips, the serde_json default.
+    #[default]
+    Shortest,
+    /// always written with the provided number of decimal places.
+    Fixed(u8),
+}
+
+/// OutputOptions controls how a transformed value is serialized to a string.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OutputOptions {
+    pub pretty: bool,
+    pub ascii_only: bool,
+    pub float_format: FloatFormat,
+    /// writes the canonical form, see canonical, ignoring all other options.
+    pub canonical: bool,
+}
+
+/// OutputSink receives transformed documents one at a time, allowing results to be streamed to
+/// files, channels or databases rather than collected in memory.
+pub trait OutputSink {
+    fn accept(&mut self, doc: Value) -> Result<()>;
+}
+
+impl OutputSink for Vec<Value> {
+    #[inline]
+    fn accept(&mut self, doc: Value) -> Result<()> {
+        self.push(doc);
+        Ok(())
+    }
+}
+
+/// LineSink writes each document on its own line, newline delimited JSON, using the provided
+/// options. Pretty printing is not supported and is ignored.
+#[derive(Debug)]
+pub struct LineSink<W> {
+    writer: W,
+    options: OutputOptions,
+}
+
+impl<W> LineSink<W>
+where
+    W: io::Write,
+{
+    #[inline]
+    pub fn new(writer: W, options: OutputOptions) -> Self {
+        LineSink {
+            writer,
+            options: OutputOptions {
+                pretty: false,
+                ..options
+            },
+        }
+    }
+
+    /// returns the underlying writer.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> OutputSink for LineSink<W>
+where
+    W: io::Write,
+{
+    #[inline]
+    fn accept(&mut self, doc: Value) -> Result<()> {
+        self.writer
+            .write_all(to_string(&doc, self.options)?.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// serializes the value to a string using the provided options.
+#[inline]
+pub(crate) fn to_string(value: &Value, options: OutputOptions) -> Result<String> {
+    if options.canonical {
+        return Ok(canonical(value));
+    }
+    let mut ser = serde_json::Serializer::with_formatter(
+        Vec::with_capacity(128),
+        OutputFormatter {
+            options,
+            pretty: PrettyFormatter::new(),
+        },
+    );
+    value.serialize(&mut ser)?;
+    String::from_utf8(ser.into_inner())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+}
+
+/// returns the canonical serialization of the value, suitable for stable hashing, following the
+/// JSON Canonicalization Scheme of RFC 8785. Object keys are sorted by their UTF-16 code units,
+/// numbers are written as ECMAScript's `Number.prototype.toString` writes the nearest double eg.
+/// `1.0` and `1e0` as `1`, `1e21` as `1e+21` and `-0` as `0`, and there is no insignificant
+/// whitespace. Integers beyond 2^53 can't be represented exactly by a double so lose precision.
+pub fn canonical(value: &Value) -> String {
+    let mut out = String::with_capacity(128);
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Number(n) => match n.as_f64() {
+            Some(f) => write_number(f, out),
+            None => out.push_str(&n.to_string()),
+        },
+        Value::Array(arr) => {
+            out.push('[');
+            for (i, v) in arr.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(v, out);
+            }
+            out.push(']');
+        }
+        Value::Object(m) => {
+            let mut entries: Vec<_> = m.iter().collect();
+            entries.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(&Value::String(k.clone()), out);
+                out.push(':');
+                write_canonical(v, out);
+            }
+            out.push('}');
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+/// writes the finite number as ECMAScript's `Number.prototype.toString` does.
+fn write_number(f: f64, out: &mut String) {
+    if f == 0.0 {
+        out.push('0');
+        return;
+    }
+    if f < 0.0 {
+        out.push('-');
+    }
+    // the shortest digits which round trip, as d.ddde<exponent>
+    let sci = format!("{:e}", f.abs());
+    let (mantissa, exponent) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // the position of the decimal point relative to the digits
+    let n = exponent.parse::<i32>().unwrap_or_default() + 1;
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', -n as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if n > 0 { '+' } else { '-' });
+        out.push_str(&(n - 1).abs().to_string());
+    }
+}
+
+struct OutputFormatter<'a> {
+    options: OutputOptions,
+    pretty: PrettyFormatter<'a>,
+}
+
+impl<'a> Formatter for OutputFormatter<'a> {
+    #[inline]
+    fn write_f32<W>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        match self.options.float_format {
+            FloatFormat::Shortest => CompactFormatter.write_f32(writer, value),
+            FloatFormat::Fixed(precision) => write!(writer, "{:.*}", precision as usize, value),
+        }
+    }
+
+    #[inline]
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        match self.options.float_format {
+            FloatFormat::Shortest => CompactFormatter.write_f64(writer, value),
+            FloatFormat::Fixed(precision) => write!(writer, "{:.*}", precision as usize, value),
+        }
+    }
+
+    #[inline]
+    fn write_string_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if !self.options.ascii_only || fragment.is_ascii() {
+            return writer.write_all(fragment.as_bytes());
+        }
+        let mut buf = [0; 2];
+        for c in fragment.chars() {
+            if c.is_ascii() {
+                writer.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut buf) {
+                    write!(writer, "\\u{:04x}", unit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.begin_array(writer)
+        } else {
+            CompactFormatter.begin_array(writer)
+        }
+    }
+
+    #[inline]
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.end_array(writer)
+        } else {
+            CompactFormatter.end_array(writer)
+        }
+    }
+
+    #[inline]
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.begin_array_value(writer, first)
+        } else {
+            CompactFormatter.begin_array_value(writer, first)
+        }
+    }
+
+    #[inline]
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.end_array_value(writer)
+        } else {
+            CompactFormatter.end_array_value(writer)
+        }
+    }
+
+    #[inline]
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.begin_object(writer)
+        } else {
+            CompactFormatter.begin_object(writer)
+        }
+    }
+
+    #[inline]
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.end_object(writer)
+        } else {
+            CompactFormatter.end_object(writer)
+        }
+    }
+
+    #[inline]
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.begin_object_key(writer, first)
+        } else {
+            CompactFormatter.begin_object_key(writer, first)
+        }
+    }
+
+    #[inline]
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.begin_object_value(writer)
+        } else {
+            CompactFormatter.begin_object_value(writer)
+        }
+    }
+
+    #[inline]
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if self.options.pretty {
+            self.pretty.end_object_value(writer)
+        } else {
+            CompactFormatter.end_object_value(writer)
+        }
+    }
+}
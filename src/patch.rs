@@ -0,0 +1,188 @@
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// PatchOp is a single RFC 6902 JSON Patch operation, where path is a JSON Pointer eg.
+/// `/user/addresses/0`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+/// returns the JSON Patch transforming the before document into the after document. Objects are
+/// patched key by key and Arrays element by element, elements being added to or removed from the
+/// end of an Array.
+pub fn diff(before: &Value, after: &Value) -> Vec<PatchOp> {
+    let mut ops = Vec::new();
+    diff_value(&mut String::new(), before, after, &mut ops);
+    ops
+}
+
+fn diff_value(path: &mut String, before: &Value, after: &Value, ops: &mut Vec<PatchOp>) {
+    match (before, after) {
+        (before, after) if before == after => {}
+        (Value::Object(before), Value::Object(after)) => diff_object(path, before, after, ops),
+        (Value::Array(before), Value::Array(after)) => {
+            let len = path.len();
+            for (i, (b, a)) in before.iter().zip(after).enumerate() {
+                push_index(path, i);
+                diff_value(path, b, a, ops);
+                path.truncate(len);
+            }
+            for (i, value) in after.iter().enumerate().skip(before.len()) {
+                push_index(path, i);
+                ops.push(PatchOp::Add {
+                    path: path.clone(),
+                    value: value.clone(),
+                });
+                path.truncate(len);
+            }
+            // removed from the end so the indexes of the remaining elements don't shift
+            for i in (after.len()..before.len()).rev() {
+                push_index(path, i);
+                ops.push(PatchOp::Remove { path: path.clone() });
+                path.truncate(len);
+            }
+        }
+        (_, after) => ops.push(PatchOp::Replace {
+            path: path.clone(),
+            value: after.clone(),
+        }),
+    }
+}
+
+fn diff_object(
+    path: &mut String,
+    before: &Map<String, Value>,
+    after: &Map<String, Value>,
+    ops: &mut Vec<PatchOp>,
+) {
+    let len = path.len();
+    for key in before.keys().filter(|k| !after.contains_key(*k)) {
+        push_key(path, key);
+        ops.push(PatchOp::Remove { path: path.clone() });
+        path.truncate(len);
+    }
+    for (key, value) in after {
+        push_key(path, key);
+        match before.get(key) {
+            Some(b) => diff_value(path, b, value, ops),
+            None => ops.push(PatchOp::Add {
+                path: path.clone(),
+                value: value.clone(),
+            }),
+        }
+        path.truncate(len);
+    }
+}
+
+/// appends the key to the JSON Pointer, escaping `~` and `/`.
+#[inline]
+fn push_key(path: &mut String, key: &str) {
+    path.push('/');
+    path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+}
+
+#[inline]
+fn push_index(path: &mut String, index: usize) {
+    path.push('/');
+    path.push_str(&index.to_string());
+}
+
+/// applies the JSON Patch to the document, eg. to check a patch produced by diff.
+pub fn apply(document: &mut Value, ops: &[PatchOp]) -> Result<()> {
+    for op in ops {
+        let path = match op {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. } => path,
+        };
+        let invalid = || Error::InvalidNamespace(format!("invalid JSON Patch path {}", path));
+        let (parent, key) = match path.rfind('/') {
+            Some(i) => (
+                &path[..i],
+                path[i + 1..].replace("~1", "/").replace("~0", "~"),
+            ),
+            None if path.is_empty() => {
+                match op {
+                    PatchOp::Add { value, .. } | PatchOp::Replace { value, .. } => {
+                        *document = value.clone()
+                    }
+                    PatchOp::Remove { .. } => *document = Value::Null,
+                }
+                continue;
+            }
+            None => return Err(invalid()),
+        };
+        match (document.pointer_mut(parent).ok_or_else(invalid)?, op) {
+            (Value::Object(m), PatchOp::Add { value, .. })
+            | (Value::Object(m), PatchOp::Replace { value, .. }) => {
+                m.insert(key, value.clone());
+            }
+            (Value::Object(m), PatchOp::Remove { .. }) => {
+                m.remove(&key).ok_or_else(invalid)?;
+            }
+            (Value::Array(arr), op) => {
+                let index = match key.as_str() {
+                    "-" => arr.len(),
+                    key => key.parse::<usize>().map_err(|_| invalid())?,
+                };
+                match op {
+                    PatchOp::Add { value, .. } if index <= arr.len() => {
+                        arr.insert(index, value.clone())
+                    }
+                    PatchOp::Replace { value, .. } if index < arr.len() => {
+                        arr[index] = value.clone()
+                    }
+                    PatchOp::Remove { .. } if index < arr.len() => {
+                        arr.remove(index);
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let before = json!({"id":1,"name":"Joey","a/b":1,"tags":["a","b","c"],"user":{"id":1}});
+        let after = json!({"id":"1","tags":["a","x"],"user":{"id":1,"name":"Joey"},"list":[]});
+        let ops = diff(&before, &after);
+        assert_eq!(
+            json!([
+                {"op":"remove","path":"/a~1b"},
+                {"op":"remove","path":"/name"},
+                {"op":"replace","path":"/id","value":"1"},
+                {"op":"add","path":"/list","value":[]},
+                {"op":"replace","path":"/tags/1","value":"x"},
+                {"op":"remove","path":"/tags/2"},
+                {"op":"add","path":"/user/name","value":"Joey"}
+            ]),
+            serde_json::to_value(&ops)?
+        );
+
+        let mut patched = before.clone();
+        apply(&mut patched, &ops)?;
+        assert_eq!(after, patched);
+        assert!(diff(&after, &after).is_empty());
+        assert_eq!(
+            vec![PatchOp::Replace {
+                path: String::new(),
+                value: json!([])
+            }],
+            diff(&before, &json!([]))
+        );
+        Ok(())
+    }
+}
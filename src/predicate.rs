@@ -0,0 +1,219 @@
+use crate::context::Context;
+use crate::errors::Result;
+use crate::namespace::{parse_path, Namespace};
+use crate::rules::{lookup, CONTEXT_PREFIX};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Predicate is a serializable condition evaluated against the source document, used by
+/// conditional rules.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Predicate {
+    /// the value is present and not `null`.
+    Exists(Subject),
+    /// the value is equal to the provided value, numbers are compared by their numeric value.
+    Equals(Subject, Value),
+    /// the value is a number and compares to the provided number as specified.
+    Compare(Subject, Comparison, f64),
+    /// the value is a String matching the glob pattern, where `*` matches any sequence of
+    /// characters and `?` any single character.
+    Matches(Subject, String),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+/// Subject is the value a Predicate is evaluated on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Subject {
+    /// a namespace relative to the root of the document.
+    Document(Vec<Namespace>),
+    /// a namespace relative to the apply time values of the Context eg. `$ctx.flags.beta`.
+    Context(Vec<Namespace>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Subject {
+    /// creates a Subject from a namespaced string eg. `user.status`, `/user/status` or
+    /// `$ctx.flags.beta`.
+    #[inline]
+    pub fn parse<'a, S>(namespace: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let namespace = namespace.into();
+        match namespace.strip_prefix(CONTEXT_PREFIX) {
+            Some(context) => Ok(Subject::Context(parse_path(context.into())?)),
+            None => Ok(Subject::Document(parse_path(namespace)?)),
+        }
+    }
+
+    /// returns the namespaced string the subject was parsed from eg. `$ctx.flags.beta`.
+    #[inline]
+    pub(crate) fn path(&self) -> String {
+        match self {
+            Subject::Document(namespace) => Namespace::format(namespace),
+            Subject::Context(namespace) => {
+                CONTEXT_PREFIX.to_owned() + &Namespace::format(namespace)
+            }
+        }
+    }
+
+    /// returns the value of the subject, treating `null` as missing.
+    #[inline]
+    pub(crate) fn value<'v>(&self, ctx: &'v Context, from: &'v Value) -> Option<&'v Value> {
+        match self {
+            Subject::Document(namespace) => lookup(namespace, from),
+            Subject::Context(namespace) => ctx.value(namespace),
+        }
+        .filter(|v| !v.is_null())
+    }
+}
+
+impl Predicate {
+    #[inline]
+    pub fn exists<'a, S>(namespace: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        Ok(Predicate::Exists(Subject::parse(namespace)?))
+    }
+
+    #[inline]
+    pub fn equals<'a, S, V>(namespace: S, value: V) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+        V: Into<Value>,
+    {
+        Ok(Predicate::Equals(Subject::parse(namespace)?, value.into()))
+    }
+
+    #[inline]
+    pub fn compare<'a, S>(namespace: S, comparison: Comparison, number: f64) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        Ok(Predicate::Compare(
+            Subject::parse(namespace)?,
+            comparison,
+            number,
+        ))
+    }
+
+    #[inline]
+    pub fn matches<'a, S>(namespace: S, pattern: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        Ok(Predicate::Matches(
+            Subject::parse(namespace)?,
+            pattern.into().into_owned(),
+        ))
+    }
+
+    /// returns if the predicate holds for the document.
+    pub fn evaluate(&self, ctx: &Context, from: &Value) -> bool {
+        match self {
+            Predicate::Exists(subject) => subject.value(ctx, from).is_some(),
+            Predicate::Equals(subject, expected) => equals(subject.value(ctx, from), expected),
+            Predicate::Compare(subject, comparison, number) => {
+                match subject.value(ctx, from).and_then(Value::as_f64) {
+                    Some(v) => match comparison {
+                        Comparison::Lt => v < *number,
+                        Comparison::Le => v <= *number,
+                        Comparison::Gt => v > *number,
+                        Comparison::Ge => v >= *number,
+                    },
+                    None => false,
+                }
+            }
+            Predicate::Matches(subject, pattern) => {
+                match subject.value(ctx, from).and_then(Value::as_str) {
+                    Some(s) => glob(
+                        &pattern.chars().collect::<Vec<_>>(),
+                        &s.chars().collect::<Vec<_>>(),
+                    ),
+                    None => false,
+                }
+            }
+            Predicate::And(predicates) => predicates.iter().all(|p| p.evaluate(ctx, from)),
+            Predicate::Or(predicates) => predicates.iter().any(|p| p.evaluate(ctx, from)),
+            Predicate::Not(predicate) => !predicate.evaluate(ctx, from),
+        }
+    }
+}
+
+/// returns if the value is equal to the expected value, numbers are compared by their numeric
+/// value and a missing value is equal to `null`.
+#[inline]
+pub(crate) fn equals(value: Option<&Value>, expected: &Value) -> bool {
+    match (value, expected) {
+        (Some(Value::Number(a)), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Some(v), expected) => v == expected,
+        (None, expected) => expected.is_null(),
+    }
+}
+
+/// returns if the input matches the glob pattern, backtracking only to the last `*`.
+fn glob(pattern: &[char], input: &[char]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    let mut star = None;
+    while i < input.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == input[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((sp, si)) => {
+                    star = Some((sp, si + 1));
+                    p = sp + 1;
+                    i = si + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let ctx = Context::default();
+        let doc = json!({"amount":1500,"country":"US","email":"joey@example.com","tags":null});
+
+        assert!(Predicate::exists("amount")?.evaluate(&ctx, &doc));
+        assert!(!Predicate::exists("tags")?.evaluate(&ctx, &doc));
+        assert!(Predicate::equals("amount", 1500.0)?.evaluate(&ctx, &doc));
+        assert!(Predicate::equals("missing", Value::Null)?.evaluate(&ctx, &doc));
+        assert!(Predicate::compare("amount", Comparison::Gt, 1000.0)?.evaluate(&ctx, &doc));
+        assert!(!Predicate::compare("country", Comparison::Gt, 1000.0)?.evaluate(&ctx, &doc));
+        assert!(Predicate::matches("email", "*@example.???")?.evaluate(&ctx, &doc));
+        assert!(!Predicate::matches("email", "*@example.org")?.evaluate(&ctx, &doc));
+
+        let p = Predicate::And(vec![
+            Predicate::compare("amount", Comparison::Gt, 1000.0)?,
+            Predicate::Not(Box::new(Predicate::equals("country", "CA")?)),
+        ]);
+        assert!(p.evaluate(&ctx, &doc));
+        assert!(!Predicate::exists("$ctx.flags.beta")?.evaluate(&ctx, &doc));
+        Ok(())
+    }
+}
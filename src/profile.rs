@@ -0,0 +1,142 @@
+use crate::context::value_type;
+use crate::namespace::Namespace;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// maximum number of distinct example values kept per path.
+const MAX_EXAMPLES: usize = 3;
+
+/// PathProfile is what has been observed at a single source path.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PathProfile {
+    count: usize,
+    types: BTreeMap<&'static str, usize>,
+    examples: Vec<Value>,
+}
+
+impl PathProfile {
+    /// returns the number of times a value was observed at the path.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// returns the number of times each JSON type was observed at the path.
+    #[inline]
+    pub fn types(&self) -> &BTreeMap<&'static str, usize> {
+        &self.types
+    }
+
+    /// returns up to the first three distinct scalar values observed at the path.
+    #[inline]
+    pub fn examples(&self) -> &[Value] {
+        &self.examples
+    }
+}
+
+/// Profile records the types and example values observed per source path across documents, eg. to
+/// detect upstream schema drift before it breaks mappings. Elements of Arrays are recorded under a
+/// wildcard eg. `items[*].id`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Profile {
+    documents: usize,
+    paths: BTreeMap<String, PathProfile>,
+}
+
+impl Profile {
+    /// returns the number of documents observed.
+    #[inline]
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+
+    /// returns the profile of every path observed, keyed by namespace.
+    #[inline]
+    pub fn paths(&self) -> &BTreeMap<String, PathProfile> {
+        &self.paths
+    }
+
+    /// returns the profile of the path eg. `items[*].id`, if observed.
+    #[inline]
+    pub fn get(&self, path: &str) -> Option<&PathProfile> {
+        self.paths.get(path)
+    }
+
+    /// records every path of the document.
+    pub fn observe(&mut self, document: &Value) {
+        self.documents += 1;
+        self.observe_value(&mut Vec::new(), document);
+    }
+
+    fn observe_value(&mut self, namespace: &mut Vec<Namespace>, value: &Value) {
+        if !namespace.is_empty() {
+            let path = self.paths.entry(Namespace::format(namespace)).or_default();
+            path.count += 1;
+            *path.types.entry(value_type(value)).or_default() += 1;
+            if !value.is_object()
+                && !value.is_array()
+                && path.examples.len() < MAX_EXAMPLES
+                && !path.examples.contains(value)
+            {
+                path.examples.push(value.clone());
+            }
+        }
+        match value {
+            Value::Object(m) => {
+                for (k, v) in m {
+                    namespace.push(Namespace::Object { id: k.clone() });
+                    self.observe_value(namespace, v);
+                    namespace.pop();
+                }
+            }
+            Value::Array(arr) => {
+                // the Array's own namespace is replaced by a wildcard while observing the elements
+                let id = match namespace.last() {
+                    Some(Namespace::Object { id }) => Some(id.clone()),
+                    _ => None,
+                };
+                if id.is_some() {
+                    namespace.pop();
+                }
+                namespace.push(Namespace::Wildcard {
+                    id: id.clone().unwrap_or_default(),
+                });
+                for v in arr {
+                    self.observe_value(namespace, v);
+                }
+                namespace.pop();
+                if let Some(id) = id {
+                    namespace.push(Namespace::Object { id });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_observe() {
+        let mut profile = Profile::default();
+        profile.observe(&json!({"id":1,"items":[{"sku":"a"},{"sku":"b"}],"matrix":[[1,2]]}));
+        profile.observe(&json!({"id":"2","items":[{"sku":"a"}],"user.name":"Joey"}));
+
+        assert_eq!(2, profile.documents());
+        let id = profile.get("id").unwrap();
+        assert_eq!(2, id.count());
+        assert_eq!(Some(&1), id.types().get("number"));
+        assert_eq!(Some(&1), id.types().get("string"));
+        assert_eq!(&[json!(1), json!("2")], id.examples());
+
+        let sku = profile.get("items[*].sku").unwrap();
+        assert_eq!(3, sku.count());
+        assert_eq!(&[json!("a"), json!("b")], sku.examples());
+        assert_eq!(2, profile.get("matrix[*][*]").unwrap().count());
+        assert!(profile.get("user\\.name").is_some());
+    }
+}
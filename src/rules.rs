@@ -1,142 +1,1910 @@
+use crate::compute::{self, ComputeOp};
+use crate::context::{value_type, Context, LocaleContext};
+#[cfg(feature = "crypto")]
+use crate::crypto::Crypt;
+use crate::duration::DurationFormat;
 use crate::errors::{Error, Result};
-use crate::namespace::Namespace;
-use serde::{Deserialize, Serialize};
+use crate::generate::Generator;
+use crate::jsonpath::JsonPath;
+use crate::mask::Mask;
+use crate::namespace::{element_matches, find_match, join_path, parse_path, Namespace};
+use crate::output;
+use crate::predicate::{equals, Predicate, Subject};
+use crate::template::Template;
+use crate::transformer::{Conflict, Missing, MissingPolicy, Transformer};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{Map, Value};
 use std::borrow::Cow;
-use std::fmt::Debug;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::mem;
+use std::sync::Arc;
+
+/// prefix used on a source namespace to reference a value that has already been written to the
+/// output, eg. `$out.user.id`.
+pub const OUTPUT_PREFIX: &str = "$out.";
+
+/// prefix used on a source namespace to reference an apply time value of the Context, eg.
+/// `$ctx.flags.new_pricing`.
+pub const CONTEXT_PREFIX: &str = "$ctx.";
+
+/// source namespace selecting the entire current document, an empty source namespace is
+/// equivalent.
+pub const DOCUMENT: &str = "$";
+
+const MATCH_DESTINATION: &str =
+    "Matching Array elements by field value is not supported in destinations";
 
 #[typetag::serde]
-pub trait Rule: Debug {
+pub trait Rule: Debug + Send + Sync {
     fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()>;
+
+    /// applies the rule with access to the apply time Context. Rules which have no need of the
+    /// Context can rely on the default which simply calls `apply`.
+    fn apply_with_context(
+        &self,
+        _ctx: &Context,
+        from: &Value,
+        to: &mut Map<String, Value>,
+    ) -> Result<()> {
+        self.apply(from, to)
+    }
+
+    /// applies the rule in place of apply_with_context when its source is within an Array with
+    /// no elements, either empty or missing, so rules collecting values into an Array can write
+    /// it empty. The default is that nothing is written.
+    fn apply_no_elements(&self, _ctx: &Context, _to: &mut Map<String, Value>) -> Result<()> {
+        Ok(())
+    }
+
+    /// returns if the rule recursively flattens it's source value, used when scoring the
+    /// complexity of a Transformer.
+    fn is_recursive(&self) -> bool {
+        false
+    }
+
+    /// returns the full namespaces of the document values the rule reads from, where namespace is
+    /// the namespace the rule is applied at. Used to find the source fields not read by any rule
+    /// eg. when passing them through. The default is that the rule reads no fields.
+    fn source_paths(&self, _namespace: &[Namespace]) -> Vec<Vec<Namespace>> {
+        Vec::new()
+    }
+
+    /// returns the JSON Schema type the rule expects the values of its source_paths to have eg.
+    /// `string`, used to describe the input a Transformer reads. The default is that it's unknown.
+    fn source_type(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// returns the destination namespace the rule writes to, used to find the rules writing a
+    /// destination eg. to remove them. The default is that the rule has no single destination.
+    fn destination(&self) -> Option<Vec<Namespace>> {
+        None
+    }
+
+    /// returns the JSON Schema type of the values the rule writes to its destination eg. `string`,
+    /// used to describe the output of a Transformer. The default is that it's unknown.
+    fn destination_type(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// returns the destination namespace the rule overwrites, used to detect rules whose
+    /// destinations conflict. Rules which don't overwrite a single destination, the default,
+    /// return None eg. those only writing absent values or collecting into an Array.
+    fn overwrites(&self) -> Option<Vec<Namespace>> {
+        None
+    }
+
+    /// returns the Mapping reversing the rule, which is applied at the provided source namespace,
+    /// used when inverting a Transformer. Rules which are not bijective, the default, return an
+    /// `Error::NotInvertible` describing why.
+    fn invert(&self, namespace: &[Namespace]) -> Result<Mapping<'static>> {
+        Err(not_invertible(
+            &Namespace::format(namespace),
+            "custom rules can not be inverted",
+        ))
+    }
+
+    /// returns the Mapping the rule was built from, where namespace is the namespace the rule is
+    /// applied at, used to list the mappings of a Transformer eg. for editing them. Custom rules,
+    /// the default, have no Mapping and return None.
+    fn mapping(&self, _namespace: &[Namespace]) -> Result<Option<Mapping<'static>>> {
+        Ok(None)
+    }
+
+    /// returns the names of the registered functions the rule calls, used to check they're all
+    /// registered when building a Transformer. The default is that the rule calls none.
+    fn functions(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    /// returns the names of the environment variables the rule reads, used to check they're all
+    /// allowed when building a Transformer. The default is that the rule reads none.
+    fn env_vars(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    /// returns the Transformers nested within the rule eg. by add_map_array. The default is that
+    /// the rule nests none.
+    fn transformers(&self) -> Vec<&Transformer> {
+        Vec::new()
+    }
 }
 
 #[typetag::serde]
-pub trait StringManipulation: Debug {
+pub trait StringManipulation: Debug + Send + Sync {
     fn apply(&self, input: &str) -> String;
+
+    /// applies the manipulation using the LocaleContext of the mapping or Transformer.
+    /// Manipulations which are not locale sensitive can rely on the default which simply calls
+    /// `apply`.
+    fn apply_with_locale(&self, _locale: &LocaleContext, input: &str) -> String {
+        self.apply(input)
+    }
+}
+
+/// Casing is a built-in StringManipulation changing the case of a String according to the
+/// language of the LocaleContext, eg. the Turkish dotted and dotless `i`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Casing {
+    Upper,
+    Lower,
+}
+
+#[typetag::serde]
+impl StringManipulation for Casing {
+    fn apply(&self, input: &str) -> String {
+        self.apply_with_locale(&LocaleContext::default(), input)
+    }
+
+    fn apply_with_locale(&self, locale: &LocaleContext, input: &str) -> String {
+        let dotted = matches!(locale.language().as_str(), "tr" | "az");
+        match self {
+            Casing::Upper if dotted => input.replace('i', "İ").to_uppercase(),
+            Casing::Upper => input.to_uppercase(),
+            Casing::Lower if dotted => input.replace('I', "ı").replace('İ', "i").to_lowercase(),
+            Casing::Lower => input.to_lowercase(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FlattenOps<'a> {
+    pub recursive: bool,
+    pub prefix: Option<&'a str>,
+    pub separator: Option<&'a str>,
+    /// separator joining Array indexes, defaults to separator when not set.
+    pub array_separator: Option<&'a str>,
+    /// the index of the first element of an Array, defaults to 1.
+    pub index_base: Option<usize>,
+    /// formats Array indexes using a template where `{i}` is replaced by the index eg. `[{i}]`
+    /// for `tags[1]`. The template replaces the array separator.
+    pub index_format: Option<&'a str>,
+    /// the number of levels a recursive flatten descends, leaving structures nested any deeper
+    /// intact. Unlimited when not set.
+    pub max_depth: Option<usize>,
+    #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+    pub manipulations: Vec<Box<dyn StringManipulation>>,
+}
+
+/// SubtreeOps filters the keys of a subtree copied by add_subtree. Keys are given as `.`
+/// separated paths relative to the subtree eg. `db.password`, which apply to every element of the
+/// Arrays along the path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SubtreeOps<'a> {
+    /// when not empty only these keys, and everything below them, are copied.
+    #[serde(borrow)]
+    pub include: Vec<&'a str>,
+    /// keys which are not copied, even when included.
+    #[serde(borrow)]
+    pub exclude: Vec<&'a str>,
+}
+
+/// Type is the expected JSON type of a value, used to validate sources and constants.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Type {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl Type {
+    /// returns if the provided value is of this type.
+    #[inline]
+    pub fn matches(self, value: &Value) -> bool {
+        match self {
+            Type::String => value.is_string(),
+            Type::Integer => value.is_i64() || value.is_u64(),
+            Type::Number => value.is_number(),
+            Type::Boolean => value.is_boolean(),
+            Type::Array => value.is_array(),
+            Type::Object => value.is_object(),
+        }
+    }
+
+    /// returns the empty value of this type.
+    #[inline]
+    pub fn empty(self) -> Value {
+        match self {
+            Type::String => Value::String(String::new()),
+            Type::Integer | Type::Number => Value::from(0),
+            Type::Boolean => Value::Bool(false),
+            Type::Array => Value::Array(Vec::new()),
+            Type::Object => Value::Object(Map::new()),
+        }
+    }
+
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::Integer => "integer",
+            Type::Number => "number",
+            Type::Boolean => "boolean",
+            Type::Array => "array",
+            Type::Object => "object",
+        }
+    }
+}
+
+/// Coerce is the JSON type a source value is converted to eg. `"42"` to `42` or `1` to `true`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Coerce {
+    /// numbers and booleans are written as text.
+    String,
+    /// numeric strings and booleans, as `1` or `0`, are converted to numbers.
+    Number,
+    /// like Number but the value must not have a fractional part.
+    Integer,
+    /// `true`/`false`, `yes`/`no` and `1`/`0`, as strings or numbers, are converted to booleans.
+    Bool,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct FlattenOps<'a> {
-    pub recursive: bool,
-    pub prefix: Option<&'a str>,
-    pub separator: Option<&'a str>,
-    pub manipulation: Option<Box<dyn StringManipulation>>,
-}
+impl Coerce {
+    /// returns the value converted to this type, or None if it can not be converted. `null` is
+    /// always returned as is.
+    pub fn apply(self, value: &Value) -> Option<Value> {
+        match (self, value) {
+            (_, Value::Null) => Some(Value::Null),
+            (Coerce::String, Value::String(_)) => Some(value.clone()),
+            (Coerce::String, Value::Number(_)) | (Coerce::String, Value::Bool(_)) => {
+                Some(Value::String(value.to_string()))
+            }
+            (Coerce::Number, Value::Number(_)) => Some(value.clone()),
+            (Coerce::Integer, Value::Number(n)) if n.is_i64() || n.is_u64() => Some(value.clone()),
+            (Coerce::Integer, Value::Number(n)) => n.as_f64().and_then(integral),
+            (Coerce::Number, Value::Bool(b)) | (Coerce::Integer, Value::Bool(b)) => {
+                Some(Value::from(*b as u8))
+            }
+            (Coerce::Number, Value::String(s)) | (Coerce::Integer, Value::String(s)) => {
+                let s = s.trim();
+                if let Ok(i) = s.parse::<i64>() {
+                    return Some(Value::from(i));
+                }
+                let f = s.parse::<f64>().ok().filter(|f| f.is_finite())?;
+                match self {
+                    Coerce::Integer => integral(f),
+                    _ => serde_json::Number::from_f64(f).map(Value::Number),
+                }
+            }
+            (Coerce::Bool, Value::Bool(_)) => Some(value.clone()),
+            (Coerce::Bool, Value::Number(n)) => match n.as_f64() {
+                Some(0.0) => Some(Value::Bool(false)),
+                Some(1.0) => Some(Value::Bool(true)),
+                _ => None,
+            },
+            (Coerce::Bool, Value::String(s)) => match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(Value::Bool(true)),
+                "false" | "no" | "0" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Coerce::String => "string",
+            Coerce::Number => "number",
+            Coerce::Integer => "integer",
+            Coerce::Bool => "boolean",
+        }
+    }
+}
+
+/// returns the float as an integer Value if it has no fractional part.
+#[inline]
+fn integral(f: f64) -> Option<Value> {
+    if f.fract() == 0.0 && f >= i64::MIN as f64 && f <= i64::MAX as f64 {
+        Some(Value::from(f as i64))
+    } else {
+        None
+    }
+}
+
+/// CoerceFailure defines the behaviour when a source value can not be coerced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CoerceFailure {
+    /// writes a `null` value.
+    #[default]
+    Null,
+    /// returns an `Error::Coercion`.
+    Error,
+}
+
+/// ArrayMerge defines how Arrays are combined when deep merging values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArrayMerge {
+    /// the Array is replaced like any other value.
+    #[default]
+    Replace,
+    /// the elements of the later Array are appended to the earlier Array.
+    Concat,
+    /// elements at the same index are deep merged, with any additional elements appended.
+    Index,
+}
+
+/// Agg is the aggregation computing a single value from the values of a source Array.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Agg {
+    /// the sum of the numbers, an integer when all of them are integers and it does not overflow.
+    Sum,
+    /// the mean of the numbers, always a float.
+    Avg,
+    Min,
+    Max,
+    /// the number of values which are not `null`, of any type.
+    Count,
+}
+
+impl Agg {
+    /// returns the aggregate of the values, None for an Avg, Min or Max of no numbers. Values which
+    /// are not numbers are reported as type mismatches and skipped, except when counting.
+    pub(crate) fn apply(
+        self,
+        ctx: &Context,
+        path: &str,
+        values: &[&Value],
+    ) -> Result<Option<Value>> {
+        let values = values.iter().filter(|v| !v.is_null());
+        if self == Agg::Count {
+            return Ok(Some(Value::from(values.count())));
+        }
+        let mut numbers = Vec::new();
+        for v in values {
+            match v {
+                Value::Number(n) => numbers.push(n),
+                v => ctx.type_mismatch(path, "number", v)?,
+            }
+        }
+        let floats = || numbers.iter().filter_map(|n| n.as_f64());
+        Ok(match self {
+            Agg::Sum => {
+                let sum = numbers
+                    .iter()
+                    .try_fold(0i64, |sum, n| n.as_i64().and_then(|n| sum.checked_add(n)));
+                match sum {
+                    Some(sum) => Some(Value::from(sum)),
+                    None => serde_json::Number::from_f64(floats().sum()).map(Value::Number),
+                }
+            }
+            Agg::Avg if numbers.is_empty() => None,
+            Agg::Avg => serde_json::Number::from_f64(floats().sum::<f64>() / numbers.len() as f64)
+                .map(Value::Number),
+            Agg::Min | Agg::Max => {
+                let mut best: Option<(&serde_json::Number, f64)> = None;
+                for n in &numbers {
+                    let f = match n.as_f64() {
+                        Some(f) => f,
+                        None => continue,
+                    };
+                    best = match best {
+                        Some((_, b))
+                            if (self == Agg::Min && f >= b) || (self == Agg::Max && f <= b) =>
+                        {
+                            best
+                        }
+                        _ => Some((n, f)),
+                    };
+                }
+                best.map(|(n, _)| Value::Number(n.clone()))
+            }
+            Agg::Count => unreachable!(),
+        })
+    }
+}
+
+/// ZipLength defines how source Arrays of different lengths are zipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ZipLength {
+    /// zips only as many elements as the shortest Array has.
+    #[default]
+    Truncate,
+    /// zips as many elements as the longest Array has, padding the others with `null`.
+    Pad,
+}
+
+/// Stringify defines how the non-String elements of an Array are written when joining it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Stringify {
+    /// numbers and booleans are written as text, Objects and Arrays as JSON and nulls are
+    /// skipped.
+    #[default]
+    Text,
+    /// only String elements are joined, all others are skipped.
+    Skip,
+    /// returns an `Error::Coercion` for any element which is not a String.
+    Error,
+}
+
+/// Fallback is what a rule writes when it fails, rather than failing the whole transformation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Fallback {
+    /// writes `null` to the destination.
+    Null,
+    /// writes the constant to the destination.
+    Constant(Value),
+    /// writes nothing.
+    Skip,
+}
+
+///
+/// Mapping is the type of transformation we will be attempting
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Mapping<'a> {
+    Direct {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        kind: Option<Type>,
+        #[serde(default)]
+        only_if_absent: bool,
+        #[serde(default)]
+        default: Option<Value>,
+    },
+    Constant {
+        #[serde(deserialize_with = "constant", serialize_with = "serialize_constant")]
+        from: Value,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        kind: Option<Type>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// the String value of an environment variable, read when applied, or the default when unset.
+    ConstantEnv {
+        var: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        default: Option<Value>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// a value which doesn't come from the source eg. an id or processing timestamp, generated
+    /// for each document, see Generator.
+    Generate {
+        generator: Generator,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Flatten {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        prefix: Option<Cow<'a, str>>,
+        separator: Option<Cow<'a, str>>,
+        #[serde(default)]
+        array_separator: Option<Cow<'a, str>>,
+        #[serde(default)]
+        index_base: Option<usize>,
+        #[serde(default)]
+        index_format: Option<Cow<'a, str>>,
+        #[serde(default)]
+        max_depth: Option<usize>,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
+        recursive: bool,
+        #[serde(default)]
+        only_if_absent: bool,
+        #[serde(default)]
+        locale: Option<LocaleContext>,
+    },
+    FlattenList {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
+        recursive: bool,
+        #[serde(default)]
+        only_if_absent: bool,
+        #[serde(default)]
+        locale: Option<LocaleContext>,
+    },
+    JsonPath {
+        expr: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Concat {
+        from: Vec<Cow<'a, str>>,
+        to: Cow<'a, str>,
+        separator: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Template {
+        template: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Coerce {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        coerce: Coerce,
+        #[serde(default)]
+        on_failure: CoerceFailure,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    MapArray {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        transformer: Box<Transformer>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Duration {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        input: DurationFormat,
+        output: DurationFormat,
+        #[serde(default)]
+        on_failure: CoerceFailure,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Canonical {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// applies the arithmetic ops in order to the numeric source value, see ComputeOp.
+    Compute {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        ops: Vec<ComputeOp>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// anonymizes the source value eg. an email or card number, see Mask.
+    Mask {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        mask: Mask,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// encrypts the source value with the Cipher supplied under the key name when applied, see
+    /// crypto.
+    #[cfg(feature = "crypto")]
+    Encrypt {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        key: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// decrypts a source value encrypted by an Encrypt mapping.
+    #[cfg(feature = "crypto")]
+    Decrypt {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        key: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// calls the function registered under the name with the source value, see FunctionRegistry.
+    Function {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        name: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    When {
+        when: Predicate,
+        mapping: Box<Mapping<'a>>,
+    },
+    /// applies the mapping, writing the fallback instead of failing when it can't be applied eg.
+    /// a value which can't be coerced.
+    OnError {
+        on_error: Fallback,
+        mapping: Box<Mapping<'a>>,
+    },
+    DeriveBool {
+        when: Predicate,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Merge {
+        from: Vec<Cow<'a, str>>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        arrays: ArrayMerge,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Switch {
+        on: Cow<'a, str>,
+        to: Cow<'a, str>,
+        cases: Vec<SwitchCase<'a>>,
+        #[serde(default)]
+        default: Option<SwitchArm<'a>>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Split {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        separator: Cow<'a, str>,
+        /// the index of the part to write, when not set all parts are written as an Array.
+        #[serde(default)]
+        part: Option<usize>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Aggregate {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        agg: Agg,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Zip {
+        from: Vec<Cow<'a, str>>,
+        to: Cow<'a, str>,
+        /// the key each source's elements are written to, in the same order as the sources.
+        keys: Vec<Cow<'a, str>>,
+        #[serde(default)]
+        length: ZipLength,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    Join {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        separator: Cow<'a, str>,
+        #[serde(default)]
+        stringify: Stringify,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+    /// copies the source, applying the manipulation to every Object key at any depth while
+    /// preserving its structure eg. renaming camelCase keys to snake_case.
+    Rekey {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        manipulation: Box<dyn StringManipulation>,
+        #[serde(default)]
+        only_if_absent: bool,
+        #[serde(default)]
+        locale: Option<LocaleContext>,
+    },
+    /// copies the source Object or Array, preserving its structure, keeping only the included keys
+    /// and dropping the excluded ones, see SubtreeOps.
+    Subtree {
+        from: Cow<'a, str>,
+        to: Cow<'a, str>,
+        #[serde(default)]
+        include: Vec<Cow<'a, str>>,
+        #[serde(default)]
+        exclude: Vec<Cow<'a, str>>,
+        #[serde(default)]
+        only_if_absent: bool,
+    },
+}
+
+/// SwitchCase is a single case of a Switch mapping, applied when the inspected value is equal to
+/// `when`. Numbers are compared by their numeric value and `null` also matches a missing value.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SwitchCase<'a> {
+    pub when: Value,
+    pub then: SwitchArm<'a>,
+}
+
+/// SwitchArm is the value written by a case of a Switch mapping.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SwitchArm<'a> {
+    Constant(Value),
+    /// a source namespace, relative to the root of the document, or `$ctx.` value.
+    Source(Cow<'a, str>),
+}
+
+impl<'a> SwitchArm<'a> {
+    #[inline]
+    fn parse(self) -> Result<Arm> {
+        Ok(match self {
+            SwitchArm::Constant(v) => Arm::Constant(v),
+            SwitchArm::Source(from) => Arm::Source(Subject::parse(from)?),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Arm {
+    Constant(Value),
+    Source(Subject),
+}
+
+impl Arm {
+    #[inline]
+    fn value(&self, ctx: &Context, from: &Value) -> Option<Value> {
+        match self {
+            Arm::Constant(v) => Some(v.clone()),
+            Arm::Source(subject) => subject.value(ctx, from).cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Transform {
+    source: Source,
+    destination: Destination,
+    #[serde(default)]
+    kind: Option<Type>,
+    #[serde(default)]
+    only_if_absent: bool,
+    // source namespace of deferred rules, which are applied from the root of the document
+    #[serde(default)]
+    absolute: Option<Vec<Namespace>>,
+    #[serde(default)]
+    default: Option<Value>,
+    #[serde(default)]
+    locale: Option<LocaleContext>,
+    #[serde(default)]
+    split: Option<Split>,
+    #[serde(default)]
+    join: Option<Join>,
+    #[serde(default)]
+    coerce: Option<(Coerce, CoerceFailure)>,
+    #[serde(default)]
+    map: Option<Box<Transformer>>,
+    #[serde(default)]
+    duration: Option<(DurationFormat, DurationFormat, CoerceFailure)>,
+    #[serde(default)]
+    canonical: bool,
+    #[serde(default)]
+    subtree: Option<Subtree>,
+    #[serde(default)]
+    rekey: Option<Box<dyn StringManipulation>>,
+    #[serde(default)]
+    compute: Option<Vec<ComputeOp>>,
+    #[serde(default)]
+    mask: Option<Mask>,
+    #[cfg(feature = "crypto")]
+    #[serde(default)]
+    crypt: Option<(Crypt, String)>,
+    #[serde(default)]
+    function: Option<String>,
+    #[serde(default)]
+    condition: Option<Predicate>,
+    #[serde(default)]
+    on_error: Option<Fallback>,
+}
+
+/// Split splits a String source value by the separator.
+#[derive(Debug, Serialize, Deserialize)]
+struct Split {
+    separator: String,
+    part: Option<usize>,
+}
+
+impl Split {
+    /// returns the parts of the value, or the selected part which is None when out of range.
+    #[inline]
+    fn apply(&self, ctx: &Context, path: &str, value: &Value) -> Result<Option<Value>> {
+        Ok(match value {
+            Value::String(s) => {
+                let mut parts = s.split(self.separator.as_str());
+                match self.part {
+                    Some(index) => parts.nth(index).map(Value::from),
+                    None => Some(Value::Array(parts.map(Value::from).collect())),
+                }
+            }
+            Value::Null => Some(Value::Null),
+            _ => {
+                ctx.type_mismatch(path, "string", value)?;
+                Some(Value::Null)
+            }
+        })
+    }
+}
+
+/// Join joins the elements of an Array source value into a String using the separator.
+#[derive(Debug, Serialize, Deserialize)]
+struct Join {
+    separator: String,
+    stringify: Stringify,
+}
+
+impl Join {
+    #[inline]
+    fn apply(&self, ctx: &Context, path: &str, value: &Value) -> Result<Option<Value>> {
+        let arr = match value {
+            Value::Array(arr) => arr,
+            Value::Null => return Ok(Some(Value::Null)),
+            _ => {
+                ctx.type_mismatch(path, "array", value)?;
+                return Ok(Some(Value::Null));
+            }
+        };
+        let mut parts = Vec::with_capacity(arr.len());
+        for v in arr {
+            match (v, self.stringify) {
+                (Value::String(s), _) => parts.push(s.clone()),
+                (Value::Null, Stringify::Text) | (_, Stringify::Skip) => {}
+                (v, Stringify::Text) => parts.push(v.to_string()),
+                (v, Stringify::Error) => {
+                    return Err(Error::Coercion {
+                        path: path.to_owned(),
+                        expected: "string",
+                        value: v.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(Some(Value::String(parts.join(&self.separator))))
+    }
+}
+
+/// Subtree filters the keys of a copied subtree, keys being split into their path segments.
+#[derive(Debug, Serialize, Deserialize)]
+struct Subtree {
+    include: Vec<Vec<String>>,
+    exclude: Vec<Vec<String>>,
+}
+
+impl Subtree {
+    #[inline]
+    fn new(include: Vec<Cow<str>>, exclude: Vec<Cow<str>>) -> Result<Self> {
+        let split = |keys: Vec<Cow<str>>| {
+            keys.iter()
+                .map(|key| {
+                    if key.is_empty() || key.split('.').any(str::is_empty) {
+                        return Err(Error::InvalidNamespace(format!(
+                            "invalid subtree key {:?}",
+                            key
+                        )));
+                    }
+                    Ok(key.split('.').map(str::to_owned).collect())
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Subtree {
+            include: split(include)?,
+            exclude: split(exclude)?,
+        })
+    }
+
+    #[inline]
+    fn keys(paths: &[Vec<String>]) -> Vec<Cow<'static, str>> {
+        paths
+            .iter()
+            .map(|path| Cow::Owned(path.join(".")))
+            .collect()
+    }
+
+    /// returns the value with only the included, and not excluded, keys.
+    #[inline]
+    fn apply(&self, value: &Value) -> Value {
+        let include = self.include.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let exclude = self.exclude.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        filter_subtree(value, &include, &exclude).unwrap_or_else(|| Value::Object(Map::new()))
+    }
+}
+
+/// returns the value with the manipulation applied to every Object key, at any depth. When keys
+/// are renamed to the same key the last one wins.
+fn rekey(value: &Value, rename: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(m) => Value::Object(
+            m.iter()
+                .map(|(k, v)| (rename(k), rekey(v, rename)))
+                .collect(),
+        ),
+        Value::Array(arr) => Value::Array(arr.iter().map(|v| rekey(v, rename)).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// filters the keys of the value where include, when not empty, and exclude are the remaining
+/// segments of the paths below it. None when nothing below the value is included.
+fn filter_subtree(value: &Value, include: &[&[String]], exclude: &[&[String]]) -> Option<Value> {
+    fn below<'p>(paths: &[&'p [String]], key: &str) -> Vec<&'p [String]> {
+        paths
+            .iter()
+            .filter(|path| path[0] == key && path.len() > 1)
+            .map(|path| &path[1..])
+            .collect()
+    }
+    let is_key = |paths: &[&[String]], key: &str| paths.iter().any(|path| path == &[key]);
+    match value {
+        Value::Object(m) => {
+            let mut filtered = Map::new();
+            for (k, v) in m {
+                if is_key(exclude, k) {
+                    continue;
+                }
+                let include = if include.is_empty() || is_key(include, k) {
+                    Vec::new()
+                } else {
+                    match below(include, k) {
+                        include if include.is_empty() => continue,
+                        include => include,
+                    }
+                };
+                if let Some(v) = filter_subtree(v, &include, &below(exclude, k)) {
+                    filtered.insert(k.clone(), v);
+                }
+            }
+            if filtered.is_empty() && !include.is_empty() {
+                return None;
+            }
+            Some(Value::Object(filtered))
+        }
+        Value::Array(arr) => {
+            let filtered = arr
+                .iter()
+                .filter_map(|v| filter_subtree(v, include, exclude))
+                .collect::<Vec<_>>();
+            if filtered.is_empty() && !include.is_empty() {
+                return None;
+            }
+            Some(Value::Array(filtered))
+        }
+        // the included keys are below a value which has none
+        _ if !include.is_empty() => None,
+        _ => Some(value.clone()),
+    }
+}
+
+#[typetag::serde]
+impl Rule for Transform {
+    fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
+        self.apply_with_context(&Context::default(), from, to)
+    }
+
+    fn invert(&self, namespace: &[Namespace]) -> Result<Mapping<'static>> {
+        let path = join_path(&Namespace::format(namespace), &self.source.path());
+        let not_invertible = |reason: &str| Err(not_invertible(&path, reason));
+        if self.absolute.is_some() {
+            return not_invertible("rules applied to the output can not be inverted");
+        }
+        if self.condition.is_some() {
+            return not_invertible("conditional mappings can not be inverted");
+        }
+        if self.default.is_some() {
+            return not_invertible("mappings with default values can not be inverted");
+        }
+        if self.kind.is_some()
+            || self.split.is_some()
+            || self.join.is_some()
+            || self.coerce.is_some()
+            || self.map.is_some()
+            || self.duration.is_some()
+            || self.canonical
+            || self.subtree.is_some()
+            || self.rekey.is_some()
+            || self.compute.is_some()
+            || self.mask.is_some()
+            || self.function.is_some()
+        {
+            return not_invertible("mappings converting values can not be inverted");
+        }
+        #[cfg(feature = "crypto")]
+        if self.crypt.is_some() {
+            return not_invertible("encrypted mappings can not be inverted");
+        }
+        if namespace.iter().any(|ns| !ns.is_object() && !ns.is_array()) {
+            return not_invertible("wildcard and matched sources can not be inverted");
+        }
+
+        let mut from = namespace.to_vec();
+        match &self.source {
+            Source::Direct(id) => from.push(Namespace::Object { id: id.clone() }),
+            Source::DirectArray { id, index } => from.push(Namespace::Array {
+                id: id.clone(),
+                index: *index,
+            }),
+            Source::Constant(_)
+            | Source::ContextConstant(_)
+            | Source::Env(_)
+            | Source::Generate(_) => return not_invertible("constants have no source"),
+            _ => return not_invertible("only direct mappings can be inverted"),
+        }
+        let to = match &self.destination {
+            Destination::Direct { namespace, id } => {
+                let mut to = namespace.clone();
+                to.push(Namespace::Object { id: id.clone() });
+                to
+            }
+            Destination::DirectArray {
+                namespace,
+                id,
+                index,
+            } => {
+                let mut to = namespace.clone();
+                to.push(Namespace::Array {
+                    id: id.clone(),
+                    index: *index,
+                });
+                to
+            }
+            Destination::DirectWildcard { .. } | Destination::Collect { .. } => {
+                return not_invertible("wildcard destinations can not be inverted")
+            }
+            _ => return not_invertible("flattened destinations can not be inverted"),
+        };
+        Ok(Mapping::Direct {
+            from: Cow::Owned(Namespace::format(&to)),
+            to: Cow::Owned(Namespace::format(&from)),
+            kind: None,
+            only_if_absent: false,
+            default: None,
+        })
+    }
+
+    fn mapping(&self, namespace: &[Namespace]) -> Result<Option<Mapping<'static>>> {
+        let to = Cow::Owned(Namespace::format(&self.destination().unwrap_or_default()));
+        let only_if_absent = self.only_if_absent;
+        let root = self.absolute.as_deref().unwrap_or(namespace);
+        let from = Cow::Owned(match &self.source {
+            Source::Output(namespace) => OUTPUT_PREFIX.to_owned() + &Namespace::format(namespace),
+            Source::Context(namespace) => CONTEXT_PREFIX.to_owned() + &Namespace::format(namespace),
+            Source::Current if root.is_empty() => DOCUMENT.to_owned(),
+            source => join_path(&Namespace::format(root), &source.path()),
+        });
+        let formatted = |sources: &[Vec<Namespace>]| {
+            sources
+                .iter()
+                .map(|namespace| Cow::Owned(Namespace::format(namespace)))
+                .collect()
+        };
+        let arm = |arm: &Arm| match arm {
+            Arm::Constant(v) => SwitchArm::Constant(v.clone()),
+            Arm::Source(subject) => SwitchArm::Source(Cow::Owned(subject.path())),
+        };
+
+        let mapping = match &self.source {
+            Source::Constant(value) => Mapping::Constant {
+                from: value.clone(),
+                to,
+                kind: self.kind,
+                only_if_absent,
+            },
+            Source::JsonPath(path) => Mapping::JsonPath {
+                expr: Cow::Owned(path.expr().to_owned()),
+                to,
+                only_if_absent,
+            },
+            Source::Concat { sources, separator } => Mapping::Concat {
+                from: formatted(sources),
+                to,
+                separator: Cow::Owned(separator.clone()),
+                only_if_absent,
+            },
+            Source::ContextConstant(template) => Mapping::Constant {
+                from: Value::String(template.template().to_owned()),
+                to,
+                kind: self.kind,
+                only_if_absent,
+            },
+            Source::Env(var) => Mapping::ConstantEnv {
+                var: Cow::Owned(var.clone()),
+                to,
+                default: self.default.clone(),
+                only_if_absent,
+            },
+            Source::Generate(generator) => Mapping::Generate {
+                generator: *generator,
+                to,
+                only_if_absent,
+            },
+            Source::Template(template) => Mapping::Template {
+                template: Cow::Owned(template.template().to_owned()),
+                to,
+                only_if_absent,
+            },
+            Source::Predicate(when) => Mapping::DeriveBool {
+                when: when.clone(),
+                to,
+                only_if_absent,
+            },
+            Source::Aggregate { namespace, agg } => Mapping::Aggregate {
+                from: Cow::Owned(Namespace::format(namespace)),
+                to,
+                agg: *agg,
+                only_if_absent,
+            },
+            Source::Merge { sources, arrays } => Mapping::Merge {
+                from: formatted(sources),
+                to,
+                arrays: *arrays,
+                only_if_absent,
+            },
+            Source::Zip {
+                sources,
+                keys,
+                length,
+            } => Mapping::Zip {
+                from: formatted(sources),
+                to,
+                keys: keys.iter().map(|key| Cow::Owned(key.clone())).collect(),
+                length: *length,
+                only_if_absent,
+            },
+            Source::Switch { on, cases, default } => Mapping::Switch {
+                on: Cow::Owned(on.path()),
+                to,
+                cases: cases
+                    .iter()
+                    .map(|(when, then)| SwitchCase {
+                        when: when.clone(),
+                        then: arm(then),
+                    })
+                    .collect(),
+                default: default.as_ref().map(arm),
+                only_if_absent,
+            },
+            _ => match &self.destination {
+                Destination::FlattenDirect {
+                    prefix,
+                    separator,
+                    array_separator,
+                    index_base,
+                    index_format,
+                    max_depth,
+                    manipulations,
+                    recursive,
+                    ..
+                }
+                | Destination::FlattenArray {
+                    prefix,
+                    separator,
+                    array_separator,
+                    index_base,
+                    index_format,
+                    max_depth,
+                    manipulations,
+                    recursive,
+                    ..
+                } => Mapping::Flatten {
+                    from,
+                    to,
+                    prefix: Some(Cow::Owned(prefix.clone())),
+                    separator: Some(Cow::Owned(separator.clone())),
+                    array_separator: array_separator.clone().map(Cow::Owned),
+                    index_base: *index_base,
+                    index_format: index_format.clone().map(Cow::Owned),
+                    max_depth: *max_depth,
+                    manipulations: copy_manipulations(manipulations)?,
+                    recursive: *recursive,
+                    only_if_absent,
+                    locale: self.locale.clone(),
+                },
+                Destination::ListDirect {
+                    manipulations,
+                    recursive,
+                    ..
+                }
+                | Destination::ListArray {
+                    manipulations,
+                    recursive,
+                    ..
+                } => Mapping::FlattenList {
+                    from,
+                    to,
+                    manipulations: copy_manipulations(manipulations)?,
+                    recursive: *recursive,
+                    only_if_absent,
+                    locale: self.locale.clone(),
+                },
+                _ => match self {
+                    Transform {
+                        coerce: Some((coerce, on_failure)),
+                        ..
+                    } => Mapping::Coerce {
+                        from,
+                        to,
+                        coerce: *coerce,
+                        on_failure: *on_failure,
+                        only_if_absent,
+                    },
+                    Transform {
+                        map: Some(transformer),
+                        ..
+                    } => Mapping::MapArray {
+                        from,
+                        to,
+                        transformer: Box::new(transformer.to_builder()?.build()?),
+                        only_if_absent,
+                    },
+                    Transform {
+                        duration: Some((input, output, on_failure)),
+                        ..
+                    } => Mapping::Duration {
+                        from,
+                        to,
+                        input: *input,
+                        output: *output,
+                        on_failure: *on_failure,
+                        only_if_absent,
+                    },
+                    Transform {
+                        split: Some(split), ..
+                    } => Mapping::Split {
+                        from,
+                        to,
+                        separator: Cow::Owned(split.separator.clone()),
+                        part: split.part,
+                        only_if_absent,
+                    },
+                    Transform {
+                        join: Some(join), ..
+                    } => Mapping::Join {
+                        from,
+                        to,
+                        separator: Cow::Owned(join.separator.clone()),
+                        stringify: join.stringify,
+                        only_if_absent,
+                    },
+                    Transform {
+                        rekey: Some(manipulation),
+                        ..
+                    } => Mapping::Rekey {
+                        from,
+                        to,
+                        manipulation: serde_json::from_value(serde_json::to_value(manipulation)?)?,
+                        only_if_absent,
+                        locale: self.locale.clone(),
+                    },
+                    Transform {
+                        subtree: Some(subtree),
+                        ..
+                    } => Mapping::Subtree {
+                        from,
+                        to,
+                        include: Subtree::keys(&subtree.include),
+                        exclude: Subtree::keys(&subtree.exclude),
+                        only_if_absent,
+                    },
+                    Transform {
+                        compute: Some(ops), ..
+                    } => Mapping::Compute {
+                        from,
+                        to,
+                        ops: ops.clone(),
+                        only_if_absent,
+                    },
+                    Transform {
+                        mask: Some(mask), ..
+                    } => Mapping::Mask {
+                        from,
+                        to,
+                        mask: mask.clone(),
+                        only_if_absent,
+                    },
+                    #[cfg(feature = "crypto")]
+                    Transform {
+                        crypt: Some((crypt, key)),
+                        ..
+                    } => {
+                        let key = Cow::Owned(key.clone());
+                        match crypt {
+                            Crypt::Encrypt => Mapping::Encrypt {
+                                from,
+                                to,
+                                key,
+                                only_if_absent,
+                            },
+                            Crypt::Decrypt => Mapping::Decrypt {
+                                from,
+                                to,
+                                key,
+                                only_if_absent,
+                            },
+                        }
+                    }
+                    Transform {
+                        function: Some(name),
+                        ..
+                    } => Mapping::Function {
+                        from,
+                        to,
+                        name: Cow::Owned(name.clone()),
+                        only_if_absent,
+                    },
+                    Transform {
+                        canonical: true, ..
+                    } => Mapping::Canonical {
+                        from,
+                        to,
+                        only_if_absent,
+                    },
+                    _ => Mapping::Direct {
+                        from,
+                        to,
+                        kind: self.kind,
+                        only_if_absent,
+                        default: self.default.clone(),
+                    },
+                },
+            },
+        };
+        let mapping = match &self.on_error {
+            Some(on_error) => Mapping::OnError {
+                on_error: on_error.clone(),
+                mapping: Box::new(mapping),
+            },
+            None => mapping,
+        };
+        Ok(Some(match &self.condition {
+            Some(when) => Mapping::When {
+                when: when.clone(),
+                mapping: Box::new(mapping),
+            },
+            None => mapping,
+        }))
+    }
+
+    fn functions(&self) -> Vec<&str> {
+        let mut names = self.function.iter().map(String::as_str).collect::<Vec<_>>();
+        if let Some(transformer) = &self.map {
+            names.extend(transformer.unregistered_functions());
+        }
+        names
+    }
+
+    fn env_vars(&self) -> Vec<&str> {
+        let mut vars = match &self.source {
+            Source::Env(var) => vec![var.as_str()],
+            _ => Vec::new(),
+        };
+        if let Some(transformer) = &self.map {
+            vars.extend(transformer.disallowed_env());
+        }
+        vars
+    }
+
+    fn transformers(&self) -> Vec<&Transformer> {
+        self.map.iter().map(|transformer| &**transformer).collect()
+    }
+
+    fn source_paths(&self, namespace: &[Namespace]) -> Vec<Vec<Namespace>> {
+        let mut path = match &self.absolute {
+            Some(absolute) => absolute.clone(),
+            None => namespace.to_vec(),
+        };
+        match &self.source {
+            Source::Direct(id) => path.push(Namespace::Object { id: id.clone() }),
+            Source::DirectArray { id, index } => path.push(Namespace::Array {
+                id: id.clone(),
+                index: *index,
+            }),
+            Source::Current => {}
+            Source::Concat { sources, .. }
+            | Source::Merge { sources, .. }
+            | Source::Zip { sources, .. } => return sources.clone(),
+            Source::Aggregate { namespace, .. } => return vec![namespace.clone()],
+            Source::Switch { on, cases, default } => {
+                return std::iter::once(on)
+                    .chain(
+                        cases.iter().map(|(_, arm)| arm).chain(default).filter_map(
+                            |arm| match arm {
+                                Arm::Source(subject) => Some(subject),
+                                Arm::Constant(_) => None,
+                            },
+                        ),
+                    )
+                    .filter_map(|subject| match subject {
+                        Subject::Document(namespace) => Some(namespace.clone()),
+                        Subject::Context(_) => None,
+                    })
+                    .collect()
+            }
+            Source::Constant(_)
+            | Source::ContextConstant(_)
+            | Source::Env(_)
+            | Source::Generate(_)
+            | Source::Output(_)
+            | Source::Context(_)
+            | Source::JsonPath(_)
+            | Source::Template(_)
+            | Source::Predicate(_) => return Vec::new(),
+        }
+        vec![path]
+    }
+
+    fn overwrites(&self) -> Option<Vec<Namespace>> {
+        match &self.destination {
+            _ if self.only_if_absent => None,
+            // merging into the parent Object or appending to an Array
+            Destination::FlattenDirect { id: None, .. } | Destination::Collect { .. } => None,
+            _ => self.destination(),
+        }
+    }
+
+    fn destination(&self) -> Option<Vec<Namespace>> {
+        let (namespace, last) = match &self.destination {
+            Destination::Direct { namespace, id }
+            | Destination::FlattenDirect {
+                namespace,
+                id: Some(id),
+                ..
+            }
+            | Destination::ListDirect { namespace, id, .. } => {
+                (namespace, Namespace::Object { id: id.clone() })
+            }
+            Destination::DirectArray {
+                namespace,
+                id,
+                index,
+            }
+            | Destination::FlattenArray {
+                namespace,
+                id,
+                index,
+                ..
+            }
+            | Destination::ListArray {
+                namespace,
+                id,
+                index,
+                ..
+            } => (
+                namespace,
+                Namespace::Array {
+                    id: id.clone(),
+                    index: *index,
+                },
+            ),
+            Destination::DirectWildcard { namespace, id, .. } => {
+                (namespace, Namespace::Wildcard { id: id.clone() })
+            }
+            Destination::Collect { namespace, id } => {
+                (namespace, Namespace::Object { id: id.clone() })
+            }
+            Destination::FlattenDirect { namespace, .. } => return Some(namespace.clone()),
+        };
+        let mut destination = namespace.clone();
+        destination.push(last);
+        Some(destination)
+    }
+
+    fn destination_type(&self) -> Option<&'static str> {
+        match &self.destination {
+            Destination::FlattenDirect { .. } | Destination::FlattenArray { .. } => {
+                return Some(Type::Object.name())
+            }
+            Destination::ListDirect { .. }
+            | Destination::ListArray { .. }
+            | Destination::Collect { .. } => return Some(Type::Array.name()),
+            _ => {}
+        }
+        if let Some(kind) = self.kind {
+            return Some(kind.name());
+        }
+        #[cfg(feature = "crypto")]
+        match self.crypt {
+            Some((Crypt::Encrypt, _)) => return Some(Type::String.name()),
+            Some((Crypt::Decrypt, _)) => return None,
+            None => {}
+        }
+        Some(
+            if self.canonical || self.join.is_some() || self.mask.is_some() {
+                Type::String.name()
+            } else if let Some(split) = &self.split {
+                match split.part {
+                    Some(_) => Type::String.name(),
+                    None => Type::Array.name(),
+                }
+            } else if self.map.is_some() {
+                Type::Array.name()
+            } else if let Some((coerce, _)) = self.coerce {
+                coerce.name()
+            } else if let Some((_, output, _)) = self.duration {
+                match output {
+                    DurationFormat::Seconds | DurationFormat::Milliseconds => Type::Number.name(),
+                    DurationFormat::Iso8601 | DurationFormat::Human => Type::String.name(),
+                }
+            } else if self.compute.is_some() {
+                Type::Number.name()
+            } else if self.function.is_some() {
+                return None;
+            } else {
+                match &self.source {
+                    Source::Constant(v) if !v.is_null() => value_type(v),
+                    Source::Generate(Generator::Sequence { .. }) => Type::Integer.name(),
+                    Source::Template(_)
+                    | Source::Concat { .. }
+                    | Source::Env(_)
+                    | Source::Generate(_) => Type::String.name(),
+                    Source::Predicate(_) => Type::Boolean.name(),
+                    Source::Merge { .. } => Type::Object.name(),
+                    Source::Zip { .. } => Type::Array.name(),
+                    Source::Aggregate {
+                        agg: Agg::Count, ..
+                    } => Type::Integer.name(),
+                    Source::Aggregate { .. } => Type::Number.name(),
+                    _ => return None,
+                }
+            },
+        )
+    }
+
+    fn source_type(&self) -> Option<&'static str> {
+        match &self.source {
+            Source::Direct(_) | Source::DirectArray { .. } | Source::Current => {}
+            _ => return None,
+        }
+        if let Some(kind) = self.kind {
+            return Some(kind.name());
+        }
+        if self.split.is_some() {
+            Some(Type::String.name())
+        } else if self.join.is_some() || self.map.is_some() {
+            Some(Type::Array.name())
+        } else {
+            None
+        }
+    }
+
+    fn is_recursive(&self) -> bool {
+        match self.destination {
+            Destination::FlattenDirect { recursive, .. }
+            | Destination::FlattenArray { recursive, .. }
+            | Destination::ListDirect { recursive, .. }
+            | Destination::ListArray { recursive, .. } => recursive,
+            _ => false,
+        }
+    }
 
-///
-/// Mapping is the type of transformation we will be attempting
-///
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Mapping<'a> {
-    Direct {
-        from: Cow<'a, str>,
-        to: Cow<'a, str>,
-    },
-    Constant {
-        from: Value,
-        to: Cow<'a, str>,
-    },
-    Flatten {
-        from: Cow<'a, str>,
-        to: Cow<'a, str>,
-        prefix: Option<Cow<'a, str>>,
-        separator: Option<Cow<'a, str>>,
-        manipulation: Option<Box<dyn StringManipulation>>,
-        recursive: bool,
-    },
-}
+    fn apply_with_context(
+        &self,
+        ctx: &Context,
+        from: &Value,
+        to: &mut Map<String, Value>,
+    ) -> Result<()> {
+        match (self.transform(ctx, from, to), &self.on_error) {
+            (Err(_), Some(Fallback::Skip)) => Ok(()),
+            (Err(_), Some(Fallback::Null)) => self.write(ctx, Value::Null, to),
+            (Err(_), Some(Fallback::Constant(v))) => self.write(ctx, v.clone(), to),
+            (res, _) => res,
+        }
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct Transform {
-    source: Source,
-    destination: Destination,
+    fn apply_no_elements(&self, ctx: &Context, to: &mut Map<String, Value>) -> Result<()> {
+        // the destination is only known when it's outside of the Array with no elements
+        if let Destination::Collect { namespace, id } = &self.destination {
+            if count_wildcards(namespace) <= ctx.depth() {
+                get_last(ctx, namespace, to)
+                    .entry(id.clone())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+            }
+        }
+        Ok(())
+    }
 }
 
-#[typetag::serde]
-impl Rule for Transform {
-    fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
-        let field = match &self.source {
-            Source::Direct(id) => match from {
-                Value::Object(obj) => obj.get(id).unwrap_or(&Value::Null).clone(),
-                _ => Value::Null,
-            },
-            Source::DirectArray { id, index } => match from {
-                Value::Object(v) => match v.get(id) {
-                    Some(arr) => arr.get(index).unwrap_or(&Value::Null).clone(),
-                    _ => Value::Null,
+impl Transform {
+    /// applies the transform, writing the transformed source value to the destination.
+    fn transform(&self, ctx: &Context, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
+        if let Some(condition) = &self.condition {
+            if !condition.evaluate(ctx, from) {
+                return Ok(());
+            }
+        }
+        let from = match &self.absolute {
+            Some(namespace) => lookup(namespace, from),
+            None => Some(from),
+        };
+        // a missing source parent is a missing source, so defaults and the MissingPolicy apply
+        let mut field = match from {
+            Some(from) => self.source_value(ctx, from, to)?,
+            None => None,
+        };
+        if !matches!(
+            self.source,
+            Source::Constant(_) | Source::ContextConstant(_) | Source::Generate(_)
+        ) {
+            ctx.record_source(|| self.missing_path(), field.is_some());
+        }
+        if let (Some(split), Some(v)) = (&self.split, &field) {
+            field = split.apply(ctx, &self.source.path(), v)?;
+        }
+        if let (Some(join), Some(v)) = (&self.join, &field) {
+            field = join.apply(ctx, &self.source.path(), v)?;
+        }
+        if let (Some(transformer), Some(v)) = (&self.map, &field) {
+            field = match v {
+                Value::Array(arr) => Some(Value::Array(
+                    arr.iter()
+                        .map(|element| transformer.transform_nested(ctx, element))
+                        .collect::<Result<_>>()?,
+                )),
+                Value::Null => Some(Value::Null),
+                _ => {
+                    ctx.type_mismatch(&self.source.path(), "array", v)?;
+                    Some(Value::Null)
+                }
+            };
+        }
+        if let (Some((coerce, on_failure)), Some(v)) = (self.coerce, &field) {
+            field = match (coerce.apply(v), on_failure) {
+                (Some(v), _) => Some(v),
+                (None, CoerceFailure::Null) => Some(Value::Null),
+                (None, CoerceFailure::Error) => {
+                    return Err(Error::Coercion {
+                        path: self.source.path(),
+                        expected: coerce.name(),
+                        value: v.to_string(),
+                    })
+                }
+            };
+        }
+        if let (Some((input, output, on_failure)), Some(v)) = (self.duration, &field) {
+            if !v.is_null() {
+                field = match (input.convert(output, v), on_failure) {
+                    (Some(v), _) => Some(v),
+                    (None, CoerceFailure::Null) => Some(Value::Null),
+                    (None, CoerceFailure::Error) => {
+                        return Err(Error::Coercion {
+                            path: self.source.path(),
+                            expected: output.name(),
+                            value: v.to_string(),
+                        })
+                    }
+                };
+            }
+        }
+        if let (true, Some(v)) = (self.canonical, &field) {
+            if !v.is_null() {
+                field = Some(Value::String(output::canonical(v)));
+            }
+        }
+        if let (Some(manipulation), Some(v)) = (&self.rekey, &field) {
+            let locale = self.locale.as_ref().unwrap_or_else(|| ctx.locale());
+            field = Some(rekey(v, &|key| manipulation.apply_with_locale(locale, key)));
+        }
+        if let (Some(subtree), Some(v)) = (&self.subtree, &field) {
+            if !v.is_null() {
+                field = Some(subtree.apply(v));
+            }
+        }
+        if let (Some(ops), Some(v)) = (&self.compute, &field) {
+            field = match v {
+                Value::Number(n) => match compute::compute(ops, n) {
+                    Some(v) => Some(v),
+                    None => {
+                        return Err(Error::Coercion {
+                            path: self.source.path(),
+                            expected: "finite number",
+                            value: v.to_string(),
+                        })
+                    }
                 },
-                Value::Array(v) => v.get(*index).unwrap_or(&Value::Null).clone(),
-                _ => Value::Null,
-            },
-            Source::Constant(v) => v.clone(),
+                Value::Null => Some(Value::Null),
+                _ => {
+                    ctx.type_mismatch(&self.source.path(), "number", v)?;
+                    Some(Value::Null)
+                }
+            };
+        }
+        if let (Some(mask), Some(v)) = (&self.mask, &field) {
+            field = Some(mask.apply(v));
+        }
+        #[cfg(feature = "crypto")]
+        if let (Some((crypt, key)), Some(v)) = (&self.crypt, &field) {
+            let cipher = ctx.key(key).ok_or_else(|| Error::UnknownKey(key.clone()))?;
+            field = Some(crypt.apply(cipher, &self.source.path(), v)?);
+        }
+        if let (Some(name), Some(v)) = (&self.function, &field) {
+            if !v.is_null() {
+                let f = ctx
+                    .function(name)
+                    .ok_or_else(|| Error::UnknownFunction(name.clone()))?;
+                field = Some(f(v)?);
+            }
+        }
+        if let (Some(kind), Some(v)) = (self.kind, &field) {
+            if !v.is_null() && !kind.matches(v) {
+                ctx.type_mismatch(&self.source.path(), kind.name(), v)?;
+                field = Some(Value::Null);
+            }
+        }
+        let field = match field {
+            Some(v) if !v.is_null() => v,
+            field => {
+                if let Some(default) = &self.default {
+                    default.clone()
+                } else if let (Some(kind), Missing::Empty) = (self.kind, ctx.missing()) {
+                    kind.empty()
+                } else if field.is_some() {
+                    Value::Null
+                } else {
+                    match ctx.missing_policy() {
+                        MissingPolicy::Null => Value::Null,
+                        MissingPolicy::Skip => return Ok(()),
+                        MissingPolicy::Error => {
+                            return Err(Error::MissingSource(self.missing_path()))
+                        }
+                    }
+                }
+            }
         };
+        if self.only_if_absent && (field.is_null() || self.destination.is_written(ctx, to)) {
+            return Ok(());
+        }
+        self.write(ctx, field, to)
+    }
+
+    /// writes the value to the destination.
+    fn write(&self, ctx: &Context, field: Value, to: &mut Map<String, Value>) -> Result<()> {
+        let locale = self.locale.as_ref().unwrap_or_else(|| ctx.locale());
         match &self.destination {
             Destination::Direct { id, namespace } => {
-                get_last(namespace, to).insert(id.clone(), field);
+                get_last(ctx, namespace, to).insert(id.clone(), field);
             }
             Destination::DirectArray {
                 id,
                 namespace,
                 index,
             } => {
-                let current = get_last(namespace, to);
+                insert_array(get_last(ctx, namespace, to), id, *index, field);
+            }
+            Destination::Collect { id, namespace } => {
+                let current = get_last(ctx, namespace, to);
                 match current.get_mut(id) {
-                    Some(v) => {
-                        if let Some(arr) = v.as_array_mut() {
-                            if *index >= arr.len() {
-                                arr.resize_with(*index + 1, Value::default);
-                            }
-                            arr[*index] = field;
-                        }
-                    }
+                    Some(Value::Array(arr)) => arr.push(field),
                     _ => {
-                        let mut new_arr = vec![Value::Null; *index];
-                        new_arr.push(field);
-                        current.insert(id.clone(), Value::Array(new_arr));
+                        current.insert(id.clone(), Value::Array(vec![field]));
                     }
                 }
             }
+            Destination::DirectWildcard {
+                id,
+                namespace,
+                level,
+            } => {
+                let index = ctx.index(*level).unwrap_or_default();
+                insert_array(get_last(ctx, namespace, to), id, index, field);
+            }
+            Destination::ListDirect {
+                id,
+                namespace,
+                manipulations,
+                recursive,
+            } => {
+                let list = flatten_list(
+                    localize(manipulations, locale).as_deref(),
+                    &field,
+                    *recursive,
+                );
+                get_last(ctx, namespace, to).insert(id.clone(), list);
+            }
+            Destination::ListArray {
+                id,
+                namespace,
+                index,
+                manipulations,
+                recursive,
+            } => {
+                let list = flatten_list(
+                    localize(manipulations, locale).as_deref(),
+                    &field,
+                    *recursive,
+                );
+                insert_array(get_last(ctx, namespace, to), id, *index, list);
+            }
             Destination::FlattenDirect {
                 id,
                 namespace,
                 recursive,
                 prefix,
-                manipulation,
+                manipulations,
                 separator,
-            } => match id {
-                Some(id) => {
-                    let mut m = Map::new();
-                    flatten(
-                        &manipulation,
-                        &separator,
-                        &prefix,
-                        &field,
-                        &mut m,
-                        *recursive,
-                    );
-                    get_last(namespace, to).insert(id.clone(), Value::Object(m));
-                }
-                None => {
-                    flatten(
-                        &manipulation,
-                        &separator,
-                        &prefix,
-                        &field,
-                        get_last(namespace, to),
-                        *recursive,
-                    );
+                array_separator,
+                index_base,
+                index_format,
+                max_depth,
+            } => {
+                let keys = ctx
+                    .index_keys(array_separator.as_deref().unwrap_or(separator))
+                    .with_index(*index_base, index_format.as_deref());
+                // the levels flattened, a single level unless recursive
+                let depth = if *recursive {
+                    max_depth.unwrap_or(usize::MAX)
+                } else {
+                    1
+                };
+                match id {
+                    Some(id) => {
+                        let mut m = Map::new();
+                        flatten(
+                            localize(manipulations, locale).as_deref(),
+                            separator,
+                            &keys,
+                            prefix,
+                            &field,
+                            &mut m,
+                            depth,
+                        );
+                        get_last(ctx, namespace, to).insert(id.clone(), Value::Object(m));
+                    }
+                    None if self.only_if_absent => {
+                        // merging into an existing Object so only the absent keys are written
+                        let mut m = Map::new();
+                        flatten(
+                            localize(manipulations, locale).as_deref(),
+                            separator,
+                            &keys,
+                            prefix,
+                            &field,
+                            &mut m,
+                            depth,
+                        );
+                        let current = get_last(ctx, namespace, to);
+                        for (k, v) in m {
+                            current.entry(k).or_insert(v);
+                        }
+                    }
+                    None => {
+                        flatten(
+                            localize(manipulations, locale).as_deref(),
+                            separator,
+                            &keys,
+                            prefix,
+                            &field,
+                            get_last(ctx, namespace, to),
+                            depth,
+                        );
+                    }
                 }
-            },
+            }
             Destination::FlattenArray {
                 id,
                 namespace,
                 prefix,
-                manipulation,
+                manipulations,
                 index,
                 recursive,
                 separator,
+                array_separator,
+                index_base,
+                index_format,
+                max_depth,
             } => {
-                let current = get_last(namespace, to);
+                let keys = ctx
+                    .index_keys(array_separator.as_deref().unwrap_or(separator))
+                    .with_index(*index_base, index_format.as_deref());
+                // the levels flattened, a single level unless recursive
+                let depth = if *recursive {
+                    max_depth.unwrap_or(usize::MAX)
+                } else {
+                    1
+                };
+                let current = get_last(ctx, namespace, to);
                 match current.get_mut(id) {
                     Some(v) => {
                         if let Some(arr) = v.as_array_mut() {
@@ -145,12 +1913,13 @@ impl Rule for Transform {
                             }
                             let mut m = Map::new();
                             flatten(
-                                &manipulation,
-                                &separator,
-                                &prefix,
+                                localize(manipulations, locale).as_deref(),
+                                separator,
+                                &keys,
+                                prefix,
                                 &field,
                                 &mut m,
-                                *recursive,
+                                depth,
                             );
                             arr[*index] = Value::Object(m);
                         }
@@ -158,12 +1927,13 @@ impl Rule for Transform {
                     _ => {
                         let mut m = Map::new();
                         flatten(
-                            &manipulation,
-                            &separator,
-                            &prefix,
+                            localize(manipulations, locale).as_deref(),
+                            separator,
+                            &keys,
+                            prefix,
                             &field,
                             &mut m,
-                            *recursive,
+                            depth,
                         );
                         let mut new_arr = vec![Value::Null; *index];
                         new_arr.push(Value::Object(m));
@@ -172,17 +1942,356 @@ impl Rule for Transform {
                 }
             }
         }
-        Ok(())
+        Ok(())
+    }
+}
+
+/// Fallible applies a custom rule, writing the fallback to the rule's destination instead of
+/// failing the whole transformation when the rule fails. Nothing is written for rules without a
+/// single destination.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fallible {
+    rule: Box<dyn Rule>,
+    on_error: Fallback,
+}
+
+impl Fallible {
+    #[inline]
+    pub fn new<R>(rule: R, on_error: Fallback) -> Self
+    where
+        R: Rule + 'static,
+    {
+        Fallible {
+            rule: Box::new(rule),
+            on_error,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Rule for Fallible {
+    fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
+        self.apply_with_context(&Context::default(), from, to)
+    }
+
+    fn apply_with_context(
+        &self,
+        ctx: &Context,
+        from: &Value,
+        to: &mut Map<String, Value>,
+    ) -> Result<()> {
+        let field = match (self.rule.apply_with_context(ctx, from, to), &self.on_error) {
+            (Ok(()), _) | (Err(_), Fallback::Skip) => return Ok(()),
+            (Err(_), Fallback::Null) => Value::Null,
+            (Err(_), Fallback::Constant(v)) => v.clone(),
+        };
+        let mut namespace = self.rule.destination().unwrap_or_default();
+        match namespace.pop() {
+            Some(Namespace::Object { id }) => {
+                get_last(ctx, &namespace, to).insert(id, field);
+            }
+            Some(Namespace::Array { id, index }) => {
+                insert_array(get_last(ctx, &namespace, to), &id, index, field);
+            }
+            Some(Namespace::Wildcard { id }) => {
+                let index = ctx.index(count_wildcards(&namespace)).unwrap_or_default();
+                insert_array(get_last(ctx, &namespace, to), &id, index, field);
+            }
+            Some(Namespace::Match { .. }) | None => {}
+        }
+        Ok(())
+    }
+
+    fn apply_no_elements(&self, ctx: &Context, to: &mut Map<String, Value>) -> Result<()> {
+        self.rule.apply_no_elements(ctx, to)
+    }
+
+    fn is_recursive(&self) -> bool {
+        self.rule.is_recursive()
+    }
+
+    fn source_paths(&self, namespace: &[Namespace]) -> Vec<Vec<Namespace>> {
+        self.rule.source_paths(namespace)
+    }
+
+    fn source_type(&self) -> Option<&'static str> {
+        self.rule.source_type()
+    }
+
+    fn destination_type(&self) -> Option<&'static str> {
+        self.rule.destination_type()
+    }
+
+    fn destination(&self) -> Option<Vec<Namespace>> {
+        self.rule.destination()
+    }
+
+    fn overwrites(&self) -> Option<Vec<Namespace>> {
+        self.rule.overwrites()
+    }
+
+    fn functions(&self) -> Vec<&str> {
+        self.rule.functions()
+    }
+
+    fn env_vars(&self) -> Vec<&str> {
+        self.rule.env_vars()
+    }
+
+    fn transformers(&self) -> Vec<&Transformer> {
+        self.rule.transformers()
+    }
+}
+
+type RuleFn = Arc<dyn Fn(&Value, &mut Map<String, Value>) -> Result<()> + Send + Sync>;
+
+/// FnRule is a custom rule applying a closure, for one-off transformations which don't warrant a
+/// typetag registered Rule. Closures can not be serialized, so serializing a Transformer, or
+/// anything relying on it eg. Transformer::to_builder, containing a FnRule returns an error.
+#[derive(Clone)]
+pub struct FnRule {
+    f: RuleFn,
+}
+
+impl FnRule {
+    #[inline]
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Value, &mut Map<String, Value>) -> Result<()> + Send + Sync + 'static,
+    {
+        FnRule { f: Arc::new(f) }
+    }
+}
+
+impl Debug for FnRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FnRule")
+    }
+}
+
+impl Serialize for FnRule {
+    fn serialize<S>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Err(ser::Error::custom("closure rules can not be serialized"))
+    }
+}
+
+impl<'de> Deserialize<'de> for FnRule {
+    fn deserialize<D>(_deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Err(de::Error::custom("closure rules can not be deserialized"))
+    }
+}
+
+#[typetag::serde]
+impl Rule for FnRule {
+    fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
+        (self.f)(from, to)
+    }
+}
+
+/// a StringManipulation bound to the LocaleContext it's applied with.
+type Localized<'a> = Box<dyn Fn(&str) -> String + 'a>;
+
+/// the tags of typed constants, see constant.
+const CONSTANT_TAGS: [&str; 7] = [
+    "$int", "$float", "$bool", "$string", "$null", "$object", "$array",
+];
+
+/// returns the tag and tagged value when the constant is a typed constant, see constant.
+#[inline]
+fn constant_tag(value: &Value) -> Option<(&str, &Value)> {
+    match value {
+        Value::Object(m) if m.len() == 1 => m
+            .iter()
+            .next()
+            .filter(|(k, _)| CONSTANT_TAGS.contains(&k.as_str()))
+            .map(|(k, v)| (&k[1..], v)),
+        _ => None,
+    }
+}
+
+/// deserializes a constant, resolving the tagged representation of typed constants used by spec
+/// authoring tools that only produce Strings eg. `{"$bool":"true"}` or `{"$int":"42"}`. The tags
+/// are `$int`, `$float`, `$bool`, `$string`, `$null`, `$object` and `$array`; an Object constant
+/// whose single key is one of them must itself be tagged eg. `{"$object":{"$int":1}}`. Other
+/// Objects, including those with `$` prefixed keys such as `{"$ref":"x"}`, are constants as is.
+fn constant<'de, D>(deserializer: D) -> std::result::Result<Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    let (tag, v) = match constant_tag(&value) {
+        Some(tagged) => tagged,
+        None => return Ok(value),
+    };
+    let typed = match (tag, v) {
+        ("null", _) => Some(Value::Null),
+        ("bool", v) => Coerce::Bool.apply(v),
+        ("int", v) => Coerce::Integer.apply(v),
+        ("float", Value::Number(n)) => n.as_f64().map(Value::from),
+        ("float", Value::String(s)) => s.trim().parse::<f64>().ok().map(Value::from),
+        ("string", Value::String(_)) => Some(v.clone()),
+        ("string", v) => Some(Value::String(v.to_string())),
+        ("object", Value::Object(_)) | ("array", Value::Array(_)) => Some(v.clone()),
+        ("object", Value::String(s)) => serde_json::from_str(s).ok().filter(Value::is_object),
+        ("array", Value::String(s)) => serde_json::from_str(s).ok().filter(Value::is_array),
+        _ => None,
+    };
+    typed
+        .filter(|typed| tag == "null" || !typed.is_null())
+        .ok_or_else(|| de::Error::custom(format!("invalid typed constant {}", value)))
+}
+
+/// serializes a constant, tagging Objects which would otherwise be read back as a typed constant,
+/// see constant.
+fn serialize_constant<S>(value: &Value, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match constant_tag(value) {
+        Some(_) => {
+            let mut tagged = Map::new();
+            tagged.insert(String::from("$object"), value.clone());
+            tagged.serialize(serializer)
+        }
+        None => value.serialize(serializer),
+    }
+}
+
+/// chains the manipulations, in order, so that they're applied using the provided LocaleContext.
+#[inline]
+fn localize<'a>(
+    manipulations: &'a [Box<dyn StringManipulation>],
+    locale: &'a LocaleContext,
+) -> Option<Localized<'a>> {
+    match manipulations {
+        [] => None,
+        [m] => Some(Box::new(move |s: &str| m.apply_with_locale(locale, s))),
+        _ => Some(Box::new(move |s: &str| {
+            manipulations
+                .iter()
+                .fold(s.to_owned(), |s, m| m.apply_with_locale(locale, &s))
+        })),
+    }
+}
+
+/// deserializes a list of manipulations, also accepting the single optional manipulation of
+/// previously serialized mappings.
+fn manipulations<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<Box<dyn StringManipulation>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let manipulation = |v| serde_json::from_value(v).map_err(de::Error::custom);
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(arr) => arr.into_iter().map(manipulation).collect(),
+        v => Ok(vec![manipulation(v)?]),
+    }
+}
+
+/// copies the manipulations of a rule by round tripping them through their serialized form.
+#[inline]
+fn copy_manipulations(
+    manipulations: &[Box<dyn StringManipulation>],
+) -> Result<Vec<Box<dyn StringManipulation>>> {
+    Ok(self::manipulations(serde_json::to_value(manipulations)?)?)
+}
+
+#[inline]
+fn insert_array(current: &mut Map<String, Value>, id: &str, index: usize, field: Value) {
+    match current.get_mut(id) {
+        Some(v) => {
+            if let Some(arr) = v.as_array_mut() {
+                if index >= arr.len() {
+                    arr.resize_with(index + 1, Value::default);
+                }
+                arr[index] = field;
+            }
+        }
+        _ => {
+            let mut new_arr = vec![Value::Null; index];
+            new_arr.push(field);
+            current.insert(id.to_owned(), Value::Array(new_arr));
+        }
+    }
+}
+
+#[inline]
+fn flatten_list(
+    manipulation: Option<&dyn Fn(&str) -> String>,
+    from: &Value,
+    recursive: bool,
+) -> Value {
+    match from {
+        Value::Null => Value::Null,
+        _ => {
+            let mut list = Vec::new();
+            flatten_list_recursive(manipulation, from, &mut list, recursive);
+            Value::Array(list)
+        }
+    }
+}
+
+fn flatten_list_recursive(
+    manipulation: Option<&dyn Fn(&str) -> String>,
+    from: &Value,
+    to: &mut Vec<Value>,
+    recursive: bool,
+) {
+    match from {
+        Value::Array(arr) => {
+            for v in arr {
+                flatten_list_element(manipulation, v, to, recursive);
+            }
+        }
+        Value::Object(m) => {
+            for v in m.values() {
+                flatten_list_element(manipulation, v, to, recursive);
+            }
+        }
+        _ => flatten_list_element(manipulation, from, to, recursive),
+    }
+}
+
+#[inline]
+fn flatten_list_element(
+    manipulation: Option<&dyn Fn(&str) -> String>,
+    v: &Value,
+    to: &mut Vec<Value>,
+    recursive: bool,
+) {
+    match (v, manipulation) {
+        (Value::Object(_), _) | (Value::Array(_), _) if recursive => {
+            flatten_list_recursive(manipulation, v, to, recursive)
+        }
+        (Value::String(s), Some(man)) => to.push(Value::String(man(s))),
+        _ => to.push(v.clone()),
     }
 }
 
 #[inline]
-fn flatten_recursive_no_id(sep: &str, id: &str, from: &Value, to: &mut Map<String, Value>) {
+fn flatten_recursive_no_id(
+    sep: &str,
+    keys: &IndexKeys,
+    id: &str,
+    from: &Value,
+    to: &mut Map<String, Value>,
+    depth: usize,
+) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id(sep, k, v, to),
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id(sep, keys, k, v, to, depth - 1)
+                    }
                     _ => {
                         to.insert(k.clone(), v.clone());
                     }
@@ -192,11 +2301,11 @@ fn flatten_recursive_no_id(sep: &str, id: &str, from: &Value, to: &mut Map<Strin
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
                 match v {
-                    Value::Object(_) | Value::Array(_) => {
-                        flatten_recursive_with_id(sep, &(i + 1).to_string(), v, to)
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id(sep, keys, &keys.index(i), v, to, depth - 1)
                     }
                     _ => {
-                        to.insert((i + 1).to_string(), v.clone());
+                        to.insert(keys.index(i), v.clone());
                     }
                 };
             }
@@ -209,25 +2318,31 @@ fn flatten_recursive_no_id(sep: &str, id: &str, from: &Value, to: &mut Map<Strin
 
 #[inline]
 fn flatten_recursive_no_id_manipulation(
-    manipulation: &dyn StringManipulation,
+    manipulation: &dyn Fn(&str) -> String,
     sep: &str,
+    keys: &IndexKeys,
     id: &str,
     from: &Value,
     to: &mut Map<String, Value>,
+    depth: usize,
 ) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id_manipulation(
-                        manipulation,
-                        sep,
-                        &manipulation.apply(k),
-                        v,
-                        to,
-                    ),
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id_manipulation(
+                            manipulation,
+                            sep,
+                            keys,
+                            &manipulation(k),
+                            v,
+                            to,
+                            depth - 1,
+                        )
+                    }
                     _ => {
-                        to.insert(manipulation.apply(k), v.clone());
+                        to.insert(manipulation(k), v.clone());
                     }
                 };
             }
@@ -235,15 +2350,19 @@ fn flatten_recursive_no_id_manipulation(
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id_manipulation(
-                        manipulation,
-                        sep,
-                        &(i + 1).to_string(),
-                        v,
-                        to,
-                    ),
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id_manipulation(
+                            manipulation,
+                            sep,
+                            keys,
+                            &keys.index(i),
+                            v,
+                            to,
+                            depth - 1,
+                        )
+                    }
                     _ => {
-                        to.insert((i + 1).to_string(), v.clone());
+                        to.insert(keys.index(i), v.clone());
                     }
                 };
             }
@@ -254,14 +2373,26 @@ fn flatten_recursive_no_id_manipulation(
     }
 }
 
-fn flatten_recursive_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<String, Value>) {
+fn flatten_recursive_with_id(
+    sep: &str,
+    keys: &IndexKeys,
+    id: &str,
+    from: &Value,
+    to: &mut Map<String, Value>,
+    depth: usize,
+) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
                 match v {
-                    Value::Object(_) | Value::Array(_) => {
-                        flatten_recursive_with_id(sep, &(id.to_owned() + sep + k), v, to)
-                    }
+                    Value::Object(_) | Value::Array(_) if depth > 1 => flatten_recursive_with_id(
+                        sep,
+                        keys,
+                        &(id.to_owned() + sep + k),
+                        v,
+                        to,
+                        depth - 1,
+                    ),
                     _ => {
                         to.insert(id.to_owned() + sep + k, v.clone());
                     }
@@ -271,14 +2402,11 @@ fn flatten_recursive_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<Str
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id(
-                        sep,
-                        &(id.to_owned() + sep + &(i + 1).to_string()),
-                        v,
-                        to,
-                    ),
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id(sep, keys, &(keys.key(id, i)), v, to, depth - 1)
+                    }
                     _ => {
-                        to.insert(id.to_owned() + sep + &(i + 1).to_string(), v.clone());
+                        to.insert(keys.key(id, i), v.clone());
                     }
                 };
             }
@@ -290,24 +2418,28 @@ fn flatten_recursive_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<Str
 }
 
 fn flatten_recursive_with_id_manipulation(
-    manipulation: &dyn StringManipulation,
+    manipulation: &dyn Fn(&str) -> String,
     sep: &str,
+    keys: &IndexKeys,
     id: &str,
     from: &Value,
     to: &mut Map<String, Value>,
+    depth: usize,
 ) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id(
+                    Value::Object(_) | Value::Array(_) if depth > 1 => flatten_recursive_with_id(
                         sep,
-                        &(id.to_owned() + sep + &manipulation.apply(k)),
+                        keys,
+                        &(id.to_owned() + sep + &manipulation(k)),
                         v,
                         to,
+                        depth - 1,
                     ),
                     _ => {
-                        to.insert(id.to_owned() + sep + &manipulation.apply(k), v.clone());
+                        to.insert(id.to_owned() + sep + &manipulation(k), v.clone());
                     }
                 };
             }
@@ -315,14 +2447,11 @@ fn flatten_recursive_with_id_manipulation(
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
                 match v {
-                    Value::Object(_) | Value::Array(_) => flatten_recursive_with_id(
-                        sep,
-                        &(id.to_owned() + sep + &(i + 1).to_string()),
-                        v,
-                        to,
-                    ),
+                    Value::Object(_) | Value::Array(_) if depth > 1 => {
+                        flatten_recursive_with_id(sep, keys, &(keys.key(id, i)), v, to, depth - 1)
+                    }
                     _ => {
-                        to.insert(id.to_owned() + sep + &(i + 1).to_string(), v.clone());
+                        to.insert(keys.key(id, i), v.clone());
                     }
                 };
             }
@@ -334,7 +2463,12 @@ fn flatten_recursive_with_id_manipulation(
 }
 
 #[inline]
-fn flatten_single_level_no_id(id: &str, from: &Value, to: &mut Map<String, Value>) {
+fn flatten_single_level_no_id(
+    keys: &IndexKeys,
+    id: &str,
+    from: &Value,
+    to: &mut Map<String, Value>,
+) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
@@ -343,7 +2477,7 @@ fn flatten_single_level_no_id(id: &str, from: &Value, to: &mut Map<String, Value
         }
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
-                to.insert((i + 1).to_string(), v.clone());
+                to.insert(keys.index(i), v.clone());
             }
         }
         _ => {
@@ -353,7 +2487,13 @@ fn flatten_single_level_no_id(id: &str, from: &Value, to: &mut Map<String, Value
 }
 
 #[inline]
-fn flatten_single_level_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<String, Value>) {
+fn flatten_single_level_with_id(
+    sep: &str,
+    keys: &IndexKeys,
+    id: &str,
+    from: &Value,
+    to: &mut Map<String, Value>,
+) {
     match from {
         Value::Object(m) => {
             for (k, v) in m {
@@ -362,7 +2502,7 @@ fn flatten_single_level_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<
         }
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
-                to.insert(id.to_owned() + sep + &(i + 1).to_string(), v.clone());
+                to.insert(keys.key(id, i), v.clone());
             }
         }
         _ => {
@@ -373,7 +2513,8 @@ fn flatten_single_level_with_id(sep: &str, id: &str, from: &Value, to: &mut Map<
 
 #[inline]
 fn flatten_single_level_no_id_manipulation(
-    manipulation: &dyn StringManipulation,
+    manipulation: &dyn Fn(&str) -> String,
+    keys: &IndexKeys,
     id: &str,
     from: &Value,
     to: &mut Map<String, Value>,
@@ -381,12 +2522,12 @@ fn flatten_single_level_no_id_manipulation(
     match from {
         Value::Object(m) => {
             for (k, v) in m {
-                to.insert(manipulation.apply(k), v.clone());
+                to.insert(manipulation(k), v.clone());
             }
         }
         Value::Array(arr) => {
             for (i, v) in arr.iter().enumerate() {
-                to.insert((i + 1).to_string(), v.clone());
+                to.insert(keys.index(i), v.clone());
             }
         }
         _ => {
@@ -397,8 +2538,9 @@ fn flatten_single_level_no_id_manipulation(
 
 #[inline]
 fn flatten_single_level_with_id_manipulation(
-    manipulation: &dyn StringManipulation,
+    manipulation: &dyn Fn(&str) -> String,
     sep: &str,
+    keys: &IndexKeys,
     id: &str,
     from: &Value,
     to: &mut Map<String, Value>,
@@ -406,103 +2548,813 @@ fn flatten_single_level_with_id_manipulation(
     match from {
         Value::Object(m) => {
             for (k, v) in m {
-                to.insert(id.to_owned() + sep + &manipulation.apply(k), v.clone());
+                to.insert(id.to_owned() + sep + &manipulation(k), v.clone());
+            }
+        }
+        Value::Array(arr) => {
+            for (i, v) in arr.iter().enumerate() {
+                to.insert(keys.key(id, i), v.clone());
+            }
+        }
+        _ => {
+            to.insert(id.to_owned(), from.clone());
+        }
+    }
+}
+
+/// IndexKeys formats the keys generated for the elements of Arrays when flattening.
+pub(crate) struct IndexKeys<'a> {
+    pub(crate) separator: &'a str,
+    pub(crate) width: usize,
+    pub(crate) base: usize,
+    pub(crate) format: Option<&'a str>,
+}
+
+impl<'a> IndexKeys<'a> {
+    /// sets the index of the first element, when set, and the template formatting indexes.
+    #[inline]
+    fn with_index(mut self, base: Option<usize>, format: Option<&'a str>) -> Self {
+        self.base = base.unwrap_or(self.base);
+        self.format = format;
+        self
+    }
+
+    /// returns the key of the element at index, counting from the base and zero-padded to the
+    /// width.
+    #[inline]
+    fn index(&self, i: usize) -> String {
+        let index = format!("{:0width$}", i + self.base, width = self.width);
+        match self.format {
+            Some(format) => format.replace(INDEX_PLACEHOLDER, &index),
+            None => index,
+        }
+    }
+
+    /// returns the key of the element at index of the Array with the id.
+    #[inline]
+    fn key(&self, id: &str, i: usize) -> String {
+        match self.format {
+            Some(_) => id.to_owned() + &self.index(i),
+            None => id.to_owned() + self.separator + &self.index(i),
+        }
+    }
+}
+
+/// the placeholder replaced by the index in an index format eg. `[{i}]`.
+const INDEX_PLACEHOLDER: &str = "{i}";
+
+#[inline]
+fn flatten(
+    manipulation: Option<&dyn Fn(&str) -> String>,
+    sep: &str,
+    keys: &IndexKeys,
+    id: &str,
+    from: &Value,
+    to: &mut Map<String, Value>,
+    depth: usize,
+) {
+    if depth > 1 {
+        match manipulation {
+            Some(man) => match id.len() {
+                0 => flatten_recursive_no_id_manipulation(man, sep, keys, id, from, to, depth),
+                _ => flatten_recursive_with_id_manipulation(man, sep, keys, id, from, to, depth),
+            },
+            None => match id.len() {
+                0 => flatten_recursive_no_id(sep, keys, id, from, to, depth),
+                _ => flatten_recursive_with_id(sep, keys, id, from, to, depth),
+            },
+        };
+    } else {
+        match manipulation {
+            Some(man) => match id.len() {
+                0 => flatten_single_level_no_id_manipulation(man, keys, id, from, to),
+                _ => flatten_single_level_with_id_manipulation(man, sep, keys, id, from, to),
+            },
+            None => match id.len() {
+                0 => flatten_single_level_no_id(keys, id, from, to),
+                _ => flatten_single_level_with_id(sep, keys, id, from, to),
+            },
+        };
+    }
+}
+
+impl Transform {
+    /// returns the source value relative to the provided value the transform is applied at.
+    #[inline]
+    fn source_value(
+        &self,
+        ctx: &Context,
+        from: &Value,
+        to: &Map<String, Value>,
+    ) -> Result<Option<Value>> {
+        Ok(match &self.source {
+            Source::Direct(id) => match from {
+                Value::Object(obj) => obj.get(id).cloned(),
+                _ => {
+                    ctx.type_mismatch("", "object", from)?;
+                    None
+                }
+            },
+            Source::DirectArray { id, index } => match from {
+                Value::Object(v) => match v.get(id) {
+                    Some(Value::Array(arr)) => arr.get(*index).cloned(),
+                    Some(other) => {
+                        ctx.type_mismatch(id, "array", other)?;
+                        None
+                    }
+                    _ => None,
+                },
+                Value::Array(v) => v.get(*index).cloned(),
+                _ => {
+                    ctx.type_mismatch("", "object", from)?;
+                    None
+                }
+            },
+            Source::Constant(v) => Some(v.clone()),
+            Source::Env(var) => ctx.env_var(var)?.map(Value::String),
+            Source::Generate(generator) => {
+                let locale = self.locale.as_ref().unwrap_or_else(|| ctx.locale());
+                Some(generator.generate(ctx, locale))
+            }
+            Source::Output(namespace) => lookup_map(namespace, to).cloned(),
+            Source::Context(namespace) => ctx.value(namespace).cloned(),
+            Source::Current => Some(from.clone()),
+            Source::JsonPath(path) if path.is_definite() => {
+                path.select(from).first().cloned().cloned()
+            }
+            Source::JsonPath(path) => Some(path.evaluate(from)),
+            Source::Concat { sources, separator } => {
+                let mut parts = Vec::with_capacity(sources.len());
+                for namespace in sources {
+                    match lookup(namespace, from) {
+                        Some(Value::String(s)) => parts.push(s.clone()),
+                        Some(v @ Value::Number(_)) | Some(v @ Value::Bool(_)) => {
+                            parts.push(v.to_string())
+                        }
+                        Some(Value::Null) | None => {}
+                        Some(v) => ctx.type_mismatch(&Namespace::format(namespace), "string", v)?,
+                    }
+                }
+                match parts.len() {
+                    0 => None,
+                    _ => Some(Value::String(parts.join(separator))),
+                }
+            }
+            Source::Template(template) => template.render(ctx, from)?.map(Value::String),
+            Source::ContextConstant(template) => match template.context_value(ctx) {
+                Some(value) => value.cloned(),
+                None => template.render(ctx, from)?.map(Value::String),
+            },
+            Source::Predicate(predicate) => Some(Value::Bool(predicate.evaluate(ctx, from))),
+            Source::Aggregate { namespace, agg } => {
+                let mut values = Vec::new();
+                lookup_all(namespace, from, &mut values);
+                // a source without wildcards aggregates the elements of the Array it refers to
+                if let (0, [Value::Array(arr)]) = (count_wildcards(namespace), values.as_slice()) {
+                    values = arr.iter().collect();
+                }
+                agg.apply(ctx, &Namespace::format(namespace), &values)?
+            }
+            Source::Zip {
+                sources,
+                keys,
+                length,
+            } => {
+                let mut arrays = Vec::with_capacity(sources.len());
+                for namespace in sources {
+                    match lookup(namespace, from) {
+                        Some(Value::Array(arr)) => arrays.push(Some(arr)),
+                        Some(Value::Null) | None => arrays.push(None),
+                        Some(v) => {
+                            ctx.type_mismatch(&Namespace::format(namespace), "array", v)?;
+                            arrays.push(None)
+                        }
+                    }
+                }
+                if arrays.iter().all(Option::is_none) {
+                    None
+                } else {
+                    let lengths = arrays.iter().map(|arr| arr.map_or(0, |arr| arr.len()));
+                    let len = match length {
+                        ZipLength::Truncate => lengths.min(),
+                        ZipLength::Pad => lengths.max(),
+                    }
+                    .unwrap_or_default();
+                    let zipped = (0..len)
+                        .map(|i| {
+                            let element = keys
+                                .iter()
+                                .zip(&arrays)
+                                .map(|(key, arr)| {
+                                    let v = arr.and_then(|arr| arr.get(i)).cloned();
+                                    (key.clone(), v.unwrap_or(Value::Null))
+                                })
+                                .collect();
+                            Value::Object(element)
+                        })
+                        .collect();
+                    Some(Value::Array(zipped))
+                }
+            }
+            Source::Merge { sources, arrays } => {
+                let mut merged: Option<Value> = None;
+                for namespace in sources {
+                    match lookup(namespace, from) {
+                        Some(v @ Value::Object(_)) => match &mut merged {
+                            Some(into) => merge(into, v, Conflict::Last, *arrays, "")?,
+                            None => merged = Some(v.clone()),
+                        },
+                        Some(Value::Null) | None => {}
+                        Some(v) => ctx.type_mismatch(&Namespace::format(namespace), "object", v)?,
+                    }
+                }
+                merged
+            }
+            Source::Switch { on, cases, default } => {
+                let value = on.value(ctx, from);
+                match cases.iter().find(|(when, _)| equals(value, when)) {
+                    Some((_, arm)) => arm.value(ctx, from),
+                    None => default.as_ref().and_then(|arm| arm.value(ctx, from)),
+                }
+            }
+        })
+    }
+
+    /// returns the full source namespace for reporting a missing source value.
+    fn missing_path(&self) -> String {
+        match (&self.source, &self.absolute) {
+            (Source::Output(namespace), _) => {
+                OUTPUT_PREFIX.to_owned() + &Namespace::format(namespace)
+            }
+            (Source::Context(namespace), _) => {
+                CONTEXT_PREFIX.to_owned() + &Namespace::format(namespace)
+            }
+            (Source::JsonPath(path), _) => path.expr().to_owned(),
+            (Source::Env(var), _) => format!("${}", var),
+            (Source::Generate(generator), _) => format!("{:?}", generator),
+            (Source::Template(template), _) | (Source::ContextConstant(template), _) => {
+                template.template().to_owned()
+            }
+            (Source::Concat { sources, .. }, _)
+            | (Source::Merge { sources, .. }, _)
+            | (Source::Zip { sources, .. }, _) => sources
+                .iter()
+                .map(|namespace| Namespace::format(namespace))
+                .collect::<Vec<_>>()
+                .join(", "),
+            (Source::Aggregate { namespace, .. }, _) => Namespace::format(namespace),
+            (Source::Switch { on, .. }, _) => on.path(),
+            (source, Some(absolute)) => join_path(&Namespace::format(absolute), &source.path()),
+            (source, None) => source.path(),
+        }
+    }
+
+    /// interprets the Array indexes of the destination relative to the index base eg. 1 for
+    /// mappings authored 1-based.
+    #[inline]
+    pub(crate) fn rebase_destination(&mut self, base: usize) -> Result<()> {
+        self.destination.rebase(base)
+    }
+
+    /// returns if this transform must be applied, from the root of the document, after all other
+    /// rules. Either because the source is a value already written to the output, because it
+    /// only writes absent values and so relies on the order rules were added or because it has a
+    /// default which must apply even when a parent of the source is missing.
+    #[inline]
+    pub(crate) fn is_deferred(&self) -> bool {
+        matches!(self.source, Source::Output(_))
+            || self.only_if_absent
+            || (self.default.is_some() && self.absolute.is_some())
+    }
+
+    pub fn parse(mapping: Mapping) -> Result<(Vec<Namespace>, Self)> {
+        Self::parse_with(mapping, &mut |path| parse_path(path))
+    }
+
+    /// parses the mapping using the provided function to parse namespaces, allowing parsed
+    /// namespaces to be reused.
+    pub(crate) fn parse_with<F>(mapping: Mapping, paths: &mut F) -> Result<(Vec<Namespace>, Self)>
+    where
+        F: FnMut(Cow<str>) -> Result<Vec<Namespace>>,
+    {
+        // conditional transforms are applied at the root, where the predicate is evaluated, and
+        // look up their source from there.
+        if let Mapping::When { when, mapping } = mapping {
+            let (namespace, mut rule) = Self::parse_with(*mapping, paths)?;
+            if rule.absolute.is_none() {
+                if count_wildcards(&namespace) > 0 {
+                    return Err(Error::InvalidNamespace(String::from(
+                        "Wildcard sources are not supported by conditional mappings",
+                    )));
+                }
+                rule.absolute = Some(namespace);
+            }
+            rule.condition = Some(match rule.condition.take() {
+                Some(condition) => Predicate::And(vec![when, condition]),
+                None => when,
+            });
+            return Ok((Vec::new(), rule));
+        }
+        if let Mapping::OnError { on_error, mapping } = mapping {
+            let (namespace, mut rule) = Self::parse_with(*mapping, paths)?;
+            rule.on_error = Some(on_error);
+            return Ok((namespace, rule));
+        }
+        let mut from_namespace;
+        let mut to_namespace;
+        let mut is_flatten = false;
+        let mut is_recursive = false;
+        let mut flatten_prefix = None;
+        let mut sep = None;
+        let mut array_sep = None;
+        let mut array_index = (None, None);
+        let mut depth = None;
+        let mut manip = Vec::new();
+        let mut list = None;
+        let mut expected = None;
+        let if_absent;
+        let mut default_value = None;
+        let mut mapping_locale = None;
+        let mut split = None;
+        let mut join = None;
+        let mut coercion = None;
+        let mut duration = None;
+        let mut map = None;
+        let mut is_canonical = false;
+        let mut subtree = None;
+        let mut rekeying = None;
+        let mut compute = None;
+        let mut masking = None;
+        #[cfg(feature = "crypto")]
+        let mut crypt = None;
+        let mut function = None;
+
+        let source = match mapping {
+            Mapping::Direct {
+                from,
+                to,
+                kind,
+                only_if_absent,
+                default,
+            } => {
+                expected = kind;
+                if_absent = only_if_absent;
+                default_value = default;
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Constant {
+                from,
+                to,
+                kind,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                // placeholders are resolved from the apply time Context values
+                let template = match &from {
+                    Value::String(s) => Template::parse_constant(s)?,
+                    _ => None,
+                };
+                match (template, kind) {
+                    (Some(template), kind) => {
+                        // the type of the resolved value is checked when applied
+                        expected = kind;
+                        Source::ContextConstant(template)
+                    }
+                    (None, Some(kind)) if !kind.matches(&from) => {
+                        return Err(Error::InvalidSourceValue(format!(
+                            "constant {} is not of type {}",
+                            from,
+                            kind.name()
+                        )));
+                    }
+                    (None, _) => Source::Constant(from),
+                }
+            }
+            Mapping::ConstantEnv {
+                var,
+                to,
+                default,
+                only_if_absent,
+            } => {
+                if var.is_empty() || var.contains('=') {
+                    return Err(Error::InvalidSourceValue(format!(
+                        "invalid environment variable name {:?}",
+                        var
+                    )));
+                }
+                if_absent = only_if_absent;
+                default_value = default;
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::Env(var.into_owned())
+            }
+            Mapping::Generate {
+                generator,
+                to,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::Generate(generator)
+            }
+            Mapping::Flatten {
+                from,
+                to,
+                prefix,
+                manipulations,
+                recursive,
+                separator,
+                array_separator,
+                index_base,
+                index_format,
+                max_depth,
+                only_if_absent,
+                locale,
+            } => {
+                if let Some(format) = &index_format {
+                    if !format.contains(INDEX_PLACEHOLDER) {
+                        return Err(Error::Rule(format!(
+                            "index format {:?} must contain {}",
+                            format, INDEX_PLACEHOLDER
+                        )));
+                    }
+                }
+                if max_depth == Some(0) {
+                    return Err(Error::Rule("max depth must be at least 1".to_owned()));
+                }
+                if_absent = only_if_absent;
+                mapping_locale = locale;
+                is_flatten = true;
+                array_sep = array_separator;
+                array_index = (index_base, index_format);
+                depth = max_depth;
+                is_recursive = recursive;
+                flatten_prefix = prefix;
+                sep = separator;
+                manip = manipulations;
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::FlattenList {
+                from,
+                to,
+                manipulations,
+                recursive,
+                only_if_absent,
+                locale,
+            } => {
+                if_absent = only_if_absent;
+                mapping_locale = locale;
+                list = Some((manipulations, recursive));
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::JsonPath {
+                expr,
+                to,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // the expression is evaluated from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::JsonPath(JsonPath::parse(&expr)?)
+            }
+            Mapping::Concat {
+                from,
+                to,
+                separator,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // each source is looked up from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                let sources = from
+                    .into_iter()
+                    .map(&mut *paths)
+                    .collect::<Result<Vec<_>>>()?;
+                if sources.iter().any(|ns| count_wildcards(ns) > 0) {
+                    return Err(Error::InvalidNamespace(String::from(
+                        "Wildcard sources are not supported when concatenating",
+                    )));
+                }
+                Source::Concat {
+                    sources,
+                    separator: separator.into_owned(),
+                }
+            }
+            Mapping::Template {
+                template,
+                to,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // placeholders are resolved from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::Template(Template::parse(&template)?)
+            }
+            Mapping::Coerce {
+                from,
+                to,
+                coerce,
+                on_failure,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                coercion = Some((coerce, on_failure));
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::MapArray {
+                from,
+                to,
+                transformer,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                map = Some(transformer);
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Duration {
+                from,
+                to,
+                input,
+                output,
+                on_failure,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                duration = Some((input, output, on_failure));
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Canonical {
+                from,
+                to,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                is_canonical = true;
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Compute {
+                from,
+                to,
+                ops,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                compute = Some(ops);
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Mask {
+                from,
+                to,
+                mask,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                masking = Some(mask);
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            #[cfg(feature = "crypto")]
+            Mapping::Encrypt {
+                from,
+                to,
+                key,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                crypt = Some((Crypt::Encrypt, key.into_owned()));
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            #[cfg(feature = "crypto")]
+            Mapping::Decrypt {
+                from,
+                to,
+                key,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                crypt = Some((Crypt::Decrypt, key.into_owned()));
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Function {
+                from,
+                to,
+                name,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                function = Some(name.into_owned());
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::When { .. } | Mapping::OnError { .. } => unreachable!(),
+            Mapping::DeriveBool {
+                when,
+                to,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // the predicate is evaluated from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::Predicate(when)
             }
-        }
-        Value::Array(arr) => {
-            for (i, v) in arr.iter().enumerate() {
-                to.insert(id.to_owned() + sep + &(i + 1).to_string(), v.clone());
+            Mapping::Merge {
+                from,
+                to,
+                arrays,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // each source is looked up from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                let sources = from
+                    .into_iter()
+                    .map(&mut *paths)
+                    .collect::<Result<Vec<_>>>()?;
+                if sources.iter().any(|ns| count_wildcards(ns) > 0) {
+                    return Err(Error::InvalidNamespace(String::from(
+                        "Wildcard sources are not supported when merging",
+                    )));
+                }
+                Source::Merge { sources, arrays }
             }
-        }
-        _ => {
-            to.insert(id.to_owned(), from.clone());
-        }
-    }
-}
-
-#[inline]
-fn flatten(
-    manipulation: &Option<Box<dyn StringManipulation>>,
-    sep: &str,
-    id: &str,
-    from: &Value,
-    to: &mut Map<String, Value>,
-    recursive: bool,
-) {
-    if recursive {
-        match manipulation {
-            Some(man) => match id.len() {
-                0 => flatten_recursive_no_id_manipulation(man.as_ref(), sep, id, from, to),
-                _ => flatten_recursive_with_id_manipulation(man.as_ref(), sep, id, from, to),
-            },
-            None => match id.len() {
-                0 => flatten_recursive_no_id(sep, id, from, to),
-                _ => flatten_recursive_with_id(sep, id, from, to),
-            },
-        };
-    } else {
-        match manipulation {
-            Some(man) => match id.len() {
-                0 => flatten_single_level_no_id_manipulation(man.as_ref(), id, from, to),
-                _ => flatten_single_level_with_id_manipulation(man.as_ref(), sep, id, from, to),
-            },
-            None => match id.len() {
-                0 => flatten_single_level_no_id(id, from, to),
-                _ => flatten_single_level_with_id(sep, id, from, to),
-            },
-        };
-    }
-}
-
-impl Transform {
-    pub fn parse(mapping: Mapping) -> Result<(Vec<Namespace>, Self)> {
-        let mut from_namespace;
-        let mut to_namespace;
-        let mut is_flatten = false;
-        let mut is_recursive = false;
-        let mut flatten_prefix = None;
-        let mut sep = None;
-        let mut manip = None;
-
-        let source = match mapping {
-            Mapping::Direct { from, to } => {
-                from_namespace = Namespace::parse(from)?;
-                to_namespace = Namespace::parse(to)?;
-                let field = from_namespace.pop().ok_or_else(|| {
-                    Error::InvalidNamespace(String::from("No field defined for namespace"))
-                })?;
-                match field {
-                    Namespace::Object { id } => Source::Direct(id),
-                    Namespace::Array { id, index } => Source::DirectArray { id, index },
-                }
-            }
-            Mapping::Constant { from, to } => {
+            Mapping::Switch {
+                on,
+                to,
+                cases,
+                default,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // the inspected value and case sources are looked up from the root of the document
                 from_namespace = Vec::new();
-                to_namespace = Namespace::parse(to)?;
-                Source::Constant(from.clone())
+                to_namespace = paths(to)?;
+                Source::Switch {
+                    on: Subject::parse(on)?,
+                    cases: cases
+                        .into_iter()
+                        .map(|case| Ok((case.when, case.then.parse()?)))
+                        .collect::<Result<_>>()?,
+                    default: default.map(SwitchArm::parse).transpose()?,
+                }
             }
-            Mapping::Flatten {
+            Mapping::Split {
                 from,
                 to,
-                prefix,
-                manipulation,
-                recursive,
                 separator,
+                part,
+                only_if_absent,
             } => {
-                is_flatten = true;
-                is_recursive = recursive;
-                flatten_prefix = prefix;
-                sep = separator;
-                manip = manipulation;
-                from_namespace = Namespace::parse(from)?;
-                to_namespace = Namespace::parse(to)?;
-                let field = from_namespace.pop().ok_or_else(|| {
-                    Error::InvalidNamespace(String::from("No field defined for namespace"))
-                })?;
-                match field {
-                    Namespace::Object { id } => Source::Direct(id),
-                    Namespace::Array { id, index } => Source::DirectArray { id, index },
+                if separator.is_empty() {
+                    return Err(Error::Rule(String::from(
+                        "Split separator must not be empty",
+                    )));
+                }
+                if_absent = only_if_absent;
+                split = Some(Split {
+                    separator: separator.into_owned(),
+                    part,
+                });
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Rekey {
+                from,
+                to,
+                manipulation,
+                only_if_absent,
+                locale,
+            } => {
+                if_absent = only_if_absent;
+                mapping_locale = locale;
+                rekeying = Some(manipulation);
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Subtree {
+                from,
+                to,
+                include,
+                exclude,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                subtree = Some(Subtree::new(include, exclude)?);
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
+            }
+            Mapping::Aggregate {
+                from,
+                to,
+                agg,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                // every value matching the source, including any wildcards, is collected from
+                // the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                Source::Aggregate {
+                    namespace: paths(from)?,
+                    agg,
+                }
+            }
+            Mapping::Zip {
+                from,
+                to,
+                keys,
+                length,
+                only_if_absent,
+            } => {
+                if from.len() != keys.len() {
+                    return Err(Error::Rule(format!(
+                        "Zip requires a key for each source, found {} sources and {} keys",
+                        from.len(),
+                        keys.len()
+                    )));
                 }
+                if_absent = only_if_absent;
+                // each source is looked up from the root of the document
+                from_namespace = Vec::new();
+                to_namespace = paths(to)?;
+                let sources = from
+                    .into_iter()
+                    .map(&mut *paths)
+                    .collect::<Result<Vec<_>>>()?;
+                if sources.iter().any(|ns| count_wildcards(ns) > 0) {
+                    return Err(Error::InvalidNamespace(String::from(
+                        "Wildcard sources are not supported when zipping",
+                    )));
+                }
+                Source::Zip {
+                    sources,
+                    keys: keys.into_iter().map(Cow::into_owned).collect(),
+                    length,
+                }
+            }
+            Mapping::Join {
+                from,
+                to,
+                separator,
+                stringify,
+                only_if_absent,
+            } => {
+                if_absent = only_if_absent;
+                join = Some(Join {
+                    separator: separator.into_owned(),
+                    stringify,
+                });
+                let (ns, source) = parse_source(from, paths)?;
+                from_namespace = ns;
+                to_namespace = paths(to)?;
+                source
             }
         };
         let field = if is_flatten {
@@ -516,9 +3368,32 @@ impl Transform {
             })?
         };
 
+        if to_namespace
+            .iter()
+            .any(|ns| matches!(ns, Namespace::Match { .. }))
+        {
+            return Err(Error::InvalidNamespace(String::from(MATCH_DESTINATION)));
+        }
+        let to_wildcards = count_wildcards(&to_namespace) + field.is_wildcard() as usize;
+        let from_wildcards = count_wildcards(&from_namespace);
+        if to_wildcards > from_wildcards {
+            return Err(Error::InvalidNamespace(String::from(
+                "Destination namespace has more wildcards than the source namespace",
+            )));
+        }
+        // values from wildcards not matched by the destination are collected into an Array
+        let is_collect = to_wildcards < from_wildcards && !is_flatten && list.is_none();
+
         let destination = match field {
             Namespace::Object { id } => {
-                if is_flatten {
+                if let Some((manipulations, recursive)) = list {
+                    Destination::ListDirect {
+                        namespace: to_namespace,
+                        id,
+                        manipulations,
+                        recursive,
+                    }
+                } else if is_flatten {
                     Destination::FlattenDirect {
                         namespace: to_namespace,
                         id: match id.len() {
@@ -533,9 +3408,18 @@ impl Transform {
                             Some(c) => c.to_string(),
                             _ => String::from(""),
                         },
-                        manipulation: manip,
+                        array_separator: array_sep.map(|c| c.to_string()),
+                        index_base: array_index.0,
+                        index_format: array_index.1.map(|c| c.to_string()),
+                        max_depth: depth,
+                        manipulations: manip,
                         recursive: is_recursive,
                     }
+                } else if is_collect {
+                    Destination::Collect {
+                        namespace: to_namespace,
+                        id,
+                    }
                 } else {
                     Destination::Direct {
                         namespace: to_namespace,
@@ -544,7 +3428,15 @@ impl Transform {
                 }
             }
             Namespace::Array { id, index } => {
-                if is_flatten {
+                if let Some((manipulations, recursive)) = list {
+                    Destination::ListArray {
+                        namespace: to_namespace,
+                        id,
+                        index,
+                        manipulations,
+                        recursive,
+                    }
+                } else if is_flatten {
                     Destination::FlattenArray {
                         namespace: to_namespace,
                         id,
@@ -556,8 +3448,12 @@ impl Transform {
                             Some(c) => c.to_string(),
                             _ => String::from(""),
                         },
+                        array_separator: array_sep.map(|c| c.to_string()),
+                        index_base: array_index.0,
+                        index_format: array_index.1.map(|c| c.to_string()),
+                        max_depth: depth,
                         index,
-                        manipulation: manip,
+                        manipulations: manip,
                         recursive: is_recursive,
                     }
                 } else {
@@ -568,48 +3464,424 @@ impl Transform {
                     }
                 }
             }
+            Namespace::Wildcard { id } => {
+                if is_flatten || list.is_some() {
+                    return Err(Error::InvalidNamespace(String::from(
+                        "Wildcard destinations are not supported when flattening",
+                    )));
+                }
+                Destination::DirectWildcard {
+                    level: count_wildcards(&to_namespace),
+                    namespace: to_namespace,
+                    id,
+                }
+            }
+            Namespace::Match { .. } => {
+                return Err(Error::InvalidNamespace(String::from(MATCH_DESTINATION)))
+            }
+        };
+        // rules only writing absent values are deferred so they apply in the order they were added
+        // and rules with a default so it still applies when a parent of the source is missing.
+        let absolute = if matches!(source, Source::Output(_)) {
+            None
+        } else if if_absent {
+            if from_wildcards > 0 {
+                return Err(Error::InvalidNamespace(String::from(
+                    "Wildcard sources are not supported when only writing absent values",
+                )));
+            }
+            Some(mem::take(&mut from_namespace))
+        } else if default_value.is_some() && from_wildcards == 0 && !from_namespace.is_empty() {
+            Some(mem::take(&mut from_namespace))
+        } else {
+            None
         };
         Ok((
             from_namespace,
             Self {
                 source,
                 destination,
+                kind: expected,
+                only_if_absent: if_absent,
+                absolute,
+                default: default_value,
+                locale: mapping_locale,
+                split,
+                join,
+                coerce: coercion,
+                map,
+                duration,
+                canonical: is_canonical,
+                subtree,
+                rekey: rekeying,
+                compute,
+                mask: masking,
+                #[cfg(feature = "crypto")]
+                crypt,
+                function,
+                condition: None,
+                on_error: None,
             },
         ))
     }
 }
 
+#[inline]
+fn parse_source<F>(from: Cow<str>, paths: &mut F) -> Result<(Vec<Namespace>, Source)>
+where
+    F: FnMut(Cow<str>) -> Result<Vec<Namespace>>,
+{
+    if from.is_empty() || from == DOCUMENT {
+        return Ok((Vec::new(), Source::Current));
+    }
+    if let Some(output) = from.strip_prefix(OUTPUT_PREFIX) {
+        return Ok((Vec::new(), Source::Output(paths(output.into())?)));
+    }
+    if let Some(context) = from.strip_prefix(CONTEXT_PREFIX) {
+        return Ok((Vec::new(), Source::Context(paths(context.into())?)));
+    }
+    let mut from_namespace = paths(from)?;
+    let field = from_namespace
+        .pop()
+        .ok_or_else(|| Error::InvalidNamespace(String::from("No field defined for namespace")))?;
+    let source = match field {
+        Namespace::Object { id } => Source::Direct(id),
+        Namespace::Array { id, index } => Source::DirectArray { id, index },
+        // the rule is applied to each element matched by the wildcard or field value
+        field @ Namespace::Wildcard { .. } | field @ Namespace::Match { .. } => {
+            from_namespace.push(field);
+            Source::Current
+        }
+    };
+    Ok((from_namespace, source))
+}
+
+/// returns the value at the provided namespace, if it exists.
+#[inline]
+pub(crate) fn lookup<'a>(namespace: &[Namespace], mut current: &'a Value) -> Option<&'a Value> {
+    for ns in namespace {
+        current = match ns {
+            Namespace::Object { id } => current.get(id.as_str())?,
+            Namespace::Array { id, index } => match id.len() {
+                0 => current.get(*index)?,
+                _ => current.get(id.as_str())?.get(*index)?,
+            },
+            Namespace::Wildcard { .. } => return None,
+            Namespace::Match { id, key, value } => match id.len() {
+                0 => find_match(current, key, value)?,
+                _ => find_match(current.get(id.as_str())?, key, value)?,
+            },
+        };
+    }
+    Some(current)
+}
+
+/// collects every value at the namespace, expanding any wildcards.
+pub(crate) fn lookup_all<'a>(namespace: &[Namespace], current: &'a Value, to: &mut Vec<&'a Value>) {
+    let (first, rest) = match namespace.split_first() {
+        Some(v) => v,
+        None => {
+            to.push(current);
+            return;
+        }
+    };
+    let next = match first {
+        Namespace::Wildcard { id } => {
+            let arr = match id.len() {
+                0 => current.as_array(),
+                _ => current.get(id.as_str()).and_then(Value::as_array),
+            };
+            for v in arr.into_iter().flatten() {
+                lookup_all(rest, v, to);
+            }
+            return;
+        }
+        ns => lookup(std::slice::from_ref(ns), current),
+    };
+    if let Some(v) = next {
+        lookup_all(rest, v, to);
+    }
+}
+
+/// merges the elements of the batch sharing the same value at the namespace into the position of
+/// the first of them. Elements without the value are kept as is.
+pub(crate) fn merge_by(
+    namespace: &[Namespace],
+    conflict: Conflict,
+    batch: &[Value],
+) -> Result<Vec<Value>> {
+    let mut merged: Vec<Value> = Vec::with_capacity(batch.len());
+    let mut positions = HashMap::new();
+    for element in batch {
+        match lookup(namespace, element).filter(|v| !v.is_null()) {
+            Some(key) => match positions.entry(output::canonical(key)) {
+                Entry::Occupied(e) => merge(
+                    &mut merged[*e.get()],
+                    element,
+                    conflict,
+                    ArrayMerge::Replace,
+                    "",
+                )?,
+                Entry::Vacant(e) => {
+                    e.insert(merged.len());
+                    merged.push(element.clone());
+                }
+            },
+            None => merged.push(element.clone()),
+        }
+    }
+    Ok(merged)
+}
+
+/// deep merges Objects, and Arrays as specified, resolving differing values of any other type
+/// using the Conflict.
+pub(crate) fn merge(
+    into: &mut Value,
+    from: &Value,
+    conflict: Conflict,
+    arrays: ArrayMerge,
+    path: &str,
+) -> Result<()> {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (k, v) in from {
+                match into.get_mut(k) {
+                    Some(existing) => merge(existing, v, conflict, arrays, &join_path(path, k))?,
+                    None => {
+                        into.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (Value::Array(into), Value::Array(from)) if arrays == ArrayMerge::Concat => {
+            into.extend(from.iter().cloned())
+        }
+        (Value::Array(into), Value::Array(from)) if arrays == ArrayMerge::Index => {
+            for (i, v) in from.iter().enumerate() {
+                match into.get_mut(i) {
+                    Some(existing) => {
+                        merge(existing, v, conflict, arrays, &format!("{}[{}]", path, i))?
+                    }
+                    None => into.push(v.clone()),
+                }
+            }
+        }
+        (into, from) if *into == *from => {}
+        (into, from) => match conflict {
+            Conflict::Last => *into = from.clone(),
+            Conflict::First => {}
+            Conflict::Error => return Err(Error::MergeConflict(path.to_owned())),
+        },
+    }
+    Ok(())
+}
+
+/// removes the Object field at the provided namespace, for every element of any wildcards. Array
+/// elements are left in place so the position of the remaining elements is unchanged.
+pub(crate) fn remove_path(namespace: &[Namespace], current: &mut Value) {
+    let (first, rest) = match namespace.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+    match first {
+        Namespace::Object { id } => {
+            if rest.is_empty() {
+                if let Value::Object(m) = current {
+                    m.remove(id);
+                }
+            } else if let Some(v) = current.get_mut(id.as_str()) {
+                remove_path(rest, v);
+            }
+        }
+        Namespace::Array { id, index } => {
+            let element = match id.len() {
+                0 => current.get_mut(*index),
+                _ => current.get_mut(id.as_str()).and_then(|v| v.get_mut(*index)),
+            };
+            if let Some(v) = element {
+                remove_path(rest, v);
+            }
+        }
+        Namespace::Wildcard { id } => {
+            let arr = match id.len() {
+                0 => Some(current),
+                _ => current.get_mut(id.as_str()),
+            };
+            if let Some(Value::Array(arr)) = arr {
+                for v in arr {
+                    remove_path(rest, v);
+                }
+            }
+        }
+        Namespace::Match { id, key, value } => {
+            let arr = match id.len() {
+                0 => Some(current),
+                _ => current.get_mut(id.as_str()),
+            };
+            if let Some(Value::Array(arr)) = arr {
+                if let Some(v) = arr.iter_mut().find(|v| element_matches(v, key, value)) {
+                    remove_path(rest, v);
+                }
+            }
+        }
+    }
+}
+
+/// returns the value at the provided namespace within an output map, if it exists.
+#[inline]
+pub(crate) fn lookup_map<'a>(
+    namespace: &[Namespace],
+    map: &'a Map<String, Value>,
+) -> Option<&'a Value> {
+    let (first, rest) = namespace.split_first()?;
+    let current = match first {
+        Namespace::Object { id } => map.get(id)?,
+        Namespace::Array { id, index } => map.get(id)?.get(*index)?,
+        Namespace::Wildcard { .. } => return None,
+        Namespace::Match { id, key, value } => find_match(map.get(id)?, key, value)?,
+    };
+    lookup(rest, current)
+}
+
 #[inline]
 fn get_last<'a>(
+    ctx: &Context,
     namespace: &[Namespace],
     mut current: &'a mut Map<String, Value>,
 ) -> &'a mut Map<String, Value> {
-    for ns in namespace {
-        match ns {
-            Namespace::Object { id } => {
-                current = current
-                    .entry(id.clone())
-                    .or_insert(Value::Object(Map::new()))
-                    .as_object_mut()
-                    .unwrap();
-            }
-            Namespace::Array { id, index } => {
-                current = current
-                    .entry(id.clone())
-                    .or_insert(Value::Array(vec![Value::Null; *index]))
-                    .as_object_mut()
-                    .unwrap();
-            }
-        };
+    let mut level = 0;
+    let mut iter = namespace.iter().peekable();
+    while let Some(ns) = iter.next() {
+        // only allocates the key when first written
+        if !current.contains_key(ns.id()) {
+            current.insert(ns.id().clone(), Value::Null);
+        }
+        let mut value = current.get_mut(ns.id()).unwrap();
+        let mut segment = Some(ns);
+        while let Some(ns) = segment {
+            value = match ns {
+                Namespace::Object { .. } => value,
+                Namespace::Array { index, .. } => element_mut(value, *index),
+                Namespace::Wildcard { .. } => {
+                    level += 1;
+                    element_mut(value, ctx.index(level - 1).unwrap_or_default())
+                }
+                Namespace::Match { .. } => unreachable!("rejected when parsing destinations"),
+            };
+            // may be array of array already without id eg. arr[0][0]
+            segment = iter.next_if(|ns| !ns.is_object() && ns.id().is_empty());
+        }
+        if !value.is_object() {
+            *value = Value::Object(Map::new());
+        }
+        current = value.as_object_mut().unwrap();
     }
     current
 }
 
+#[inline]
+fn element_mut(value: &mut Value, index: usize) -> &mut Value {
+    if !value.is_array() {
+        *value = Value::Array(Vec::new());
+    }
+    let arr = value.as_array_mut().unwrap();
+    if index >= arr.len() {
+        arr.resize_with(index + 1, Value::default);
+    }
+    &mut arr[index]
+}
+
+/// shifts the Array indexes of a destination namespace, written relative to the index base eg. 1,
+/// to be 0-based.
+pub(crate) fn rebase(namespace: &mut [Namespace], base: usize) -> Result<()> {
+    for ns in namespace.iter_mut() {
+        if let Namespace::Array { index, .. } = ns {
+            rebase_index(index, base)?;
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn rebase_index(index: &mut usize, base: usize) -> Result<()> {
+    match index.checked_sub(base) {
+        Some(i) => {
+            *index = i;
+            Ok(())
+        }
+        None => Err(Error::InvalidNamespace(format!(
+            "destination index {} is below the index base of {}",
+            index, base
+        ))),
+    }
+}
+
+/// returns an Error::NotInvertible for the rule reading the source path.
+#[inline]
+fn not_invertible(path: &str, reason: &str) -> Error {
+    let path = if path.is_empty() { "$" } else { path };
+    Error::NotInvertible(format!("`{}`: {}", path, reason))
+}
+
+#[inline]
+fn count_wildcards(namespace: &[Namespace]) -> usize {
+    namespace.iter().filter(|ns| ns.is_wildcard()).count()
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum Source {
     Direct(String),
-    DirectArray { id: String, index: usize },
+    DirectArray {
+        id: String,
+        index: usize,
+    },
     Constant(Value),
+    // a String constant with `${ctx.name}` placeholders
+    ContextConstant(Template),
+    // the value of an environment variable
+    Env(String),
+    // a value generated for each document
+    Generate(Generator),
+    Output(Vec<Namespace>),
+    Context(Vec<Namespace>),
+    Current,
+    JsonPath(JsonPath),
+    Concat {
+        sources: Vec<Vec<Namespace>>,
+        separator: String,
+    },
+    Template(Template),
+    Predicate(Predicate),
+    Aggregate {
+        namespace: Vec<Namespace>,
+        agg: Agg,
+    },
+    Merge {
+        sources: Vec<Vec<Namespace>>,
+        arrays: ArrayMerge,
+    },
+    Zip {
+        sources: Vec<Vec<Namespace>>,
+        keys: Vec<String>,
+        length: ZipLength,
+    },
+    Switch {
+        on: Subject,
+        cases: Vec<(Value, Arm)>,
+        default: Option<Arm>,
+    },
+}
+
+impl Source {
+    /// returns the source namespace relative to the node the rule is applied at.
+    #[inline]
+    fn path(&self) -> String {
+        match self {
+            Source::Direct(id) => id.clone(),
+            Source::DirectArray { id, index } => format!("{}[{}]", id, index),
+            _ => String::new(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -623,12 +3895,30 @@ pub(crate) enum Destination {
         id: String,
         index: usize,
     },
+    DirectWildcard {
+        namespace: Vec<Namespace>,
+        id: String,
+        level: usize,
+    },
+    Collect {
+        namespace: Vec<Namespace>,
+        id: String,
+    },
     FlattenDirect {
         namespace: Vec<Namespace>,
         id: Option<String>,
         prefix: String,
         separator: String,
-        manipulation: Option<Box<dyn StringManipulation>>,
+        #[serde(default)]
+        array_separator: Option<String>,
+        #[serde(default)]
+        index_base: Option<usize>,
+        #[serde(default)]
+        index_format: Option<String>,
+        #[serde(default)]
+        max_depth: Option<usize>,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
         recursive: bool,
     },
     FlattenArray {
@@ -636,8 +3926,107 @@ pub(crate) enum Destination {
         id: String,
         prefix: String,
         separator: String,
-        manipulation: Option<Box<dyn StringManipulation>>,
+        #[serde(default)]
+        array_separator: Option<String>,
+        #[serde(default)]
+        index_base: Option<usize>,
+        #[serde(default)]
+        index_format: Option<String>,
+        #[serde(default)]
+        max_depth: Option<usize>,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
+        index: usize,
+        recursive: bool,
+    },
+    ListDirect {
+        namespace: Vec<Namespace>,
+        id: String,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
+        recursive: bool,
+    },
+    ListArray {
+        namespace: Vec<Namespace>,
+        id: String,
         index: usize,
+        #[serde(default, alias = "manipulation", deserialize_with = "manipulations")]
+        manipulations: Vec<Box<dyn StringManipulation>>,
         recursive: bool,
     },
 }
+
+impl Destination {
+    /// shifts the Array indexes of the destination, written relative to the index base eg. 1, to
+    /// be 0-based.
+    fn rebase(&mut self, base: usize) -> Result<()> {
+        let (namespace, index) = match self {
+            Destination::DirectArray {
+                namespace, index, ..
+            }
+            | Destination::ListArray {
+                namespace, index, ..
+            }
+            | Destination::FlattenArray {
+                namespace, index, ..
+            } => (namespace, Some(index)),
+            Destination::Direct { namespace, .. }
+            | Destination::Collect { namespace, .. }
+            | Destination::DirectWildcard { namespace, .. }
+            | Destination::ListDirect { namespace, .. }
+            | Destination::FlattenDirect { namespace, .. } => (namespace, None),
+        };
+        rebase(namespace, base)?;
+        match index {
+            Some(index) => rebase_index(index, base),
+            None => Ok(()),
+        }
+    }
+
+    /// returns if a value has already been written to the destination. `null` values, including
+    /// those padding Arrays, are not considered written.
+    #[inline]
+    fn is_written(&self, ctx: &Context, to: &mut Map<String, Value>) -> bool {
+        let (namespace, id, index) = match self {
+            Destination::Direct { namespace, id }
+            | Destination::Collect { namespace, id }
+            | Destination::ListDirect { namespace, id, .. }
+            | Destination::FlattenDirect {
+                namespace,
+                id: Some(id),
+                ..
+            } => (namespace, id, None),
+            Destination::DirectArray {
+                namespace,
+                id,
+                index,
+            }
+            | Destination::ListArray {
+                namespace,
+                id,
+                index,
+                ..
+            }
+            | Destination::FlattenArray {
+                namespace,
+                id,
+                index,
+                ..
+            } => (namespace, id, Some(*index)),
+            Destination::DirectWildcard {
+                namespace,
+                id,
+                level,
+            } => (namespace, id, Some(ctx.index(*level).unwrap_or_default())),
+            // merges into the existing Object, handled per key when applying
+            Destination::FlattenDirect { id: None, .. } => return false,
+        };
+        // the parent is always created when writing so creating it early is harmless
+        let current = get_last(ctx, namespace, to);
+        match (current.get(id), index) {
+            (Some(v), None) => !v.is_null(),
+            (Some(Value::Array(arr)), Some(index)) => arr.get(index).is_some_and(|v| !v.is_null()),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use crate::context::value_type;
+use crate::errors::{Error, Result};
+use crate::namespace::Namespace;
+use crate::predicate::equals;
+use crate::rules::DOCUMENT;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+const TYPES: &[&str] = &[
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+/// SchemaPolicy is what happens when an input document does not match its schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaPolicy {
+    /// fails the transformation with an `Error::SchemaViolation`, before any rule is applied.
+    #[default]
+    Reject,
+    /// transforms the document regardless. The `Error::SchemaViolation` is only reported when
+    /// collecting errors, see Transformer::apply_collecting.
+    Warn,
+}
+
+/// Violation is a value of a document which does not match its schema.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// the namespace of the value eg. `items[0].id`, `$` for the whole document.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` {}", self.path, self.message)
+    }
+}
+
+/// Schema is a JSON Schema documents are validated against. The keywords describing the shape of
+/// a document are supported: `type`, `enum`, `const`, `properties`, `required`,
+/// `additionalProperties`, `minProperties`, `maxProperties`, `items`, `minItems`, `maxItems`,
+/// `uniqueItems`, `minLength`, `maxLength`, `minimum`, `maximum`, `exclusiveMinimum`,
+/// `exclusiveMaximum`, `multipleOf`, `allOf`, `anyOf`, `oneOf`, `not` and local `$ref`s eg.
+/// `#/definitions/address`. Other keywords are ignored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Schema {
+    schema: Value,
+}
+
+impl Schema {
+    /// creates a Schema, returning an error when it isn't a valid JSON Schema.
+    #[inline]
+    pub fn new(schema: Value) -> Result<Self> {
+        check(&schema, &schema)?;
+        Ok(Schema { schema })
+    }
+
+    /// returns the JSON Schema.
+    #[inline]
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    /// returns every value of the document which does not match the schema.
+    #[inline]
+    pub fn validate(&self, document: &Value) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        Validator {
+            root: &self.schema,
+            violations: &mut violations,
+        }
+        .validate(&self.schema, document, &mut Vec::new());
+        violations
+    }
+
+    /// returns an `Error::SchemaViolation` when the document does not match the schema, where
+    /// document names it in the error eg. `output`.
+    #[inline]
+    pub(crate) fn check(&self, document: &'static str, value: &Value) -> Result<()> {
+        match self.violation(document, value) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// returns the `Error::SchemaViolation` of a document which does not match the schema.
+    #[inline]
+    pub(crate) fn violation(&self, document: &'static str, value: &Value) -> Option<Error> {
+        let violations = self.validate(value);
+        if violations.is_empty() {
+            return None;
+        }
+        Some(Error::SchemaViolation {
+            document,
+            summary: violations
+                .iter()
+                .map(Violation::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            violations,
+        })
+    }
+}
+
+/// returns an error if the schema, or any of its subschemas, is not a valid JSON Schema.
+fn check(root: &Value, schema: &Value) -> Result<()> {
+    let invalid = |message: String| Err(Error::InvalidSchema(message));
+    let s = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(s) => s,
+        _ => {
+            return invalid(format!(
+                "expected an object or boolean but found {}",
+                schema
+            ))
+        }
+    };
+    match s.get("type") {
+        None => {}
+        Some(Value::String(t)) if TYPES.contains(&t.as_str()) => {}
+        Some(Value::Array(types))
+            if types
+                .iter()
+                .all(|t| t.as_str().is_some_and(|t| TYPES.contains(&t))) => {}
+        Some(t) => return invalid(format!("unknown type {}", t)),
+    }
+    if let Some(required) = s.get("required") {
+        if !required
+            .as_array()
+            .is_some_and(|r| r.iter().all(Value::is_string))
+        {
+            return invalid(format!(
+                "required must be an array of strings, found {}",
+                required
+            ));
+        }
+    }
+    for key in &["properties", "definitions", "$defs"] {
+        match s.get(*key) {
+            None => {}
+            Some(Value::Object(m)) => m.values().try_for_each(|v| check(root, v))?,
+            Some(v) => return invalid(format!("{} must be an object, found {}", key, v)),
+        }
+    }
+    for key in &["allOf", "anyOf", "oneOf"] {
+        match s.get(*key) {
+            None => {}
+            Some(Value::Array(arr)) if !arr.is_empty() => {
+                arr.iter().try_for_each(|v| check(root, v))?
+            }
+            Some(v) => return invalid(format!("{} must be a non-empty array, found {}", key, v)),
+        }
+    }
+    match s.get("items") {
+        Some(Value::Array(arr)) => arr.iter().try_for_each(|v| check(root, v))?,
+        Some(items) => check(root, items)?,
+        None => {}
+    }
+    for key in &["additionalProperties", "not"] {
+        if let Some(v) = s.get(*key) {
+            check(root, v)?;
+        }
+    }
+    if let Some(reference) = s.get("$ref") {
+        if resolve(root, reference).is_none() {
+            return invalid(format!("unresolved $ref {}", reference));
+        }
+    }
+    Ok(())
+}
+
+/// returns the subschema of a local `$ref` eg. `#/definitions/address`.
+#[inline]
+fn resolve<'s>(root: &'s Value, reference: &Value) -> Option<&'s Value> {
+    match reference.as_str()? {
+        "#" => Some(root),
+        r if r.starts_with("#/") => root.pointer(&r[1..]),
+        _ => None,
+    }
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    violations: &'a mut Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, path: &[Namespace], message: String) {
+        let path = match path {
+            [] => DOCUMENT.to_owned(),
+            path => Namespace::format(path),
+        };
+        self.violations.push(Violation { path, message });
+    }
+
+    /// returns if the value matches the schema, without recording any violations.
+    fn matches(&mut self, schema: &Value, value: &Value, path: &mut Vec<Namespace>) -> bool {
+        let mut violations = Vec::new();
+        Validator {
+            root: self.root,
+            violations: &mut violations,
+        }
+        .validate(schema, value, path);
+        violations.is_empty()
+    }
+
+    fn validate(&mut self, schema: &Value, value: &Value, path: &mut Vec<Namespace>) {
+        let s = match schema {
+            Value::Object(s) => s,
+            Value::Bool(false) => return self.violation(path, String::from("is not allowed")),
+            _ => return,
+        };
+        if let Some(schema) = s.get("$ref").and_then(|r| resolve(self.root, r)) {
+            self.validate(schema, value, path);
+        }
+        if let Some(t) = s.get("type") {
+            let types = match t {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                t => t.as_str().into_iter().collect::<Vec<_>>(),
+            };
+            if !types.iter().any(|t| is_type(t, value)) {
+                return self.violation(
+                    path,
+                    format!(
+                        "expected {} but found {}",
+                        types.join(" or "),
+                        value_type(value)
+                    ),
+                );
+            }
+        }
+        if let Some(Value::Array(values)) = s.get("enum") {
+            if !values.iter().any(|v| equals(Some(value), v)) {
+                self.violation(path, format!("{} is not one of the allowed values", value));
+            }
+        }
+        if let Some(expected) = s.get("const") {
+            if !equals(Some(value), expected) {
+                self.violation(path, format!("expected {} but found {}", expected, value));
+            }
+        }
+        match value {
+            Value::Number(n) => self.validate_number(s, n.as_f64().unwrap_or_default(), path),
+            Value::String(v) => {
+                let len = v.chars().count();
+                if let Some(min) = s.get("minLength").and_then(Value::as_u64) {
+                    if (len as u64) < min {
+                        self.violation(path, format!("is shorter than {} characters", min));
+                    }
+                }
+                if let Some(max) = s.get("maxLength").and_then(Value::as_u64) {
+                    if len as u64 > max {
+                        self.violation(path, format!("is longer than {} characters", max));
+                    }
+                }
+            }
+            Value::Array(arr) => self.validate_array(s, arr, path),
+            Value::Object(m) => self.validate_object(s, m, path),
+            _ => {}
+        }
+        if let Some(Value::Array(all)) = s.get("allOf") {
+            for schema in all {
+                self.validate(schema, value, path);
+            }
+        }
+        if let Some(Value::Array(any)) = s.get("anyOf") {
+            if !any.iter().any(|schema| self.matches(schema, value, path)) {
+                self.violation(
+                    path,
+                    String::from("does not match any of the allowed schemas"),
+                );
+            }
+        }
+        if let Some(Value::Array(one)) = s.get("oneOf") {
+            let count = one
+                .iter()
+                .filter(|schema| self.matches(schema, value, path))
+                .count();
+            if count != 1 {
+                self.violation(
+                    path,
+                    format!("matches {} of the schemas rather than exactly one", count),
+                );
+            }
+        }
+        if let Some(not) = s.get("not") {
+            if self.matches(not, value, path) {
+                self.violation(path, String::from("matches a disallowed schema"));
+            }
+        }
+    }
+
+    fn validate_number(&mut self, s: &Map<String, Value>, n: f64, path: &[Namespace]) {
+        let bound = |key: &str| s.get(key).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum") {
+            if n < min {
+                self.violation(path, format!("is less than the minimum of {}", min));
+            }
+        }
+        if let Some(max) = bound("maximum") {
+            if n > max {
+                self.violation(path, format!("is greater than the maximum of {}", max));
+            }
+        }
+        if let Some(min) = bound("exclusiveMinimum") {
+            if n <= min {
+                self.violation(path, format!("is not greater than {}", min));
+            }
+        }
+        if let Some(max) = bound("exclusiveMaximum") {
+            if n >= max {
+                self.violation(path, format!("is not less than {}", max));
+            }
+        }
+        if let Some(multiple) = bound("multipleOf") {
+            if multiple > 0.0 && (n / multiple).fract() != 0.0 {
+                self.violation(path, format!("is not a multiple of {}", multiple));
+            }
+        }
+    }
+
+    fn validate_array(&mut self, s: &Map<String, Value>, arr: &[Value], path: &mut Vec<Namespace>) {
+        if let Some(min) = s.get("minItems").and_then(Value::as_u64) {
+            if (arr.len() as u64) < min {
+                self.violation(path, format!("has fewer than {} items", min));
+            }
+        }
+        if let Some(max) = s.get("maxItems").and_then(Value::as_u64) {
+            if arr.len() as u64 > max {
+                self.violation(path, format!("has more than {} items", max));
+            }
+        }
+        if s.get("uniqueItems") == Some(&Value::Bool(true))
+            && arr
+                .iter()
+                .enumerate()
+                .any(|(i, a)| arr[..i].iter().any(|b| equals(Some(a), b)))
+        {
+            self.violation(path, String::from("has duplicate items"));
+        }
+        let items = match s.get("items") {
+            Some(items) => items,
+            None => return,
+        };
+        let id = match path.pop() {
+            Some(Namespace::Object { id }) => id,
+            Some(ns) => {
+                path.push(ns);
+                String::new()
+            }
+            None => String::new(),
+        };
+        for (index, v) in arr.iter().enumerate() {
+            let schema = match items {
+                Value::Array(tuple) => match tuple.get(index) {
+                    Some(schema) => schema,
+                    None => break,
+                },
+                items => items,
+            };
+            path.push(Namespace::Array {
+                id: id.clone(),
+                index,
+            });
+            self.validate(schema, v, path);
+            path.pop();
+        }
+        if !id.is_empty() {
+            path.push(Namespace::Object { id });
+        }
+    }
+
+    fn validate_object(
+        &mut self,
+        s: &Map<String, Value>,
+        m: &Map<String, Value>,
+        path: &mut Vec<Namespace>,
+    ) {
+        if let Some(Value::Array(required)) = s.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !m.contains_key(key) {
+                    self.violation(path, format!("is missing required property `{}`", key));
+                }
+            }
+        }
+        if let Some(min) = s.get("minProperties").and_then(Value::as_u64) {
+            if (m.len() as u64) < min {
+                self.violation(path, format!("has fewer than {} properties", min));
+            }
+        }
+        if let Some(max) = s.get("maxProperties").and_then(Value::as_u64) {
+            if m.len() as u64 > max {
+                self.violation(path, format!("has more than {} properties", max));
+            }
+        }
+        let properties = s.get("properties").and_then(Value::as_object);
+        let additional = s.get("additionalProperties");
+        for (key, v) in m {
+            let schema = match properties.and_then(|p| p.get(key)) {
+                Some(schema) => schema,
+                None => match additional {
+                    Some(Value::Bool(false)) => {
+                        self.violation(path, format!("has unexpected property `{}`", key));
+                        continue;
+                    }
+                    Some(schema) => schema,
+                    None => continue,
+                },
+            };
+            path.push(Namespace::Object { id: key.clone() });
+            self.validate(schema, v, path);
+            path.pop();
+        }
+    }
+}
+
+/// returns if the value is of the JSON Schema type.
+#[inline]
+fn is_type(t: &str, value: &Value) -> bool {
+    match (t, value) {
+        ("integer", Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        (t, value) => t == value_type(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let schema = Schema::new(json!({
+            "type": "object",
+            "required": ["id", "name"],
+            "additionalProperties": false,
+            "properties": {
+                "id": {"type": "integer", "minimum": 1},
+                "name": {"type": "string", "minLength": 1},
+                "status": {"enum": ["active", "inactive"]},
+                "tags": {"type": "array", "items": {"type": "string"}, "uniqueItems": true},
+                "address": {"$ref": "#/definitions/address"}
+            },
+            "definitions": {
+                "address": {"type": ["object", "null"], "required": ["city"]}
+            }
+        }))?;
+        let valid = json!({"id":1,"name":"Joey","tags":["a"],"address":null});
+        assert!(schema.validate(&valid).is_empty());
+
+        let invalid = json!({"id":0.5,"status":"gone","tags":["a",1,"a"],"address":{},"extra":1});
+        let violations = schema
+            .validate(&invalid)
+            .into_iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "`$` is missing required property `name`",
+                "`address` is missing required property `city`",
+                "`$` has unexpected property `extra`",
+                "`id` expected integer but found number",
+                "`status` \"gone\" is not one of the allowed values",
+                "`tags` has duplicate items",
+                "`tags[1]` expected string but found number",
+            ],
+            violations
+        );
+
+        for schema in &[
+            json!(1),
+            json!({"type": "text"}),
+            json!({"required": "id"}),
+            json!({"anyOf": []}),
+            json!({"$ref": "#/definitions/missing"}),
+        ] {
+            assert!(Schema::new(schema.clone()).is_err(), "{}", schema);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use crate::context::Context;
+use crate::errors::{Error, Result};
+use crate::namespace::{parse_path, Namespace};
+use crate::rules::{lookup, CONTEXT_PREFIX};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Template is a compiled string template eg. `https://example.com/users/{user.id}` whose
+/// placeholders are source namespaces resolved from the root of the document, or apply time
+/// Context values eg. `{$ctx.tenant_id}`.
+///
+/// Literal braces are written as `{{` and `}}`. Placeholders may not contain wildcards or braces.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Template {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Part {
+    Literal(String),
+    Placeholder(Vec<Namespace>),
+    Context(Vec<Namespace>),
+}
+
+impl Template {
+    /// parses a template, returning an error for unbalanced braces or invalid placeholders.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => {
+                                return Err(Error::InvalidTemplate(format!(
+                                    "unclosed placeholder in {}",
+                                    template
+                                )))
+                            }
+                            Some(c) => placeholder.push(c),
+                        }
+                    }
+                    let placeholder = placeholder.trim();
+                    if placeholder.is_empty() {
+                        return Err(Error::InvalidTemplate(format!(
+                            "empty placeholder in {}",
+                            template
+                        )));
+                    }
+                    if let Some(context) = placeholder.strip_prefix(CONTEXT_PREFIX) {
+                        if !literal.is_empty() {
+                            parts.push(Part::Literal(std::mem::take(&mut literal)));
+                        }
+                        parts.push(Part::Context(parse_path(context.into())?));
+                        continue;
+                    }
+                    let namespace = parse_path(placeholder.into())?;
+                    if namespace.iter().any(|ns| ns.is_wildcard()) {
+                        return Err(Error::InvalidTemplate(format!(
+                            "wildcard placeholder {} in {}",
+                            placeholder, template
+                        )));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(namespace));
+                }
+                '}' => {
+                    return Err(Error::InvalidTemplate(format!(
+                        "unescaped `}}` in {}",
+                        template
+                    )))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template {
+            template: template.to_owned(),
+            parts,
+        })
+    }
+
+    /// parses a constant String containing `${ctx.name}` placeholders, resolved from the apply
+    /// time Context values, returning None when it has none. Any other text, including braces,
+    /// is literal.
+    pub fn parse_constant(constant: &str) -> Result<Option<Self>> {
+        let mut parts = Vec::new();
+        let mut rest = constant;
+        while let Some(start) = rest.find("${ctx.") {
+            let end = rest[start..].find('}').ok_or_else(|| {
+                Error::InvalidTemplate(format!("unclosed placeholder in {}", constant))
+            })?;
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let namespace = &rest[start + "${ctx.".len()..start + end];
+            parts.push(Part::Context(parse_path(namespace.trim().into())?));
+            rest = &rest[start + end + 1..];
+        }
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+        Ok(Some(Template {
+            template: constant.to_owned(),
+            parts,
+        }))
+    }
+
+    /// returns the Context value of a template consisting of a single Context placeholder eg.
+    /// `${ctx.limits}`, which is written as is rather than as text.
+    #[inline]
+    pub(crate) fn context_value<'c>(&self, ctx: &'c Context) -> Option<Option<&'c Value>> {
+        match &self.parts[..] {
+            [Part::Context(namespace)] => Some(ctx.value(namespace)),
+            _ => None,
+        }
+    }
+
+    /// returns the template the Template was parsed from.
+    #[inline]
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// renders the template using values from the document, returning None when any placeholder
+    /// value is missing or null. Numbers and booleans are written as text.
+    pub fn render(&self, ctx: &Context, from: &Value) -> Result<Option<String>> {
+        let mut rendered = String::with_capacity(self.template.len());
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => rendered.push_str(s),
+                Part::Context(namespace) => match ctx.value(namespace) {
+                    Some(Value::String(s)) => rendered.push_str(s),
+                    Some(Value::Null) | None => return Ok(None),
+                    Some(v) => rendered.push_str(&v.to_string()),
+                },
+                Part::Placeholder(namespace) => match lookup(namespace, from) {
+                    Some(Value::String(s)) => rendered.push_str(s),
+                    Some(v @ Value::Number(_)) | Some(v @ Value::Bool(_)) => {
+                        rendered.push_str(&v.to_string())
+                    }
+                    Some(Value::Null) | None => return Ok(None),
+                    Some(v) => {
+                        ctx.type_mismatch(&Namespace::format(namespace), "string", v)?;
+                        return Ok(None);
+                    }
+                },
+            }
+        }
+        Ok(Some(rendered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() -> Result<()> {
+        let template =
+            Template::parse("{{id}}: {user.id}/{ user[\"first name\"] }?active={active}")?;
+        let input = serde_json::json!({"user":{"id":1,"first name":"Joey"},"active":true});
+        assert_eq!(
+            Some(String::from("{id}: 1/Joey?active=true")),
+            template.render(&Context::default(), &input)?
+        );
+        assert_eq!(
+            None,
+            template.render(&Context::default(), &serde_json::json!({"user":{"id":1}}))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_constant() -> Result<()> {
+        assert!(Template::parse_constant("{id} costs $5")?.is_none());
+        assert!(Template::parse_constant("${ctx.tenant").is_err());
+        let template = Template::parse_constant("${ctx.tenant}-${ctx.env}")?.unwrap();
+        assert_eq!(
+            Some(String::from("acme-")),
+            template.render(
+                &Context::default().with_values(
+                    serde_json::json!({"tenant":"acme","env":""})
+                        .as_object()
+                        .unwrap()
+                ),
+                &Value::Null
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for template in &["{user", "user}", "{}", "{users[*].id}", "{a{b}}"] {
+            assert!(Template::parse(template).is_err(), "{}", template);
+        }
+    }
+}
@@ -1,38 +1,268 @@
-use crate::errors::Result;
-use crate::namespace::Namespace;
-use crate::rules::{FlattenOps, Mapping, Rule, Transform};
-use crate::tree::{Arena, Node};
+use crate::automap::{self, NameMatch};
+use crate::complexity::{ComplexityLimits, ComplexityScore};
+use crate::compute::ComputeOp;
+use crate::context::{value_type, Context, Entropy, LocaleContext};
+#[cfg(feature = "crypto")]
+use crate::crypto::Keys;
+use crate::dsl;
+use crate::duration::DurationFormat;
+use crate::errors::{Error, Result};
+use crate::explain::{Metrics, RuleId, Timings, TransformReport};
+use crate::functions::FunctionRegistry;
+use crate::generate::Generator;
+use crate::input::ArrayReader;
+use crate::mask::Mask;
+use crate::namespace::{find_match, parse_path, Namespace};
+use crate::output::{self, LineSink, OutputOptions, OutputSink};
+use crate::patch::{self, PatchOp};
+use crate::predicate::Predicate;
+use crate::profile::Profile;
+use crate::rules::{
+    lookup, merge_by, rebase, remove_path, Agg, ArrayMerge, Coerce, CoerceFailure, Fallback,
+    Fallible, FlattenOps, FnRule, Mapping, Rule, StringManipulation, Stringify, SubtreeOps,
+    SwitchArm, SwitchCase, Transform, Type, ZipLength,
+};
+#[cfg(feature = "schema")]
+use crate::schema::{Schema, SchemaPolicy, Violation};
+use crate::tree::{Access, Arena, Plan};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::borrow::Cow;
-use std::fmt::Debug;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fmt::{self, Debug};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
 
 /// Mode defines the Transformers behaviour when encountering multiple element top level data such as
 /// Array's. 99.99% of the time the default will suffice, however, there are times when you may wish to
 /// transform from multiple in to a single which the One2One option allows.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum Mode {
     One2One,
+    #[default]
     Many2Many, // does OneToOne when input is NOT an array
-               //    One2Many, // future functionality...maybe
+    /// collapses the elements of a top level Array into a single output Object, with rules
+    /// addressing elements by index eg. `[0].id` or across them eg. `[*].amount`. Input which is
+    /// not an Array is treated as an Array of one element.
+    Many2One,
+    //    One2Many, // future functionality...maybe
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::Many2Many
+/// Batch defines which top level Array's are treated as a batch of documents when in Many2Many mode,
+/// Array's which are not are transformed as a single document.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Batch {
+    /// every top level Array is a batch.
+    #[default]
+    AnyArray,
+    /// only Array's whose elements are all Objects are a batch.
+    ObjectsOnly,
+    /// only Array's whose first element contains the namespace are a batch.
+    Probe(Vec<Namespace>),
+}
+
+impl Batch {
+    /// creates a Probe from a namespaced string eg. `properties.id` or JSON Pointer eg.
+    /// `/properties/id`.
+    #[inline]
+    pub fn probe<'a, S>(namespace: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        Ok(Batch::Probe(parse_path(namespace.into())?))
+    }
+
+    #[inline]
+    fn is_batch(&self, arr: &[Value]) -> bool {
+        match self {
+            Batch::AnyArray => true,
+            Batch::ObjectsOnly => arr.iter().all(Value::is_object),
+            Batch::Probe(namespace) => arr.first().and_then(|v| lookup(namespace, v)).is_some(),
+        }
+    }
+}
+
+/// key a top level scalar or null input is wrapped under when using `Scalar::Wrap`.
+pub const SCALAR_KEY: &str = "$value";
+
+/// Scalar defines the Transformers behaviour when a document is a bare scalar or null value rather
+/// than an Object or Array.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Scalar {
+    /// the value is passed to the rules as is, which will generally result in `null` values.
+    #[default]
+    AsIs,
+    /// the value is wrapped in an Object under the `$value` key, eg. `add_direct("$value", "reading")`.
+    Wrap,
+    /// an `Error::InvalidSourceValue` is returned.
+    Reject,
+}
+
+/// Navigation defines the Transformers behaviour when a source value is present but not of the type
+/// required to continue traversing it, eg. expecting an Object but finding a String.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Navigation {
+    /// silently treats values of the wrong type as missing, resulting in `null` values.
+    #[default]
+    NullSafe,
+    /// returns an `Error::TypeMismatch` for values of the wrong type, missing values continue to
+    /// result in `null` values.
+    Strict,
+}
+
+/// Missing defines the value written for a typed mapping whose source value is missing.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Missing {
+    /// writes a `null` value.
+    #[default]
+    Null,
+    /// writes the empty value of the declared type eg. `""`, `0`, `false`, `[]` or `{}`.
+    Empty,
+}
+
+/// MissingPolicy defines the Transformers behaviour when a source value, or a parent of it, is not
+/// found, whether read within the source tree or from the root. Source values which are present
+/// but `null` are always written as is.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum MissingPolicy {
+    /// writes a `null` value.
+    #[default]
+    Null,
+    /// does not write the destination at all.
+    Skip,
+    /// returns an `Error::MissingSource` with the full source namespace.
+    Error,
+}
+
+/// Conflict defines how differing values are resolved when merging Objects.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Conflict {
+    /// the value merged last wins.
+    #[default]
+    Last,
+    /// the value merged first wins.
+    First,
+    /// returns an `Error::MergeConflict` with the namespace of the value.
+    Error,
+}
+
+/// DestinationConflict defines how rules overwriting the same destination, or a destination and
+/// one of it's children eg. `a` and `a.b`, are handled when building.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum DestinationConflict {
+    /// the conflict is resolved by the rule applied last.
+    #[default]
+    LastWins,
+    /// returns an `Error::DestinationConflict` listing every conflict.
+    Error,
+}
+
+/// Quarantined is a document, or an element of a batch in Many2Many mode, which failed to
+/// transform. The document is kept unchanged along with the error so it can be dead-lettered.
+#[derive(Debug)]
+pub struct Quarantined {
+    /// the position of the document within the batch, None for a single document.
+    pub index: Option<usize>,
+    pub document: Value,
+    pub error: Error,
+}
+
+type BeforeHook = Arc<dyn Fn(&mut Value) + Send + Sync>;
+type AfterHook = Arc<dyn Fn(&mut Map<String, Value>) + Send + Sync>;
+
+/// Hooks are the callbacks invoked for every document, see TransformerBuilder::before_each and
+/// TransformerBuilder::after_each. Hooks are not serialized.
+#[derive(Clone, Default)]
+struct Hooks {
+    before: Vec<BeforeHook>,
+    after: Vec<AfterHook>,
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
+            .finish()
     }
 }
 
 /// TransformerBuilder is used to construct a new Transformer. Once a Transformer is build it is
 /// immutable.
+///
+/// Rules are evaluated in the order of the source tree, with rules at the same level running in
+/// the order they were added. Rules whose source is a back-reference to the output (`$out.`), which
+/// only write absent values or which have a default value for a nested source, are deferred and
+/// run, in the order they were added, once all other rules have been applied.
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TransformerBuilder {
     root: Arena,
     mode: Mode,
+    #[serde(default)]
+    batch: Batch,
+    #[serde(default)]
+    scalar: Scalar,
+    #[serde(default)]
+    navigation: Navigation,
+    #[serde(default)]
+    missing: Missing,
+    #[serde(default)]
+    missing_policy: MissingPolicy,
+    #[serde(default)]
+    locale: LocaleContext,
+    #[serde(default)]
+    passthrough: bool,
+    // full source namespaces read by rules which are not passed through
+    #[serde(default)]
+    consumed: Vec<Vec<Namespace>>,
+    #[serde(default)]
+    excluded: Vec<Vec<Namespace>>,
+    #[serde(default)]
+    destination_prefix: String,
+    #[serde(default)]
+    destination_root: Vec<String>,
+    // source namespace batch elements are merged by
+    #[serde(default)]
+    merge: Option<(Vec<Namespace>, Conflict)>,
+    #[serde(default)]
+    limits: ComplexityLimits,
+    #[serde(default)]
+    destination_conflict: DestinationConflict,
+    #[serde(default)]
+    index_base: usize,
+    #[serde(default)]
+    index_width: usize,
+    #[serde(default)]
+    parallel: bool,
+    #[serde(skip)]
+    hooks: Hooks,
+    #[serde(skip)]
+    functions: FunctionRegistry,
+    #[serde(skip)]
+    env: BTreeSet<String>,
+    #[cfg(feature = "schema")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_schema: Option<(Schema, SchemaPolicy)>,
+    #[cfg(feature = "schema")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_schema: Option<Schema>,
+    #[serde(default)]
+    deferred: Vec<Box<dyn Rule>>,
 }
 
 impl TransformerBuilder {
+    /// creates a TransformerBuilder with the mappings written in the text DSL eg. `user_id -> id`,
+    /// see the `dsl` module for the syntax.
+    pub fn from_dsl(dsl: &str) -> Result<Self> {
+        TransformerBuilder::default().add_mappings(dsl::parse(dsl)?)
+    }
+
     /// sets the mode for which the Transformer will operate.
     #[inline]
     pub fn mode(mut self, mode: Mode) -> Self {
@@ -40,23 +270,316 @@ impl TransformerBuilder {
         self
     }
 
+    /// sets which top level Array's are treated as a batch of documents when in Many2Many mode.
+    #[inline]
+    pub fn batch(mut self, batch: Batch) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// sets how the Transformer will handle documents which are a bare scalar or null value.
+    #[inline]
+    pub fn scalar(mut self, scalar: Scalar) -> Self {
+        self.scalar = scalar;
+        self
+    }
+
+    /// sets how the Transformer will handle source values that can not be traversed.
+    #[inline]
+    pub fn navigation(mut self, navigation: Navigation) -> Self {
+        self.navigation = navigation;
+        self
+    }
+
+    /// sets the value written by typed mappings when their source value is missing.
+    #[inline]
+    pub fn missing(mut self, missing: Missing) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    /// sets how the Transformer will handle source values that are not found.
+    #[inline]
+    pub fn missing_policy(mut self, missing_policy: MissingPolicy) -> Self {
+        self.missing_policy = missing_policy;
+        self
+    }
+
+    /// sets the LocaleContext used by locale sensitive rules, unless overridden by the mapping.
+    #[inline]
+    pub fn locale(mut self, locale: LocaleContext) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// sets if all fields of the source document not read by a mapping are copied to the output
+    /// untouched, so that mappings need only rename or relocate specific fields. Fields within
+    /// Arrays are removed but the elements themselves are always kept in place.
+    #[inline]
+    pub fn passthrough(mut self, passthrough: bool) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// sets a prefix added to every top level key of the output eg. `out_`, applied once all
+    /// mappings have been applied so back-references (`$out.`) use the unprefixed destination.
+    #[inline]
+    pub fn destination_prefix<'a, S>(mut self, prefix: S) -> Self
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.destination_prefix = prefix.into().into_owned();
+        self
+    }
+
+    /// sets the namespace, of Object fields only eg. `envelope.data`, under which the output is
+    /// nested, allowing the same mappings to be mounted under different envelopes. It's applied
+    /// after destination_prefix.
+    #[inline]
+    pub fn destination_root<'a, S>(mut self, root: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.destination_root = parse_path(root.into())?
+            .into_iter()
+            .map(|ns| match ns {
+                Namespace::Object { id } => Ok(id),
+                _ => Err(Error::InvalidNamespace(String::from(
+                    "destination root must only contain Object fields",
+                ))),
+            })
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    /// sets the base Array indexes in the destinations of mappings added afterwards are relative
+    /// to eg. 1 for mappings authored by spreadsheet users, so `items[1]` is the first element.
+    /// Defaults to 0.
+    #[inline]
+    pub fn index_base(mut self, base: usize) -> Self {
+        self.index_base = base;
+        self
+    }
+
+    /// sets the width the keys generated for Array elements when flattening are zero-padded to
+    /// eg. `item_01` with a width of 2. Defaults to 0, no padding.
+    #[inline]
+    pub fn index_width(mut self, width: usize) -> Self {
+        self.index_width = width;
+        self
+    }
+
+    /// sets if the elements of a batch, in Many2Many mode, are transformed in parallel on the
+    /// rayon thread pool. Worthwhile for large batches only. Defaults to false.
+    ///
+    /// Every element is seeded from the Entropy in order either way, so seeded transformations,
+    /// see Transformer::apply_with_entropy, generate the same output in parallel.
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// sets how rules overwriting the same destination are handled when building. Defaults to
+    /// `DestinationConflict::LastWins`.
+    #[inline]
+    pub fn on_destination_conflict(mut self, conflict: DestinationConflict) -> Self {
+        self.destination_conflict = conflict;
+        self
+    }
+
+    /// returns a warning for every pair of rules overwriting the same destination, or a
+    /// destination and one of it's children eg. `a` and `a.b`. Rules only writing absent values
+    /// are expected to overlap others so are not reported.
+    pub fn destination_conflicts(&self) -> Vec<String> {
+        let destinations = self.overwrites();
+        let mut conflicts = Vec::new();
+        for (i, a) in destinations.iter().enumerate() {
+            for b in &destinations[i + 1..] {
+                if overlaps(a, b) {
+                    conflicts.push(conflict(a, b));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// returns the destinations overwritten by every rule.
+    fn overwrites(&self) -> Vec<Vec<Namespace>> {
+        let mut destinations = Vec::new();
+        self.root
+            .visit_rules(&mut |_, rule: &dyn Rule| destinations.extend(rule.overwrites()));
+        destinations.extend(self.deferred.iter().filter_map(|rule| rule.overwrites()));
+        destinations
+    }
+
+    /// combines the rules of another builder with this one's, eg. a shared base mapping with
+    /// per-customer additions. The other builder's rules are applied after this one's, and it's
+    /// hooks run after this one's, while the rest of this builder's settings are kept. When
+    /// destination conflicts are an error, rules of the two builders overwriting the same
+    /// destinations return an `Error::DestinationConflict` listing them.
+    pub fn merge(mut self, other: TransformerBuilder) -> Result<Self> {
+        if self.destination_conflict == DestinationConflict::Error {
+            let theirs = other.overwrites();
+            let conflicts = self
+                .overwrites()
+                .iter()
+                .flat_map(|a| {
+                    theirs
+                        .iter()
+                        .filter(move |b| overlaps(a, b))
+                        .map(move |b| conflict(a, b))
+                })
+                .collect::<Vec<_>>();
+            if !conflicts.is_empty() {
+                return Err(Error::DestinationConflict(conflicts.join(", ")));
+            }
+        }
+        for (namespace, rule) in other.root.into_rules() {
+            self.root.add_boxed(&namespace, rule);
+        }
+        self.deferred.extend(other.deferred);
+        self.consumed.extend(other.consumed);
+        self.excluded.extend(other.excluded);
+        self.hooks.before.extend(other.hooks.before);
+        self.hooks.after.extend(other.hooks.after);
+        self.functions.extend(&other.functions);
+        Ok(self)
+    }
+
+    /// registers a hook invoked with every source document, including each element of a batch in
+    /// Many2Many mode, before it's transformed eg. to normalize it. Hooks run in the order they
+    /// were registered.
+    #[inline]
+    pub fn before_each<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut Value) + Send + Sync + 'static,
+    {
+        self.hooks.before.push(Arc::new(hook));
+        self
+    }
+
+    /// registers a hook invoked with the output of every document, including each element of a
+    /// batch in Many2Many mode, once it's been transformed. Hooks run in the order they were
+    /// registered.
+    #[inline]
+    pub fn after_each<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut Map<String, Value>) + Send + Sync + 'static,
+    {
+        self.hooks.after.push(Arc::new(hook));
+        self
+    }
+
+    /// sets the maximum complexity the Transformer may have, checked when it is built, eg. to
+    /// reject overly expensive user authored mappings up front.
+    #[inline]
+    pub fn max_complexity(mut self, limits: ComplexityLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// sets the source namespace eg. `id` by which the elements of a batch, in Many2Many mode, are
+    /// merged before being transformed. Elements sharing the same value are deep merged, in order,
+    /// into the position of the first of them using the Conflict to resolve differing values, eg.
+    /// to combine partial updates of the same entity. Elements without the value are left as is.
+    #[inline]
+    pub fn merge_by<'a, S>(mut self, namespace: S, conflict: Conflict) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.merge = Some((parse_path(namespace.into())?, conflict));
+        Ok(self)
+    }
+
+    /// allows ConstantEnv mappings to read the environment variables eg. `&["SERVICE_REGION"]`.
+    /// Building fails if a mapping reads a variable which isn't allowed, and as the allowed
+    /// variables aren't serialized, loaded Transformers read none until rebuilt with them, see
+    /// Transformer::into_builder.
+    #[inline]
+    pub fn allow_env(mut self, vars: &[&str]) -> Self {
+        self.env.extend(vars.iter().map(|var| (*var).to_owned()));
+        self
+    }
+
+    /// sets the named functions Function mappings call, see FunctionRegistry. Building fails if a
+    /// mapping calls a function which isn't registered.
+    #[inline]
+    pub fn functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
     /// add allows any custom rule(s) to be added to the transformation beyond the built-in ones.
     #[inline]
     pub fn add<R>(mut self, namespace: &[Namespace], rule: R) -> Result<Self>
     where
         R: Rule + Debug + 'static,
     {
+        self.consumed.extend(rule.source_paths(namespace));
         self.root.add(namespace, rule);
         Ok(self)
     }
 
+    /// adds a custom rule which writes the fallback to it's destination, rather than failing the
+    /// whole transformation, when it fails.
+    #[inline]
+    pub fn add_fallible<R>(
+        self,
+        namespace: &[Namespace],
+        rule: R,
+        on_error: Fallback,
+    ) -> Result<Self>
+    where
+        R: Rule + Debug + 'static,
+    {
+        self.add(namespace, Fallible::new(rule, on_error))
+    }
+
+    /// adds a closure as a custom rule, applied at the namespace like any other rule, for one-off
+    /// transformations which don't need to be serialized eg.
+    /// `add_fn(&[], |from, to| { to.insert("n".into(), from["a"].clone()); Ok(()) })`.
+    /// A Transformer containing closures can not be serialized.
+    #[inline]
+    pub fn add_fn<F>(self, namespace: &[Namespace], f: F) -> Result<Self>
+    where
+        F: Fn(&Value, &mut Map<String, Value>) -> Result<()> + Send + Sync + 'static,
+    {
+        self.add(namespace, FnRule::new(f))
+    }
+
+    /// adds Direct mappings for the fields of the target sample document with an identical
+    /// namespace, or failing that name, in the source sample, eg. to bootstrap a large mapping.
+    /// Returns the namespaces of the target fields which weren't matched, so they can be mapped
+    /// by hand. See auto_map_with for matching names which differ in case or separators.
+    #[inline]
+    pub fn auto_map(self, source: &Value, target: &Value) -> Result<(Self, Vec<String>)> {
+        self.auto_map_with(source, target, NameMatch::Exact)
+    }
+
+    /// adds Direct mappings for the fields of the target sample document found in the source
+    /// sample, comparing field names using the NameMatch. Returns the namespaces of the target
+    /// fields which weren't matched.
+    pub fn auto_map_with(
+        mut self,
+        source: &Value,
+        target: &Value,
+        name_match: NameMatch,
+    ) -> Result<(Self, Vec<String>)> {
+        let (matched, unmatched) = automap::auto_map(source, target, name_match);
+        for (from, to) in matched {
+            self = self.add_direct(from, to)?;
+        }
+        Ok((self, unmatched))
+    }
+
     /// adds mappings that may have been saved outside of this library for building UI's or other
     /// means of generically building transformations.
     #[inline]
     pub fn add_mappings(mut self, mappings: Vec<Mapping>) -> Result<Self> {
         for mapping in mappings {
-            let (ns, rule) = Transform::parse(mapping)?;
-            self = self.add(&ns, rule)?;
+            self = self.add_mapping(mapping)?;
         }
         Ok(self)
     }
@@ -65,10 +588,126 @@ impl TransformerBuilder {
     /// other means of generically building transformations.
     #[inline]
     pub fn add_mapping(self, mapping: Mapping) -> Result<Self> {
-        let (ns, rule) = Transform::parse(mapping)?;
+        self.add_mapping_with(mapping, &mut |path| parse_path(path))
+    }
+
+    #[inline]
+    fn add_mapping_with<F>(mut self, mapping: Mapping, paths: &mut F) -> Result<Self>
+    where
+        F: FnMut(Cow<str>) -> Result<Vec<Namespace>>,
+    {
+        let (ns, mut rule) = Transform::parse_with(mapping, paths)?;
+        rule.rebase_destination(self.index_base)?;
+        if rule.is_deferred() {
+            self.consumed.extend(rule.source_paths(&ns));
+            self.deferred.push(Box::new(rule));
+            return Ok(self);
+        }
         self.add(&ns, rule)
     }
 
+    /// adds a field to be removed from the output once all mappings have been applied eg. to strip
+    /// PII copied using passthrough or from a whole Object. Wildcards eg. `users[*].email` remove
+    /// the field from every element of an Array.
+    #[inline]
+    pub fn add_exclude<'a, S>(mut self, path: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.excluded.push(parse_path(path.into())?);
+        Ok(self)
+    }
+
+    /// removes mappings previously added, see remove_mapping.
+    #[inline]
+    pub fn remove_mappings(mut self, mappings: Vec<Mapping>) -> Result<Self> {
+        for mapping in mappings {
+            self = self.remove_mapping(mapping)?;
+        }
+        Ok(self)
+    }
+
+    /// removes the first rule equal to the provided mapping, pruning any part of the source tree
+    /// no longer in use. Combined with Transformer::into_builder this allows a small change to be
+    /// made to an existing Transformer without rebuilding it from the full list of mappings.
+    #[inline]
+    pub fn remove_mapping(mut self, mapping: Mapping) -> Result<Self> {
+        let description = format!("{:?}", mapping);
+        let (ns, mut rule) = Transform::parse(mapping)?;
+        rule.rebase_destination(self.index_base)?;
+        let source_paths = rule.source_paths(&ns);
+        let deferred = rule.is_deferred();
+        let rule: Box<dyn Rule> = Box::new(rule);
+        let expected = serde_json::to_value(&rule)?;
+        let is_match = |r: &dyn Rule| {
+            serde_json::to_value(r)
+                .map(|v| v == expected)
+                .unwrap_or(false)
+        };
+
+        let removed = if deferred {
+            match self.deferred.iter().position(|r| is_match(r.as_ref())) {
+                Some(pos) => {
+                    self.deferred.remove(pos);
+                    true
+                }
+                None => false,
+            }
+        } else {
+            self.root.remove(&ns, is_match)
+        };
+        if !removed {
+            return Err(Error::Rule(format!("mapping not found: {}", description)));
+        }
+        for path in source_paths {
+            if let Some(pos) = self.consumed.iter().position(|p| *p == path) {
+                self.consumed.remove(pos);
+            }
+        }
+        Ok(self)
+    }
+
+    /// removes every rule writing to the destination, pruning any part of the source tree no
+    /// longer in use, eg. to remove a mapping of a base template from a tenant's builder. Returns
+    /// an error if no rule writes to the destination.
+    pub fn remove_mapping_to<'a, S>(mut self, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let to = to.into();
+        let mut destination = parse_path(Cow::Borrowed(to.as_ref()))?;
+        rebase(&mut destination, self.index_base)?;
+        let writes_to = |r: &dyn Rule| r.destination().as_ref() == Some(&destination);
+
+        let mut removed = self.root.retain(|r| !writes_to(r));
+        let len = self.deferred.len();
+        self.deferred.retain(|r| !writes_to(r.as_ref()));
+        removed += len - self.deferred.len();
+        if removed == 0 {
+            return Err(Error::Rule(format!("no mapping writes to `{}`", to)));
+        }
+
+        // the remaining rules may still read the removed rules sources
+        let mut consumed = Vec::new();
+        self.root
+            .visit_rules(&mut |ns, rule: &dyn Rule| consumed.extend(rule.source_paths(ns)));
+        for rule in &self.deferred {
+            consumed.extend(rule.source_paths(&[]));
+        }
+        self.consumed = consumed;
+        Ok(self)
+    }
+
+    /// replaces every rule writing to the destination with the mapping, eg. to override a mapping
+    /// of a base template for a tenant. Returns an error if no rule writes to the destination.
+    #[inline]
+    pub fn replace_mapping<'a, S>(self, to: S, mapping: Mapping) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.remove_mapping_to(to)?.add_mapping(mapping)
+    }
+
     /// adds a constant value to a value on the output.
     #[inline]
     pub fn add_constant<'a, S, F>(self, from: F, to: S) -> Result<Self>
@@ -79,592 +718,5779 @@ impl TransformerBuilder {
         self.add_mapping(Mapping::Constant {
             from: from.into(),
             to: to.into(),
+            kind: None,
+            only_if_absent: false,
         })
     }
 
-    /// adds a direct mapping from an existing value to a new value on the output.
+    /// adds a constant value to a value on the output only when nothing has been written to the
+    /// destination yet, eg. as a default after other mappings.
     #[inline]
-    pub fn add_direct<'a, S>(self, from: S, to: S) -> Result<Self>
+    pub fn add_constant_if_absent<'a, S, F>(self, from: F, to: S) -> Result<Self>
     where
         S: Into<Cow<'a, str>>,
+        F: Into<Value>,
     {
-        self.add_mapping(Mapping::Direct {
+        self.add_mapping(Mapping::Constant {
             from: from.into(),
             to: to.into(),
+            kind: None,
+            only_if_absent: true,
         })
     }
 
-    /// adds a mapping which takes the existing value, either Object or Array, and flattens the data
-    /// and places that at the desired output location.
+    /// adds a constant value to a value on the output, validating the constant is of the declared
+    /// type.
     #[inline]
-    pub fn add_flatten<'a, S>(self, from: S, to: S, options: FlattenOps) -> Result<Self>
+    pub fn add_constant_typed<'a, S, F>(self, from: F, to: S, kind: Type) -> Result<Self>
     where
         S: Into<Cow<'a, str>>,
+        F: Into<Value>,
     {
-        self.add_mapping(Mapping::Flatten {
+        self.add_mapping(Mapping::Constant {
             from: from.into(),
             to: to.into(),
-            prefix: match options.prefix {
-                Some(v) => Some(v.into()),
-                None => None,
-            },
-            separator: match options.separator {
-                Some(v) => Some(v.into()),
-                None => None,
-            },
-            manipulation: match options.manipulation {
-                Some(v) => Some(v.into()),
-                None => None,
-            },
-            recursive: options.recursive,
+            kind: Some(kind),
+            only_if_absent: false,
         })
     }
 
-    pub fn build(self) -> Result<Transformer> {
-        Ok(Transformer {
-            root: self.root,
-            mode: self.mode,
-        })
+    /// adds a boolean constant to a value on the output.
+    #[inline]
+    pub fn add_constant_bool<'a, S>(self, from: bool, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_constant_typed(from, to, Type::Boolean)
     }
-}
 
-/// Transformer is used to apply the transformation that's been built to any Serializable data.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Transformer {
-    root: Arena,
-    mode: Mode,
-}
-
-impl Transformer {
-    /// applies the transformation to JSON withing a string
+    /// adds an integer constant to a value on the output.
     #[inline]
-    pub fn apply_from_str<'a, S>(&self, input: S) -> Result<Value>
+    pub fn add_constant_int<'a, S>(self, from: i64, to: S) -> Result<Self>
     where
         S: Into<Cow<'a, str>>,
     {
-        let results = transform(
-            &self.mode,
-            &self.root,
-            self.root.tree.get(0).unwrap(), // root
-            &serde_json::from_str(&input.into())?,
-        )?;
-        Ok(results)
+        self.add_constant_typed(from, to, Type::Integer)
     }
 
-    /// applies the transformation to any serializable data and returns your desired structure.
+    /// adds a floating point constant to a value on the output, which must be finite.
     #[inline]
-    pub fn apply_to<S, D>(&self, input: S) -> Result<D>
+    pub fn add_constant_float<'a, S>(self, from: f64, to: S) -> Result<Self>
     where
-        S: Serialize,
-        D: DeserializeOwned,
+        S: Into<Cow<'a, str>>,
     {
-        let results = transform(
-            &self.mode,
-            &self.root,
-            self.root.tree.get(0).unwrap(), // root
-            &serde_json::to_value(input)?,
-        )?;
-        Ok(serde_json::from_value::<D>(results)?)
+        self.add_constant_typed(from, to, Type::Number)
     }
-}
 
-#[inline]
-fn transform(mode: &Mode, arena: &Arena, node: &Node, source: &Value) -> Result<Value> {
-    match source {
-        Value::Array(v) if mode == &Mode::Many2Many => {
-            let mut new_arr = Vec::with_capacity(v.len());
-            for value in v {
-                let mut results = Map::new();
-                transform_recursive(arena, node, value, &mut results)?;
-                new_arr.push(Value::Object(results));
-            }
-            Ok(Value::Array(new_arr))
-        }
-        _ => {
-            let mut results = Map::new();
-            transform_recursive(arena, node, source, &mut results)?;
-            Ok(Value::Object(results))
-        }
+    /// adds a `null` constant to a value on the output.
+    #[inline]
+    pub fn add_constant_null<'a, S>(self, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_constant(Value::Null, to)
+    }
+
+    /// adds the value of an environment variable, read when applied, to a value on the output,
+    /// keeping environment specific values out of stored mapping definitions. An unset variable
+    /// is a missing source value. The variable must be allowed, see allow_env.
+    #[inline]
+    pub fn add_constant_env<'a, S>(self, var: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::ConstantEnv {
+            var: var.into(),
+            to: to.into(),
+            default: None,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds the value of an environment variable, read when applied, to a value on the output,
+    /// writing the default instead when the variable is unset.
+    #[inline]
+    pub fn add_constant_env_with_default<'a, S>(self, var: S, to: S, default: Value) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::ConstantEnv {
+            var: var.into(),
+            to: to.into(),
+            default: Some(default),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a value generated for each document, rather than read from the source, to a value on
+    /// the output eg. a UUID, the processing time or the record's position in a Many2Many batch.
+    #[inline]
+    pub fn add_generate<'a, S>(self, to: S, generator: Generator) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Generate {
+            generator,
+            to: to.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a direct mapping from an existing value to a new value on the output.
+    ///
+    /// When `from` is prefixed with `$out.` the value is instead copied from a destination path
+    /// that has already been written to the output, eg. `$out.user.id`.
+    ///
+    /// A `from` of `$`, or empty, selects the entire current document.
+    ///
+    /// Both `from` and `to` may instead be a JSON Pointer eg. `/user/addresses/0/city`.
+    ///
+    /// Wildcards eg. `items[*].name` select from every element of an Array. Each wildcard in `to`
+    /// is aligned with the matching wildcard in `from`, any remaining are collected into an Array,
+    /// which is empty when the source Array is empty or, unless the MissingPolicy is `Skip` or
+    /// `Error`, missing.
+    #[inline]
+    pub fn add_direct<'a, S>(self, from: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Direct {
+            from: from.into(),
+            to: to.into(),
+            kind: None,
+            only_if_absent: false,
+            default: None,
+        })
+    }
+
+    /// adds a direct mapping from an existing value to a new value on the output, writing the
+    /// default instead when the source value, or any of its parents, is missing or `null`.
+    #[inline]
+    pub fn add_direct_with_default<'a, S>(self, from: S, to: S, default: Value) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Direct {
+            from: from.into(),
+            to: to.into(),
+            kind: None,
+            only_if_absent: false,
+            default: Some(default),
+        })
+    }
+
+    /// adds a direct mapping which only writes to the output when nothing, other than `null`, has
+    /// been written to the destination yet, allowing fallbacks to be layered after more specific
+    /// mappings. Missing source values are not written.
+    #[inline]
+    pub fn add_direct_if_absent<'a, S>(self, from: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Direct {
+            from: from.into(),
+            to: to.into(),
+            kind: None,
+            only_if_absent: true,
+            default: None,
+        })
+    }
+
+    /// adds a direct mapping from an existing value, expected to be of the declared type, to a
+    /// new value on the output. Source values of any other type are handled according to the
+    /// Navigation the Transformer is built with.
+    #[inline]
+    pub fn add_direct_typed<'a, S>(self, from: S, to: S, kind: Type) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Direct {
+            from: from.into(),
+            to: to.into(),
+            kind: Some(kind),
+            only_if_absent: false,
+            default: None,
+        })
+    }
+
+    /// adds a mapping which selects values from the document using a JSONPath expression eg.
+    /// `$.items[?(@.active==true)].id`. Expressions containing only names and indexes produce a
+    /// single value, all others an Array of every value selected.
+    #[inline]
+    pub fn add_json_path<'a, S>(self, expr: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::JsonPath {
+            expr: expr.into(),
+            to: to.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which joins the values of several source namespaces, from the root of the
+    /// document, into a single String using the provided separator eg. `first_name` and `last_name`
+    /// into `full_name`. Missing and null sources are skipped; numbers and booleans are written as
+    /// text.
+    #[inline]
+    pub fn add_concat<'a, S>(self, from: &[S], to: S, separator: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>> + Clone,
+    {
+        self.add_mapping(Mapping::Concat {
+            from: from.iter().cloned().map(Into::into).collect(),
+            to: to.into(),
+            separator: separator.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which deep merges several source Objects, from the root of the document,
+    /// into a single Object eg. `defaults` and `overrides` into `config`. Later sources win and
+    /// Arrays are replaced; missing and null sources are skipped.
+    #[inline]
+    pub fn add_merge<'a, S>(self, from: &[S], to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>> + Clone,
+    {
+        self.add_merge_with(from, to, ArrayMerge::Replace)
+    }
+
+    /// adds a mapping which deep merges several source Objects, as add_merge, combining Arrays
+    /// using the provided ArrayMerge.
+    #[inline]
+    pub fn add_merge_with<'a, S>(self, from: &[S], to: S, arrays: ArrayMerge) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>> + Clone,
+    {
+        self.add_mapping(Mapping::Merge {
+            from: from.iter().cloned().map(Into::into).collect(),
+            to: to.into(),
+            arrays,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which renders a String template whose placeholders are source namespaces,
+    /// resolved from the root of the document, eg. `https://example.com/users/{user.id}`. Literal
+    /// braces are written as `{{` and `}}`. The value is missing if any placeholder is missing.
+    #[inline]
+    pub fn add_template<'a, S>(self, template: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Template {
+            template: template.into(),
+            to: to.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which converts the source value to the provided type eg. `"42"` to `42`,
+    /// writing `null` for values which can not be converted.
+    #[inline]
+    pub fn add_coerce<'a, S>(self, from: S, to: S, coerce: Coerce) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_coerce_with(from, to, coerce, CoerceFailure::Null)
+    }
+
+    /// adds a mapping which converts the source value to the provided type, with the provided
+    /// behaviour for values which can not be converted.
+    #[inline]
+    pub fn add_coerce_with<'a, S>(
+        self,
+        from: S,
+        to: S,
+        coerce: Coerce,
+        on_failure: CoerceFailure,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Coerce {
+            from: from.into(),
+            to: to.into(),
+            coerce,
+            on_failure,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which applies the nested Transformer to each element of a source Array
+    /// eg. `orders` to `items`, writing the Array of results.
+    #[inline]
+    pub fn add_map_array<'a, S>(self, from: S, to: S, transformer: Transformer) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::MapArray {
+            from: from.into(),
+            to: to.into(),
+            transformer: Box::new(transformer),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which converts a duration source value eg. `1h30m`, `PT20S` or a number of
+    /// seconds from the input format to the output format, writing `null` for values which are
+    /// not valid durations. Strings may be ISO-8601 or human durations regardless of the input
+    /// format, see DurationFormat::parse.
+    #[inline]
+    pub fn add_duration<'a, S>(
+        self,
+        from: S,
+        to: S,
+        input: DurationFormat,
+        output: DurationFormat,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_duration_with(from, to, input, output, CoerceFailure::Null)
+    }
+
+    /// adds a mapping which converts a duration source value, as add_duration, with the provided
+    /// behaviour for values which are not valid durations.
+    #[inline]
+    pub fn add_duration_with<'a, S>(
+        self,
+        from: S,
+        to: S,
+        input: DurationFormat,
+        output: DurationFormat,
+        on_failure: CoerceFailure,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Duration {
+            from: from.into(),
+            to: to.into(),
+            input,
+            output,
+            on_failure,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which writes the canonical serialization of the source value as a String,
+    /// see output::canonical, giving a stable input for hashing and checksums.
+    #[inline]
+    pub fn add_canonical<'a, S>(self, from: S, to: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Canonical {
+            from: from.into(),
+            to: to.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping applying the arithmetic ops in order to the numeric source value eg.
+    /// `[Scale(0.01), Round(2)]` to convert cents to dollars, see ComputeOp.
+    #[inline]
+    pub fn add_compute<'a, S>(self, from: S, to: S, ops: Vec<ComputeOp>) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Compute {
+            from: from.into(),
+            to: to.into(),
+            ops,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping writing the source value anonymized by the mask eg. hashing emails or
+    /// redacting all but the last digits of card numbers, see Mask.
+    #[inline]
+    pub fn add_mask<'a, S>(self, from: S, to: S, mask: Mask) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Mask {
+            from: from.into(),
+            to: to.into(),
+            mask,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping encrypting the source value with the Cipher supplied under the key name
+    /// when applied, see Transformer::apply_with_keys. Only the name is stored, so the key never
+    /// ends up in the serialized Transformer.
+    #[cfg(feature = "crypto")]
+    #[inline]
+    pub fn add_encrypt<'a, S>(self, from: S, to: S, key: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Encrypt {
+            from: from.into(),
+            to: to.into(),
+            key: key.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping decrypting a source value encrypted by an Encrypt mapping, see add_encrypt.
+    #[cfg(feature = "crypto")]
+    #[inline]
+    pub fn add_decrypt<'a, S>(self, from: S, to: S, key: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Decrypt {
+            from: from.into(),
+            to: to.into(),
+            key: key.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping calling the function registered under the name, see
+    /// TransformerBuilder::functions, with the source value and writing the value it returns.
+    /// Only the name is stored, so the mapping remains serializable.
+    #[inline]
+    pub fn add_function<'a, S>(self, from: S, to: S, name: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Function {
+            from: from.into(),
+            to: to.into(),
+            name: name.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which is only applied when the predicate holds for the document, otherwise
+    /// the destination is not written. Conditional mappings are applied at the root of the
+    /// document and so do not support wildcard sources.
+    #[inline]
+    pub fn add_when(self, when: Predicate, mapping: Mapping) -> Result<Self> {
+        self.add_mapping(Mapping::When {
+            when,
+            mapping: Box::new(mapping),
+        })
+    }
+
+    /// adds a mapping which writes the fallback, rather than failing the whole transformation,
+    /// when it can't be applied eg. a value which can't be coerced.
+    #[inline]
+    pub fn add_with_fallback(self, mapping: Mapping, on_error: Fallback) -> Result<Self> {
+        self.add_mapping(Mapping::OnError {
+            on_error,
+            mapping: Box::new(mapping),
+        })
+    }
+
+    /// adds a mapping which inspects the source value and writes the arm of the first case equal
+    /// to it, or the default arm when no case matches, eg. mapping `status` codes to labels. The
+    /// inspected value and arm sources are looked up from the root of the document and may be
+    /// `$ctx.` values; wildcards are not supported.
+    #[inline]
+    pub fn add_switch<'a, S>(
+        self,
+        on: S,
+        to: S,
+        cases: Vec<SwitchCase<'a>>,
+        default: Option<SwitchArm<'a>>,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Switch {
+            on: on.into(),
+            to: to.into(),
+            cases,
+            default,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which writes the result of evaluating the predicate against the document as
+    /// a boolean eg. a `high_value` flag derived from the `amount` and `country`.
+    #[inline]
+    pub fn add_derive_bool<'a, S>(self, to: S, when: Predicate) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::DeriveBool {
+            when,
+            to: to.into(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which splits a String source value by the separator into an Array of the
+    /// parts eg. `full_name` into `names`. The inverse of add_concat.
+    #[inline]
+    pub fn add_split<'a, S>(self, from: S, to: S, separator: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Split {
+            from: from.into(),
+            to: to.into(),
+            separator: separator.into(),
+            part: None,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping per destination which splits a String source value by the separator and
+    /// writes each part to the destination at the same position eg. `full_name` into `first` and
+    /// `last`. Destinations without a corresponding part are treated as missing.
+    #[inline]
+    pub fn add_split_into<'a, S>(mut self, from: S, to: &[S], separator: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>> + Clone,
+    {
+        let from = from.into();
+        let separator = separator.into();
+        for (i, to) in to.iter().cloned().enumerate() {
+            self = self.add_mapping(Mapping::Split {
+                from: from.clone(),
+                to: to.into(),
+                separator: separator.clone(),
+                part: Some(i),
+                only_if_absent: false,
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// adds a mapping which copies the source to the destination applying the manipulation to
+    /// every Object key, at any depth, eg. renaming camelCase keys to snake_case without
+    /// flattening the structure as add_flatten would.
+    #[inline]
+    pub fn add_rekey<'a, S>(
+        self,
+        from: S,
+        to: S,
+        manipulation: Box<dyn StringManipulation>,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Rekey {
+            from: from.into(),
+            to: to.into(),
+            manipulation,
+            only_if_absent: false,
+            locale: None,
+        })
+    }
+
+    /// adds a mapping which copies the source Object or Array to the destination, preserving its
+    /// structure, keeping only the keys included and dropping those excluded by the options eg.
+    /// `config` as `settings` without `db.password`.
+    #[inline]
+    pub fn add_subtree<'a, S>(self, from: S, to: S, options: SubtreeOps<'a>) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Subtree {
+            from: from.into(),
+            to: to.into(),
+            include: options.include.into_iter().map(Cow::Borrowed).collect(),
+            exclude: options.exclude.into_iter().map(Cow::Borrowed).collect(),
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which computes a single value from the values of the source eg. the `Sum`
+    /// of `line_items[*].amount` as `total`. The source is looked up from the root of the document
+    /// and may contain wildcards; a source without wildcards aggregates the elements of the Array
+    /// it refers to. Null values are skipped.
+    #[inline]
+    pub fn add_aggregate<'a, S>(self, from: S, to: S, agg: Agg) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Aggregate {
+            from: from.into(),
+            to: to.into(),
+            agg,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which zips two source Arrays, from the root of the document, element-wise into
+    /// an Array of Objects with the provided keys eg. `names` and `ages` into `people` as `name`
+    /// and `age`. Only as many elements as the shorter Array has are zipped.
+    #[inline]
+    pub fn add_zip<'a, S>(self, left: S, right: S, to: S, keys: (S, S)) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_zip_with(left, right, to, keys, ZipLength::Truncate)
+    }
+
+    /// adds a mapping which zips two source Arrays, as add_zip, handling Arrays of different
+    /// lengths as specified.
+    #[inline]
+    pub fn add_zip_with<'a, S>(
+        self,
+        left: S,
+        right: S,
+        to: S,
+        keys: (S, S),
+        length: ZipLength,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Zip {
+            from: vec![left.into(), right.into()],
+            to: to.into(),
+            keys: vec![keys.0.into(), keys.1.into()],
+            length,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which joins the elements of a source Array into a single String using the
+    /// separator eg. `tags` into `tags_csv`. The inverse of add_split. Numbers and booleans are
+    /// written as text, Objects and Arrays as JSON and nulls are skipped.
+    #[inline]
+    pub fn add_join<'a, S>(self, from: S, to: S, separator: S) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_join_with(from, to, separator, Stringify::Text)
+    }
+
+    /// adds a mapping which joins the elements of a source Array, as add_join, writing the
+    /// non-String elements as specified.
+    #[inline]
+    pub fn add_join_with<'a, S>(
+        self,
+        from: S,
+        to: S,
+        separator: S,
+        stringify: Stringify,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Join {
+            from: from.into(),
+            to: to.into(),
+            separator: separator.into(),
+            stringify,
+            only_if_absent: false,
+        })
+    }
+
+    /// adds a mapping which takes the existing value, either Object or Array, and flattens the data
+    /// and places that at the desired output location.
+    #[inline]
+    pub fn add_flatten<'a, S>(self, from: S, to: S, options: FlattenOps) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::Flatten {
+            from: from.into(),
+            to: to.into(),
+            prefix: options.prefix.map(|v| v.into()),
+            separator: options.separator.map(|v| v.into()),
+            array_separator: options.array_separator.map(|v| v.into()),
+            index_base: options.index_base,
+            index_format: options.index_format.map(|v| v.into()),
+            max_depth: options.max_depth,
+            manipulations: options.manipulations,
+            recursive: options.recursive,
+            only_if_absent: false,
+            locale: None,
+        })
+    }
+
+    /// adds a mapping which takes the existing value, either Object or Array, and flattens the
+    /// values in order into an Array at the desired output location. When recursive nested values
+    /// are flattened into the same Array and the manipulations are applied, in order, to every
+    /// String value.
+    #[inline]
+    pub fn add_flatten_list<'a, S>(
+        self,
+        from: S,
+        to: S,
+        recursive: bool,
+        manipulations: Vec<Box<dyn StringManipulation>>,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.add_mapping(Mapping::FlattenList {
+            from: from.into(),
+            to: to.into(),
+            manipulations,
+            recursive,
+            only_if_absent: false,
+            locale: None,
+        })
+    }
+
+    /// builds a Transformer for each of the specs. Each distinct namespace is parsed only once
+    /// across all of the specs, and the keys the Transformers read source values by are interned
+    /// once and shared by all of them, reducing the time and memory taken to load many similar
+    /// specs at once. The rules keep their own copies of their namespaces.
+    pub fn build_many(specs: Vec<Vec<Mapping>>) -> Result<Vec<Transformer>> {
+        let mut keys = HashMap::new();
+        let mut cache: HashMap<String, Vec<Namespace>> = HashMap::new();
+        let mut paths = |path: Cow<str>| -> Result<Vec<Namespace>> {
+            if let Some(namespace) = cache.get(path.as_ref()) {
+                return Ok(namespace.clone());
+            }
+            let namespace = parse_path(Cow::Borrowed(path.as_ref()))?;
+            cache.insert(path.into_owned(), namespace.clone());
+            Ok(namespace)
+        };
+        specs
+            .into_iter()
+            .map(|spec| {
+                let mut builder = TransformerBuilder::default();
+                for mapping in spec {
+                    builder = builder.add_mapping_with(mapping, &mut paths)?;
+                }
+                builder.build_with(&mut keys)
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn build(self) -> Result<Transformer> {
+        self.build_with(&mut HashMap::new())
+    }
+
+    /// builds the Transformer, interning the keys its Plan reads in the provided map.
+    fn build_with(self, keys: &mut HashMap<String, Arc<str>>) -> Result<Transformer> {
+        self.limits
+            .check(&ComplexityScore::new(&self.root, &self.deferred))?;
+        let mut unknown = BTreeSet::new();
+        let mut check = |rule: &dyn Rule| {
+            for name in rule.functions() {
+                if !self.functions.contains(name) {
+                    unknown.insert(name.to_owned());
+                }
+            }
+        };
+        self.root.visit_rules(&mut |_, rule| check(rule));
+        self.deferred.iter().for_each(|rule| check(rule.as_ref()));
+        if !unknown.is_empty() {
+            return Err(Error::UnknownFunction(
+                unknown.into_iter().collect::<Vec<_>>().join(", "),
+            ));
+        }
+        let mut disallowed = BTreeSet::new();
+        let mut check = |rule: &dyn Rule| {
+            for var in rule.env_vars() {
+                if !self.env.contains(var) {
+                    disallowed.insert(var.to_owned());
+                }
+            }
+        };
+        self.root.visit_rules(&mut |_, rule| check(rule));
+        self.deferred.iter().for_each(|rule| check(rule.as_ref()));
+        if !disallowed.is_empty() {
+            return Err(Error::EnvNotAllowed(
+                disallowed.into_iter().collect::<Vec<_>>().join(", "),
+            ));
+        }
+        if self.destination_conflict == DestinationConflict::Error {
+            let conflicts = self.destination_conflicts();
+            if !conflicts.is_empty() {
+                return Err(Error::DestinationConflict(conflicts.join(", ")));
+            }
+        }
+        Ok(Transformer {
+            plan: OnceLock::from(Plan::compile_with(&self.root, keys)),
+            root: self.root,
+            mode: self.mode,
+            batch: self.batch,
+            scalar: self.scalar,
+            navigation: self.navigation,
+            missing: self.missing,
+            missing_policy: self.missing_policy,
+            locale: self.locale,
+            passthrough: self.passthrough,
+            consumed: self.consumed,
+            excluded: self.excluded,
+            destination_prefix: self.destination_prefix,
+            destination_root: self.destination_root,
+            merge: self.merge,
+            limits: self.limits,
+            destination_conflict: self.destination_conflict,
+            index_base: self.index_base,
+            index_width: self.index_width,
+            parallel: self.parallel,
+            hooks: self.hooks,
+            functions: self.functions,
+            env: Arc::new(self.env),
+            #[cfg(feature = "schema")]
+            input_schema: self.input_schema,
+            #[cfg(feature = "schema")]
+            output_schema: self.output_schema,
+            deferred: self.deferred,
+        })
+    }
+}
+
+/// Transformable is implemented by structs which can be built from other documents by a
+/// Transformer, usually derived with `#[derive(Bumblebee)]` using the `derive` feature so the
+/// source namespaces are written alongside the fields they populate.
+pub trait Transformable: DeserializeOwned {
+    /// returns the TransformerBuilder with the mappings populating the struct, which further
+    /// mappings or settings can be added to.
+    fn transformer_builder() -> Result<TransformerBuilder>;
+
+    /// returns the Transformer populating the struct.
+    #[inline]
+    fn transformer() -> Result<Transformer> {
+        Self::transformer_builder()?.build()
+    }
+
+    /// builds the struct from any serializable data, building the Transformer each time. Prefer
+    /// keeping the Transformer and using Transformer::apply_to when transforming many inputs.
+    #[inline]
+    fn transform_from<S>(input: S) -> Result<Self>
+    where
+        S: Serialize,
+    {
+        Self::transformer()?.apply_to(input)
+    }
+}
+
+/// DEFINITION_VERSION is the version of the JSON definition written by
+/// `Transformer::to_json_definition`. Version 1 is the bare, unversioned, serialized Transformer
+/// written before definitions were versioned.
+pub const DEFINITION_VERSION: u64 = 2;
+
+/// Definition is the versioned envelope a Transformer is stored in.
+#[derive(Serialize)]
+struct Definition<'t> {
+    version: u64,
+    transformer: &'t Transformer,
+}
+
+/// StoredDefinition is a Definition being loaded.
+#[derive(Deserialize)]
+struct StoredDefinition {
+    transformer: Transformer,
+}
+
+/// Transformer is used to apply the transformation that's been built to any Serializable data.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transformer {
+    root: Arena,
+    // the root compiled when built, or first applied when deserialized
+    #[serde(skip)]
+    plan: OnceLock<Plan>,
+    mode: Mode,
+    #[serde(default)]
+    batch: Batch,
+    #[serde(default)]
+    scalar: Scalar,
+    #[serde(default)]
+    navigation: Navigation,
+    #[serde(default)]
+    missing: Missing,
+    #[serde(default)]
+    missing_policy: MissingPolicy,
+    #[serde(default)]
+    locale: LocaleContext,
+    #[serde(default)]
+    passthrough: bool,
+    // full source namespaces read by rules which are not passed through
+    #[serde(default)]
+    consumed: Vec<Vec<Namespace>>,
+    #[serde(default)]
+    excluded: Vec<Vec<Namespace>>,
+    #[serde(default)]
+    destination_prefix: String,
+    #[serde(default)]
+    destination_root: Vec<String>,
+    // source namespace batch elements are merged by
+    #[serde(default)]
+    merge: Option<(Vec<Namespace>, Conflict)>,
+    #[serde(default)]
+    limits: ComplexityLimits,
+    #[serde(default)]
+    destination_conflict: DestinationConflict,
+    #[serde(default)]
+    index_base: usize,
+    #[serde(default)]
+    index_width: usize,
+    #[serde(default)]
+    parallel: bool,
+    #[serde(skip)]
+    hooks: Hooks,
+    #[serde(skip)]
+    functions: FunctionRegistry,
+    // environment variables ConstantEnv mappings are allowed to read, never serialized so a
+    // stored definition can't grant itself access
+    #[serde(skip)]
+    env: Arc<BTreeSet<String>>,
+    #[cfg(feature = "schema")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_schema: Option<(Schema, SchemaPolicy)>,
+    #[cfg(feature = "schema")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_schema: Option<Schema>,
+    #[serde(default)]
+    deferred: Vec<Box<dyn Rule>>,
+}
+
+impl Transformer {
+    /// returns the Transformers nested within the rules, at any depth.
+    fn nested(&self) -> Vec<&Transformer> {
+        let mut nested = Vec::new();
+        for rule in self
+            .root
+            .rules()
+            .chain(self.deferred.iter().map(|rule| rule.as_ref()))
+        {
+            for transformer in rule.transformers() {
+                nested.push(transformer);
+                nested.extend(transformer.nested());
+            }
+        }
+        nested
+    }
+
+    /// returns the names of the functions called by the rules, including those of nested
+    /// Transformers, which aren't registered with the Transformer itself. They must be registered
+    /// with the Transformer it's nested in.
+    pub(crate) fn unregistered_functions(&self) -> Vec<&str> {
+        self.root
+            .rules()
+            .chain(self.deferred.iter().map(|rule| rule.as_ref()))
+            .flat_map(|rule| rule.functions())
+            .filter(|name| !self.functions.contains(name))
+            .collect()
+    }
+
+    /// returns the names of the environment variables read by the rules, including those of
+    /// nested Transformers, which the Transformer itself isn't allowed to read. They must be
+    /// allowed by the Transformer it's nested in.
+    pub(crate) fn disallowed_env(&self) -> Vec<&str> {
+        self.root
+            .rules()
+            .chain(self.deferred.iter().map(|rule| rule.as_ref()))
+            .flat_map(|rule| rule.env_vars())
+            .filter(|var| !self.env.contains(*var))
+            .collect()
+    }
+
+    /// returns a static estimate of how expensive the Transformer is to apply.
+    #[inline]
+    pub fn complexity(&self) -> ComplexityScore {
+        ComplexityScore::new(&self.root, &self.deferred)
+    }
+
+    /// returns a Transformer reversing this one eg. for two-way sync between systems. Only
+    /// direct mappings between Object keys and Array indexes can be inverted, an
+    /// `Error::NotInvertible` listing every rule which can't is returned otherwise.
+    pub fn invert(&self) -> Result<Transformer> {
+        let mut reasons = Vec::new();
+        if !self.hooks.before.is_empty() || !self.hooks.after.is_empty() {
+            reasons.push(String::from("hooks can not be inverted"));
+        }
+        if self.merge.is_some() {
+            reasons.push(String::from("merged batches can not be inverted"));
+        }
+        let wrapped = !self.destination_prefix.is_empty() || !self.destination_root.is_empty();
+        if self.passthrough && wrapped {
+            reasons.push(String::from(
+                "passthrough with a destination prefix or root can not be inverted",
+            ));
+        }
+
+        let mut mappings = Vec::new();
+        let mut invert = |namespace: &[Namespace], rule: &dyn Rule| match rule.invert(namespace) {
+            Ok(mapping) => mappings.push(mapping),
+            Err(Error::NotInvertible(reason)) => reasons.push(reason),
+            Err(e) => reasons.push(e.to_string()),
+        };
+        self.root.visit_rules(&mut invert);
+        for rule in &self.deferred {
+            invert(&[], rule.as_ref());
+        }
+
+        // the inverse reads destinations from within the prefixed and rooted output
+        let mut mappings = mappings
+            .into_iter()
+            .map(|mapping| match mapping {
+                Mapping::Direct { from, to, .. } if wrapped => {
+                    let mut namespace = parse_path(from)?;
+                    if let Some(Namespace::Object { id } | Namespace::Array { id, .. }) =
+                        namespace.first_mut()
+                    {
+                        id.insert_str(0, &self.destination_prefix);
+                    }
+                    let root = self
+                        .destination_root
+                        .iter()
+                        .map(|id| Namespace::Object { id: id.clone() });
+                    let namespace = root.chain(namespace).collect::<Vec<_>>();
+                    Ok(Mapping::Direct {
+                        from: Cow::Owned(Namespace::format(&namespace)),
+                        to,
+                        kind: None,
+                        only_if_absent: false,
+                        default: None,
+                    })
+                }
+                mapping => Ok(mapping),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // a destination written by multiple rules can't be read back into all of them
+        mappings.sort_by(|a, b| mapping_from(a).cmp(mapping_from(b)));
+        for pair in mappings.windows(2) {
+            if mapping_from(&pair[0]) == mapping_from(&pair[1]) {
+                reasons.push(format!(
+                    "`{}`: destinations written by multiple rules can not be inverted",
+                    mapping_from(&pair[0])
+                ));
+            }
+        }
+        if !reasons.is_empty() {
+            reasons.dedup();
+            return Err(Error::NotInvertible(reasons.join(", ")));
+        }
+
+        TransformerBuilder {
+            mode: self.mode,
+            batch: self.batch.clone(),
+            scalar: self.scalar,
+            navigation: self.navigation,
+            missing: self.missing,
+            missing_policy: self.missing_policy,
+            locale: self.locale.clone(),
+            passthrough: self.passthrough,
+            limits: self.limits,
+            destination_conflict: self.destination_conflict,
+            index_width: self.index_width,
+            parallel: self.parallel,
+            ..TransformerBuilder::default()
+        }
+        .add_mappings(mappings)?
+        .build()
+    }
+
+    /// returns a TransformerBuilder with a copy of the Transformer's rules and settings, leaving
+    /// the Transformer in place eg. to derive per-customer Transformers from a shared base.
+    ///
+    /// Rules are copied by serializing them, so rules which can't be, such as those added by
+    /// add_fn, fail. Hooks and registered functions aren't serialized, those of the Transformer
+    /// are copied but an `Error::Definition` is returned when a nested Transformer, see
+    /// add_map_array, has any rather than dropping them.
+    pub fn to_builder(&self) -> Result<TransformerBuilder> {
+        if self.nested().iter().any(|nested| {
+            !nested.hooks.before.is_empty()
+                || !nested.hooks.after.is_empty()
+                || !nested.functions.is_empty()
+        }) {
+            return Err(Error::Definition(String::from(
+                "hooks and functions of nested Transformers can not be copied",
+            )));
+        }
+        let mut copy: Transformer = serde_json::from_value(serde_json::to_value(self)?)?;
+        copy.hooks = self.hooks.clone();
+        copy.functions = self.functions.clone();
+        copy.env = self.env.clone();
+        Ok(copy.into_builder())
+    }
+
+    /// returns a JSON Schema describing the source documents the Transformer reads, the fields
+    /// rules read from and, where a rule expects one, their type, eg. to publish as a contract to
+    /// the teams producing them. Fields are never required, as missing values are tolerated.
+    pub fn input_schema(&self) -> Value {
+        let mut schema = Map::new();
+        schema.insert(
+            String::from("$schema"),
+            Value::from("http://json-schema.org/draft-07/schema#"),
+        );
+        let mut describe_rule = |namespace: &[Namespace], rule: &dyn Rule| {
+            for path in rule.source_paths(namespace) {
+                describe(&mut schema, &path, rule.source_type(), false);
+            }
+        };
+        self.root.visit_rules(&mut describe_rule);
+        for rule in &self.deferred {
+            describe_rule(&[], rule.as_ref());
+        }
+        if !schema.contains_key("type") {
+            set_type(&mut schema, Type::Object.name());
+        }
+        Value::Object(schema)
+    }
+
+    /// returns a JSON Schema describing the documents the Transformer produces, the fields rules
+    /// write to, including Array indexes, and the type of value written where known, eg. to
+    /// generate typed structs downstream. Values written by rules may always be `null`, for when
+    /// their source is missing, and passed through fields aren't described.
+    pub fn output_schema(&self) -> Value {
+        let mut schema = Map::new();
+        let mut describe_rule = |rule: &dyn Rule| {
+            if let Some(destination) = rule.destination() {
+                let kind = rule.destination_type();
+                describe(&mut schema, &destination, kind, true);
+                if kind.is_some() {
+                    describe(&mut schema, &destination, Some("null"), true);
+                }
+            }
+        };
+        self.root.visit_rules(&mut |_, rule| describe_rule(rule));
+        for rule in &self.deferred {
+            describe_rule(rule.as_ref());
+        }
+        set_type(&mut schema, Type::Object.name());
+
+        if let (false, Some(Value::Object(properties))) = (
+            self.destination_prefix.is_empty(),
+            schema.get_mut("properties"),
+        ) {
+            *properties = mem::take(properties)
+                .into_iter()
+                .map(|(k, v)| (self.destination_prefix.clone() + &k, v))
+                .collect();
+        }
+        for id in self.destination_root.iter().rev() {
+            let mut properties = Map::new();
+            properties.insert(id.clone(), Value::Object(schema));
+            schema = Map::new();
+            set_type(&mut schema, Type::Object.name());
+            schema.insert(String::from("properties"), Value::Object(properties));
+        }
+        schema.insert(
+            String::from("$schema"),
+            Value::from("http://json-schema.org/draft-07/schema#"),
+        );
+        Value::Object(schema)
+    }
+
+    /// returns the Mappings the Transformer was built from, reconstructed from its rules, so they
+    /// can be displayed and edited eg. after loading a serialized Transformer. Destination Array
+    /// indexes are relative to the index base, so the Mappings can be added to a builder with
+    /// the same base. Custom rules have no Mapping and are skipped.
+    pub fn mappings(&self) -> Result<Vec<Mapping<'static>>> {
+        let mut mappings = Vec::new();
+        let mut error = None;
+        self.root
+            .visit_rules(&mut |namespace, rule| match rule.mapping(namespace) {
+                Ok(Some(mapping)) => mappings.push(mapping),
+                Ok(None) => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        for rule in &self.deferred {
+            mappings.extend(rule.mapping(&[])?);
+        }
+        if self.index_base > 0 {
+            for mapping in &mut mappings {
+                let to = mapping_to(mapping);
+                let mut namespace = parse_path(mem::take(to))?;
+                for ns in &mut namespace {
+                    if let Namespace::Array { index, .. } = ns {
+                        *index += self.index_base;
+                    }
+                }
+                *to = Cow::Owned(Namespace::format(&namespace));
+            }
+        }
+        Ok(mappings)
+    }
+
+    /// returns the Transformer as a versioned JSON definition eg.
+    /// `{"version":2,"transformer":{...}}`, to be stored and later loaded by
+    /// `from_json_definition`. Hooks are not serializable and so are not part of the definition.
+    pub fn to_json_definition(&self) -> Result<String> {
+        Ok(serde_json::to_string(&Definition {
+            version: DEFINITION_VERSION,
+            transformer: self,
+        })?)
+    }
+
+    /// loads a Transformer from a JSON definition written by `to_json_definition`, migrating
+    /// definitions written by earlier versions, including bare unversioned Transformers. Returns
+    /// an `Error::Definition` for definitions written by a newer version than supported.
+    pub fn from_json_definition(definition: &str) -> Result<Transformer> {
+        let value: Value = serde_json::from_str(definition)?;
+        let version = match value.get("version") {
+            Some(version) => version.as_u64().ok_or_else(|| {
+                Error::Definition(format!(
+                    "version must be a positive integer, found {}",
+                    version
+                ))
+            })?,
+            None => 1,
+        };
+        // deserialized again from the text so errors report their line and column
+        match version {
+            // the bare Transformer, any Arena child ranges are expanded when it's deserialized
+            1 => Ok(serde_json::from_str(definition)?),
+            DEFINITION_VERSION if value.get("transformer").is_none() => {
+                Err(Error::Definition(String::from("missing transformer")))
+            }
+            DEFINITION_VERSION => {
+                Ok(serde_json::from_str::<StoredDefinition>(definition)?.transformer)
+            }
+            0 => Err(Error::Definition(String::from("unknown version 0"))),
+            version => Err(Error::Definition(format!(
+                "version {} is newer than the supported version {}",
+                version, DEFINITION_VERSION
+            ))),
+        }
+    }
+
+    /// loads a Transformer from the definition file at the path, the format being detected by
+    /// the extension. `.json` files may either contain a JSON definition, see
+    /// `from_json_definition`, or an Array of Mappings and `.bb` files mappings written in the
+    /// text DSL, see the `dsl` module. Errors parsing the file are returned as an `Error::Parse`
+    /// with the line and column they occurred at.
+    pub fn from_path<P>(path: P) -> Result<Transformer>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let contents = fs::read_to_string(path)?;
+        let transformer = match path.extension().and_then(OsStr::to_str) {
+            Some("json") if contents.trim_start().starts_with('[') => {
+                serde_json::from_str::<Vec<Mapping>>(&contents)
+                    .map_err(Error::from)
+                    .and_then(|mappings| TransformerBuilder::default().add_mappings(mappings))
+                    .and_then(TransformerBuilder::build)
+            }
+            Some("json") => Transformer::from_json_definition(&contents),
+            Some("bb") => TransformerBuilder::from_dsl(&contents).and_then(|b| b.build()),
+            _ => {
+                return Err(Error::Definition(format!(
+                    "`{}` is not a supported format, expected a .json or .bb file",
+                    name
+                )))
+            }
+        };
+        transformer.map_err(|e| match e {
+            Error::Json(e) if e.line() > 0 => {
+                let location = format!(" at line {} column {}", e.line(), e.column());
+                let message = e.to_string();
+                Error::Parse {
+                    line: e.line(),
+                    column: e.column(),
+                    message: message.trim_end_matches(&location).to_owned(),
+                    path: name,
+                }
+            }
+            Error::Dsl {
+                line,
+                column,
+                message,
+            } => Error::Parse {
+                path: name,
+                line,
+                column,
+                message,
+            },
+            e => e,
+        })
+    }
+
+    /// sets the JSON Schema every transformed document is validated against, failing the
+    /// transformation with an `Error::SchemaViolation` listing every violation when it doesn't
+    /// match, eg. to catch required values silently written as `null`. In Many2Many mode each
+    /// element of a batch is validated. The schema is kept when the Transformer is serialized.
+    #[cfg(feature = "schema")]
+    #[inline]
+    pub fn with_output_schema(mut self, schema: Value) -> Result<Self> {
+        self.output_schema = Some(Schema::new(schema)?);
+        Ok(self)
+    }
+
+    /// sets the JSON Schema every source document is validated against before it's transformed,
+    /// eg. to fail fast when an upstream changes shape rather than writing a document full of
+    /// `null`s. The policy decides whether a document which doesn't match is rejected or
+    /// transformed regardless. In Many2Many mode each element of a batch is validated.
+    #[cfg(feature = "schema")]
+    #[inline]
+    pub fn with_input_schema(mut self, schema: Value, policy: SchemaPolicy) -> Result<Self> {
+        self.input_schema = Some((Schema::new(schema)?, policy));
+        Ok(self)
+    }
+
+    /// returns every value of the source document which does not match the input schema, see
+    /// with_input_schema.
+    #[cfg(feature = "schema")]
+    #[inline]
+    pub fn validate_input(&self, input: &Value) -> Vec<Violation> {
+        match &self.input_schema {
+            Some((schema, _)) => schema.validate(input),
+            None => Vec::new(),
+        }
+    }
+
+    /// turns the Transformer back into a TransformerBuilder, keeping the existing source tree, so
+    /// mappings can be added or removed without rebuilding from scratch.
+    #[inline]
+    pub fn into_builder(self) -> TransformerBuilder {
+        TransformerBuilder {
+            root: self.root,
+            mode: self.mode,
+            batch: self.batch,
+            scalar: self.scalar,
+            navigation: self.navigation,
+            missing: self.missing,
+            missing_policy: self.missing_policy,
+            locale: self.locale,
+            passthrough: self.passthrough,
+            consumed: self.consumed,
+            excluded: self.excluded,
+            destination_prefix: self.destination_prefix,
+            destination_root: self.destination_root,
+            merge: self.merge,
+            limits: self.limits,
+            destination_conflict: self.destination_conflict,
+            index_base: self.index_base,
+            index_width: self.index_width,
+            parallel: self.parallel,
+            hooks: self.hooks,
+            functions: self.functions,
+            env: (*self.env).clone(),
+            #[cfg(feature = "schema")]
+            input_schema: self.input_schema,
+            #[cfg(feature = "schema")]
+            output_schema: self.output_schema,
+            deferred: self.deferred,
+        }
+    }
+
+    /// applies the transformation to an already parsed JSON Value, without taking ownership of it.
+    #[inline]
+    pub fn apply(&self, input: &Value) -> Result<Value> {
+        self.transform(input)
+    }
+
+    /// applies the transformation to JSON withing a string
+    #[inline]
+    pub fn apply_from_str<'a, S>(&self, input: S) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.apply(&serde_json::from_str(&input.into())?)
+    }
+
+    /// applies the transformation to JSON within a string and serializes the result to a string
+    /// using the provided options.
+    #[inline]
+    pub fn apply_from_str_with<'a, S>(&self, input: S, options: OutputOptions) -> Result<String>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        output::to_string(&self.apply_from_str(input)?, options)
+    }
+
+    /// applies the transformation to any serializable data and returns your desired structure.
+    #[inline]
+    pub fn apply_to<S, D>(&self, input: S) -> Result<D>
+    where
+        S: Serialize,
+        D: DeserializeOwned,
+    {
+        let results = self.apply(&serde_json::to_value(input)?)?;
+        Ok(serde_json::from_value::<D>(results)?)
+    }
+
+    /// applies the transformation to JSON read from the reader, without first reading it into a
+    /// string. The reader is buffered.
+    #[inline]
+    pub fn apply_from_reader<R>(&self, reader: R) -> Result<Value>
+    where
+        R: io::Read,
+    {
+        self.apply(&serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+
+    /// applies the transformation to any serializable data and serializes the result as JSON to
+    /// the writer, without first serializing it to a string. The writer is buffered.
+    #[inline]
+    pub fn apply_to_writer<S, W>(&self, input: S, writer: W) -> Result<()>
+    where
+        S: Serialize,
+        W: io::Write,
+    {
+        let results = self.apply(&serde_json::to_value(input)?)?;
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, &results)?;
+        io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    /// applies the transformation to an owned JSON value, returning the output along with the
+    /// untouched input. This avoids cloning or re-serializing the input when both are required.
+    /// The input is also returned with the error when the transformation fails eg. to quarantine
+    /// it.
+    // the input is returned as is, boxing it would cost an allocation on every error
+    #[allow(clippy::result_large_err)]
+    #[inline]
+    pub fn apply_owned(&self, input: Value) -> std::result::Result<(Value, Value), (Error, Value)> {
+        match self.transform(&input) {
+            Ok(output) => Ok((output, input)),
+            Err(e) => Err((e, input)),
+        }
+    }
+
+    /// applies the transformation, returning the RFC 6902 JSON Patch which transforms the input
+    /// into the output instead of the output itself.
+    #[inline]
+    pub fn diff_patch(&self, input: &Value) -> Result<Vec<PatchOp>> {
+        Ok(patch::diff(input, &self.transform(input)?))
+    }
+
+    /// applies the transformation to JSON within a string using the provided seed and time for
+    /// any generated values, allowing the results to be reproduced exactly.
+    #[inline]
+    pub fn apply_with_entropy<'a, S>(&self, input: S, seed: u64, now: SystemTime) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.transform_with(
+            &self.context(Entropy::new(seed, now)),
+            &serde_json::from_str(&input.into())?,
+        )
+    }
+
+    /// applies the transformation to JSON within a string with the provided apply time values,
+    /// which mappings can reference as `$ctx.` sources, `{$ctx.}` template placeholders and
+    /// `${ctx.}` placeholders in String constants eg. `"${ctx.request_id}"`, so per request
+    /// values can be injected without rebuilding the Transformer.
+    #[inline]
+    pub fn apply_with_context<'a, S>(&self, input: S, context: &Map<String, Value>) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.transform_with(
+            &self.context(Entropy::default()).with_values(context),
+            &serde_json::from_str(&input.into())?,
+        )
+    }
+
+    /// applies the transformation to JSON within a string with the Ciphers used by Encrypt and
+    /// Decrypt mappings, see crypto.
+    #[cfg(feature = "crypto")]
+    #[inline]
+    pub fn apply_with_keys<'a, S>(&self, input: S, keys: &Keys) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.transform_with(
+            &self.context(Entropy::default()).with_keys(keys),
+            &serde_json::from_str(&input.into())?,
+        )
+    }
+
+    /// applies the transformation to JSON within a string with the provided apply time flags,
+    /// which mappings can reference using `$ctx.flags.` eg. `$ctx.flags.new_pricing`, allowing a
+    /// single Transformer to branch on per request rollout flags.
+    #[inline]
+    pub fn apply_with_flags<'a, S>(&self, input: S, flags: Map<String, Value>) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        self.transform_with(
+            &self.context(Entropy::default()).with_flags(flags),
+            &serde_json::from_str(&input.into())?,
+        )
+    }
+
+    /// applies the transformation to JSON within a string while measuring the time spent in each
+    /// rule, returning the output along with the Timings.
+    #[inline]
+    pub fn apply_timed<'a, S>(&self, input: S) -> Result<(Value, Timings)>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let ctx = self.context(Entropy::default()).timed();
+        let output = self.transform_with(&ctx, &serde_json::from_str(&input.into())?)?;
+        let timings = Timings::new(&self.root, &self.deferred, ctx.take_timings());
+        Ok((output, timings))
+    }
+
+    /// applies the transformation to JSON within a string, adding the destination fields written
+    /// and source paths resolved to the Metrics. Metrics are opt-in as they add considerable
+    /// overhead.
+    pub fn apply_with_metrics<'a, S>(&self, input: S, metrics: &mut Metrics) -> Result<Value>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let ctx = self.context(Entropy::default()).traced();
+        let output = self.transform_with(&ctx, &serde_json::from_str(&input.into())?);
+        metrics.record(&self.root, ctx.take_traces());
+        output
+    }
+
+    /// applies the transformation to JSON within a string, continuing past the failure of a rule,
+    /// missing source or type mismatch and returning all of the errors along with the output
+    /// eg. to report every problem with a record at once. The values of failed rules are not
+    /// written. Errors which prevent transforming entirely, such as invalid JSON, are returned
+    /// with a `null` output.
+    pub fn apply_collecting<'a, S>(&self, input: S) -> (Value, Vec<Error>)
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let ctx = self.context(Entropy::default()).collecting();
+        let output = serde_json::from_str(&input.into())
+            .map_err(Error::from)
+            .and_then(|source| self.transform_with(&ctx, &source));
+        let mut errors = ctx.take_errors();
+        match output {
+            Ok(output) => (output, errors),
+            Err(e) => {
+                errors.push(e);
+                (Value::Null, errors)
+            }
+        }
+    }
+
+    /// applies the transformation to JSON within a string while recording, for each rule
+    /// applied, the source path read, if it resolved and what was written where eg. to debug
+    /// mappings. Rules not applied, as a parent of their source is missing, are reported as not
+    /// resolving their source. A failed transformation is reported rather than returned as an
+    /// error, so the rules applied up to the failure can be inspected.
+    pub fn explain<'a, S>(&self, input: S) -> Result<TransformReport>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let ctx = self.context(Entropy::default()).traced();
+        let output = self.transform_with(&ctx, &serde_json::from_str(&input.into())?);
+        Ok(TransformReport::new(
+            &self.root,
+            &self.deferred,
+            ctx.take_traces(),
+            output,
+        ))
+    }
+
+    /// records the types and example values of every source path of the document in the
+    /// profile, without transforming it. In Many2Many mode each element of a batch is recorded as
+    /// a separate document.
+    #[inline]
+    pub fn profile(&self, source: &Value, profile: &mut Profile) {
+        match source {
+            Value::Array(v) if self.mode == Mode::Many2Many && self.batch.is_batch(v) => {
+                for document in v {
+                    profile.observe(document);
+                }
+            }
+            _ => profile.observe(&self.collapse(source)),
+        }
+    }
+
+    /// returns the source paths of the document which are not read by any rule, with Array
+    /// indexes replaced by wildcards eg. `items[*].sku`, to audit if the mappings still cover
+    /// everything a source sends. In Many2Many mode each element of a batch is audited as a
+    /// separate document. The sources of custom rules are unknown so are always reported.
+    pub fn unmapped_fields(&self, source: &Value) -> Vec<String> {
+        let mut paths = BTreeSet::new();
+        match source {
+            Value::Array(v) if self.mode == Mode::Many2Many && self.batch.is_batch(v) => {
+                for document in v {
+                    self.unmapped(document, &mut paths);
+                }
+            }
+            _ => self.unmapped(&self.collapse(source), &mut paths),
+        }
+        paths.into_iter().collect()
+    }
+
+    #[inline]
+    fn unmapped(&self, document: &Value, paths: &mut BTreeSet<String>) {
+        if self.consumed.iter().any(|path| path.is_empty()) {
+            return;
+        }
+        let mut remaining = document.clone();
+        for path in &self.consumed {
+            remove_path(path, &mut remaining);
+        }
+        collect_unmapped(&mut Vec::new(), &remaining, document, paths);
+    }
+
+    /// applies the transformation to a stream of JSON values read from the reader, whitespace or
+    /// newline delimited, passing each result to the sink as soon as it's transformed. In
+    /// Many2Many mode each element of a batch is passed to the sink separately. Each value is a
+    /// record numbered by its position in the stream. Processing stops at the first error.
+    #[inline]
+    pub fn apply_stream_to_sink<R, K>(&self, reader: R, sink: &mut K) -> Result<()>
+    where
+        R: io::Read,
+        K: OutputSink + ?Sized,
+    {
+        let stream = serde_json::Deserializer::from_reader(reader).into_iter::<Value>();
+        for (index, source) in stream.enumerate() {
+            match self.transform_record(index, &source?)? {
+                Value::Array(results) if self.mode == Mode::Many2Many => {
+                    for doc in results {
+                        sink.accept(doc)?;
+                    }
+                }
+                result => sink.accept(result)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// applies the transformation to newline delimited JSON read from the reader, writing each
+    /// result as newline delimited JSON to the writer as soon as it's transformed, so the stream
+    /// is never held in memory. Both the reader and writer are buffered. In Many2Many mode each
+    /// element of a batch is written on it's own line. Processing stops at the first error.
+    #[inline]
+    pub fn apply_ndjson<R, W>(&self, reader: R, writer: W) -> Result<()>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut sink = LineSink::new(io::BufWriter::new(writer), OutputOptions::default());
+        self.apply_stream_to_sink(io::BufReader::new(reader), &mut sink)?;
+        io::Write::flush(&mut sink.into_inner())?;
+        Ok(())
+    }
+
+    /// applies the transformation to each element of a top-level JSON Array read from the reader,
+    /// parsing the elements incrementally so the Array never has to be fully held in memory. Each
+    /// element is a record numbered by its index. The iterator ends after the first error.
+    #[inline]
+    pub fn iter_array<'t, R>(&'t self, reader: R) -> impl Iterator<Item = Result<Value>> + 't
+    where
+        R: io::Read + 't,
+    {
+        ArrayReader::new(reader)
+            .enumerate()
+            .map(move |(index, element)| self.transform_record(index, &element?))
+    }
+
+    /// applies the transformation to JSON within a string, routing any documents that fail to
+    /// transform into the returned quarantine rather than failing all of them. In Many2Many mode
+    /// the output contains only the batch elements which succeeded, otherwise the output of a
+    /// failed document is `null`. An error is only returned when the input is not valid JSON or
+    /// the batch can not be merged, see TransformerBuilder::merge_by.
+    #[inline]
+    pub fn apply_quarantined<'a, S>(&self, input: S) -> Result<(Value, Vec<Quarantined>)>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let source: Value = serde_json::from_str(&input.into())?;
+        let mut quarantine = Vec::new();
+        let output = match source {
+            Value::Array(v) if self.mode == Mode::Many2Many && self.batch.is_batch(&v) => {
+                let v = match &self.merge {
+                    Some((namespace, conflict)) => merge_by(namespace, *conflict, &v)?,
+                    None => v,
+                };
+                let mut new_arr = Vec::with_capacity(v.len());
+                for (index, document) in v.into_iter().enumerate() {
+                    let ctx = self.context(Entropy::default());
+                    ctx.set_record(index);
+                    match self.transform_document(&ctx, &document) {
+                        Ok(m) => new_arr.push(Value::Object(m)),
+                        Err(error) => quarantine.push(Quarantined {
+                            index: Some(index),
+                            document,
+                            error,
+                        }),
+                    }
+                }
+                Value::Array(new_arr)
+            }
+            document => match self.transform(&document) {
+                Ok(v) => v,
+                Err(error) => {
+                    quarantine.push(Quarantined {
+                        index: None,
+                        document,
+                        error,
+                    });
+                    Value::Null
+                }
+            },
+        };
+        Ok((output, quarantine))
+    }
+
+    #[inline]
+    fn context(&self, entropy: Entropy) -> Context {
+        Context::new(self.navigation, entropy)
+            .with_missing(self.missing)
+            .with_missing_policy(self.missing_policy)
+            .with_locale(self.locale.clone())
+            .with_index_width(self.index_width)
+            .with_functions(self.functions.clone())
+            .with_env(self.env.clone())
+    }
+
+    /// applies the transformation to a single element of a nested Array, see add_map_array,
+    /// with a Context derived from the parent's.
+    #[inline]
+    pub(crate) fn transform_nested(&self, parent: &Context, element: &Value) -> Result<Value> {
+        let ctx = self.context(parent.entropy().split()).nested_in(parent);
+        let res = self.transform_document(&ctx, element);
+        parent.join(ctx);
+        Ok(Value::Object(res?))
+    }
+
+    /// returns the source as an Array in Many2One mode, otherwise as is.
+    #[inline]
+    fn collapse<'v>(&self, source: &'v Value) -> Cow<'v, Value> {
+        match source {
+            Value::Array(_) => Cow::Borrowed(source),
+            _ if self.mode == Mode::Many2One => Cow::Owned(Value::Array(vec![source.clone()])),
+            _ => Cow::Borrowed(source),
+        }
+    }
+
+    #[inline]
+    fn transform(&self, source: &Value) -> Result<Value> {
+        self.transform_with(&self.context(Entropy::default()), source)
+    }
+
+    /// transforms the source as the record at the index of a stream.
+    #[inline]
+    fn transform_record(&self, index: usize, source: &Value) -> Result<Value> {
+        let ctx = self.context(Entropy::default());
+        ctx.set_record(index);
+        self.transform_with(&ctx, source)
+    }
+
+    #[inline]
+    fn transform_with(&self, ctx: &Context, source: &Value) -> Result<Value> {
+        match source {
+            Value::Array(v) if self.mode == Mode::Many2Many && self.batch.is_batch(v) => {
+                let merged;
+                let v = match &self.merge {
+                    Some((namespace, conflict)) => {
+                        merged = merge_by(namespace, *conflict, v)?;
+                        &merged
+                    }
+                    None => v,
+                };
+                #[cfg(feature = "rayon")]
+                {
+                    if self.parallel {
+                        return self.transform_parallel(ctx, v);
+                    }
+                }
+                let mut new_arr = Vec::with_capacity(v.len());
+                for (index, value) in v.iter().enumerate() {
+                    ctx.set_record(index);
+                    // each document draws from its own seed, as when forked to run in parallel,
+                    // so seeded output doesn't depend on how the batch is transformed
+                    let seed = ctx.entropy().next_u64();
+                    let state = ctx.entropy().reseed(seed);
+                    let res = self.transform_document(ctx, value);
+                    ctx.entropy().reseed(state);
+                    new_arr.push(Value::Object(res?));
+                }
+                Ok(Value::Array(new_arr))
+            }
+            _ => Ok(Value::Object(
+                self.transform_document(ctx, &self.collapse(source))?,
+            )),
+        }
+    }
+
+    /// transforms the elements of a batch on the rayon thread pool, each with a Context forked
+    /// from the provided one.
+    #[cfg(feature = "rayon")]
+    fn transform_parallel(&self, ctx: &Context, v: &[Value]) -> Result<Value> {
+        use rayon::prelude::*;
+
+        let forks = (0..v.len())
+            .map(|index| {
+                let fork = ctx.fork();
+                fork.set_record(index);
+                fork
+            })
+            .collect::<Vec<_>>();
+        let results = forks
+            .into_par_iter()
+            .zip(v.par_iter())
+            .map(|(fork, value)| (self.transform_document(&fork, value), fork))
+            .collect::<Vec<_>>();
+        let mut new_arr = Vec::with_capacity(results.len());
+        for (res, fork) in results {
+            ctx.join(fork);
+            new_arr.push(Value::Object(res?));
+        }
+        Ok(Value::Array(new_arr))
+    }
+
+    #[inline]
+    fn transform_document(&self, ctx: &Context, source: &Value) -> Result<Map<String, Value>> {
+        #[cfg(feature = "schema")]
+        {
+            if let Some((schema, policy)) = &self.input_schema {
+                if let Some(e) = schema.violation("input", source) {
+                    match policy {
+                        SchemaPolicy::Reject => return Err(e),
+                        SchemaPolicy::Warn => ctx.warn(e),
+                    }
+                }
+            }
+        }
+        let mut results = if self.hooks.before.is_empty() {
+            self.transform_source(ctx, source)?
+        } else {
+            let mut source = source.clone();
+            for hook in &self.hooks.before {
+                hook(&mut source);
+            }
+            self.transform_source(ctx, &source)?
+        };
+        for hook in &self.hooks.after {
+            hook(&mut results);
+        }
+        #[cfg(feature = "schema")]
+        {
+            if let Some(schema) = &self.output_schema {
+                let output = Value::Object(results);
+                schema.check("output", &output)?;
+                results = match output {
+                    Value::Object(m) => m,
+                    _ => unreachable!(),
+                };
+            }
+        }
+        Ok(results)
+    }
+
+    #[inline]
+    fn transform_source(&self, ctx: &Context, source: &Value) -> Result<Map<String, Value>> {
+        if !source.is_object() && !source.is_array() {
+            match self.scalar {
+                Scalar::AsIs => {}
+                Scalar::Wrap => {
+                    let mut wrapped = Map::new();
+                    wrapped.insert(String::from(SCALAR_KEY), source.clone());
+                    return self.transform_source(ctx, &Value::Object(wrapped));
+                }
+                Scalar::Reject => {
+                    return Err(Error::InvalidSourceValue(format!(
+                        "expected an Object or Array document but found {}",
+                        value_type(source)
+                    )))
+                }
+            }
+        }
+        let mut results = match source {
+            Value::Object(_) if self.passthrough => self.unconsumed(source),
+            _ => Map::new(),
+        };
+        let plan = self.plan.get_or_init(|| Plan::compile(&self.root));
+        transform_recursive(ctx, &self.root, plan, 0, source, &mut results)?;
+        for (i, rule) in self.deferred.iter().enumerate() {
+            ctx.apply(RuleId::Deferred(i), rule.as_ref(), source, &mut results)?;
+        }
+        if !self.excluded.is_empty() {
+            let mut output = Value::Object(results);
+            for path in &self.excluded {
+                remove_path(path, &mut output);
+            }
+            results = match output {
+                Value::Object(m) => m,
+                _ => unreachable!(),
+            };
+        }
+        Ok(self.envelope(results))
+    }
+
+    /// applies the destination prefix and root to the output of a document.
+    #[inline]
+    fn envelope(&self, mut results: Map<String, Value>) -> Map<String, Value> {
+        if !self.destination_prefix.is_empty() {
+            results = results
+                .into_iter()
+                .map(|(k, v)| (self.destination_prefix.clone() + &k, v))
+                .collect();
+        }
+        for id in self.destination_root.iter().rev() {
+            let mut parent = Map::new();
+            parent.insert(id.clone(), Value::Object(results));
+            results = parent;
+        }
+        results
+    }
+
+    /// returns a copy of the source document without any of the fields read by rules.
+    #[inline]
+    fn unconsumed(&self, source: &Value) -> Map<String, Value> {
+        if self.consumed.iter().any(|path| path.is_empty()) {
+            return Map::new();
+        }
+        let mut value = source.clone();
+        for path in &self.consumed {
+            remove_path(path, &mut value);
+        }
+        match value {
+            Value::Object(m) => m,
+            _ => Map::new(),
+        }
+    }
+}
+
+/// applies several transformations to the same JSON within a string, parsing the input only once.
+/// The results are returned in the same order as the transformers were provided.
+#[inline]
+pub fn apply_many_specs<'a, 'b, S, I>(transformers: I, input: S) -> Result<Vec<Value>>
+where
+    S: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = &'b Transformer>,
+{
+    let source: Value = serde_json::from_str(&input.into())?;
+    transformers
+        .into_iter()
+        .map(|trans| trans.transform(&source))
+        .collect()
+}
+
+#[inline]
+fn conflict(a: &[Namespace], b: &[Namespace]) -> String {
+    format!(
+        "`{}` and `{}` are both written",
+        Namespace::format(a),
+        Namespace::format(b)
+    )
+}
+
+/// returns if the destinations are equal or one is the parent of the other, a wildcard
+/// overlapping every index of the same Array and different kinds of segment with the same id eg.
+/// `a` and `a[0]` conflicting.
+#[inline]
+fn overlaps(a: &[Namespace], b: &[Namespace]) -> bool {
+    a.iter().zip(b).all(|(a, b)| {
+        a.id() == b.id()
+            && match (a, b) {
+                (Namespace::Array { index: a, .. }, Namespace::Array { index: b, .. }) => a == b,
+                (Namespace::Match { .. }, Namespace::Match { .. }) => a == b,
+                // a wildcard or match may select any element of the Array, and an Object written
+                // in place of the Array conflicts with anything written within it
+                _ => true,
+            }
+    })
+}
+
+/// adds the paths of the values remaining once consumed paths are removed from the original
+/// document. Objects and Arrays emptied by the removal were entirely consumed.
+fn collect_unmapped(
+    namespace: &mut Vec<Namespace>,
+    remaining: &Value,
+    original: &Value,
+    paths: &mut BTreeSet<String>,
+) {
+    match (remaining, original) {
+        (Value::Object(m), Value::Object(original)) if !original.is_empty() => {
+            for (k, v) in m {
+                namespace.push(Namespace::Object { id: k.clone() });
+                collect_unmapped(namespace, v, &original[k], paths);
+                namespace.pop();
+            }
+        }
+        (Value::Array(arr), Value::Array(original)) if !original.is_empty() => {
+            // the Array's own namespace is replaced by a wildcard while visiting the elements
+            let id = match namespace.last() {
+                Some(Namespace::Object { id }) => Some(id.clone()),
+                _ => None,
+            };
+            if id.is_some() {
+                namespace.pop();
+            }
+            namespace.push(Namespace::Wildcard {
+                id: id.clone().unwrap_or_default(),
+            });
+            for (v, original) in arr.iter().zip(original) {
+                collect_unmapped(namespace, v, original, paths);
+            }
+            namespace.pop();
+            if let Some(id) = id {
+                namespace.push(Namespace::Object { id });
+            }
+        }
+        _ if namespace.is_empty() => {}
+        _ => {
+            paths.insert(Namespace::format(namespace));
+        }
+    }
+}
+
+/// adds the namespace, and the type of the value it refers to when known, to the JSON Schema
+/// describing the document. When describing tuples the elements of Arrays at an index are each
+/// described, otherwise all elements are described by a single schema.
+fn describe(
+    schema: &mut Map<String, Value>,
+    namespace: &[Namespace],
+    kind: Option<&'static str>,
+    tuples: bool,
+) {
+    let (ns, rest) = match namespace.split_first() {
+        Some(split) => split,
+        None => {
+            if let Some(kind) = kind {
+                set_type(schema, kind);
+            }
+            return;
+        }
+    };
+    let mut node = schema;
+    if !ns.id().is_empty() {
+        set_type(node, Type::Object.name());
+        node = match node
+            .entry("properties")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .and_then(|properties| {
+                properties
+                    .entry(ns.id().clone())
+                    .or_insert_with(|| Value::Object(Map::new()))
+                    .as_object_mut()
+            }) {
+            Some(node) => node,
+            None => return,
+        };
+    }
+    if !ns.is_object() {
+        set_type(node, Type::Array.name());
+        let items = node.entry("items");
+        let element = match (ns, tuples) {
+            (Namespace::Array { index, .. }, true) => {
+                match items.or_insert_with(|| Value::Array(Vec::new())) {
+                    Value::Array(tuple) => {
+                        if tuple.len() <= *index {
+                            tuple.resize(index + 1, Value::Object(Map::new()));
+                        }
+                        tuple[*index].as_object_mut()
+                    }
+                    items => items.as_object_mut(),
+                }
+            }
+            // Arrays already described as tuples are left as is
+            _ => items
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut(),
+        };
+        node = match element {
+            Some(node) => node,
+            None => return,
+        };
+    }
+    describe(node, rest, kind, tuples);
+}
+
+/// sets the type of the JSON Schema, allowing either type when it's already of another.
+fn set_type(schema: &mut Map<String, Value>, kind: &'static str) {
+    match schema.get_mut("type") {
+        None => {
+            schema.insert(String::from("type"), Value::from(kind));
+        }
+        Some(Value::Array(types)) => {
+            if !types.iter().any(|t| t == kind) {
+                types.push(Value::from(kind));
+            }
+        }
+        Some(t) if t != kind => *t = Value::Array(vec![t.take(), Value::from(kind)]),
+        Some(_) => {}
+    }
+}
+
+/// returns the source namespace of an inverted, always Direct, Mapping.
+#[inline]
+fn mapping_from<'m>(mapping: &'m Mapping) -> &'m str {
+    match mapping {
+        Mapping::Direct { from, .. } => from,
+        _ => "",
+    }
+}
+
+/// returns the destination namespace of a Mapping.
+fn mapping_to<'m, 'a>(mapping: &'m mut Mapping<'a>) -> &'m mut Cow<'a, str> {
+    match mapping {
+        Mapping::When { mapping, .. } | Mapping::OnError { mapping, .. } => mapping_to(mapping),
+        Mapping::Direct { to, .. }
+        | Mapping::Constant { to, .. }
+        | Mapping::ConstantEnv { to, .. }
+        | Mapping::Generate { to, .. }
+        | Mapping::Flatten { to, .. }
+        | Mapping::FlattenList { to, .. }
+        | Mapping::JsonPath { to, .. }
+        | Mapping::Concat { to, .. }
+        | Mapping::Template { to, .. }
+        | Mapping::Coerce { to, .. }
+        | Mapping::MapArray { to, .. }
+        | Mapping::Duration { to, .. }
+        | Mapping::Canonical { to, .. }
+        | Mapping::Compute { to, .. }
+        | Mapping::Mask { to, .. }
+        | Mapping::Function { to, .. }
+        | Mapping::DeriveBool { to, .. }
+        | Mapping::Merge { to, .. }
+        | Mapping::Switch { to, .. }
+        | Mapping::Split { to, .. }
+        | Mapping::Aggregate { to, .. }
+        | Mapping::Zip { to, .. }
+        | Mapping::Join { to, .. }
+        | Mapping::Rekey { to, .. }
+        | Mapping::Subtree { to, .. } => to,
+        #[cfg(feature = "crypto")]
+        Mapping::Encrypt { to, .. } | Mapping::Decrypt { to, .. } => to,
+    }
+}
+
+fn transform_recursive(
+    ctx: &Context,
+    arena: &Arena,
+    plan: &Plan,
+    step_idx: usize,
+    source: &Value,
+    dest: &mut Map<String, Value>,
+) -> Result<()> {
+    let step = &plan.steps[step_idx];
+    if let Some(rulz) = arena.tree[step.node].rules() {
+        for (i, rule) in rulz.iter().enumerate() {
+            let id = RuleId::Tree {
+                node: step.node,
+                rule: i,
+            };
+            ctx.apply(id, rule.as_ref(), source, dest)?;
+        }
+    }
+    // the children are the descendants not nested within an earlier child
+    let mut idx = step_idx + 1;
+    while idx < step.end {
+        let child = &plan.steps[idx];
+        match &child.access {
+            Access::Root => unreachable!("only the first step reads the root"),
+            Access::Object(id) => {
+                // if we find the source value
+                if let Some(current_level) = source.get(&**id) {
+                    ctx.within(
+                        || id.to_string(),
+                        || transform_recursive(ctx, arena, plan, idx, current_level, dest),
+                    )?;
+                } else {
+                    if !source.is_object() {
+                        ctx.type_mismatch("", "object", source)?;
+                    }
+                    missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                }
+            }
+            Access::Array { id, index } => {
+                // may be array of array already without id eg. arr[0][0]
+                if !id.is_empty() {
+                    match source.get(&**id) {
+                        Some(Value::Array(arr)) => {
+                            if let Some(v) = arr.get(*index) {
+                                ctx.within(
+                                    || format!("{}[{}]", id, index),
+                                    || transform_recursive(ctx, arena, plan, idx, v, dest),
+                                )?;
+                            } else {
+                                missing(ctx, arena, plan, idx, dest, || {
+                                    format!("{}[{}]", id, index)
+                                })?;
+                            }
+                        }
+                        Some(current_level) => {
+                            ctx.type_mismatch(id, "array", current_level)?;
+                            missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                        }
+                        None => {
+                            if !source.is_object() {
+                                ctx.type_mismatch("", "object", source)?;
+                            }
+                            missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                        }
+                    }
+                } else if let Some(arr) = source.as_array() {
+                    if let Some(v) = arr.get(*index) {
+                        ctx.within(
+                            || format!("[{}]", index),
+                            || transform_recursive(ctx, arena, plan, idx, v, dest),
+                        )?;
+                    } else {
+                        missing(ctx, arena, plan, idx, dest, || format!("[{}]", index))?;
+                    }
+                } else {
+                    ctx.type_mismatch("", "array", source)?;
+                    missing(ctx, arena, plan, idx, dest, || format!("[{}]", index))?;
+                }
+            }
+            Access::Wildcard(id) => {
+                let current_level = match id.len() {
+                    0 => Some(source),
+                    _ => source.get(&**id),
+                };
+                match current_level {
+                    Some(Value::Array(arr)) if arr.is_empty() => {
+                        no_elements(ctx, arena, plan, idx, dest)?;
+                    }
+                    Some(Value::Array(arr)) => {
+                        for (i, v) in arr.iter().enumerate() {
+                            ctx.within(
+                                || format!("{}[{}]", id, i),
+                                || {
+                                    ctx.push_index(i);
+                                    let res = transform_recursive(ctx, arena, plan, idx, v, dest);
+                                    ctx.pop_index();
+                                    res
+                                },
+                            )?;
+                        }
+                    }
+                    Some(current_level) => {
+                        ctx.type_mismatch(id, "array", current_level)?;
+                        missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                    }
+                    None => {
+                        if !source.is_object() {
+                            ctx.type_mismatch("", "object", source)?;
+                        }
+                        missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                    }
+                }
+            }
+            Access::Match { id, key, value } => {
+                let current_level = match id.len() {
+                    0 => Some(source),
+                    _ => source.get(&**id),
+                };
+                match current_level {
+                    Some(arr @ Value::Array(_)) => {
+                        let segment = || format!("{}[{}={}]", id, key, value);
+                        match find_match(arr, key, value) {
+                            Some(v) => ctx.within(segment, || {
+                                transform_recursive(ctx, arena, plan, idx, v, dest)
+                            })?,
+                            None => missing(ctx, arena, plan, idx, dest, segment)?,
+                        }
+                    }
+                    Some(current_level) => {
+                        ctx.type_mismatch(id, "array", current_level)?;
+                        missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                    }
+                    None => {
+                        if !source.is_object() {
+                            ctx.type_mismatch("", "object", source)?;
+                        }
+                        missing(ctx, arena, plan, idx, dest, || id.to_string())?;
+                    }
+                }
+            }
+        }
+        idx = child.end;
+    }
+    Ok(())
+}
+
+/// handles the source of the step being missing, or not of the expected type. Under
+/// `MissingPolicy::Null` the rules of the step and its descendants are applied to an empty Object,
+/// so each writes `null` as when only its own source is missing, except for those within a
+/// wildcard which are applied as there are no elements. Otherwise they're skipped, and an
+/// `Error::MissingSource` for the path returned under `MissingPolicy::Error`.
+#[inline]
+fn missing<F>(
+    ctx: &Context,
+    arena: &Arena,
+    plan: &Plan,
+    step_idx: usize,
+    dest: &mut Map<String, Value>,
+    path: F,
+) -> Result<()>
+where
+    F: FnOnce() -> String,
+{
+    match (ctx.missing_policy(), &plan.steps[step_idx].access) {
+        (MissingPolicy::Null, Access::Wildcard(_)) => {
+            skip(ctx, arena, plan, step_idx);
+            no_elements(ctx, arena, plan, step_idx, dest)
+        }
+        (MissingPolicy::Skip | MissingPolicy::Error, _) => {
+            skip(ctx, arena, plan, step_idx);
+            ctx.missing_source_with(path)
+        }
+        (MissingPolicy::Null, _) => {
+            let empty = Value::Object(Map::new());
+            transform_recursive(ctx, arena, plan, step_idx, &empty, dest)
+        }
+    }
+}
+
+/// applies the rules of the wildcard step, and its descendants, when the Array has no elements
+/// eg. so values collected from it are written as an empty Array.
+#[inline]
+fn no_elements(
+    ctx: &Context,
+    arena: &Arena,
+    plan: &Plan,
+    step_idx: usize,
+    dest: &mut Map<String, Value>,
+) -> Result<()> {
+    for step in &plan.steps[step_idx..plan.steps[step_idx].end] {
+        if let Some(rules) = arena.tree[step.node].rules() {
+            for rule in rules {
+                rule.apply_no_elements(ctx, dest)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// explains the rules of the step, and its descendants, which are skipped as a parent of their
+/// source is missing, when tracing.
+#[inline]
+fn skip(ctx: &Context, arena: &Arena, plan: &Plan, step_idx: usize) {
+    if !ctx.is_traced() {
+        return;
+    }
+    for step in &plan.steps[step_idx..plan.steps[step_idx].end] {
+        if let Some(rules) = arena.tree[step.node].rules() {
+            for (i, rule) in rules.iter().enumerate() {
+                let id = RuleId::Tree {
+                    node: step.node,
+                    rule: i,
+                };
+                ctx.trace_skipped(id, rule.as_ref());
+            }
+        }
     }
 }
 
-fn transform_recursive(
-    arena: &Arena,
-    node: &Node,
-    source: &Value,
-    dest: &mut Map<String, Value>,
-) -> Result<()> {
-    match node {
-        Node::Object {
-            rules, children, ..
-        }
-        | Node::Array {
-            rules, children, ..
-        } => {
-            if let Some(rulz) = rules {
-                for rule in rulz {
-                    rule.apply(source, dest)?;
-                }
-            }
-            if let Some((start, end)) = children {
-                for idx in *start..=*end {
-                    if let Some(n) = arena.tree.get(idx) {
-                        match n {
-                            Node::Object { id, .. } => {
-                                // if we find the source value
-                                if let Some(current_level) = source.get(id.as_str()) {
-                                    transform_recursive(arena, n, current_level, dest)?;
-                                }
-                            }
-                            Node::Array { id, index, .. } => {
-                                // may be array of array already without id eg. arr[0][0]
-                                if id != "" {
-                                    if let Some(current_level) = source.get(id.as_str()) {
-                                        if let Some(arr) = current_level.as_array() {
-                                            if let Some(v) = arr.get(*index) {
-                                                transform_recursive(arena, n, v, dest)?;
-                                            }
-                                        }
-                                    }
-                                } else if let Some(arr) = source.as_array() {
-                                    if let Some(v) = arr.get(*index) {
-                                        transform_recursive(arena, n, v, dest)?;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{FloatFormat, LineSink};
+    use crate::predicate::Comparison;
+    use crate::rules::Casing;
+    use serde::Deserialize;
+
+    #[test]
+    fn test_top_level() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("existing_key", "rename_from_existing_key")?
+            .add_direct("my_array[0]", "used_to_be_array")?
+            .add_constant(Value::String("consant_value".to_string()), "const")?
+            .build()?;
+
+        let input = r#"
+            {
+                "existing_key":"my_val1",
+                "my_array":["idx_0_value"]
+            }"#;
+        let expected = r#"{"const":"consant_value","rename_from_existing_key":"my_val1","used_to_be_array":"idx_0_value"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("nested.key1", "unnested_key1")?
+            .add_direct("nested.nested.key2", "unnested_key2")?
+            .add_direct("nested.arr[0].nested.key3", "unnested_key3")?
+            .build()?;
+        let input = r#"
+                    {
+                        "nested": {
+                            "key1": "val1",
+                            "nested": {
+                                "key2": "val2"
+                            },
+                            "arr": [{
+                                "nested": {
+                                    "key3": "val3"
+                                }
+                            }]
+                        }
+                    }"#;
+        let expected = r#"{"unnested_key1":"val1","unnested_key2":"val2","unnested_key3":"val3"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_out_of_order_rules() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("nested.nested.key2", "nested_new.nested")?
+            .add_direct("top", "nested_new.top")?
+            .build()?;
+        let input = r#"
+                    {
+                        "nested": {
+                            "nested": {
+                                "key2": "val2"
+                            }
+                        },
+                        "top": "top_val"
+                    }"#;
+        let expected = r#"{"nested_new":{"nested":"val2","top":"top_val"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_objects() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("nested.nested.key2", "nested_new.nested")?
+            .add_direct("top", "nested_new.top")?
+            .build()?;
+        let input = r#"
+                    {
+                        "nested": {
+                            "nested": {
+                                "key2": "val2"
+                            }
+                        },
+                        "top": "top_val"
+                    }"#;
+        let expected = r#"{"nested_new":{"nested":"val2","top":"top_val"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_struct() -> Result<()> {
+        #[derive(Debug, Serialize)]
+        struct From {
+            existing: String,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct To {
+            new: String,
+        }
+
+        let trans = TransformerBuilder::default()
+            .add_direct("existing", "new")?
+            .build()?;
+
+        let from = From {
+            existing: String::from("existing_value"),
+        };
+
+        let expected = To {
+            new: String::from("existing_value"),
+        };
+        let res: To = trans.apply_to(from)?;
+        assert_eq!(expected, res);
+        Ok(())
+    }
+
+    #[test]
+    fn test_struct_enum() -> Result<()> {
+        #[derive(Debug, Serialize)]
+        struct From {
+            existing: String,
+        }
+
+        let trans = TransformerBuilder::default()
+            .add_direct("existing", "new")?
+            .build()?;
+
+        let from = From {
+            existing: String::from("existing_value"),
+        };
+
+        let mut m = Map::new();
+        m.insert(
+            String::from("new"),
+            Value::String(String::from("existing_value")),
+        );
+        let expected = Value::Object(m);
+        let res: Value = trans.apply_to(from)?;
+        assert_eq!(expected, res);
+        Ok(())
+    }
+
+    #[test]
+    fn test_array() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .mode(Mode::One2One)
+            .add_direct("[0]", "new")?
+            .build()?;
+        let input = r#"[
+                "test"
+            ]"#;
+        let expected = r#"{"new":"test"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_2_one() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .mode(Mode::Many2One)
+            .add_direct("[0].id", "first_id")?
+            .add_aggregate("[*].amount", "total", Agg::Sum)?
+            .add_aggregate("[*]", "count", Agg::Count)?
+            .build()?;
+        let input = r#"[{"id":1,"amount":10},{"id":2,"amount":5}]"#;
+        let expected = r#"{"count":2,"first_id":1,"total":15}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let expected = r#"{"count":1,"first_id":3,"total":7}"#;
+        let res = trans.apply_from_str(r#"{"id":3,"amount":7}"#)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_2_many() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user_id", "id")?
+            .add_direct("full_name", "name")?
+            .build()?;
+        let input = r#"[
+                {"user_id":1,"full_name":"Dean Karn"},
+                {"user_id":2, "full_name":"Joey Bloggs"}
+            ]"#;
+        let expected = r#"[{"id":1,"name":"Dean Karn"},{"id":2,"name":"Joey Bloggs"}]"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_many_specs() -> Result<()> {
+        let trans1 = TransformerBuilder::default()
+            .add_direct("user_id", "id")?
+            .build()?;
+        let trans2 = TransformerBuilder::default()
+            .add_direct("full_name", "name")?
+            .build()?;
+        let input = r#"{"user_id":1,"full_name":"Dean Karn"}"#;
+        let res = apply_many_specs(&[trans1, trans2], input)?;
+        assert_eq!(2, res.len());
+        assert_eq!(r#"{"id":1}"#, res[0].to_string());
+        assert_eq!(r#"{"name":"Dean Karn"}"#, res[1].to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_back_reference() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("$out.user.id", "owner_id")?
+            .add_direct("user_id", "user.id")?
+            .add_direct("$out.missing", "missing")?
+            .build()?;
+        let input = r#"{"user_id":"111"}"#;
+        let expected = r#"{"missing":null,"owner_id":"111","user":{"id":"111"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_navigation_strict() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .navigation(Navigation::Strict)
+            .add_direct("nested.arr[0].key", "key")?
+            .add_direct("missing.key", "missing")?
+            .build()?;
+
+        let input = r#"{"nested":{"arr":[{"key":"value"}]}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(r#"{"key":"value","missing":null}"#, res.to_string());
+
+        let input = r#"{"nested":{"arr":["value"]}}"#;
+        match trans.apply_from_str(input).map_err(Error::into_root_cause) {
+            Err(Error::TypeMismatch {
+                path,
+                expected,
+                found,
+            }) => {
+                assert_eq!("nested.arr[0]", path);
+                assert_eq!("object", expected);
+                assert_eq!("string", found);
+            }
+            res => panic!("expected type mismatch, got {:?}", res),
+        }
+
+        let input = r#"{"nested":{"arr":{"key":"value"}}}"#;
+        match trans.apply_from_str(input) {
+            Err(Error::TypeMismatch { path, .. }) => assert_eq!("nested.arr", path),
+            res => panic!("expected type mismatch, got {:?}", res),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_navigation_null_safe() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("nested.arr[0].key", "key")?
+            .build()?;
+        let input = r#"{"nested":{"arr":["value"]}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(r#"{"key":null}"#, res.to_string());
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RandomRule {}
+
+    #[typetag::serde]
+    impl Rule for RandomRule {
+        fn apply(&self, from: &Value, to: &mut Map<String, Value>) -> Result<()> {
+            self.apply_with_context(&Context::default(), from, to)
+        }
+
+        fn apply_with_context(
+            &self,
+            ctx: &Context,
+            _from: &Value,
+            to: &mut Map<String, Value>,
+        ) -> Result<()> {
+            to.insert(
+                String::from("random"),
+                Value::from(ctx.entropy().next_u64()),
+            );
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_apply_with_entropy() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add(&[], RandomRule {})?
+            .build()?;
+        let input = r#"[{},{}]"#;
+        let now = SystemTime::now();
+        let res1 = trans.apply_with_entropy(input, 42, now)?;
+        let res2 = trans.apply_with_entropy(input, 42, now)?;
+        assert_eq!(res1, res2);
+        assert_ne!(res1[0], res1[1]);
+        assert_ne!(res1, trans.apply_with_entropy(input, 43, now)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_direct() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "",
+                FlattenOps {
+                    recursive: false,
+                    prefix: Some("flattened_"),
+                    separator: None,
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .build()?;
+        let input = r#"{
+                "nested":{
+                    "key1":"value1",
+                    "key2":"value2"
+                }
+            }"#;
+        let expected = r#"{"flattened_key1":"value1","flattened_key2":"value2"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_direct_with_to() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "flattened",
+                FlattenOps {
+                    recursive: false,
+                    prefix: Some("flattened_"),
+                    separator: None,
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .build()?;
+        let input = r#"{
+                "nested":{
+                    "key1":"value1",
+                    "key2":"value2"
+                }
+            }"#;
+        let expected = r#"{"flattened":{"flattened_key1":"value1","flattened_key2":"value2"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+    #[test]
+    fn test_flatten_direct_with_to_no_profix() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten("nested", "flattened", FlattenOps::default())?
+            .build()?;
+        let input = r#"{
+                "nested":{
+                    "key1":"value1",
+                    "key2":"value2"
+                }
+            }"#;
+        let expected = r#"{"flattened":{"key1":"value1","key2":"value2"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_direct_recursive_with_to_no_prefix() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "",
+                FlattenOps {
+                    recursive: true,
+                    prefix: None,
+                    separator: Some("_"),
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .build()?;
+        let input = r#"{
+            "nested":{
+                "key1":"value1",
+                "key2":{
+                    "inner":"value2"
+                }
+            }
+        }"#;
+        let expected = r#"{"key1":"value1","key2_inner":"value2"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_direct_nonrecursive_with_to_no_prefix() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten("nested", "", FlattenOps::default())?
+            .build()?;
+        let input = r#"{
+            "nested":{
+                "key1":"value1",
+                "key2":{
+                    "inner":"value2"
+                }
+            }
+        }"#;
+        let expected = r#"{"key1":"value1","key2":{"inner":"value2"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_flatten() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "",
+                FlattenOps {
+                    recursive: false,
+                    prefix: Some("new"),
+                    separator: Some("_"),
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .build()?;
+        let input = r#"{
+            "nested":[
+                "value1",
+                "value2",
+                "value3"
+            ]
+        }"#;
+        let expected = r#"{"new_1":"value1","new_2":"value2","new_3":"value3"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_flatten_to() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "flattened[1]",
+                FlattenOps {
+                    recursive: false,
+                    prefix: Some("new"),
+                    separator: Some("_"),
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .build()?;
+        let input = r#"{
+            "nested":[
+                "value1",
+                "value2",
+                "value3"
+            ]
+        }"#;
+        let expected =
+            r#"{"flattened":[null,{"new_1":"value1","new_2":"value2","new_3":"value3"}]}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_example() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user_id", "id")?
+            .add_direct("full-name", "name")?
+            .add_flatten(
+                "nicknames",
+                "",
+                FlattenOps {
+                    recursive: true,
+                    prefix: Some("nickname"),
+                    separator: Some("_"),
+                    array_separator: None,
+                    index_base: None,
+                    index_format: None,
+                    max_depth: None,
+                    manipulations: Vec::new(),
+                },
+            )?
+            .add_direct("nested.inner.key", "prev_nested")?
+            .add_direct("nested.my_arr[1]", "prev_arr")?
+            .build()?;
+
+        let input = r#"
+            {
+                "user_id":"111",
+                "full-name":"Dean Karn",
+                "nicknames":["Deano","Joey Bloggs"],
+                "nested": {
+                    "inner":{
+                        "key":"value"
+                    },
+                    "my_arr":[null,"arr_value",null]
+                }
+            }"#;
+        let expected = r#"{"id":"111","name":"Dean Karn","nickname_1":"Deano","nickname_2":"Joey Bloggs","prev_arr":"arr_value","prev_nested":"value"}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, serde_json::to_string(&res)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_array_separator() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "",
+                "",
+                FlattenOps {
+                    recursive: true,
+                    separator: Some("."),
+                    array_separator: Some("_"),
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{"spans":[{"duration":5},{"duration":7,"tags":["a"]}]}"#;
+        let expected = r#"{"spans_1.duration":5,"spans_2.duration":7,"spans_2.tags_1":"a"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_index_format() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "",
+                "",
+                FlattenOps {
+                    recursive: true,
+                    separator: Some("."),
+                    index_base: Some(0),
+                    index_format: Some("[{i}]"),
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{"spans":[{"duration":5},{"duration":7,"tags":["a"]}]}"#;
+        let expected = r#"{"spans[0].duration":5,"spans[1].duration":7,"spans[1].tags[0]":"a"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = TransformerBuilder::default().add_flatten(
+            "",
+            "",
+            FlattenOps {
+                index_format: Some("[]"),
+                ..FlattenOps::default()
+            },
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_max_depth() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "",
+                "",
+                FlattenOps {
+                    recursive: true,
+                    separator: Some("."),
+                    max_depth: Some(2),
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{"id":1,"user":{"name":"Joe","address":{"city":"Berlin"}},"tags":[["a"]]}"#;
+        let expected =
+            r#"{"id":1,"tags.1":["a"],"user.address":{"city":"Berlin"},"user.name":"Joe"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = TransformerBuilder::default().add_flatten(
+            "",
+            "",
+            FlattenOps {
+                recursive: true,
+                max_depth: Some(0),
+                ..FlattenOps::default()
+            },
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ManipDashRemover {}
+
+    #[typetag::serde]
+    impl StringManipulation for ManipDashRemover {
+        fn apply(&self, input: &str) -> String {
+            input.replace('-', "")
+        }
+    }
+
+    #[test]
+    fn test_flatten_direct_with_maipulation() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "",
+                FlattenOps {
+                    manipulations: vec![Box::new(ManipDashRemover {})],
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{
+            "nested":{
+                "key-1":"value1",
+                "key-2":{
+                    "inner":"value2"
+                }
+            }
+        }"#;
+        let expected = r#"{"key1":"value1","key2":{"inner":"value2"}}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_chained_manipulations() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten(
+                "nested",
+                "",
+                FlattenOps {
+                    manipulations: vec![Box::new(ManipDashRemover {}), Box::new(Casing::Upper)],
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{"nested":{"key-1":"value1"}}"#;
+        let expected = r#"{"KEY1":"value1"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        // mappings serialized with a single manipulation remain valid
+        let mapping: Mapping = serde_json::from_str(
+            r#"{"Flatten":{"from":"nested","to":"","prefix":null,"separator":null,"manipulation":{"Casing":"Upper"},"recursive":false}}"#,
+        )?;
+        let trans = TransformerBuilder::default()
+            .add_mapping(mapping)?
+            .build()?;
+        let expected = r#"{"KEY-1":"value1"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_list() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_flatten_list(
+                "nicknames",
+                "aka",
+                false,
+                vec![Box::new(ManipDashRemover {})],
+            )?
+            .add_flatten_list("nested", "lists[1]", true, Vec::new())?
+            .build()?;
+        let input = r#"{
+            "nicknames":["Dea-no","Joey-Bloggs",["nested"]],
+            "nested":[1,[2,3],{"key":4}]
+        }"#;
+        let expected = r#"{"aka":["Deano","JoeyBloggs",["nested"]],"lists":[null,[1,2,3,4]]}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wildcard_destinations() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("items[*].price", "prices[*]")?
+            .add_direct("items[*].id", "lines[*].id")?
+            .add_direct("items[*].tags[*]", "lines[*].labels[*]")?
+            .add_direct("items[*]", "copies[*]")?
+            .build()?;
+        let input = r#"{
+            "items":[
+                {"id":1,"price":10,"tags":["a","b"]},
+                {"id":2,"price":20,"tags":["c"]}
+            ]
+        }"#;
+        let expected = r#"{"copies":[{"id":1,"price":10,"tags":["a","b"]},{"id":2,"price":20,"tags":["c"]}],"lines":[{"id":1,"labels":["a","b"]},{"id":2,"labels":["c"]}],"prices":[10,20]}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+
+        let res = TransformerBuilder::default().add_direct("id", "ids[*]");
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_array_destination() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("key", "arr[1].key")?
+            .add_direct("key", "arr[1].other[1]")?
+            .build()?;
+        let input = r#"{"key":"value"}"#;
+        let expected = r#"{"arr":[null,{"key":"value","other":[null,"value"]}]}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed() -> Result<()> {
+        let builder = || {
+            TransformerBuilder::default()
+                .add_direct_typed("age", "age", Type::Integer)?
+                .add_direct_typed("name", "name", Type::String)
+        };
+        let input = r#"{"age":"42","name":"Dean Karn"}"#;
+
+        let trans = builder()?.build()?;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(r#"{"age":null,"name":"Dean Karn"}"#, res.to_string());
+
+        let trans = builder()?.navigation(Navigation::Strict).build()?;
+        match trans.apply_from_str(input).map_err(Error::into_root_cause) {
+            Err(Error::TypeMismatch {
+                path,
+                expected,
+                found,
+            }) => {
+                assert_eq!("age", path);
+                assert_eq!("integer", expected);
+                assert_eq!("string", found);
+            }
+            res => panic!("expected type mismatch, got {:?}", res),
+        }
+
+        assert!(TransformerBuilder::default()
+            .add_constant_typed(true, "flag", Type::Boolean)
+            .is_ok());
+        assert!(TransformerBuilder::default()
+            .add_constant_typed("true", "flag", Type::Boolean)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_owned() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user_id", "id")?
+            .build()?;
+        let input: Value = serde_json::from_str(r#"{"user_id":1,"other":true}"#)?;
+        let (output, input) = trans.apply_owned(input).map_err(|(e, _)| e)?;
+        assert_eq!(r#"{"id":1}"#, output.to_string());
+        assert_eq!(r#"{"other":true,"user_id":1}"#, input.to_string());
+
+        let trans = trans
+            .into_builder()
+            .missing_policy(MissingPolicy::Error)
+            .build()?;
+        match trans.apply_owned(serde_json::json!({"other":true})) {
+            Err((e, input)) => {
+                assert!(matches!(e.root_cause(), Error::MissingSource(path) if path == "user_id"));
+                assert_eq!(r#"{"other":true}"#, input.to_string());
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch() -> Result<()> {
+        let input = r#"[[1,2],[3,4]]"#;
+        let trans = TransformerBuilder::default()
+            .batch(Batch::ObjectsOnly)
+            .add_direct("[1]", "second")?
+            .build()?;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(r#"{"second":[3,4]}"#, res.to_string());
+
+        let trans = TransformerBuilder::default()
+            .batch(Batch::probe("id")?)
+            .add_direct("id", "id")?
+            .build()?;
+        let res = trans.apply_from_str(r#"[{"id":1},{"id":2}]"#)?;
+        assert_eq!(r#"[{"id":1},{"id":2}]"#, res.to_string());
+        let res = trans.apply_from_str(r#"[{"other":1}]"#)?;
+        assert_eq!(r#"{"id":null}"#, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wildcard_collect() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("items[*].name", "names")?
+            .add_direct("orders[*].items[*].sku", "skus")?
+            .add_direct("orders[*].items[*].sku", "orders[*].skus")?
+            .build()?;
+        let input = r#"{
+            "items":[{"name":"first"},{"id":2},{"name":"third"}],
+            "orders":[
+                {"items":[{"sku":"a"},{"sku":"b"}]},
+                {"items":[{"sku":"c"}]}
+            ]
+        }"#;
+        let expected = r#"{"names":["first",null,"third"],"orders":[{"skus":["a","b"]},{"skus":["c"]}],"skus":["a","b","c"]}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+
+        // Arrays with no elements, empty or missing, are collected into an empty Array
+        let input = r#"{"items":[],"orders":[{"items":[]},{"other":1}]}"#;
+        let expected = r#"{"names":[],"orders":[{"skus":[]},{"skus":[]}],"skus":[]}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        let expected = r#"{"names":[],"skus":[]}"#;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
+        let trans = trans
+            .into_builder()
+            .missing_policy(MissingPolicy::Skip)
+            .build()?;
+        assert_eq!("{}", trans.apply_from_str("{}")?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scalar() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .scalar(Scalar::Wrap)
+            .add_direct("$value", "reading")?
+            .build()?;
+        let res = trans.apply_from_str("42")?;
+        assert_eq!(r#"{"reading":42}"#, res.to_string());
+        let res = trans.apply_from_str("[1,null]")?;
+        assert_eq!(r#"[{"reading":1},{"reading":null}]"#, res.to_string());
+
+        let trans = TransformerBuilder::default()
+            .scalar(Scalar::Reject)
+            .add_direct("$value", "reading")?
+            .build()?;
+        assert!(trans.apply_from_str("null").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_whole_document() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("$", "raw")?
+            .add_flatten(
+                "",
+                "",
+                FlattenOps {
+                    prefix: Some("flat"),
+                    separator: Some("_"),
+                    array_separator: None,
+                    ..FlattenOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"[{"id":1},{"id":2}]"#;
+        let expected = r#"[{"flat_id":1,"raw":{"id":1}},{"flat_id":2,"raw":{"id":2}}]"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_timed() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("nested.key", "key")?
+            .add_direct("top", "top")?
+            .add_direct("$out.top", "copy")?
+            .build()?;
+        let input = r#"[{"top":1,"nested":{"key":2}},{"top":3}]"#;
+        let (res, timings) = trans.apply_timed(input)?;
+        assert_eq!(
+            r#"[{"copy":1,"key":2,"top":1},{"copy":3,"key":null,"top":3}]"#,
+            res.to_string()
+        );
+        assert_eq!(3, timings.rules().len());
+        let nested = timings
+            .rules()
+            .iter()
+            .find(|t| t.path() == "nested")
+            .unwrap();
+        assert_eq!(2, nested.calls());
+        let deferred = timings.rules().iter().find(|t| t.path() == "$out").unwrap();
+        assert_eq!(2, deferred.calls());
+        assert_eq!(2, timings.slowest(2).len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_from_str_with() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("name", "name")?
+            .add_direct("price", "price")?
+            .build()?;
+        let input = r#"{"name":"Zoë","price":10.5}"#;
+        let res = trans.apply_from_str_with(
+            input,
+            OutputOptions {
+                ascii_only: true,
+                float_format: FloatFormat::Fixed(2),
+                ..OutputOptions::default()
+            },
+        )?;
+        assert_eq!(r#"{"name":"Zo\u00eb","price":10.50}"#, res);
+
+        let res = trans.apply_from_str_with(
+            input,
+            OutputOptions {
+                pretty: true,
+                ..OutputOptions::default()
+            },
+        )?;
+        assert_eq!("{\n  \"name\": \"Zoë\",\n  \"price\": 10.5\n}", res);
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_canonical("order", "digest_input")?
+            .add_direct("order.total", "total")?
+            .build()?;
+        let input =
+            r#"{"order":{"total":10.0,"items":[{"sku":"b","qty":1e1},{"sku":"a","delta":-0.0}]}}"#;
+        let expected = r#"{"items":[{"qty":10,"sku":"b"},{"delta":0,"sku":"a"}],"total":10}"#;
+        let res = trans.apply_from_str(input)?;
+        assert_eq!(expected, res["digest_input"].as_str().unwrap());
+
+        let res = trans.apply_from_str_with(
+            input,
+            OutputOptions {
+                pretty: true,
+                canonical: true,
+                ..OutputOptions::default()
+            },
+        )?;
+        assert!(res.starts_with(r#"{"digest_input":"#));
+        assert!(res.ends_with(r#","total":10}"#));
+
+        for (number, expected) in &[
+            ("1", "1"),
+            ("1.0", "1"),
+            ("1e2", "100"),
+            ("-0.0", "0"),
+            ("0.5", "0.5"),
+            ("-1.25e-3", "-0.00125"),
+            ("1e-7", "1e-7"),
+            ("1.5e-7", "1.5e-7"),
+            ("1e20", "100000000000000000000"),
+            ("1e21", "1e+21"),
+            ("4.35e22", "4.35e+22"),
+            ("9007199254740993", "9007199254740992"),
+            ("123.456e1", "1234.56"),
+        ] {
+            let value: Value = serde_json::from_str(number)?;
+            assert_eq!(*expected, output::canonical(&value), "{}", number);
+        }
+        let value = serde_json::json!({"\u{e9}": 1, "\u{1f600}": 2, "a": 3});
+        assert_eq!(
+            "{\"a\":3,\"\u{e9}\":1,\"\u{1f600}\":2}",
+            output::canonical(&value)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_into_builder() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.name", "name")?
+            .add_direct("$out.id", "copy")?
+            .add_constant("tenant", "kind")?
+            .build()?;
+        let input = r#"{"user":{"id":1,"name":"Joey"}}"#;
+        let expected = r#"{"copy":1,"id":1,"kind":"tenant","name":"Joey"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = trans
+            .into_builder()
+            .remove_mappings(vec![
+                Mapping::Direct {
+                    from: "user.name".into(),
+                    to: "name".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+                Mapping::Direct {
+                    from: "$out.id".into(),
+                    to: "copy".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+            ])?
+            .add_direct("user.name", "full_name")?
+            .build()?;
+        let expected = r#"{"full_name":"Joey","id":1,"kind":"tenant"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = trans.into_builder().remove_mapping(Mapping::Direct {
+            from: "user.name".into(),
+            to: "name".into(),
+            kind: None,
+            only_if_absent: false,
+            default: None,
+        });
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_pointer() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("/user/addresses/0/city", "/city")?
+            .add_direct("/a~1b", "nested.a/b")?
+            .add_direct("$out./city", "/copy/0")?
+            .build()?;
+        let input = r#"{"user":{"addresses":[{"city":"Calgary"}]},"a/b":true}"#;
+        let expected = r#"{"city":"Calgary","copy":["Calgary"],"nested":{"a/b":true}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_path() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_json_path("$.items[?(@.active==true)].id", "active_ids")?
+            .add_json_path("$.items[0].id", "first.id")?
+            .build()?;
+        let input =
+            r#"{"items":[{"id":1,"active":true},{"id":2,"active":false},{"id":3,"active":true}]}"#;
+        let expected = r#"{"active_ids":[1,3],"first":{"id":1}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_concat(&["first_name", "last_name"], "full_name", " ")?
+            .add_concat(
+                &["address.street", "address.number", "address.unit"],
+                "street",
+                ", ",
+            )?
+            .build()?;
+        let input = r#"[{"first_name":"Joey","last_name":"Bloggs","address":{"street":"Main","number":12}},{"last_name":"Bloggs"},{}]"#;
+        let expected = r#"[{"full_name":"Joey Bloggs","street":"Main, 12"},{"full_name":"Bloggs","street":null},{"full_name":null,"street":null}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let mapping: Mapping =
+            serde_json::from_str(r#"{"Concat":{"from":["a","b[1]"],"to":"ab","separator":"-"}}"#)?;
+        let trans = TransformerBuilder::default()
+            .add_mapping(mapping)?
+            .build()?;
+        let trans: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        let expected = r#"{"ab":"x-z"}"#;
+        assert_eq!(
+            expected,
+            trans
+                .apply_from_str(r#"{"a":"x","b":["y","z"]}"#)?
+                .to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_template() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_template(
+                "https://example.com/users/{user.id}?tz={settings.tz}",
+                "profile_url",
+            )?
+            .build()?;
+        let input = r#"[{"user":{"id":7},"settings":{"tz":"UTC"}},{"user":{"id":8}}]"#;
+        let expected =
+            r#"[{"profile_url":"https://example.com/users/7?tz=UTC"},{"profile_url":null}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_with_flags() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("price", "price")?
+            .add_direct("$ctx.flags.new_pricing", "new_pricing")?
+            .add_direct_with_default("$ctx.flags.region", "region", "us".into())?
+            .build()?;
+        let input = r#"{"price":10}"#;
+
+        let mut flags = Map::new();
+        flags.insert(String::from("new_pricing"), Value::Bool(true));
+        let expected = r#"{"new_pricing":true,"price":10,"region":"us"}"#;
+        assert_eq!(expected, trans.apply_with_flags(input, flags)?.to_string());
+
+        let expected = r#"{"new_pricing":null,"price":10,"region":"us"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_coerce() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_coerce("id", "id", Coerce::Integer)?
+            .add_coerce("price", "price", Coerce::Number)?
+            .add_coerce("active", "active", Coerce::Bool)?
+            .add_coerce("zip", "zip", Coerce::String)?
+            .build()?;
+        let input = r#"[{"id":"42","price":" 9.5 ","active":1,"zip":90210},{"id":"4.2","price":"n/a","active":"No","zip":true},{"id":7.0,"price":false,"active":"maybe","zip":[1]}]"#;
+        let expected = r#"[{"active":true,"id":42,"price":9.5,"zip":"90210"},{"active":false,"id":null,"price":null,"zip":"true"},{"active":null,"id":7,"price":0,"zip":null}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = TransformerBuilder::default()
+            .add_coerce_with("user.age", "age", Coerce::Integer, CoerceFailure::Error)?
+            .build()?;
+        assert_eq!(
+            r#"{"age":30}"#,
+            trans
+                .apply_from_str(r#"{"user":{"age":"30"}}"#)?
+                .to_string()
+        );
+        match trans
+            .apply_from_str(r#"{"user":{"age":"thirty"}}"#)
+            .map_err(Error::into_root_cause)
+        {
+            Err(Error::Coercion { path, expected, .. }) => {
+                assert_eq!("user.age", path);
+                assert_eq!("integer", expected);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_by() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct("id", "id")?
+                .add_direct("user.name", "name")?
+                .add_direct("user.email", "email")
+        };
+        let input = r#"[{"id":1,"user":{"name":"Joey"}},{"id":2,"user":{"name":"Dean"}},{"user":{"name":"Anon"}},{"id":1,"user":{"name":"Joe","email":"joe@example.com"}}]"#;
+
+        let trans = builder()?.merge_by("id", Conflict::Last)?.build()?;
+        let expected = r#"[{"email":"joe@example.com","id":1,"name":"Joe"},{"email":null,"id":2,"name":"Dean"},{"email":null,"id":null,"name":"Anon"}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.merge_by("id", Conflict::First)?.build()?;
+        let expected = r#"[{"email":"joe@example.com","id":1,"name":"Joey"},{"email":null,"id":2,"name":"Dean"},{"email":null,"id":null,"name":"Anon"}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.merge_by("id", Conflict::Error)?.build()?;
+        match trans.apply_from_str(input) {
+            Err(Error::MergeConflict(path)) => assert_eq!("user.name", path),
+            res => panic!("unexpected result {:?}", res),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_stream_to_sink() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("id", "user_id")?
+            .build()?;
+        let input = "{\"id\":1}\n[{\"id\":2},{\"id\":3}]\n{\"id\":4}";
+
+        let mut sink = Vec::new();
+        trans.apply_stream_to_sink(input.as_bytes(), &mut sink)?;
+        let ids: Vec<_> = sink.iter().map(|doc| doc["user_id"].clone()).collect();
+        assert_eq!(vec![1, 2, 3, 4], ids);
+
+        let mut sink = LineSink::new(Vec::new(), OutputOptions::default());
+        trans.apply_stream_to_sink(input.as_bytes(), &mut sink)?;
+        let expected = "{\"user_id\":1}\n{\"user_id\":2}\n{\"user_id\":3}\n{\"user_id\":4}\n";
+        assert_eq!(expected, String::from_utf8(sink.into_inner()).unwrap());
+
+        let mut sink = Vec::new();
+        assert!(trans
+            .apply_stream_to_sink("{\"id\":1} {".as_bytes(), &mut sink)
+            .is_err());
+        assert_eq!(1, sink.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let input = r#"{"defaults":{"retries":3,"db":{"host":"localhost","port":5432},"tags":["a"]},"overrides":{"db":{"host":"db.internal"},"tags":["b"]},"empty":null}"#;
+        let trans = TransformerBuilder::default()
+            .add_merge(&["defaults", "empty", "missing", "overrides"], "config")?
+            .build()?;
+        let expected =
+            r#"{"config":{"db":{"host":"db.internal","port":5432},"retries":3,"tags":["b"]}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = TransformerBuilder::default()
+            .add_merge_with(&["defaults", "overrides"], "config", ArrayMerge::Concat)?
+            .build()?;
+        let expected =
+            r#"{"config":{"db":{"host":"db.internal","port":5432},"retries":3,"tags":["a","b"]}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let input = r#"{"a":{"items":[{"id":1,"qty":1},{"id":2}]},"b":{"items":[{"qty":5}]}}"#;
+        let trans = TransformerBuilder::default()
+            .add_merge_with(&["a", "b"], "merged", ArrayMerge::Index)?
+            .build()?;
+        let expected = r#"{"merged":{"items":[{"id":1,"qty":5},{"id":2}]}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = TransformerBuilder::default().add_merge(&["a", "b[*]"], "merged");
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_reader_and_writer() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let res = trans.apply_from_reader(r#"{"user":{"id":1}}"#.as_bytes())?;
+        assert_eq!(r#"{"id":1}"#, res.to_string());
+        match trans.apply_from_reader(r#"{"user":"#.as_bytes()) {
+            Err(Error::Json(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut output = Vec::new();
+        trans.apply_to_writer(serde_json::json!([{"user":{"id":2}}]), &mut output)?;
+        assert_eq!(r#"[{"id":2}]"#, String::from_utf8(output).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invert() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.names[0]", "name.first")?
+            .add_direct("tags[1]", "tags[0]")?
+            .destination_root("data")?
+            .build()?;
+        let input = r#"{"user":{"id":1,"names":["Joey","Bloggs"]},"tags":["a","b"]}"#;
+        let output = trans.apply_from_str(input)?;
+        assert_eq!(
+            r#"{"data":{"id":1,"name":{"first":"Joey"},"tags":["b"]}}"#,
+            output.to_string()
+        );
+        assert_eq!(
+            r#"{"tags":[null,"b"],"user":{"id":1,"names":["Joey"]}}"#,
+            trans.invert()?.apply(&output)?.to_string()
+        );
+
+        let res = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_constant(Value::from(1), "version")?
+            .add_flatten("user", "", FlattenOps::default())?
+            .add_direct("other_id", "id")?
+            .build()?
+            .invert();
+        match res {
+            Err(Error::NotInvertible(reasons)) => assert_eq!(
+                "`$`: constants have no source, `user`: flattened destinations can not be \
+                 inverted, `id`: destinations written by multiple rules can not be inverted",
+                reasons
+            ),
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.email", "contact.email")?
+            .add_direct("items[*].sku", "skus[*]")?
+            .add_constant(Value::from(1), "version")?
+            .build()?;
+        let report = trans.explain(r#"{"user":{"id":1},"items":[{"sku":"a"},{"sku":"b"}]}"#)?;
+        assert_eq!(
+            Some(&serde_json::json!({
+                "id": 1,
+                "contact": {"email": null},
+                "skus": ["a", "b"],
+                "version": 1
+            })),
+            report.output()
+        );
+        let rules = report
+            .rules()
+            .iter()
+            .map(|r| (r.source(), r.resolved(), r.written().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (None, None, vec![("version".to_owned(), Value::from(1))]),
+                (
+                    Some("user.id"),
+                    Some(true),
+                    vec![("id".to_owned(), Value::from(1))]
+                ),
+                (
+                    Some("user.email"),
+                    Some(false),
+                    vec![("contact.email".to_owned(), Value::Null)]
+                ),
+                (
+                    Some("items[0].sku"),
+                    Some(true),
+                    vec![("skus[0]".to_owned(), Value::from("a"))]
+                ),
+                (
+                    Some("items[1].sku"),
+                    Some(true),
+                    vec![("skus[1]".to_owned(), Value::from("b"))]
+                ),
+            ],
+            rules
+        );
+        assert_eq!(Some("user.email"), report.unresolved()[0].source());
+
+        // rules under a missing parent are explained as not resolving their source
+        let report = trans.explain(r#"{"account":{}}"#)?;
+        let unresolved = report
+            .unresolved()
+            .iter()
+            .map(|r| r.source())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![Some("user.id"), Some("user.email"), Some("items[*].sku")],
+            unresolved
+        );
+        assert_eq!(4, report.rules().len());
+
+        let report = trans
+            .into_builder()
+            .missing_policy(MissingPolicy::Error)
+            .build()?
+            .explain(r#"{"user":{"id":1}}"#)?;
+        assert_eq!(None, report.output());
+        assert_eq!(
+            Some("`Direct` rule 1 of node 1 reading `user.email` writing `contact.email` failed: missing source value at `user.email`"),
+            report.error()
+        );
+        assert_eq!(report.error(), report.rules().last().unwrap().error());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.email", "email")?
+            .add_direct("items[*].sku", "skus[*]")?
+            .build()?;
+        let mut metrics = Metrics::default();
+        trans.apply_with_metrics(r#"{"user":{"id":1},"items":[{"sku":"a"}]}"#, &mut metrics)?;
+        trans.apply_with_metrics(r#"{"user":{"id":2},"items":[{}]}"#, &mut metrics)?;
+        assert_eq!(2, metrics.applies());
+        assert_eq!(6, metrics.written());
+        assert_eq!(3, metrics.nulls());
+        assert_eq!(Some((2, 0)), metrics.source("user.id"));
+        assert_eq!(Some((1, 1)), metrics.source("items[*].sku"));
+        assert_eq!(vec!["user.email"], metrics.never_resolved());
+
+        // rules under a missing parent don't resolve their source either
+        let mut metrics = Metrics::default();
+        trans.apply_with_metrics(r#"{"account":{}}"#, &mut metrics)?;
+        assert_eq!(
+            vec!["items[*].sku", "user.email", "user.id"],
+            metrics.never_resolved()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unmapped_fields() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("items[*].sku", "skus[*]")?
+            .add_flatten("tags", "", FlattenOps::default())?
+            .build()?;
+        let input = serde_json::json!({
+            "user": {"id": 1, "name": "Joey"},
+            "items": [{"sku": "a", "qty": 1}, {"sku": "b", "price": 2}],
+            "tags": ["x", "y"],
+            "meta": {},
+            "matrix": [[1]]
+        });
+        assert_eq!(
+            vec![
+                "items[*].price",
+                "items[*].qty",
+                "matrix[*][*]",
+                "meta",
+                "user.name"
+            ],
+            trans.unmapped_fields(&input)
+        );
+        assert_eq!(
+            vec!["other"],
+            trans.unmapped_fields(&serde_json::json!([{"user": {"id": 1}}, {"other": 1}]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination_conflicts() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct("id", "id")?
+                .add_direct("user.id", "id")?
+                .add_direct("user", "user")?
+                .add_direct("user.name", "user.name")?
+                .add_direct("items[*].sku", "skus[*]")?
+                .add_direct("first", "skus[0]")?
+                .add_constant_if_absent("unknown", "user.name")?
+                .add_direct("name", "name")?
+                .add_direct("code", "codes")?
+                .add_direct("first_code", "codes[0]")?
+                .add_direct("label", "labels")?
+                .add_direct("items[*].label", "labels[*]")?
+                .add_direct("second", "skus[1]")
+        };
+        let expected = vec![
+            "`id` and `id` are both written",
+            "`user` and `user.name` are both written",
+            "`skus[0]` and `skus[*]` are both written",
+            "`codes` and `codes[0]` are both written",
+            "`labels` and `labels[*]` are both written",
+            "`skus[1]` and `skus[*]` are both written",
+        ];
+        assert_eq!(expected, builder()?.destination_conflicts());
+        assert!(builder()?.build().is_ok());
+        match builder()?
+            .on_destination_conflict(DestinationConflict::Error)
+            .build()
+        {
+            Err(Error::DestinationConflict(conflicts)) => {
+                assert_eq!(expected.join(", "), conflicts)
+            }
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_and_replace_mapping_to() -> Result<()> {
+        let base = TransformerBuilder::default()
+            .passthrough(true)
+            .index_base(1)
+            .add_direct("user.id", "id")?
+            .add_direct("user.name", "name")?
+            .add_constant_if_absent("unknown", "name")?
+            .add_direct("tags[0]", "tags[1]")?;
+        let input = r#"{"user":{"id":1,"name":"Joey","nickname":"JB"},"tags":["a"]}"#;
+
+        let trans = base
+            .remove_mapping_to("name")?
+            .remove_mapping_to("tags[1]")?
+            .build()?;
+        assert_eq!(
+            r#"{"id":1,"tags":["a"],"user":{"name":"Joey","nickname":"JB"}}"#,
+            trans.apply_from_str(input)?.to_string()
+        );
+
+        let trans = trans
+            .into_builder()
+            .replace_mapping(
+                "id",
+                Mapping::Direct {
+                    from: "user.nickname".into(),
+                    to: "id".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+            )?
+            .build()?;
+        assert_eq!(
+            r#"{"id":"JB","tags":["a"],"user":{"id":1,"name":"Joey"}}"#,
+            trans.apply_from_str(input)?.to_string()
+        );
+        assert!(trans.into_builder().remove_mapping_to("other").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_builders() -> Result<()> {
+        let base = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.name", "name")?
+            .build()?;
+        let customer = TransformerBuilder::default()
+            .add_direct("user.tier", "tier")?
+            .add_direct("user.nickname", "name")?
+            .after_each(|output| {
+                output.insert("customer".to_owned(), Value::from(true));
+            });
+        let input = r#"{"user":{"id":1,"name":"Joey","nickname":"JB","tier":"gold"}}"#;
+
+        let trans = base.to_builder()?.merge(customer)?.build()?;
+        assert_eq!(
+            r#"{"customer":true,"id":1,"name":"JB","tier":"gold"}"#,
+            trans.apply_from_str(input)?.to_string()
+        );
+        assert_eq!(
+            r#"{"id":1,"name":"Joey"}"#,
+            base.apply_from_str(input)?.to_string()
+        );
+
+        let res = base
+            .to_builder()?
+            .on_destination_conflict(DestinationConflict::Error)
+            .merge(TransformerBuilder::default().add_direct("user.nickname", "name")?);
+        match res {
+            Err(Error::DestinationConflict(conflicts)) => {
+                assert_eq!("`name` and `name` are both written", conflicts)
+            }
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+
+        // hooks of nested Transformers can't be copied
+        let item = TransformerBuilder::default()
+            .add_direct("sku", "sku")?
+            .after_each(|output| {
+                output.insert("checked".to_owned(), Value::from(true));
+            })
+            .build()?;
+        let trans = TransformerBuilder::default()
+            .add_map_array("items", "items", item)?
+            .build()?;
+        assert!(matches!(trans.to_builder(), Err(Error::Definition(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mappings() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .index_base(1)
+            .add_direct("order.id", "id")?
+            .add_direct_with_default("order.customer.name", "customer", "guest".into())?
+            .add_direct("order.lines[*].sku", "skus[*]")?
+            .add_direct("order.total", "totals[1]")?
+            .add_constant("web", "channel")?
+            .add_template("#{order.id}-{order.status}", "reference")?
+            .add_coerce("order.total", "amount", Coerce::String)?
+            .add_split("order.tags", "tags", ",")?
+            .add_flatten("order.meta", "", FlattenOps::default())?
+            .add_when(
+                Predicate::equals("order.status", "shipped")?,
+                Mapping::Direct {
+                    from: "order.tracking".into(),
+                    to: "tracking".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+            )?
+            .build()?;
+        let input = r#"{"order":{"id":1,"status":"shipped","total":15,"tags":"a,b","tracking":"1Z","lines":[{"sku":"x"},{"sku":"y"}],"meta":{"source":"app"}}}"#;
+
+        let loaded: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        let mappings = loaded.mappings()?;
+        assert_eq!(10, mappings.len());
+        let rebuilt = TransformerBuilder::default()
+            .index_base(1)
+            .add_mappings(mappings)?
+            .build()?;
+        assert_eq!(trans.apply_from_str(input)?, rebuilt.apply_from_str(input)?);
+        assert_eq!(
+            r##"{"amount":"15","channel":"web","customer":"guest","id":1,"reference":"#1-shipped","skus":["x","y"],"source":"app","tags":["a","b"],"totals":[15],"tracking":"1Z"}"##,
+            rebuilt.apply_from_str(input)?.to_string()
+        );
+
+        // custom rules have no Mapping
+        let trans = TransformerBuilder::default()
+            .add(&[], RandomRule {})?
+            .build()?;
+        assert!(trans.mappings()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_definition() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.name", "name")?
+            .build()?;
+        let input = r#"{"user":{"id":1,"name":"Joey"}}"#;
+        let expected = r#"{"id":1,"name":"Joey"}"#;
+
+        let definition = trans.to_json_definition()?;
+        assert!(definition.starts_with(r#"{"version":2,"transformer":{"#));
+        let loaded = Transformer::from_json_definition(&definition)?;
+        assert_eq!(expected, loaded.apply_from_str(input)?.to_string());
+
+        // version 1 is the bare Transformer, with the Arena child ranges of earlier versions
+        let legacy = r#"{"root":{"tree":[
+            {"Object":{"id":"","children":[1,1],"rules":null}},
+            {"Object":{"id":"user","children":null,"rules":[{"Transform":{"source":{"Direct":"id"},"destination":{"Direct":{"namespace":[],"id":"id"}}}}]}}
+        ]},"mode":"Many2Many"}"#;
+        let loaded = Transformer::from_json_definition(legacy)?;
+        assert_eq!(r#"{"id":1}"#, loaded.apply_from_str(input)?.to_string());
+        let bare = serde_json::to_string(&trans)?;
+        let loaded = Transformer::from_json_definition(&bare)?;
+        assert_eq!(expected, loaded.apply_from_str(input)?.to_string());
+
+        for (definition, err) in [
+            (
+                r#"{"version":3,"transformer":{}}"#,
+                "version 3 is newer than the supported version 2",
+            ),
+            (r#"{"version":2}"#, "missing transformer"),
+            (
+                r#"{"version":"2","transformer":{}}"#,
+                r#"version must be a positive integer, found "2""#,
+            ),
+        ] {
+            match Transformer::from_json_definition(definition) {
+                Err(Error::Definition(e)) => assert_eq!(err, e),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_path() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("bumblebee-from-path-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let input = r#"{"user":{"id":1,"name":"Joey"}}"#;
+        let expected = r#"{"id":1,"name":"Joey"}"#;
+        let definition = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.name", "name")?
+            .build()?
+            .to_json_definition()?;
+        let files = [
+            ("definition.json", definition.as_str()),
+            (
+                "mappings.json",
+                r#"[{"Direct":{"from":"user.id","to":"id"}},{"Direct":{"from":"user.name","to":"name"}}]"#,
+            ),
+            ("mappings.bb", "user.id -> id\nuser.name -> name\n"),
+        ];
+        for (name, contents) in &files {
+            fs::write(dir.join(name), contents)?;
+            let trans = Transformer::from_path(dir.join(name))?;
+            assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        }
+
+        let invalid = [
+            ("invalid.json", "[\n  {\"Direct\":{\"from\":1}}\n]", 2, 21),
+            ("invalid.bb", "user.id -> id\nuser.name => upper()", 2, 14),
+        ];
+        for (name, contents, line, column) in &invalid {
+            let path = dir.join(name);
+            fs::write(&path, contents)?;
+            match Transformer::from_path(&path) {
+                Err(Error::Parse {
+                    path: p,
+                    line: l,
+                    column: c,
+                    ..
+                }) => assert_eq!((path.display().to_string(), *line, *column), (p, l, c)),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
+        }
+        fs::write(dir.join("mappings.yaml"), "")?;
+        assert!(matches!(
+            Transformer::from_path(dir.join("mappings.yaml")),
+            Err(Error::Definition(_))
+        ));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_failed() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_coerce_with(
+                "order.lines[*].qty",
+                "quantities[*]",
+                Coerce::Integer,
+                CoerceFailure::Error,
+            )?
+            .build()?;
+        let input = r#"{"id":1,"order":{"lines":[{"qty":"1"},{"qty":"many"}]}}"#;
+        match trans.apply_from_str(input) {
+            Err(Error::RuleFailed {
+                rule,
+                rule_id,
+                source_path,
+                destination_path,
+                cause,
+            }) => {
+                assert_eq!("Coerce", rule);
+                assert_eq!("0 of node 2", rule_id.to_string());
+                assert_eq!("order.lines[1].qty", source_path);
+                assert_eq!(Some("quantities[*]"), destination_path.as_deref());
+                match *cause {
+                    Error::Coercion { path, .. } => assert_eq!("order.lines[1].qty", path),
+                    e => panic!("unexpected cause {:?}", e),
+                }
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_collecting() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.email", "email")?
+            .add_coerce_with("user.age", "age", Coerce::Integer, CoerceFailure::Error)?
+            .add_coerce_with(
+                "user.tags[*].score",
+                "scores[*]",
+                Coerce::Number,
+                CoerceFailure::Error,
+            )?
+            .missing_policy(MissingPolicy::Error)
+            .build()?;
+        let input = r#"{"user":{"id":1,"age":"old","tags":[{"score":"1"},{"score":"high"}]}}"#;
+        let (output, errors) = trans.apply_collecting(input);
+        assert_eq!(r#"{"id":1,"scores":[1]}"#, output.to_string());
+        let errors = errors
+            .iter()
+            .map(|e| e.root_cause().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "missing source value at `user.email`",
+                "can not coerce \"old\" at `user.age` to integer",
+                "can not coerce \"high\" at `user.tags[1].score` to number",
+            ],
+            errors
+        );
+        assert!(trans.apply_from_str(input).is_err());
+
+        let (output, errors) = trans.apply_collecting(r#"{"user":"#);
+        assert_eq!(Value::Null, output);
+        assert!(matches!(errors[..], [Error::Json(_)]));
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct FailingRule {}
+
+    #[typetag::serde]
+    impl Rule for FailingRule {
+        fn apply(&self, _from: &Value, _to: &mut Map<String, Value>) -> Result<()> {
+            Err(Error::Rule(String::from("flaky")))
+        }
+
+        fn destination(&self) -> Option<Vec<Namespace>> {
+            Some(parse_path("meta.flaky".into()).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_fallback() -> Result<()> {
+        let coerce = |to: &'static str| Mapping::Coerce {
+            from: "age".into(),
+            to: to.into(),
+            coerce: Coerce::Integer,
+            on_failure: CoerceFailure::Error,
+            only_if_absent: false,
+        };
+        let trans = TransformerBuilder::default()
+            .add_with_fallback(coerce("null"), Fallback::Null)?
+            .add_with_fallback(coerce("constant"), Fallback::Constant(Value::from(-1)))?
+            .add_with_fallback(coerce("skip"), Fallback::Skip)?
+            .add_fallible(&[], FailingRule {}, Fallback::Constant("n/a".into()))?
+            .build()?;
+        let expected = r#"{"constant":-1,"meta":{"flaky":"n/a"},"null":null}"#;
+        assert_eq!(
+            expected,
+            trans.apply_from_str(r#"{"age":"old"}"#)?.to_string()
+        );
+        assert_eq!(
+            r#"{"constant":42,"meta":{"flaky":"n/a"},"null":42,"skip":42}"#,
+            trans.apply_from_str(r#"{"age":"42"}"#)?.to_string()
+        );
+
+        // the fallback is part of the serialized mapping
+        let mapping = r#"{"OnError":{"on_error":{"Constant":0},"mapping":{"Coerce":{"from":"age","to":"age","coerce":"Integer","on_failure":"Error","only_if_absent":false}}}}"#;
+        let trans = TransformerBuilder::default()
+            .add_mapping(serde_json::from_str(mapping)?)?
+            .build()?;
+        assert_eq!(
+            r#"{"age":0}"#,
+            trans.apply_from_str(r#"{"age":"old"}"#)?.to_string()
+        );
+        let loaded: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        assert_eq!(mapping, serde_json::to_string(&loaded.mappings()?[0])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_with_context() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_constant("${ctx.request_id}", "meta.request")?
+            .add_constant("${ctx.limits}", "meta.limits")?
+            .add_constant("tenant-${ctx.tenant.id}/${ctx.region}", "meta.key")?
+            .add_template("{$ctx.tenant.id}:{id}", "reference")?
+            .add_constant("{literal}", "literal")?
+            .build()?;
+        let mut context = Map::new();
+        context.insert("request_id".to_owned(), "req-1".into());
+        context.insert("limits".to_owned(), serde_json::json!({"max":10}));
+        context.insert("tenant".to_owned(), serde_json::json!({"id":7}));
+        context.insert("region".to_owned(), "eu".into());
+
+        let expected = r#"{"id":1,"literal":"{literal}","meta":{"key":"tenant-7/eu","limits":{"max":10},"request":"req-1"},"reference":"7:1"}"#;
+        let output = trans.apply_with_context(r#"{"id":1}"#, &context)?;
+        assert_eq!(expected, output.to_string());
+
+        // missing Context values are missing sources
+        let expected = r#"{"id":1,"literal":"{literal}","meta":{"key":null,"limits":null,"request":null},"reference":null}"#;
+        assert_eq!(expected, trans.apply_from_str(r#"{"id":1}"#)?.to_string());
+
+        let loaded: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        let output = loaded.apply_with_context(r#"{"id":2}"#, &context)?;
+        assert_eq!(Some(&Value::from("7:2")), output.get("reference"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_env() -> Result<()> {
+        // cargo sets the manifest directory when running tests, the other variable is never set
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let builder = TransformerBuilder::default()
+            .add_constant_env("CARGO_MANIFEST_DIR", "meta.dir")?
+            .add_constant_env_with_default(
+                "BUMBLEBEE_TEST_CONSTANT_ENV_UNSET",
+                "meta.zone",
+                "a".into(),
+            )?
+            .add_constant_env("BUMBLEBEE_TEST_CONSTANT_ENV_UNSET", "meta.missing")?;
+        let trans = builder
+            .allow_env(&["CARGO_MANIFEST_DIR", "BUMBLEBEE_TEST_CONSTANT_ENV_UNSET"])
+            .build()?;
+        let expected = serde_json::json!({"meta":{"dir":dir,"missing":null,"zone":"a"}});
+        assert_eq!(expected, trans.apply_from_str("{}")?);
+
+        // the variable is read when applied, not when built or loaded, and loaded Transformers
+        // read none until rebuilt allowing them
+        let serialized = serde_json::to_string(&trans)?;
+        assert!(!serialized.contains(dir));
+        let loaded: Transformer = serde_json::from_str(&serialized)?;
+        match loaded.apply_from_str("{}") {
+            Err(e) => assert!(
+                matches!(e.root_cause(), Error::EnvNotAllowed(var) if var == "CARGO_MANIFEST_DIR")
+            ),
+            res => panic!("unexpected result {:?}", res),
+        }
+        let loaded = loaded
+            .into_builder()
+            .allow_env(&["CARGO_MANIFEST_DIR", "BUMBLEBEE_TEST_CONSTANT_ENV_UNSET"])
+            .build()?;
+        assert_eq!(expected, loaded.apply_from_str("{}")?);
+        assert_eq!(expected, trans.to_builder()?.build()?.apply_from_str("{}")?);
+
+        let err = TransformerBuilder::default()
+            .add_constant_env("CARGO_MANIFEST_DIR", "meta.dir")?
+            .add_constant_env("HOME", "meta.home")?
+            .allow_env(&["CARGO_MANIFEST_DIR"])
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, Error::EnvNotAllowed(vars) if vars == "HOME"));
+
+        let mappings = trans.mappings()?;
+        assert!(matches!(
+            &mappings[1],
+            Mapping::ConstantEnv { var, default: Some(_), .. } if var == "BUMBLEBEE_TEST_CONSTANT_ENV_UNSET"
+        ));
+        assert!(TransformerBuilder::default()
+            .add_constant_env("", "region")
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_fn() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_fn(&parse_path("user".into())?, |from, to| {
+                let name = format!(
+                    "{} {}",
+                    from["first"].as_str().unwrap_or_default(),
+                    from["last"].as_str().unwrap_or_default()
+                );
+                to.insert("name".to_owned(), name.trim().into());
+                Ok(())
+            })?
+            .build()?;
+        let output = trans.apply_from_str(r#"{"id":1,"user":{"first":"Joey","last":"Bloggs"}}"#)?;
+        assert_eq!(r#"{"id":1,"name":"Joey Bloggs"}"#, output.to_string());
+
+        // closures are applied like any other rule, but can not be serialized
+        assert!(serde_json::to_string(&trans).is_err());
+        assert!(trans.to_builder().is_err());
+        assert!(serde_json::from_str::<Box<dyn Rule>>(r#"{"FnRule":null}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_function() -> Result<()> {
+        let functions = FunctionRegistry::new().register("normalize_phone", |v| match v {
+            Value::String(s) => Ok(Value::String(
+                s.chars().filter(char::is_ascii_digit).collect(),
+            )),
+            _ => Err(Error::InvalidSourceValue(format!(
+                "not a phone number: {}",
+                v
+            ))),
+        });
+        let trans = TransformerBuilder::default()
+            .functions(functions.clone())
+            .add_function("user.phone", "phone", "normalize_phone")?
+            .add_function("user.fax", "fax", "normalize_phone")?
+            .build()?;
+        let output =
+            trans.apply_from_str(r#"{"user":{"phone":"+1 (555) 010-9999","fax":null}}"#)?;
+        assert_eq!(r#"{"fax":null,"phone":"15550109999"}"#, output.to_string());
+        let err = trans
+            .apply_from_str(r#"{"user":{"phone":5550109999}}"#)
+            .map_err(Error::into_root_cause)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidSourceValue(_)));
+
+        // only the name is serialized, the functions are registered again when loaded
+        let serialized = serde_json::to_string(&trans)?;
+        let loaded: Transformer = serde_json::from_str(&serialized)?;
+        let err = loaded
+            .apply_from_str(r#"{"user":{"phone":"1"}}"#)
+            .map_err(Error::into_root_cause)
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownFunction(name) if name == "normalize_phone"));
+        let loaded = loaded.into_builder().functions(functions.clone()).build()?;
+        assert_eq!(
+            output,
+            loaded.apply_from_str(r#"{"user":{"phone":"+1 (555) 010-9999","fax":null}}"#)?
+        );
+        assert!(matches!(
+            &trans.mappings()?[0],
+            Mapping::Function { name, .. } if name == "normalize_phone"
+        ));
+
+        let err = TransformerBuilder::default()
+            .add_function("phone", "phone", "missing")?
+            .build()
+            .unwrap_err();
+        assert_eq!("unknown function: missing", err.to_string());
+
+        // functions called by nested Transformers are registered with the outermost one
+        let contact = TransformerBuilder::default()
+            .functions(functions.clone())
+            .add_function("phone", "phone", "normalize_phone")?
+            .build()?;
+        let trans = TransformerBuilder::default()
+            .add_map_array("contacts", "contacts", contact)?
+            .build()?;
+        let input = r#"{"contacts":[{"phone":"555-0100"}]}"#;
+        let expected = r#"{"contacts":[{"phone":"5550100"}]}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        let loaded = Transformer::from_json_definition(&trans.to_json_definition()?)?;
+        let err = loaded.into_builder().build().unwrap_err();
+        assert_eq!("unknown function: normalize_phone", err.to_string());
+        let loaded = Transformer::from_json_definition(&trans.to_json_definition()?)?
+            .into_builder()
+            .functions(functions)
+            .build()?;
+        assert_eq!(expected, loaded.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() -> Result<()> {
+        use crate::Bumblebee;
+
+        #[derive(Debug, Serialize)]
+        struct Nested {
+            key: String,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct From {
+            user_id: u64,
+            name: String,
+            nested: Nested,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Bumblebee)]
+        struct To {
+            #[bee(from = "user_id")]
+            id: u64,
+            name: String,
+            #[serde(rename = "inner-key")]
+            #[bee(from = "nested.key")]
+            key: String,
+            #[bee(skip)]
+            #[serde(default)]
+            cached: Option<String>,
+        }
+
+        let from = From {
+            user_id: 1,
+            name: String::from("Joey"),
+            nested: Nested {
+                key: String::from("value"),
+            },
+        };
+        let expected = To {
+            id: 1,
+            name: String::from("Joey"),
+            key: String::from("value"),
+            cached: None,
+        };
+        assert_eq!(expected, To::transform_from(&from)?);
+
+        let trans = To::transformer_builder()?
+            .add_constant("cached", "cached")?
+            .build()?;
+        let to: To = trans.apply_to(&from)?;
+        assert_eq!(Some(String::from("cached")), to.cached);
+
+        #[derive(Debug, Deserialize, PartialEq, Bumblebee)]
+        #[serde(rename_all = "camelCase")]
+        struct Renamed {
+            #[bee(from = "user_id")]
+            user_id: u64,
+            #[bee(from = "name")]
+            display_name: String,
+        }
+        let expected = Renamed {
+            user_id: 1,
+            display_name: String::from("Joey"),
+        };
+        assert_eq!(expected, Renamed::transform_from(&from)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_map() -> Result<()> {
+        let source = serde_json::json!({
+            "user_id": 1,
+            "profile": {"display_name": "Joey", "email": "joey@example.com"},
+            "orders": [{"order_id": 7, "total": 9.5}]
+        });
+        let target = serde_json::json!({
+            "userId": 0,
+            "displayName": "",
+            "contact": {"email": "", "phone": ""},
+            "orders": [{"orderId": 0, "total": 0.0}]
+        });
+        let (builder, unmatched) =
+            TransformerBuilder::default().auto_map_with(&source, &target, NameMatch::Fuzzy)?;
+        assert_eq!(vec!["contact.phone"], unmatched);
+        let trans = builder.add_constant("555", "contact.phone")?.build()?;
+
+        let input = r#"{"user_id":2,"profile":{"display_name":"Dean","email":"dean@example.com"},"orders":[{"order_id":8,"total":1.5},{"order_id":9,"total":2}]}"#;
+        let expected = r#"{"contact":{"email":"dean@example.com","phone":"555"},"displayName":"Dean","orders":[{"orderId":8,"total":1.5},{"orderId":9,"total":2}],"userId":2}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let (_, unmatched) = TransformerBuilder::default().auto_map(&source, &target)?;
+        assert_eq!(
+            vec![
+                "contact.phone",
+                "displayName",
+                "orders[*].orderId",
+                "userId"
+            ],
+            unmatched
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_output_schema() -> Result<()> {
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["id", "email"],
+            "properties": {
+                "id": {"type": "integer"},
+                "email": {"type": "string"}
+            }
+        });
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct("user.email", "email")?
+            .build()?
+            .with_output_schema(schema)?;
+        trans.apply_from_str(r#"{"user":{"id":1,"email":"joey@example.com"}}"#)?;
+
+        let err = trans.apply_from_str(r#"{"user":{"id":"1"}}"#).unwrap_err();
+        assert_eq!(
+            "output does not match its schema: `email` expected string but found null, `id` expected integer but found string",
+            err.to_string()
+        );
+        assert!(matches!(err, Error::SchemaViolation { violations, .. } if violations.len() == 2));
+
+        // the schema is kept when serialized
+        let loaded: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        assert!(loaded.apply_from_str(r#"{"user":{"id":1}}"#).is_err());
+        assert!(TransformerBuilder::default()
+            .build()?
+            .with_output_schema(serde_json::json!({"type":"text"}))
+            .is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_input_schema() -> Result<()> {
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["user"],
+            "properties": {
+                "user": {"type": "object", "required": ["id"]}
+            }
+        });
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?
+            .with_input_schema(schema.clone(), SchemaPolicy::Reject)?;
+        assert_eq!(
+            r#"{"id":1}"#,
+            trans.apply_from_str(r#"{"user":{"id":1}}"#)?.to_string()
+        );
+        let err = trans.apply_from_str(r#"{"account":{"id":1}}"#).unwrap_err();
+        assert_eq!(
+            "input does not match its schema: `$` is missing required property `user`",
+            err.to_string()
+        );
+        assert_eq!(
+            "user",
+            trans.validate_input(&serde_json::json!({"user":{}}))[0].path
+        );
+
+        let trans = trans
+            .into_builder()
+            .build()?
+            .with_input_schema(schema, SchemaPolicy::Warn)?;
+        let (output, errors) = trans.apply_collecting(r#"{"user":{}}"#);
+        assert_eq!(r#"{"id":null}"#, output.to_string());
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::SchemaViolation {
+                document: "input",
+                ..
+            }]
+        ));
+        assert!(trans.apply_from_str(r#"{"user":{}}"#).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_input_schema() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .add_direct_typed("user.name", "name", Type::String)?
+            .add_split("user.tags", "tags", ",")?
+            .add_direct("items[*].sku", "skus[*]")?
+            .add_direct("matrix[0][1]", "cell")?
+            .add_direct_with_default("account.plan.tier", "tier", "free".into())?
+            .add_constant("constant", "constant")?
+            .build()?;
+        let expected = serde_json::json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {
+                "account": {"type": "object", "properties": {
+                    "plan": {"type": "object", "properties": {"tier": {}}}
+                }},
+                "items": {"type": "array", "items": {"type": "object", "properties": {"sku": {}}}},
+                "matrix": {"type": "array", "items": {"type": "array", "items": {}}},
+                "user": {"type": "object", "properties": {
+                    "id": {},
+                    "name": {"type": "string"},
+                    "tags": {"type": "string"}
+                }}
+            }
+        });
+        assert_eq!(expected, trans.input_schema());
+        assert_eq!(
+            serde_json::json!({"$schema": "http://json-schema.org/draft-07/schema#", "type": "object"}),
+            TransformerBuilder::default().build()?.input_schema()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_output_schema() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("id", "user.id")?
+            .add_constant("v1", "meta.version")?
+            .add_coerce("age", "user.age", Coerce::Integer)?
+            .add_direct("phones[0]", "contact.phones[1]")?
+            .add_direct("items[*].sku", "skus[*]")?
+            .add_template("{first} {last}", "user.name")?
+            .destination_root("data")?
+            .build()?;
+        let expected = serde_json::json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {"data": {
+                "type": "object",
+                "properties": {
+                    "contact": {"type": "object", "properties": {
+                        "phones": {"type": "array", "items": [{}, {}]}
+                    }},
+                    "meta": {"type": "object", "properties": {
+                        "version": {"type": ["string", "null"]}
+                    }},
+                    "skus": {"type": "array", "items": {}},
+                    "user": {"type": "object", "properties": {
+                        "age": {"type": ["integer", "null"]},
+                        "id": {},
+                        "name": {"type": ["string", "null"]}
+                    }}
+                }
+            }}
+        });
+        let schema = trans.output_schema();
+        assert_eq!(expected, schema);
+
+        #[cfg(feature = "schema")]
+        {
+            let trans = trans.with_output_schema(schema)?;
+            trans.apply_from_str(r#"{"id":1,"age":"30","items":[{"sku":"a"}]}"#)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_patch() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user_id", "user_id")?
+            .add_direct("name", "user.name")?
+            .add_constant(true, "active")?
+            .build()?;
+        let input: Value = serde_json::from_str(r#"{"user_id":"111","name":"Joey"}"#)?;
+        let ops = trans.diff_patch(&input)?;
+        assert_eq!(
+            r#"[{"op":"remove","path":"/name"},{"op":"add","path":"/active","value":true},{"op":"add","path":"/user","value":{"name":"Joey"}}]"#,
+            serde_json::to_string(&ops)?
+        );
+
+        let mut patched = input.clone();
+        patch::apply(&mut patched, &ops)?;
+        assert_eq!(trans.apply(&input)?, patched);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute() -> Result<()> {
+        let cents = |v: f64| serde_json::Number::from_f64(v).unwrap();
+        let trans = TransformerBuilder::default()
+            .add_compute(
+                "price_cents",
+                "price",
+                vec![ComputeOp::Scale(cents(0.01)), ComputeOp::Round(2)],
+            )?
+            .add_compute(
+                "delta",
+                "distance",
+                vec![ComputeOp::Offset((-10).into()), ComputeOp::Abs],
+            )?
+            .build()?;
+        let output = trans.apply_from_str(r#"{"price_cents":1999,"delta":4}"#)?;
+        assert_eq!(r#"{"distance":6,"price":19.99}"#, output.to_string());
+
+        let output = trans.apply_from_str(r#"{"price_cents":"1999","delta":4}"#)?;
+        assert_eq!(r#"{"distance":6,"price":null}"#, output.to_string());
+
+        let serialized = serde_json::to_string(&trans)?;
+        let trans: Transformer = serde_json::from_str(&serialized)?;
+        let output = trans.apply_from_str(r#"{"price_cents":5,"delta":null}"#)?;
+        assert_eq!(r#"{"distance":null,"price":0.05}"#, output.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_mask("email", "email_hash", Mask::Sha256)?
+            .add_mask("card", "card", Mask::Redact { keep_last: 4 })?
+            .add_mask("token", "token", Mask::Fixed("[redacted]".to_owned()))?
+            .build()?;
+        let input = r#"{"email":"abc","card":"4242424242424242","token":"s3cr3t"}"#;
+        let expected = r#"{"card":"************4242","email_hash":"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad","token":"[redacted]"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_encrypt() -> Result<()> {
+        use crate::crypto::Cipher;
+
+        struct Xor;
+
+        impl Cipher for Xor {
+            fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+                Ok(plaintext.iter().map(|b| b ^ 0x5a).collect())
+            }
+
+            fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+                self.encrypt(ciphertext)
+            }
+        }
+
+        let keys = Keys::new().insert("pii", Xor);
+        let encrypt = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_encrypt("user", "user", "pii")?
+            .build()?;
+        let decrypt = TransformerBuilder::default()
+            .add_direct("id", "id")?
+            .add_decrypt("user", "user", "pii")?
+            .build()?;
+        let input = r#"{"id":1,"user":{"ssn":"123-45-6789"}}"#;
+
+        let encrypted = encrypt.apply_with_keys(input, &keys)?;
+        assert_eq!(1, encrypted["id"]);
+        assert!(encrypted["user"].is_string());
+        let decrypted = decrypt.apply_with_keys(encrypted.to_string(), &keys)?;
+        assert_eq!(input, decrypted.to_string());
+
+        match encrypt.apply_from_str(input) {
+            Err(Error::RuleFailed { cause, .. }) => {
+                assert!(matches!(*cause, Error::UnknownKey(name) if name == "pii"))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        let encrypt: Transformer = serde_json::from_str(&serde_json::to_string(&encrypt)?)?;
+        assert_eq!(encrypted, encrypt.apply_with_keys(input, &keys)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let trans = TransformerBuilder::default()
+            .mode(Mode::Many2Many)
+            .add_direct("name", "name")?
+            .add_generate("id", Generator::UuidV4)?
+            .add_generate("processed_at", Generator::NowRfc3339)?
+            .add_generate("line", Generator::Sequence { start: 1 })?
+            .build()?;
+        let input = r#"[{"name":"a"},{"name":"b"}]"#;
+        let now = UNIX_EPOCH + Duration::from_millis(1_556_713_800_250);
+        let output = trans.apply_with_entropy(input, 42, now)?;
+
+        assert_eq!(output, trans.apply_with_entropy(input, 42, now)?);
+        assert_ne!(output[0]["id"], output[1]["id"]);
+        let id = output[0]["id"].as_str().unwrap();
+        assert_eq!(36, id.len());
+        assert_eq!(Some('4'), id.chars().nth(14));
+        assert!(matches!(id.chars().nth(19), Some('8'..='9' | 'a'..='b')));
+        assert_eq!("2019-05-01T12:30:00.250Z", output[1]["processed_at"]);
+        assert_eq!(1, output[0]["line"]);
+        assert_eq!(2, output[1]["line"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtree() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_subtree(
+                "config",
+                "settings",
+                SubtreeOps {
+                    include: vec!["db", "servers.host", "name"],
+                    exclude: vec!["db.password"],
+                },
+            )?
+            .add_subtree(
+                "config.servers",
+                "servers",
+                SubtreeOps {
+                    exclude: vec!["port"],
+                    ..SubtreeOps::default()
+                },
+            )?
+            .build()?;
+        let input = r#"{"config":{"name":"a","debug":true,"db":{"user":"u","password":"p"},"servers":[{"host":"h1","port":1},{"host":"h2","port":2}]}}"#;
+        let output = trans.apply_from_str(input)?;
+        assert_eq!(
+            r#"{"servers":[{"host":"h1"},{"host":"h2"}],"settings":{"db":{"user":"u"},"name":"a","servers":[{"host":"h1"},{"host":"h2"}]}}"#,
+            output.to_string()
+        );
+
+        let trans: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        assert_eq!(output, trans.apply_from_str(input)?);
+        assert!(TransformerBuilder::default()
+            .add_subtree(
+                "config",
+                "settings",
+                SubtreeOps {
+                    exclude: vec!["db..password"],
+                    ..SubtreeOps::default()
+                }
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ManipSnakeCase {}
+
+    #[typetag::serde]
+    impl StringManipulation for ManipSnakeCase {
+        fn apply(&self, input: &str) -> String {
+            let mut out = String::with_capacity(input.len());
+            for c in input.chars() {
+                if c.is_uppercase() {
+                    out.push('_');
+                }
+                out.extend(c.to_lowercase());
+            }
+            out
+        }
+    }
+
+    #[test]
+    fn test_rekey() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_rekey("user", "user", Box::new(ManipSnakeCase {}))?
+            .add_rekey("meta", "meta", Box::new(Casing::Upper))?
+            .build()?;
+        let input = r#"{"user":{"firstName":"Joey","homeAddress":{"zipCode":"1"},"phoneNumbers":[{"isPrimary":true}]},"meta":{"version":1}}"#;
+        let expected = r#"{"meta":{"VERSION":1},"user":{"first_name":"Joey","home_address":{"zip_code":"1"},"phone_numbers":[{"is_primary":true}]}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let input = serde_json::json!({"user":{"id":1}});
+        assert_eq!(r#"{"id":1}"#, trans.apply(&input)?.to_string());
+        // the input is only borrowed so can be applied again
+        assert_eq!(trans.apply(&input)?, trans.apply_to::<_, Value>(&input)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_ndjson() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let input = "{\"user\":{\"id\":1}}\n{\"user\":{\"id\":2}}\n\n[{\"user\":{\"id\":3}}]\n";
+        let mut output = Vec::new();
+        trans.apply_ndjson(input.as_bytes(), &mut output)?;
+        assert_eq!(
+            "{\"id\":1}\n{\"id\":2}\n{\"id\":3}\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let mut output = Vec::new();
+        let res = trans.apply_ndjson("{\"user\":{\"id\":1}}\n{".as_bytes(), &mut output);
+        assert!(res.is_err());
+        assert_eq!("{\"id\":1}\n", String::from_utf8(output).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_array() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let input = r#"[{"user":{"id":1}}, {"user":{"id":2}}, {"user":{"id":3}}]"#;
+        let results = trans
+            .iter_array(input.as_bytes())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            vec![
+                serde_json::json!({"id":1}),
+                serde_json::json!({"id":2}),
+                serde_json::json!({"id":3})
+            ],
+            results
+        );
+
+        let trans = TransformerBuilder::default()
+            .add_generate("line", Generator::Sequence { start: 1 })?
+            .build()?;
+        let lines = trans
+            .iter_array("[{},{},{}]".as_bytes())
+            .map(|res| res.map(|output| output["line"].clone()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(vec![Value::from(1), Value::from(2), Value::from(3)], lines);
+        let mut output = Vec::new();
+        trans.apply_ndjson("{}\n{}\n".as_bytes(), &mut output)?;
+        assert_eq!(
+            "{\"line\":1}\n{\"line\":2}\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let mut iter = trans.iter_array(r#"[{"user":{"id":1}},{"user":"#.as_bytes());
+        assert_eq!(serde_json::json!({"id":1}), iter.next().unwrap()?);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_duration() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_duration(
+                "timeout",
+                "timeout_ms",
+                DurationFormat::Seconds,
+                DurationFormat::Milliseconds,
+            )?
+            .add_duration(
+                "sla",
+                "sla",
+                DurationFormat::Seconds,
+                DurationFormat::Iso8601,
+            )?
+            .add_duration(
+                "interval_ms",
+                "interval",
+                DurationFormat::Milliseconds,
+                DurationFormat::Human,
+            )?
+            .build()?;
+        let input = r#"[{"timeout":"1h30m","sla":90,"interval_ms":1500},{"timeout":"PT20S","sla":"4h","interval_ms":null},{"timeout":"soon"}]"#;
+        let expected = r#"[{"interval":"1s500ms","sla":"PT1M30S","timeout_ms":5400000},{"interval":null,"sla":"PT4H","timeout_ms":20000},{"interval":null,"sla":null,"timeout_ms":null}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = TransformerBuilder::default()
+            .add_duration_with(
+                "timeout",
+                "timeout",
+                DurationFormat::Seconds,
+                DurationFormat::Seconds,
+                CoerceFailure::Error,
+            )?
+            .build()?;
+        match trans
+            .apply_from_str(r#"{"timeout":"soon"}"#)
+            .map_err(Error::into_root_cause)
+        {
+            Err(Error::Coercion { path, expected, .. }) => {
+                assert_eq!("timeout", path);
+                assert_eq!("seconds", expected);
             }
+            res => panic!("unexpected result {:?}", res),
         }
-    };
-    Ok(())
-}
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::rules::StringManipulation;
-    use serde::Deserialize;
+    #[test]
+    fn test_derive_bool() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_derive_bool(
+                "high_value",
+                Predicate::And(vec![
+                    Predicate::compare("amount", Comparison::Gt, 1000.0)?,
+                    Predicate::equals("country", "US")?,
+                ]),
+            )?
+            .add_derive_bool("beta", Predicate::exists("$ctx.flags.beta")?)?
+            .build()?;
+        let input = r#"[{"amount":1500,"country":"US"},{"amount":1500,"country":"CA"},{}]"#;
+        let expected = r#"[{"beta":false,"high_value":true},{"beta":false,"high_value":false},{"beta":false,"high_value":false}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
 
     #[test]
-    fn test_top_level() -> Result<()> {
+    fn test_map_array() -> Result<()> {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let item = TransformerBuilder::default()
+            .add_direct("product.sku", "sku")?
+            .add_direct("quantity", "qty")?
+            .add_when(
+                Predicate::exists("$ctx.flags.debug")?,
+                Mapping::Direct {
+                    from: "product.id".into(),
+                    to: "id".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+            )?
+            .build()?;
         let trans = TransformerBuilder::default()
-            .add_direct("existing_key", "rename_from_existing_key")?
-            .add_direct("my_array[0]", "used_to_be_array")?
-            .add_constant(Value::String("consant_value".to_string()), "const")?
+            .add_direct("id", "order_id")?
+            .add_map_array("lines", "items", item)?
             .build()?;
+        let input = r#"{"id":7,"lines":[{"product":{"id":1,"sku":"A-1"},"quantity":2},{"product":{"id":2,"sku":"B-2"}}]}"#;
+        let expected = r#"{"items":[{"qty":2,"sku":"A-1"},{"qty":null,"sku":"B-2"}],"order_id":7}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
 
-        let input = r#"
-            {
-                "existing_key":"my_val1",
-                "my_array":["idx_0_value"]
-            }"#;
-        let expected = r#"{"const":"consant_value","rename_from_existing_key":"my_val1","used_to_be_array":"idx_0_value"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
+        let mut flags = Map::new();
+        flags.insert(String::from("debug"), Value::Bool(true));
+        let expected = r#"{"items":[{"id":1,"qty":2,"sku":"A-1"},{"id":2,"qty":null,"sku":"B-2"}],"order_id":7}"#;
+        assert_eq!(expected, trans.apply_with_flags(input, flags)?.to_string());
+
+        // round trips through serialization with the nested Transformer
+        let trans: Transformer = serde_json::from_str(&serde_json::to_string(&trans)?)?;
+        let res = trans.apply_from_str(r#"{"id":8,"lines":null}"#)?;
+        assert_eq!(r#"{"items":null,"order_id":8}"#, res.to_string());
+
+        // nested Transformers draw from the seeded Entropy and collect errors with the parent
+        let item = TransformerBuilder::default()
+            .add_generate("id", Generator::UuidV4)?
+            .add_direct("qty", "qty")?
+            .missing_policy(MissingPolicy::Error)
+            .build()?;
+        let trans = TransformerBuilder::default()
+            .add_generate("id", Generator::UuidV4)?
+            .add_map_array("items", "items", item)?
+            .build()?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_556_713_800);
+        let input = r#"{"items":[{"qty":1},{"qty":2}]}"#;
+        let res = trans.apply_with_entropy(input, 7, now)?;
+        assert_eq!(res, trans.apply_with_entropy(input, 7, now)?);
+        assert_ne!(res["items"][0]["id"], res["items"][1]["id"]);
+        let (_, errors) = trans.apply_collecting(r#"{"items":[{},{}]}"#);
+        assert_eq!(2, errors.len());
         Ok(())
     }
 
     #[test]
-    fn test_nested() -> Result<()> {
+    fn test_hooks() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
         let trans = TransformerBuilder::default()
-            .add_direct("nested.key1", "unnested_key1")?
-            .add_direct("nested.nested.key2", "unnested_key2")?
-            .add_direct("nested.arr[0].nested.key3", "unnested_key3")?
+            .add_direct("email", "email")?
+            .before_each(|input| {
+                if let Some(Value::String(s)) = input.get_mut("email") {
+                    *s = s.trim().to_lowercase();
+                }
+            })
+            .after_each(move |output| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                output.insert(String::from("version"), Value::from(1));
+            })
             .build()?;
-        let input = r#"
-                    {
-                        "nested": {
-                            "key1": "val1",
-                            "nested": {
-                                "key2": "val2"
-                            },
-                            "arr": [{
-                                "nested": {
-                                    "key3": "val3"
-                                }
-                            }]
-                        }
-                    }"#;
-        let expected = r#"{"unnested_key1":"val1","unnested_key2":"val2","unnested_key3":"val3"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
+        let input = r#"[{"email":" Joey@Example.com "},{"email":"a@b.c"}]"#;
+        let expected =
+            r#"[{"email":"joey@example.com","version":1},{"email":"a@b.c","version":1}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        assert_eq!(2, count.load(Ordering::SeqCst));
+
+        let mut sink = Vec::new();
+        trans.apply_stream_to_sink(r#"{"email":"X@Y.z"}"#.as_bytes(), &mut sink)?;
+        assert_eq!(
+            r#"[{"email":"x@y.z","version":1}]"#,
+            Value::Array(sink).to_string()
+        );
+        assert_eq!(3, count.load(Ordering::SeqCst));
+
+        // hooks are kept when modifying an existing Transformer
+        let trans = trans.into_builder().add_direct("id", "id")?.build()?;
+        let res = trans.apply_from_str(r#"{"id":1,"email":"A"}"#)?;
+        assert_eq!(r#"{"email":"a","id":1,"version":1}"#, res.to_string());
         Ok(())
     }
 
     #[test]
-    fn test_nested_out_of_order_rules() -> Result<()> {
+    fn test_index_base_and_width() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_direct("nested.nested.key2", "nested_new.nested")?
-            .add_direct("top", "nested_new.top")?
+            .index_base(1)
+            .index_width(2)
+            .add_direct("first", "names[1]")?
+            .add_direct("second", "names[2]")?
+            .add_direct("nested", "rows[1].values[2]")?
+            .add_flatten(
+                "tags",
+                "",
+                FlattenOps {
+                    prefix: Some("tag"),
+                    separator: Some("_"),
+                    ..FlattenOps::default()
+                },
+            )?
             .build()?;
-        let input = r#"
-                    {
-                        "nested": {
-                            "nested": {
-                                "key2": "val2"
-                            }
-                        },
-                        "top": "top_val"
-                    }"#;
-        let expected = r#"{"nested_new":{"nested":"val2","top":"top_val"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
+        let input = r#"{"first":"a","second":"b","nested":1,"tags":["x","y"]}"#;
+        let expected =
+            r#"{"names":["a","b"],"rows":[{"values":[null,1]}],"tag_01":"x","tag_02":"y"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = trans.into_builder().remove_mapping(Mapping::Direct {
+            from: "second".into(),
+            to: "names[2]".into(),
+            kind: None,
+            only_if_absent: false,
+            default: None,
+        })?;
+        assert!(trans.add_direct("first", "names[0]").is_err());
         Ok(())
     }
 
     #[test]
-    fn test_full_objects() -> Result<()> {
+    fn test_join() -> Result<()> {
+        let input = r#"{"tags":["a",1,true,null,{"k":"v"},"b"],"name":"x","empty":[]}"#;
         let trans = TransformerBuilder::default()
-            .add_direct("nested.nested.key2", "nested_new.nested")?
-            .add_direct("top", "nested_new.top")?
+            .add_join("tags", "tags_csv", ",")?
+            .add_join_with("tags", "strings", "|", Stringify::Skip)?
+            .add_join("empty", "empty", ",")?
             .build()?;
-        let input = r#"
-                    {
-                        "nested": {
-                            "nested": {
-                                "key2": "val2"
-                            }
-                        },
-                        "top": "top_val"
-                    }"#;
-        let expected = r#"{"nested_new":{"nested":"val2","top":"top_val"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
+        let expected = r#"{"empty":"","strings":"a|b","tags_csv":"a,1,true,{\"k\":\"v\"},b"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = TransformerBuilder::default()
+            .add_join_with("tags", "tags", ",", Stringify::Error)?
+            .build()?;
+        assert!(trans.apply_from_str(input).is_err());
+        let trans = TransformerBuilder::default()
+            .navigation(Navigation::Strict)
+            .add_join("name", "name", ",")?
+            .build()?;
+        assert!(trans.apply_from_str(input).is_err());
         Ok(())
     }
 
     #[test]
-    fn test_struct() -> Result<()> {
-        #[derive(Debug, Serialize)]
-        struct From {
-            existing: String,
-        }
+    fn test_aggregate() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_aggregate("line_items[*].amount", "total", Agg::Sum)?
+            .add_aggregate("line_items[*].amount", "avg", Agg::Avg)?
+            .add_aggregate("line_items[*].amount", "min", Agg::Min)?
+            .add_aggregate("line_items[*].amount", "max", Agg::Max)?
+            .add_aggregate("line_items", "lines", Agg::Count)?
+            .add_aggregate("orders[*].lines[*].qty", "qty", Agg::Sum)?
+            .add_aggregate("weights", "weight", Agg::Sum)?
+            .build()?;
+        let input = r#"{"line_items":[{"amount":10},{"amount":2},{"amount":null},{"amount":6}],"orders":[{"lines":[{"qty":1},{"qty":2}]},{"lines":[{"qty":3}]}],"weights":[1.5,2]}"#;
+        let expected = r#"{"avg":6.0,"lines":4,"max":10,"min":2,"qty":6,"total":18,"weight":3.5}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
 
-        #[derive(Debug, Deserialize, PartialEq)]
-        struct To {
-            new: String,
-        }
+        let expected =
+            r#"{"avg":null,"lines":0,"max":null,"min":null,"qty":0,"total":0,"weight":0}"#;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
 
         let trans = TransformerBuilder::default()
-            .add_direct("existing", "new")?
+            .navigation(Navigation::Strict)
+            .add_aggregate("amounts", "total", Agg::Sum)?
+            .build()?;
+        assert!(trans.apply_from_str(r#"{"amounts":[1,"2"]}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip() -> Result<()> {
+        let input = r#"{"names":["a","b","c"],"ages":[1,2]}"#;
+        let trans = TransformerBuilder::default()
+            .add_zip("names", "ages", "people", ("name", "age"))?
+            .add_zip_with("names", "ages", "padded", ("name", "age"), ZipLength::Pad)?
+            .add_zip("names", "missing", "one_sided", ("name", "other"))?
             .build()?;
+        let expected = r#"{"one_sided":[],"padded":[{"age":1,"name":"a"},{"age":2,"name":"b"},{"age":null,"name":"c"}],"people":[{"age":1,"name":"a"},{"age":2,"name":"b"}]}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        assert_eq!(
+            r#"{"one_sided":null,"padded":null,"people":null}"#,
+            trans.apply_from_str("{}")?.to_string()
+        );
 
-        let from = From {
-            existing: String::from("existing_value"),
+        let mapping: Mapping =
+            serde_json::from_str(r#"{"Zip":{"from":["a","b","c"],"to":"z","keys":["x","y"]}}"#)?;
+        assert!(TransformerBuilder::default().add_mapping(mapping).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_complexity() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct("id", "id")?
+                .add_direct("order.items[*].sku", "skus[*]")?
+                .add_direct_if_absent("order.id", "id")?
+                .add_flatten(
+                    "order.meta",
+                    "",
+                    FlattenOps {
+                        recursive: true,
+                        ..FlattenOps::default()
+                    },
+                )
         };
+        let trans = builder()?.build()?;
+        let score = trans.complexity();
+        assert_eq!(4, score.rules());
+        assert_eq!(2, score.max_depth());
+        assert_eq!(1, score.recursive_flattens());
+        assert_eq!(13, score.fan_out());
 
-        let expected = To {
-            new: String::from("existing_value"),
+        let limits = ComplexityLimits {
+            rules: Some(4),
+            fan_out: Some(13),
+            ..ComplexityLimits::default()
         };
-        let res: To = trans.apply_to(from)?;
-        assert_eq!(expected, res);
+        assert!(builder()?.max_complexity(limits).build().is_ok());
+        let limits = ComplexityLimits {
+            recursive_flattens: Some(0),
+            ..limits
+        };
+        match builder()?.max_complexity(limits).build() {
+            Err(Error::ComplexityExceeded(msg)) => {
+                assert_eq!("recursive_flattens of 1 exceeds the limit of 0", msg)
+            }
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
         Ok(())
     }
 
     #[test]
-    fn test_struct_enum() -> Result<()> {
-        #[derive(Debug, Serialize)]
-        struct From {
-            existing: String,
-        }
+    fn test_switch() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_switch(
+                "order.status",
+                "label",
+                vec![
+                    SwitchCase {
+                        when: 1.into(),
+                        then: SwitchArm::Constant("pending".into()),
+                    },
+                    SwitchCase {
+                        when: 2.0.into(),
+                        then: SwitchArm::Source("order.carrier".into()),
+                    },
+                ],
+                Some(SwitchArm::Constant("unknown".into())),
+            )?
+            .add_switch(
+                "$ctx.flags.region",
+                "currency",
+                vec![SwitchCase {
+                    when: "eu".into(),
+                    then: SwitchArm::Constant("EUR".into()),
+                }],
+                None,
+            )?
+            .build()?;
+        let input =
+            r#"[{"order":{"status":1}},{"order":{"status":2,"carrier":"UPS"}},{"order":{}}]"#;
+        // no matching case and no default arm is a missing value
+        let expected = r#"[{"currency":null,"label":"pending"},{"currency":null,"label":"UPS"},{"currency":null,"label":"unknown"}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
 
-        #[derive(Debug, Deserialize, PartialEq)]
-        struct To {
-            new: String,
-        }
+        let mut flags = Map::new();
+        flags.insert(String::from("region"), Value::from("eu"));
+        let expected = r#"{"currency":"EUR","label":"pending"}"#;
+        let res = trans.apply_with_flags(r#"{"order":{"status":1}}"#, flags)?;
+        assert_eq!(expected, res.to_string());
+
+        let mapping: Mapping = serde_json::from_str(
+            r#"{"Switch":{"on":"kind","to":"type","cases":[{"when":"a","then":{"Source":"a_type"}}]}}"#,
+        )?;
+        let trans = TransformerBuilder::default()
+            .add_mapping(mapping)?
+            .build()?;
+        let res = trans.apply_from_str(r#"{"kind":"a","a_type":"alpha"}"#)?;
+        assert_eq!(r#"{"type":"alpha"}"#, res.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_when() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("order.id", "id")?
+            .add_when(
+                Predicate::equals("order.status", "shipped")?,
+                Mapping::Direct {
+                    from: "order.tracking.number".into(),
+                    to: "tracking".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
+                },
+            )?
+            .add_when(
+                Predicate::Or(vec![
+                    Predicate::compare("order.total", Comparison::Ge, 100.0)?,
+                    Predicate::exists("$ctx.flags.free_shipping")?,
+                ]),
+                Mapping::Constant {
+                    from: true.into(),
+                    to: "free_shipping".into(),
+                    kind: None,
+                    only_if_absent: false,
+                },
+            )?
+            .build()?;
+        let input = r#"[{"order":{"id":1,"status":"shipped","total":150,"tracking":{"number":"1Z"}}},{"order":{"id":2,"status":"pending","total":20,"tracking":{"number":"2Z"}}}]"#;
+        let expected = r#"[{"free_shipping":true,"id":1,"tracking":"1Z"},{"id":2}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let mut flags = Map::new();
+        flags.insert(String::from("free_shipping"), Value::Bool(true));
+        let expected =
+            r#"[{"free_shipping":true,"id":1,"tracking":"1Z"},{"free_shipping":true,"id":2}]"#;
+        assert_eq!(expected, trans.apply_with_flags(input, flags)?.to_string());
+
+        let res = TransformerBuilder::default().add_when(
+            Predicate::exists("id")?,
+            Mapping::Direct {
+                from: "items[*].id".into(),
+                to: "ids".into(),
+                kind: None,
+                only_if_absent: false,
+                default: None,
+            },
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_direct("user.id", "id")?
+            .build()?;
+        let mut profile = Profile::default();
+        trans.profile(
+            &serde_json::from_str(r#"[{"user":{"id":1}},{"user":{"id":"2"}}]"#)?,
+            &mut profile,
+        );
+        trans.profile(&serde_json::from_str(r#"{"user":{}}"#)?, &mut profile);
+
+        assert_eq!(3, profile.documents());
+        assert_eq!(3, profile.get("user").unwrap().count());
+        let id = profile.get("user.id").unwrap();
+        assert_eq!(2, id.count());
+        assert_eq!(2, id.types().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_constants() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_constant_bool(true, "flags.active")?
+            .add_constant_int(42, "count")?
+            .add_constant_float(1.5, "ratio")?
+            .add_constant_null("removed")?
+            .build()?;
+        let expected = r#"{"count":42,"flags":{"active":true},"ratio":1.5,"removed":null}"#;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
+        assert!(TransformerBuilder::default()
+            .add_constant_float(f64::NAN, "ratio")
+            .is_err());
+
+        let spec = r#"[
+            {"Constant":{"from":{"$bool":"true"},"to":"active"}},
+            {"Constant":{"from":{"$int":"42"},"to":"count"}},
+            {"Constant":{"from":{"$float":"2"},"to":"ratio"}},
+            {"Constant":{"from":{"$null":""},"to":"removed"}},
+            {"Constant":{"from":{"$object":"{\"$id\":1}"},"to":"object"}},
+            {"Constant":{"from":{"$string":7},"to":"code"}},
+            {"Constant":{"from":{"id":1},"to":"raw"}}
+        ]"#;
+        let mappings: Vec<Mapping> = serde_json::from_str(spec)?;
+        let trans = TransformerBuilder::default()
+            .add_mappings(mappings)?
+            .build()?;
+        let expected = r#"{"active":true,"code":"7","count":42,"object":{"$id":1},"ratio":2.0,"raw":{"id":1},"removed":null}"#;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
+
+        let res =
+            serde_json::from_str::<Mapping>(r#"{"Constant":{"from":{"$bool":"maybe"},"to":"x"}}"#);
+        assert!(res.is_err());
+
+        // only the tags are typed, Objects which look like tags round trip through mappings
+        let spec = r#"[
+            {"Constant":{"from":{"$ref":"x"},"to":"ref"}},
+            {"Constant":{"from":{"$object":{"$int":"1"}},"to":"tagged"}}
+        ]"#;
+        let mappings: Vec<Mapping> = serde_json::from_str(spec)?;
+        let trans = TransformerBuilder::default()
+            .add_mappings(mappings)?
+            .build()?;
+        let expected = r#"{"ref":{"$ref":"x"},"tagged":{"$int":"1"}}"#;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
+        let mappings: Vec<Mapping> =
+            serde_json::from_str(&serde_json::to_string(&trans.mappings()?)?)?;
+        let trans = TransformerBuilder::default()
+            .add_mappings(mappings)?
+            .build()?;
+        assert_eq!(expected, trans.apply_from_str("{}")?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split() -> Result<()> {
+        let trans = TransformerBuilder::default()
+            .add_split("full_name", "names", " ")?
+            .add_split_into(
+                "full_name",
+                &["name.first", "name.middle", "name.last"],
+                " ",
+            )?
+            .build()?;
+        let input = r#"[{"full_name":"Joey Bloggs"},{"full_name":null},{}]"#;
+        let expected = r#"[{"name":{"first":"Joey","last":null,"middle":"Bloggs"},"names":["Joey","Bloggs"]},{"name":{"first":null,"last":null,"middle":null},"names":null},{"name":{"first":null,"last":null,"middle":null},"names":null}]"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = TransformerBuilder::default()
+            .add_split_into("full_name", &["first", "last"], " ")?
+            .missing_policy(MissingPolicy::Skip)
+            .build()?;
+        let expected = r#"{"first":"Joey"}"#;
+        assert_eq!(
+            expected,
+            trans.apply_from_str(r#"{"full_name":"Joey"}"#)?.to_string()
+        );
+        assert!(TransformerBuilder::default()
+            .add_split("full_name", "names", "")
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination_prefix_and_root() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct("user.id", "id")?
+                .add_direct("user.name", "user.name")?
+                .add_direct("$out.id", "ref")
+        };
+        let input = r#"{"user":{"id":1,"name":"Joey"}}"#;
+
+        let trans = builder()?.destination_prefix("out_").build()?;
+        let expected = r#"{"out_id":1,"out_ref":1,"out_user":{"name":"Joey"}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.destination_root("envelope.data")?.build()?;
+        let expected = r#"{"envelope":{"data":{"id":1,"ref":1,"user":{"name":"Joey"}}}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        assert!(builder()?.destination_root("data[0]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_empty() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct_typed("name", "name", Type::String)?
+                .add_direct_typed("age", "age", Type::Integer)?
+                .add_direct_typed("tags", "tags", Type::Array)?
+                .add_direct("other", "other")
+        };
+        let input = r#"{"age":"unknown"}"#;
+
+        let trans = builder()?.build()?;
+        let expected = r#"{"age":null,"name":null,"other":null,"tags":null}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.missing(Missing::Empty).build()?;
+        let expected = r#"{"age":0,"name":"","other":null,"tags":[]}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        Ok(())
+    }
 
+    #[test]
+    fn test_only_if_absent() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_direct("existing", "new")?
+            .add_direct("profile.nickname", "name")?
+            .add_direct_if_absent("user.full_name", "name")?
+            .add_direct_if_absent("user.id", "name")?
+            .add_constant_if_absent("anonymous", "name")?
+            .add_direct_if_absent("user.id", "ids[0]")?
             .build()?;
 
-        let from = From {
-            existing: String::from("existing_value"),
-        };
+        let input = r#"{"profile":{"nickname":"Joey"},"user":{"full_name":"Joey Bloggs","id":1}}"#;
+        let expected = r#"{"ids":[1],"name":"Joey"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
 
-        let mut m = Map::new();
-        m.insert(
-            String::from("new"),
-            Value::String(String::from("existing_value")),
-        );
-        let expected = Value::Object(m);
-        let res: Value = trans.apply_to(from)?;
-        assert_eq!(expected, res);
+        let input = r#"{"profile":{},"user":{"full_name":"Joey Bloggs","id":1}}"#;
+        let expected = r#"{"ids":[1],"name":"Joey Bloggs"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let input = r#"{"user":{}}"#;
+        let expected = r#"{"name":"anonymous"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = TransformerBuilder::default().add_direct_if_absent("items[*].id", "ids");
+        assert!(res.is_err());
         Ok(())
     }
 
     #[test]
-    fn test_array() -> Result<()> {
+    fn test_passthrough() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .mode(Mode::One2One)
-            .add_direct("[0]", "new")?
+            .passthrough(true)
+            .add_direct("user.first_name", "user.name")?
+            .add_direct("items[*].sku", "items[*].id")?
+            .add_direct("internal", "meta.internal")?
             .build()?;
-        let input = r#"[
-                "test"
-            ]"#;
-        let expected = r#"{"new":"test"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
+        let input = r#"{"user":{"first_name":"Joey","age":30},"items":[{"sku":"a","qty":1}],"internal":true,"other":[1]}"#;
+        let expected = r#"{"items":[{"id":"a","qty":1}],"meta":{"internal":true},"other":[1],"user":{"age":30,"name":"Joey"}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
         Ok(())
     }
 
     #[test]
-    fn test_many_2_many() -> Result<()> {
+    fn test_exclude() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_direct("user_id", "id")?
-            .add_direct("full_name", "name")?
+            .passthrough(true)
+            .add_direct("account", "profile")?
+            .add_exclude("ssn")?
+            .add_exclude("profile.password")?
+            .add_exclude("contacts[*].email")?
             .build()?;
-        let input = r#"[
-                {"user_id":1,"full_name":"Dean Karn"},
-                {"user_id":2, "full_name":"Joey Bloggs"}
-            ]"#;
-        let expected = r#"[{"id":1,"name":"Dean Karn"},{"id":2,"name":"Joey Bloggs"}]"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+        let input = r#"{"ssn":"123","account":{"id":1,"password":"secret"},"contacts":[{"name":"a","email":"a@b.c"}]}"#;
+        let expected = r#"{"contacts":[{"name":"a"}],"profile":{"id":1}}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
         Ok(())
     }
 
     #[test]
-    fn test_flatten_direct() -> Result<()> {
+    fn test_match_element() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "",
-                FlattenOps {
-                    recursive: false,
-                    prefix: Some("flattened_"),
-                    separator: None,
-                    manipulation: None,
-                },
-            )?
+            .add_direct("phones[type=mobile].number", "mobile")?
+            .add_direct("phones[type=home]", "home")?
+            .add_direct("phones[id=3].number", "third")?
+            .add_direct("phones[type=work].number", "work")?
             .build()?;
-        let input = r#"{
-                "nested":{
-                    "key1":"value1",
-                    "key2":"value2"
-                }
-            }"#;
-        let expected = r#"{"flattened_key1":"value1","flattened_key2":"value2"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+        let input = r#"{"phones":[{"id":3,"type":"home","number":"555-1234"},{"id":4,"type":"mobile","number":"555-9876"}]}"#;
+        let expected = r#"{"home":{"id":3,"number":"555-1234","type":"home"},"mobile":"555-9876","third":"555-1234","work":null}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let res = TransformerBuilder::default().add_direct("number", "phones[type=mobile]");
+        assert!(res.is_err());
         Ok(())
     }
 
     #[test]
-    fn test_flatten_direct_with_to() -> Result<()> {
-        let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "flattened",
-                FlattenOps {
-                    recursive: false,
-                    prefix: Some("flattened_"),
-                    separator: None,
-                    manipulation: None,
+    fn test_build_many() -> Result<()> {
+        let spec = |to: &'static str| {
+            vec![
+                Mapping::Direct {
+                    from: "user.id".into(),
+                    to: to.into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
                 },
-            )?
-            .build()?;
-        let input = r#"{
-                "nested":{
-                    "key1":"value1",
-                    "key2":"value2"
-                }
-            }"#;
-        let expected = r#"{"flattened":{"flattened_key1":"value1","flattened_key2":"value2"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+                Mapping::Constant {
+                    from: "tenant".into(),
+                    to: "kind".into(),
+                    kind: None,
+                    only_if_absent: false,
+                },
+            ]
+        };
+        let trans = TransformerBuilder::build_many(vec![spec("id"), spec("user.id")])?;
+        assert_eq!(2, trans.len());
+        let input = r#"{"user":{"id":1}}"#;
+        let res = apply_many_specs(&trans, input)?;
+        assert_eq!(r#"{"id":1,"kind":"tenant"}"#, res[0].to_string());
+        assert_eq!(r#"{"kind":"tenant","user":{"id":1}}"#, res[1].to_string());
+        let key = |trans: &Transformer| match &trans.plan.get().unwrap().steps[1].access {
+            Access::Object(key) => key.clone(),
+            access => panic!("unexpected access {:?}", access),
+        };
+        assert!(Arc::ptr_eq(&key(&trans[0]), &key(&trans[1])));
+
+        let res = TransformerBuilder::build_many(vec![spec("id"), spec("bad[")]);
+        assert!(res.is_err());
         Ok(())
     }
+
     #[test]
-    fn test_flatten_direct_with_to_no_profix() -> Result<()> {
+    fn test_direct_with_default() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_flatten("nested", "flattened", FlattenOps::default())?
+            .add_direct_with_default("user.name", "name", "unknown".into())?
+            .add_direct_with_default("user.tags[1]", "tag", "none".into())?
+            .add_direct_with_default("status", "status", "active".into())?
+            .add_direct_with_default("items[*].qty", "items[*].qty", 1.into())?
             .build()?;
-        let input = r#"{
-                "nested":{
-                    "key1":"value1",
-                    "key2":"value2"
-                }
-            }"#;
-        let expected = r#"{"flattened":{"key1":"value1","key2":"value2"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+
+        let input =
+            r#"{"user":{"name":"Joey","tags":["a","b"]},"status":null,"items":[{"qty":2},{}]}"#;
+        let expected =
+            r#"{"items":[{"qty":2},{"qty":1}],"name":"Joey","status":"active","tag":"b"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let input = r#"{}"#;
+        let expected = r#"{"name":"unknown","status":"active","tag":"none"}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
         Ok(())
     }
 
     #[test]
-    fn test_flatten_direct_recursive_with_to_no_prefix() -> Result<()> {
+    fn test_locale() -> Result<()> {
+        let mapping = |locale| Mapping::FlattenList {
+            from: "names".into(),
+            to: "names".into(),
+            manipulations: vec![Box::new(Casing::Upper)],
+            recursive: false,
+            only_if_absent: false,
+            locale,
+        };
         let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "",
-                FlattenOps {
-                    recursive: true,
-                    prefix: None,
-                    separator: Some("_"),
-                    manipulation: None,
-                },
-            )?
+            .locale(LocaleContext::new("tr-TR", 180))
+            .add_mapping(mapping(None))?
             .build()?;
-        let input = r#"{
-            "nested":{
-                "key1":"value1",
-                "key2":{
-                    "inner":"value2"
-                }
-            }
-        }"#;
-        let expected = r#"{"key1":"value1","key2_inner":"value2"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
-        Ok(())
-    }
+        let input = r#"{"names":["istanbul"]}"#;
+        assert_eq!(
+            r#"{"names":["İSTANBUL"]}"#,
+            trans.apply_from_str(input)?.to_string()
+        );
 
-    #[test]
-    fn test_flatten_direct_nonrecursive_with_to_no_prefix() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_flatten("nested", "", FlattenOps::default())?
+            .locale(LocaleContext::new("tr-TR", 180))
+            .add_mapping(mapping(Some(LocaleContext::default())))?
             .build()?;
-        let input = r#"{
-            "nested":{
-                "key1":"value1",
-                "key2":{
-                    "inner":"value2"
-                }
-            }
-        }"#;
-        let expected = r#"{"key1":"value1","key2":{"inner":"value2"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
-        Ok(())
-    }
+        assert_eq!(
+            r#"{"names":["ISTANBUL"]}"#,
+            trans.apply_from_str(input)?.to_string()
+        );
 
-    #[test]
-    fn test_array_flatten() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "",
-                FlattenOps {
-                    recursive: false,
-                    prefix: Some("new"),
-                    separator: Some("_"),
-                    manipulation: None,
-                },
-            )?
+            .locale(LocaleContext::new("tr-TR", 180))
+            .add_generate("processed_at", Generator::NowRfc3339)?
             .build()?;
-        let input = r#"{
-            "nested":[
-                "value1",
-                "value2",
-                "value3"
-            ]
-        }"#;
-        let expected = r#"{"new_1":"value1","new_2":"value2","new_3":"value3"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+        let now = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_556_713_800);
+        assert_eq!(
+            r#"{"processed_at":"2019-05-01T15:30:00.000+03:00"}"#,
+            trans.apply_with_entropy("{}", 42, now)?.to_string()
+        );
         Ok(())
     }
 
     #[test]
-    fn test_array_flatten_to() -> Result<()> {
-        let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "flattened[1]",
-                FlattenOps {
-                    recursive: false,
-                    prefix: Some("new"),
-                    separator: Some("_"),
-                    manipulation: None,
+    fn test_missing_policy() -> Result<()> {
+        let builder = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default()
+                .add_direct("user.name", "name")?
+                .add_direct("user.nickname", "nickname")?
+                .add_direct_with_default("user.age", "age", 0.into())
+        };
+        let input = r#"{"user":{"name":null}}"#;
+
+        let trans = builder()?.build()?;
+        let expected = r#"{"age":0,"name":null,"nickname":null}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.missing_policy(MissingPolicy::Skip).build()?;
+        let expected = r#"{"age":0,"name":null}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+
+        let trans = builder()?.missing_policy(MissingPolicy::Error).build()?;
+        match trans.apply_from_str(input).map_err(Error::into_root_cause) {
+            Err(Error::MissingSource(path)) => assert_eq!("user.nickname", path),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match trans.apply_from_str(r#"{"other":{}}"#) {
+            Err(Error::MissingSource(path)) => assert_eq!("user", path),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // a missing parent is treated as each source beneath it missing
+        let input = r#"{"other":{}}"#;
+        let trans = builder()?.build()?;
+        let expected = r#"{"age":0,"name":null,"nickname":null}"#;
+        assert_eq!(expected, trans.apply_from_str(input)?.to_string());
+        let trans = builder()?.missing_policy(MissingPolicy::Skip).build()?;
+        assert_eq!(r#"{"age":0}"#, trans.apply_from_str(input)?.to_string());
+
+        // as it is by rules applied from the root
+        let when = || -> Result<TransformerBuilder> {
+            TransformerBuilder::default().add_when(
+                Predicate::exists("flag")?,
+                Mapping::Direct {
+                    from: "user.name".into(),
+                    to: "name".into(),
+                    kind: None,
+                    only_if_absent: false,
+                    default: None,
                 },
-            )?
-            .build()?;
-        let input = r#"{
-            "nested":[
-                "value1",
-                "value2",
-                "value3"
-            ]
-        }"#;
-        let expected =
-            r#"{"flattened":[null,{"new_1":"value1","new_2":"value2","new_3":"value3"}]}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+            )
+        };
+        let input = r#"{"flag":true}"#;
+        let trans = when()?.build()?;
+        assert_eq!(r#"{"name":null}"#, trans.apply_from_str(input)?.to_string());
+        let trans = when()?.missing_policy(MissingPolicy::Error).build()?;
+        match trans.apply_from_str(input).map_err(Error::into_root_cause) {
+            Err(Error::MissingSource(path)) => assert_eq!("user.name", path),
+            res => panic!("unexpected result {:?}", res),
+        }
         Ok(())
     }
 
     #[test]
-    fn test_example() -> Result<()> {
+    fn test_apply_quarantined() -> Result<()> {
         let trans = TransformerBuilder::default()
-            .add_direct("user_id", "id")?
-            .add_direct("full-name", "name")?
-            .add_flatten(
-                "nicknames",
-                "",
-                FlattenOps {
-                    recursive: true,
-                    prefix: Some("nickname"),
-                    separator: Some("_"),
-                    manipulation: None,
-                },
-            )?
-            .add_direct("nested.inner.key", "prev_nested")?
-            .add_direct("nested.my_arr[1]", "prev_arr")?
+            .add_direct("user.name", "name")?
+            .missing_policy(MissingPolicy::Error)
             .build()?;
 
-        let input = r#"
-            {
-                "user_id":"111",
-                "full-name":"Dean Karn",
-                "nicknames":["Deano","Joey Bloggs"],
-                "nested": {
-                    "inner":{
-                        "key":"value"
-                    },
-                    "my_arr":[null,"arr_value",null]
-                }
-            }"#;
-        let expected = r#"{"id":"111","name":"Dean Karn","nickname_1":"Deano","nickname_2":"Joey Bloggs","prev_arr":"arr_value","prev_nested":"value"}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, serde_json::to_string(&res)?);
-        Ok(())
-    }
+        let input = r#"[{"user":{"name":"Joey"}},{"user":{}},{"user":{"name":"Bloggs"}}]"#;
+        let (output, quarantine) = trans.apply_quarantined(input)?;
+        assert_eq!(r#"[{"name":"Joey"},{"name":"Bloggs"}]"#, output.to_string());
+        assert_eq!(1, quarantine.len());
+        assert_eq!(Some(1), quarantine[0].index);
+        assert_eq!(r#"{"user":{}}"#, quarantine[0].document.to_string());
+        match quarantine[0].error.root_cause() {
+            Error::MissingSource(path) => assert_eq!("user.name", path),
+            e => panic!("unexpected error {:?}", e),
+        }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct ManipDashRemover {}
+        let (output, quarantine) = trans.apply_quarantined(r#"{"other":1}"#)?;
+        assert_eq!(Value::Null, output);
+        assert_eq!(None, quarantine[0].index);
+        assert_eq!(r#"{"other":1}"#, quarantine[0].document.to_string());
 
-    #[typetag::serde]
-    impl StringManipulation for ManipDashRemover {
-        fn apply(&self, input: &str) -> String {
-            input.replace('-', "")
-        }
+        assert!(trans.apply_quarantined("{").is_err());
+        Ok(())
     }
 
+    #[cfg(feature = "rayon")]
     #[test]
-    fn test_flatten_direct_with_maipulation() -> Result<()> {
+    fn test_parallel() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Transformer>();
+
         let trans = TransformerBuilder::default()
-            .add_flatten(
-                "nested",
-                "",
-                FlattenOps {
-                    manipulation: Some(Box::new(ManipDashRemover {})),
-                    ..FlattenOps::default()
-                },
-            )?
+            .mode(Mode::Many2Many)
+            .add_direct("id", "user_id")?
+            .missing_policy(MissingPolicy::Error)
+            .parallel(true)
             .build()?;
-        let input = r#"{
-            "nested":{
-                "key-1":"value1",
-                "key-2":{
-                    "inner":"value2"
-                }
-            }
-        }"#;
-        let expected = r#"{"key1":"value1","key2":{"inner":"value2"}}"#;
-        let res = trans.apply_from_str(input)?;
-        assert_eq!(expected, res.to_string());
+
+        let input = (0..100)
+            .map(|id| serde_json::json!({ "id": id }))
+            .collect::<Vec<_>>();
+        let expected = (0..100)
+            .map(|id| serde_json::json!({ "user_id": id }))
+            .collect::<Vec<_>>();
+        assert_eq!(Value::Array(expected), trans.apply_to::<_, Value>(&input)?);
+
+        let (res, timings) = trans.apply_timed(r#"[{"id":1},{"id":2}]"#)?;
+        assert_eq!(r#"[{"user_id":1},{"user_id":2}]"#, res.to_string());
+        assert_eq!(2, timings.rules()[0].calls());
+
+        assert!(trans.apply_from_str(r#"[{"id":1},{}]"#).is_err());
+
+        // seeded output is the same whether or not the batch is transformed in parallel
+        let generate = |parallel| {
+            TransformerBuilder::default()
+                .mode(Mode::Many2Many)
+                .add_generate("id", Generator::UuidV4)?
+                .add_generate("at", Generator::NowRfc3339)?
+                .parallel(parallel)
+                .build()
+        };
+        let now = SystemTime::UNIX_EPOCH;
+        let input = r#"[{},{},{},{}]"#;
+        let res = generate(true)?.apply_with_entropy(input, 7, now)?;
+        assert_eq!(res, generate(false)?.apply_with_entropy(input, 7, now)?);
+        assert_ne!(res[0]["id"], res[1]["id"]);
         Ok(())
     }
 }
@@ -1,27 +1,173 @@
-use crate::namespace::Namespace;
+use crate::namespace::{join_path, Namespace};
 use crate::rules::Rule;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::mem;
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) enum Node {
     Object {
         id: String,
-        children: Option<(usize, usize)>, // start + end tuple
+        #[serde(default, deserialize_with = "nullable")]
+        children: Vec<usize>,
         rules: Option<Vec<Box<dyn Rule>>>,
     },
     Array {
         index: usize,
         id: String,
-        children: Option<(usize, usize)>, // start + end tuple
+        #[serde(default, deserialize_with = "nullable")]
+        children: Vec<usize>,
+        rules: Option<Vec<Box<dyn Rule>>>,
+    },
+    Wildcard {
+        id: String,
+        #[serde(default, deserialize_with = "nullable")]
+        children: Vec<usize>,
+        rules: Option<Vec<Box<dyn Rule>>>,
+    },
+    Match {
+        id: String,
+        key: String,
+        value: String,
+        #[serde(default, deserialize_with = "nullable")]
+        children: Vec<usize>,
         rules: Option<Vec<Box<dyn Rule>>>,
     },
 }
 
+impl Node {
+    #[inline]
+    fn new(ns: &Namespace) -> Self {
+        match ns {
+            Namespace::Object { id } => Node::Object {
+                id: id.clone(),
+                children: Vec::new(),
+                rules: None,
+            },
+            Namespace::Array { id, index } => Node::Array {
+                index: *index,
+                id: id.clone(),
+                children: Vec::new(),
+                rules: None,
+            },
+            Namespace::Wildcard { id } => Node::Wildcard {
+                id: id.clone(),
+                children: Vec::new(),
+                rules: None,
+            },
+            Namespace::Match { id, key, value } => Node::Match {
+                id: id.clone(),
+                key: key.clone(),
+                value: value.clone(),
+                children: Vec::new(),
+                rules: None,
+            },
+        }
+    }
+
+    /// returns the namespace level this node represents, the reverse of new.
+    #[inline]
+    fn namespace(&self) -> Namespace {
+        match self {
+            Node::Object { id, .. } => Namespace::Object { id: id.clone() },
+            Node::Array { id, index, .. } => Namespace::Array {
+                id: id.clone(),
+                index: *index,
+            },
+            Node::Wildcard { id, .. } => Namespace::Wildcard { id: id.clone() },
+            Node::Match { id, key, value, .. } => Namespace::Match {
+                id: id.clone(),
+                key: key.clone(),
+                value: value.clone(),
+            },
+        }
+    }
+
+    /// returns if this node represents the provided namespace level.
+    #[inline]
+    fn matches(&self, ns: &Namespace) -> bool {
+        match (self, ns) {
+            (Node::Object { id, .. }, Namespace::Object { id: ns_id }) => id == ns_id,
+            (
+                Node::Array { id, index, .. },
+                Namespace::Array {
+                    id: ns_id,
+                    index: ns_index,
+                },
+            ) => id == ns_id && index == ns_index,
+            (Node::Wildcard { id, .. }, Namespace::Wildcard { id: ns_id }) => id == ns_id,
+            (
+                Node::Match { id, key, value, .. },
+                Namespace::Match {
+                    id: ns_id,
+                    key: ns_key,
+                    value: ns_value,
+                },
+            ) => id == ns_id && key == ns_key && value == ns_value,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn children(&self) -> &[usize] {
+        match self {
+            Node::Object { children, .. }
+            | Node::Array { children, .. }
+            | Node::Wildcard { children, .. }
+            | Node::Match { children, .. } => children,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn rules(&self) -> &Option<Vec<Box<dyn Rule>>> {
+        match self {
+            Node::Object { rules, .. }
+            | Node::Array { rules, .. }
+            | Node::Wildcard { rules, .. }
+            | Node::Match { rules, .. } => rules,
+        }
+    }
+
+    /// returns the namespace segment this node represents eg. `arr[0]`.
+    #[inline]
+    pub(crate) fn segment(&self) -> String {
+        match self {
+            Node::Object { id, .. } => id.clone(),
+            Node::Array { id, index, .. } => format!("{}[{}]", id, index),
+            Node::Wildcard { id, .. } => format!("{}[*]", id),
+            Node::Match { id, key, value, .. } => format!("{}[{}={}]", id, key, value),
+        }
+    }
+
+    #[inline]
+    fn children_mut(&mut self) -> &mut Vec<usize> {
+        match self {
+            Node::Object { children, .. }
+            | Node::Array { children, .. }
+            | Node::Wildcard { children, .. }
+            | Node::Match { children, .. } => children,
+        }
+    }
+
+    #[inline]
+    fn rules_mut(&mut self) -> &mut Option<Vec<Box<dyn Rule>>> {
+        match self {
+            Node::Object { rules, .. }
+            | Node::Array { rules, .. }
+            | Node::Wildcard { rules, .. }
+            | Node::Match { rules, .. } => rules,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(from = "RawArena")]
 pub(crate) struct Arena {
     pub(crate) tree: Vec<Node>,
+    // distinguishes child index lists from the contiguous `[start, end]` child ranges serialized
+    // by earlier versions, always true once deserialized.
+    child_lists: bool,
 }
 
 impl Default for Arena {
@@ -29,9 +175,35 @@ impl Default for Arena {
         Arena {
             tree: vec![Node::Object {
                 id: String::from(""),
-                children: None,
+                children: Vec::new(),
                 rules: None,
             }],
+            child_lists: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawArena {
+    tree: Vec<Node>,
+    #[serde(default)]
+    child_lists: bool,
+}
+
+impl From<RawArena> for Arena {
+    fn from(raw: RawArena) -> Self {
+        let mut tree = raw.tree;
+        if !raw.child_lists {
+            for node in tree.iter_mut() {
+                let children = node.children_mut();
+                if let [start, end] = children[..] {
+                    *children = (start..=end).collect();
+                }
+            }
+        }
+        Arena {
+            tree,
+            child_lists: true,
         }
     }
 }
@@ -43,216 +215,297 @@ impl Arena {
     where
         R: Rule + Debug + 'static,
     {
+        self.add_boxed(namespace, Box::new(rule));
+    }
+
+    /// adds an already boxed rule, see add.
+    pub fn add_boxed(&mut self, namespace: &[Namespace], rule: Box<dyn Rule>) {
         // when top level there will be no namespaces
         let mut n = 0;
-        'outer: for ns in namespace {
-            // TODO: validate the children's namespace type matches the Namespace type
-
-            match self.tree.get(n).unwrap() {
-                Node::Object { children, .. } => {
-                    if let Some((start, end)) = children.as_ref() {
-                        for idx in *start..=*end {
-                            match self.tree.get(idx).unwrap() {
-                                Node::Object { id, .. } => {
-                                    if id == ns.id() && ns.is_object() {
-                                        n = idx;
-                                        continue 'outer;
-                                    }
-                                }
-                                Node::Array { index, id, .. } => {
-                                    if id == ns.id()
-                                        && ns.is_array()
-                                        && index == ns.as_array().unwrap().1
-                                    {
-                                        n = idx;
-                                        continue 'outer;
-                                    }
-                                }
-                            }
-                        }
-
-                        let parent_idx = Some(n);
-                        n = end + 1;
-                        match ns {
-                            Namespace::Object { id } => {
-                                let new_node = Node::Object {
-                                    id: id.clone(),
-                                    children: None,
-                                    rules: None,
-                                };
-                                self.reindex(parent_idx, n, new_node);
-                            }
-                            Namespace::Array { id, index } => {
-                                let new_node = Node::Array {
-                                    index: *index,
-                                    id: id.clone(),
-                                    children: None,
-                                    rules: None,
-                                };
-                                self.reindex(parent_idx, n, new_node);
-                            }
-                        }
-                        continue 'outer;
-                    }
-
-                    let parent_idx = Some(n);
-                    n = self.tree.len();
-
-                    match ns {
-                        Namespace::Object { id } => {
-                            let new_node = Node::Object {
-                                id: id.clone(),
-                                children: None,
-                                rules: None,
-                            };
-                            self.reindex(parent_idx, n, new_node);
-                        }
-                        Namespace::Array { id, index } => {
-                            let new_node = Node::Array {
-                                index: *index,
-                                id: id.clone(),
-                                children: None,
-                                rules: None,
-                            };
-                            self.reindex(parent_idx, n, new_node);
-                        }
-                    }
-                }
-                Node::Array {
-                    // never be Node::Array for the root of the tree
-                    children,
-                    ..
-                } => {
-                    if let Some((start, end)) = children.as_ref() {
-                        for idx in *start..=*end {
-                            match self.tree.get(idx).unwrap() {
-                                Node::Object { id, .. } => {
-                                    if id == ns.id() && ns.is_object() {
-                                        n = idx;
-                                        continue 'outer;
-                                    }
-                                }
-                                Node::Array { index, id, .. } => {
-                                    if id == ns.id()
-                                        && ns.is_array()
-                                        && index == ns.as_array().unwrap().1
-                                    {
-                                        n = idx;
-                                        continue 'outer;
-                                    }
-                                }
-                            }
-                        }
-
-                        let parent_idx = Some(n);
-                        n = end + 1;
-                        match ns {
-                            Namespace::Object { id } => {
-                                let new_node = Node::Object {
-                                    id: id.clone(),
-                                    children: None,
-                                    rules: None,
-                                };
-                                self.reindex(parent_idx, n, new_node);
-                            }
-                            Namespace::Array { id, index } => {
-                                let new_node = Node::Array {
-                                    index: *index,
-                                    id: id.clone(),
-                                    children: None,
-                                    rules: None,
-                                };
-                                self.reindex(parent_idx, n, new_node);
-                            }
-                        }
-                        continue 'outer;
-                    }
-
-                    let parent_idx = Some(n);
-                    n = self.tree.len();
-                    match ns {
-                        Namespace::Object { id } => {
-                            let new_node = Node::Object {
-                                id: id.clone(),
-                                children: None,
-                                rules: None,
-                            };
-                            self.reindex(parent_idx, n, new_node);
-                        }
-                        Namespace::Array { id, index } => {
-                            let new_node = Node::Array {
-                                index: *index,
-                                id: id.clone(),
-                                children: None,
-                                rules: None,
-                            };
-                            self.reindex(parent_idx, n, new_node);
-                        }
-                    }
+        for ns in namespace {
+            n = match self.child(n, ns) {
+                Some(idx) => idx,
+                None => {
+                    let idx = self.tree.len();
+                    self.tree.push(Node::new(ns));
+                    self.tree[n].children_mut().push(idx);
+                    idx
                 }
-            }
+            };
         }
-        let boxed_rule = Box::new(rule);
-        let node = self.tree.get_mut(n).unwrap();
-        match node {
-            Node::Object { rules, .. } => match rules {
-                Some(v) => v.push(boxed_rule),
-                None => *rules = Some(vec![boxed_rule]),
-            },
-            Node::Array { rules, .. } => match rules {
-                Some(v) => v.push(boxed_rule),
-                None => *rules = Some(vec![boxed_rule]),
-            },
+        let rules = self.tree.get_mut(n).unwrap().rules_mut();
+        match rules {
+            Some(v) => v.push(rule),
+            None => *rules = Some(vec![rule]),
         }
     }
 
+    /// returns the index of the child of the node at index representing the namespace level.
     #[inline]
-    fn reindex(&mut self, parent_idx: Option<usize>, index: usize, mut node: Node) {
-        // loop over all nodes in tree
-        for i in 0..self.tree.len() {
-            // increase child count for any nodes that will be reindexed
-            match self.tree.get_mut(i).unwrap() {
-                Node::Object { children, .. } => {
-                    if let Some((start, end)) = children {
-                        if *start >= index {
-                            *start += 1;
-                            *end += 1;
-                        }
-                    }
+    fn child(&self, idx: usize, ns: &Namespace) -> Option<usize> {
+        self.tree[idx]
+            .children()
+            .iter()
+            .copied()
+            .find(|&child| self.tree[child].matches(ns))
+    }
+
+    /// removes the first rule at the namespace matching the predicate, pruning any nodes left
+    /// without rules or children. Returns if a rule was removed.
+    pub fn remove<F>(&mut self, namespace: &[Namespace], predicate: F) -> bool
+    where
+        F: Fn(&dyn Rule) -> bool,
+    {
+        // indexes of every node from the root down to the rules node
+        let mut path = vec![0];
+        for ns in namespace {
+            match self.child(*path.last().unwrap(), ns) {
+                Some(idx) => path.push(idx),
+                None => return false,
+            }
+        }
+
+        let rules = self.tree[*path.last().unwrap()].rules_mut();
+        let v = match rules {
+            Some(v) => v,
+            None => return false,
+        };
+        match v.iter().position(|r| predicate(r.as_ref())) {
+            Some(pos) => {
+                v.remove(pos);
+                if v.is_empty() {
+                    *rules = None;
                 }
-                Node::Array { children, .. } => {
-                    if let Some((start, end)) = children {
-                        if *start >= index {
-                            *start += 1;
-                            *end += 1;
-                        }
-                    }
+            }
+            None => return false,
+        }
+
+        while path.len() > 1 {
+            let idx = path.pop().unwrap();
+            let node = &self.tree[idx];
+            if node.rules().is_some() || !node.children().is_empty() {
+                break;
+            }
+            self.prune(*path.last().unwrap(), idx);
+        }
+        true
+    }
+
+    /// removes every rule not matching the predicate, pruning any nodes left without rules or
+    /// children. Returns the number of rules removed.
+    pub fn retain<F>(&mut self, predicate: F) -> usize
+    where
+        F: Fn(&dyn Rule) -> bool,
+    {
+        let mut removed = 0;
+        for node in self.tree.iter_mut() {
+            let rules = node.rules_mut();
+            if let Some(v) = rules {
+                let len = v.len();
+                v.retain(|r| predicate(r.as_ref()));
+                removed += len - v.len();
+                if v.is_empty() {
+                    *rules = None;
                 }
             }
-            // if we're at the new nodes insertion point start reindexing
-            if i >= index {
-                node = mem::replace(&mut self.tree[i], node);
+        }
+
+        // children always follow their parents so are pruned first
+        for idx in (1..self.tree.len()).rev() {
+            let node = &self.tree[idx];
+            if node.rules().is_none() && node.children().is_empty() {
+                let parent = self
+                    .tree
+                    .iter()
+                    .position(|n| n.children().contains(&idx))
+                    .unwrap();
+                self.prune(parent, idx);
+            }
+        }
+        removed
+    }
+
+    /// consumes the tree returning every rule along with the full source namespace it's applied
+    /// at, parents before children.
+    pub(crate) fn into_rules(mut self) -> Vec<(Vec<Namespace>, Box<dyn Rule>)> {
+        let mut rules = Vec::new();
+        self.take_rules(0, &mut Vec::new(), &mut rules);
+        rules
+    }
+
+    fn take_rules(
+        &mut self,
+        idx: usize,
+        namespace: &mut Vec<Namespace>,
+        rules: &mut Vec<(Vec<Namespace>, Box<dyn Rule>)>,
+    ) {
+        if let Some(v) = self.tree[idx].rules_mut().take() {
+            rules.extend(v.into_iter().map(|rule| (namespace.clone(), rule)));
+        }
+        for child in self.tree[idx].children().to_vec() {
+            namespace.push(self.tree[child].namespace());
+            self.take_rules(child, namespace, rules);
+            namespace.pop();
+        }
+    }
+
+    /// returns every rule in the tree, in no particular order.
+    pub(crate) fn rules(&self) -> impl Iterator<Item = &dyn Rule> {
+        self.tree
+            .iter()
+            .filter_map(|node| node.rules().as_ref())
+            .flatten()
+            .map(|rule| rule.as_ref())
+    }
+
+    /// calls the function with every rule in the tree and the full source namespace it's applied
+    /// at, parents before children.
+    pub(crate) fn visit_rules<F>(&self, f: &mut F)
+    where
+        F: FnMut(&[Namespace], &dyn Rule),
+    {
+        self.visit_rules_at(0, &mut Vec::new(), f);
+    }
+
+    fn visit_rules_at<F>(&self, idx: usize, namespace: &mut Vec<Namespace>, f: &mut F)
+    where
+        F: FnMut(&[Namespace], &dyn Rule),
+    {
+        if let Some(rules) = self.tree[idx].rules() {
+            for rule in rules {
+                f(namespace, rule.as_ref());
+            }
+        }
+        for &child in self.tree[idx].children() {
+            namespace.push(self.tree[child].namespace());
+            self.visit_rules_at(child, namespace, f);
+            namespace.pop();
+        }
+    }
+
+    /// returns the full source namespace of every node in the tree, indexed by node.
+    pub(crate) fn paths(&self) -> Vec<String> {
+        let mut paths = vec![String::new(); self.tree.len()];
+        self.fill_paths(0, &mut paths);
+        paths
+    }
+
+    fn fill_paths(&self, idx: usize, paths: &mut Vec<String>) {
+        for &child in self.tree[idx].children() {
+            paths[child] = join_path(&paths[idx], &self.tree[child].segment());
+            self.fill_paths(child, paths);
+        }
+    }
+
+    /// removes the childless node at index from the tree and it's parent.
+    #[inline]
+    fn prune(&mut self, parent_idx: usize, index: usize) {
+        self.tree[parent_idx].children_mut().retain(|&c| c != index);
+        self.tree.remove(index);
+        for node in self.tree.iter_mut() {
+            for child in node.children_mut().iter_mut() {
+                if *child > index {
+                    *child -= 1;
+                }
             }
         }
-        self.tree.push(node);
-
-        // increase or set the parent nodes child count to it's new range.
-        if let Some(idx) = parent_idx {
-            match self.tree.get_mut(idx).unwrap() {
-                Node::Object { children, .. } => match children {
-                    Some((_, end)) => {
-                        *end += 1;
-                    }
-                    None => *children = Some((index, index)),
+    }
+}
+
+/// deserializes a missing or null list of children, as serialized by earlier versions for nodes
+/// without children, as empty.
+fn nullable<'de, D>(deserializer: D) -> Result<Vec<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Vec<usize>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Plan is an Arena compiled into a flat list of steps, one per node in the order they're walked,
+/// parents before children, so applying a Transformer doesn't chase child lists through the tree.
+/// The descendants of a step are the contiguous steps following it, up to its end, and the keys
+/// steps read are interned.
+#[derive(Debug, Default)]
+pub(crate) struct Plan {
+    pub(crate) steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Step {
+    // index of the node in the Arena, whose rules are applied
+    pub(crate) node: usize,
+    // index of the step following the node's descendants
+    pub(crate) end: usize,
+    pub(crate) access: Access,
+}
+
+/// Access is how a step reads its source value from its parent's.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Access {
+    Root,
+    Object(Arc<str>),
+    Array {
+        id: Arc<str>,
+        index: usize,
+    },
+    Wildcard(Arc<str>),
+    Match {
+        id: Arc<str>,
+        key: Arc<str>,
+        value: Arc<str>,
+    },
+}
+
+impl Plan {
+    pub(crate) fn compile(arena: &Arena) -> Self {
+        Plan::compile_with(arena, &mut HashMap::new())
+    }
+
+    /// compiles the Arena interning keys in the provided map, so Plans compiled with the same map
+    /// share their keys.
+    pub(crate) fn compile_with(arena: &Arena, keys: &mut HashMap<String, Arc<str>>) -> Self {
+        let mut plan = Plan {
+            steps: Vec::with_capacity(arena.tree.len()),
+        };
+        plan.push(arena, 0, Access::Root, keys);
+        plan
+    }
+
+    fn push(
+        &mut self,
+        arena: &Arena,
+        idx: usize,
+        access: Access,
+        keys: &mut HashMap<String, Arc<str>>,
+    ) {
+        let pos = self.steps.len();
+        self.steps.push(Step {
+            node: idx,
+            end: 0,
+            access,
+        });
+        for &child in arena.tree[idx].children() {
+            let mut intern = |key: &String| {
+                keys.entry(key.clone())
+                    .or_insert_with(|| Arc::from(key.as_str()))
+                    .clone()
+            };
+            let access = match &arena.tree[child] {
+                Node::Object { id, .. } => Access::Object(intern(id)),
+                Node::Array { id, index, .. } => Access::Array {
+                    id: intern(id),
+                    index: *index,
                 },
-                Node::Array { children, .. } => match children {
-                    Some((_, end)) => {
-                        *end += 1;
-                    }
-                    None => *children = Some((index, index)),
+                Node::Wildcard { id, .. } => Access::Wildcard(intern(id)),
+                Node::Match { id, key, value, .. } => Access::Match {
+                    id: intern(id),
+                    key: intern(key),
+                    value: intern(value),
                 },
-            }
+            };
+            self.push(arena, child, access, keys);
         }
+        self.steps[pos].end = self.steps.len();
     }
 }
 
@@ -285,6 +538,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_plan() {
+        let mut arena = Arena::default();
+        let object = |id: &str| Namespace::Object { id: id.to_owned() };
+        arena.add(&[object("a"), object("b")], MyRule {});
+        arena.add(&[object("c")], MyRule {});
+        arena.add(
+            &[
+                object("a"),
+                Namespace::Wildcard {
+                    id: String::from("c"),
+                },
+            ],
+            MyRule {},
+        );
+
+        let plan = Plan::compile(&arena);
+        let steps = plan
+            .steps
+            .iter()
+            .map(|step| (step.node, step.end))
+            .collect::<Vec<_>>();
+        // a is followed by its descendants, b and c[*], before c
+        assert_eq!(vec![(0, 5), (1, 4), (2, 3), (4, 4), (3, 5)], steps);
+        assert_eq!(Access::Root, plan.steps[0].access);
+        assert_eq!(Access::Wildcard(Arc::from("c")), plan.steps[3].access);
+        match (&plan.steps[3].access, &plan.steps[4].access) {
+            (Access::Wildcard(a), Access::Object(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn test_simple() {
         let rule = MyRule {};
@@ -378,51 +663,129 @@ mod tests {
         let tree = vec![
             Node::Object {
                 id: "".to_string(),
-                children: Some((1, 3)),
+                children: vec![1, 2, 8],
                 rules: Some(vec![Box::new(MyRule {}), Box::new(MyRule2 {})]),
             },
             Node::Object {
                 id: "embedded".to_string(),
-                children: Some((4, 6)),
+                children: vec![3, 4, 6],
                 rules: Some(vec![Box::new(MyRule {}), Box::new(MyRule2 {})]),
             },
             Node::Object {
                 id: "embedded2".to_string(),
-                children: Some((7, 8)),
+                children: vec![5, 7],
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
-                id: "injected-embedded-after".to_string(),
-                children: None,
+                id: "injected-child".to_string(),
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
-                id: "injected-child".to_string(),
-                children: None,
+                id: "injected-child2".to_string(),
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
-                id: "injected-child2".to_string(),
-                children: None,
+                id: "embedded2-injected-child".to_string(),
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
                 id: "injected-child3".to_string(),
-                children: None,
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
-                id: "embedded2-injected-child".to_string(),
-                children: None,
+                id: "embedded2-injected-child2".to_string(),
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
             Node::Object {
-                id: "embedded2-injected-child2".to_string(),
-                children: None,
+                id: "injected-embedded-after".to_string(),
+                children: Vec::new(),
+                rules: Some(vec![Box::new(MyRule {})]),
+            },
+        ];
+        let expected = Arena {
+            tree,
+            child_lists: true,
+        };
+        assert_eq!(format!("{:?}", expected), format!("{:?}", arena));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut arena = Arena::default();
+        let embedded = vec![Namespace::Object {
+            id: String::from("embedded"),
+        }];
+        let nested = vec![
+            Namespace::Object {
+                id: String::from("embedded"),
+            },
+            Namespace::Object {
+                id: String::from("nested"),
+            },
+        ];
+        let other = vec![Namespace::Object {
+            id: String::from("other"),
+        }];
+        arena.add(&nested, MyRule {});
+        arena.add(&embedded, MyRule2 {});
+        arena.add(&other, MyRule {});
+
+        let is_rule = |r: &dyn Rule| format!("{:?}", r) == "MyRule";
+        assert!(!arena.remove(&embedded, is_rule));
+        assert!(arena.remove(&nested, is_rule));
+        assert!(!arena.remove(&nested, is_rule));
+
+        let tree = vec![
+            Node::Object {
+                id: "".to_string(),
+                children: vec![1, 2],
+                rules: None,
+            },
+            Node::Object {
+                id: "embedded".to_string(),
+                children: Vec::new(),
+                rules: Some(vec![Box::new(MyRule2 {})]),
+            },
+            Node::Object {
+                id: "other".to_string(),
+                children: Vec::new(),
                 rules: Some(vec![Box::new(MyRule {})]),
             },
         ];
-        let expected = Arena { tree };
+        let expected = Arena {
+            tree,
+            child_lists: true,
+        };
         assert_eq!(format!("{:?}", expected), format!("{:?}", arena));
+
+        assert!(arena.remove(&embedded, |_| true));
+        assert!(arena.remove(&other, |_| true));
+        assert_eq!(format!("{:?}", Arena::default()), format!("{:?}", arena));
+    }
+
+    #[test]
+    fn test_deserialize_child_ranges() -> Result<()> {
+        // the contiguous child ranges serialized by earlier versions
+        let legacy = r#"{"tree":[
+            {"Object":{"id":"","children":[1,3],"rules":null}},
+            {"Object":{"id":"a","children":null,"rules":null}},
+            {"Object":{"id":"b","children":null,"rules":null}},
+            {"Object":{"id":"c","children":[4,4],"rules":null}},
+            {"Object":{"id":"d","children":null,"rules":null}}
+        ]}"#;
+        let arena: Arena = serde_json::from_str(legacy)?;
+        assert_eq!(&[1, 2, 3][..], arena.tree[0].children());
+        assert_eq!(&[4][..], arena.tree[3].children());
+        assert!(arena.tree[1].children().is_empty());
+
+        let arena: Arena = serde_json::from_str(&serde_json::to_string(&arena)?)?;
+        assert_eq!(&[1, 2, 3][..], arena.tree[0].children());
+        assert_eq!(&[4][..], arena.tree[3].children());
+        Ok(())
     }
 }